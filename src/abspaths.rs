@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Keep absolute paths of the machine running buckify out of the generated
+//! rules.
+//!
+//! They get in through build script outputs captured as fixups, such as
+//! `cargo:rustc-link-search`, hand-written `env` and `rustc_flags`, and
+//! packages with no relative path to them. A rule which mentions one builds
+//! differently on every machine, so remote execution never finds it in the
+//! cache, and the files it names aren't inputs of the action. A path to files
+//! in the third-party directory becomes the `$(location)` of a filegroup of
+//! them; the rest are reported.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Result;
+use walkdir::WalkDir;
+
+use crate::buck::BuckPath;
+use crate::buck::Filegroup;
+use crate::buck::Name;
+use crate::buck::PlatformRustCommon;
+use crate::buck::Rule;
+use crate::buck::RustBinary;
+use crate::buck::RustLibrary;
+use crate::buck::StringOrPath;
+use crate::buck::SubtargetOrPath;
+use crate::buck::Visibility;
+use crate::buckify::relative_path;
+use crate::config::CheckLevel;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::Paths;
+
+pub(crate) fn check_absolute_paths(
+    config: &Config,
+    paths: &Paths,
+    rules: &mut BTreeSet<Rule>,
+) -> Result<()> {
+    let severity = match config.buck.absolute_paths {
+        CheckLevel::Ignore => return Ok(()),
+        CheckLevel::Warn => Severity::Warning,
+        CheckLevel::Error => Severity::Error,
+    };
+
+    let mut rewriter = Rewriter {
+        third_party_dir: &paths.third_party_dir,
+        filegroups: BTreeMap::new(),
+    };
+    let mut found = 0;
+    *rules = mem::take(rules)
+        .into_iter()
+        .map(|mut rule| {
+            let name = rule.get_name().clone();
+            let mut report = |attr: &str, path: &str| {
+                found += 1;
+                Diagnostic::new(
+                    severity,
+                    format!("`{}` {}: absolute path `{}`", name, attr, path),
+                )
+                .suggestion("put the files in the third-party directory, or refer to a rule with $(location)")
+                .report();
+            };
+            match &mut rule {
+                Rule::Binary(RustBinary { common, .. })
+                | Rule::BuildscriptBinary(RustBinary { common, .. })
+                | Rule::Bench(RustBinary { common, .. })
+                | Rule::Library(RustLibrary { common, .. })
+                | Rule::RootPackage(RustLibrary { common, .. })
+                | Rule::Doc(RustLibrary { common, .. })
+                | Rule::Doctest(RustLibrary { common, .. }) => {
+                    if common.crate_root.0.is_absolute() {
+                        report("crate_root", &common.crate_root.to_string());
+                    }
+                    for plat in
+                        std::iter::once(&mut common.base).chain(common.platform.values_mut())
+                    {
+                        rewriter.platform_attrs(plat, &mut report);
+                    }
+                }
+                Rule::BuildscriptGenrule(genrule) => {
+                    for (attr, env) in [
+                        ("env", &mut genrule.env),
+                        ("path_env", &mut genrule.path_env),
+                        ("args_env", &mut genrule.args_env),
+                    ] {
+                        for value in env.values_mut() {
+                            rewriter.string(value, &mut |path| report(attr, path));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            rule
+        })
+        .collect();
+
+    for (name, srcs) in rewriter.filegroups {
+        rules.insert(Rule::Filegroup(Filegroup {
+            name,
+            srcs,
+            visibility: Visibility::private(&config.buck),
+        }));
+    }
+
+    if found > 0 && config.buck.absolute_paths == CheckLevel::Error {
+        bail!(
+            "{} absolute path{} in the generated rules",
+            found,
+            if found == 1 { "" } else { "s" },
+        );
+    }
+    Ok(())
+}
+
+struct Rewriter<'a> {
+    third_party_dir: &'a Path,
+    /// Filegroups for the paths into the third-party directory, each mapping
+    /// the files to where they are relative to the path
+    filegroups: BTreeMap<Name, BTreeMap<String, SubtargetOrPath>>,
+}
+
+impl Rewriter<'_> {
+    fn platform_attrs(
+        &mut self,
+        plat: &mut PlatformRustCommon,
+        report: &mut impl FnMut(&str, &str),
+    ) {
+        for src in &plat.srcs {
+            if src.0.is_absolute() {
+                report("srcs", &src.to_string());
+            }
+        }
+        for src in plat.mapped_srcs.keys() {
+            match src {
+                SubtargetOrPath::Path(path) if path.0.is_absolute() => {
+                    report("mapped_srcs", &path.to_string());
+                }
+                _ => {}
+            }
+        }
+        for flag in &mut plat.rustc_flags {
+            self.string(flag, &mut |path| report("rustc_flags", path));
+        }
+        for value in plat.env.values_mut() {
+            match value {
+                StringOrPath::Path(path) => {
+                    if path.0.is_absolute() {
+                        report("env", &path.to_string());
+                    }
+                }
+                StringOrPath::String(string) => {
+                    self.string(string, &mut |path| report("env", path));
+                }
+            }
+        }
+    }
+
+    /// Replace the paths to files in the third-party directory in a string
+    /// with their location, and report any other absolute path.
+    fn string(&mut self, string: &mut String, report: &mut impl FnMut(&str)) {
+        let mut rewritten = String::new();
+        let mut rest = 0;
+        for range in absolute_paths(string) {
+            let path = &string[range.clone()];
+            match self.location(Path::new(path)) {
+                Some(location) => {
+                    rewritten.push_str(&string[rest..range.start]);
+                    rewritten.push_str(&location);
+                    rest = range.end;
+                }
+                None => report(path),
+            }
+        }
+        if rest > 0 {
+            rewritten.push_str(&string[rest..]);
+            *string = rewritten;
+        }
+    }
+
+    /// `$(location)` of a filegroup of the file or directory at `path`, if
+    /// it's in the third-party directory.
+    fn location(&mut self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(self.third_party_dir).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+
+        let slashed = relative.to_string_lossy().replace('\\', "/");
+        let name = Name(format!("{}-files", slashed.replace('/', "-")));
+        let location = if path.is_file() {
+            let file_name = relative.file_name()?.to_string_lossy().into_owned();
+            let srcs = BTreeMap::from([(
+                file_name.clone(),
+                SubtargetOrPath::Path(BuckPath(relative.to_owned())),
+            )]);
+            self.filegroups.insert(name.clone(), srcs);
+            format!("$(location :{})/{}", name, file_name)
+        } else if path.is_dir() {
+            let srcs = WalkDir::new(path)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| {
+                    let within = relative_path(path, entry.path());
+                    (
+                        within.to_string_lossy().replace('\\', "/"),
+                        SubtargetOrPath::Path(BuckPath(relative.join(within))),
+                    )
+                })
+                .collect();
+            self.filegroups.insert(name.clone(), srcs);
+            format!("$(location :{})", name)
+        } else {
+            return None;
+        };
+        Some(location)
+    }
+}
+
+/// The absolute paths in a string, such as `/usr/lib` in `-Lnative=/usr/lib`,
+/// going by where a path could start. Labels like `//foo:bar` don't count.
+fn absolute_paths(string: &str) -> Vec<Range<usize>> {
+    let is_start = |i: usize| i == 0 || string[..i].ends_with([' ', '=', ',', ';', '\'', '"', '(']);
+    let end = |i: usize| {
+        string[i..]
+            .find([' ', ',', ';', '\'', '"', ')'])
+            .map_or(string.len(), |len| i + len)
+    };
+
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < string.len() {
+        let rest = &string[i..];
+        let unix = rest.starts_with('/') && !rest.starts_with("//");
+        let bytes = rest.as_bytes();
+        let windows = bytes.len() >= 3
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && (bytes[2] == b'\\' || bytes[2] == b'/');
+        if (unix || windows) && is_start(i) {
+            let end = end(i);
+            paths.push(i..end);
+            i = end;
+        } else {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use super::absolute_paths;
+    use super::Rewriter;
+
+    fn found(string: &str) -> Vec<&str> {
+        absolute_paths(string)
+            .into_iter()
+            .map(|range| &string[range])
+            .collect()
+    }
+
+    #[test]
+    fn test_absolute_paths() {
+        assert_eq!(found("-Lnative=/usr/lib"), vec!["/usr/lib"]);
+        assert_eq!(
+            found("-Clink-arg=-Wl,-rpath,/opt/foo/lib"),
+            vec!["/opt/foo/lib"],
+        );
+        assert_eq!(found(r"C:\foo\bar"), vec![r"C:\foo\bar"]);
+        assert!(found("$(location //third-party:foo)").is_empty());
+        assert!(found("fixups/foo/out_dir").is_empty());
+        assert!(found("--cfg=foo_bar").is_empty());
+        assert!(found("a/b").is_empty());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let third_party_dir = tempfile::tempdir().unwrap();
+        let lib = third_party_dir.path().join("fixups/foo/lib");
+        fs::create_dir_all(lib.join("x86_64")).unwrap();
+        fs::write(lib.join("x86_64/libfoo.a"), "").unwrap();
+
+        let mut rewriter = Rewriter {
+            third_party_dir: third_party_dir.path(),
+            filegroups: BTreeMap::new(),
+        };
+        let mut reported = Vec::new();
+        let mut flag = format!("-Lnative={}/x86_64", lib.display());
+        rewriter.string(&mut flag, &mut |path| reported.push(path.to_owned()));
+        assert_eq!(flag, "-Lnative=$(location :fixups-foo-lib-x86_64-files)");
+        let srcs: Vec<&String> = rewriter
+            .filegroups
+            .values()
+            .flat_map(|srcs| srcs.keys())
+            .collect();
+        assert_eq!(srcs, ["libfoo.a"]);
+
+        let mut env = lib.join("x86_64/libfoo.a").display().to_string();
+        rewriter.string(&mut env, &mut |path| reported.push(path.to_owned()));
+        assert_eq!(
+            env,
+            "$(location :fixups-foo-lib-x86_64-libfoo.a-files)/libfoo.a"
+        );
+        assert!(reported.is_empty());
+
+        // Not in the third-party directory, or not there at all.
+        let missing = format!("-Lnative={}/missing", lib.display());
+        for flag in ["-Lnative=/usr/lib", &missing] {
+            let mut rewritten = flag.to_owned();
+            rewriter.string(&mut rewritten, &mut |path| reported.push(path.to_owned()));
+            assert_eq!(rewritten, flag);
+        }
+        assert_eq!(reported.len(), 2);
+    }
+}
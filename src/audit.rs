@@ -0,0 +1,382 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Fix the problems with fixups which don't need anyone to think about them
+//!
+//! Of what `reindeer lint` reports, fixups for packages which have left the
+//! dependency graph and `version`s which the package's only version has moved
+//! past have one obvious remedy. So do packages whose build script only
+//! detects the compiler, which get a `rustc_flags` fixup instead of being left
+//! unresolved. `reindeer audit` lists these and `reindeer audit --fix` makes
+//! the changes, leaving alone the packages in `never_autofix`.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use toml_edit::ArrayOfTables;
+use toml_edit::Document;
+use toml_edit::Item;
+use toml_edit::Table;
+
+use crate::buckify::relative_path;
+use crate::cargo;
+use crate::cargo::DepKind;
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::fixups;
+use crate::index::semver_compatible_version;
+use crate::lint;
+use crate::platform::PlatformExpr;
+use crate::Args;
+use crate::Paths;
+
+/// Build dependencies of build scripts which do nothing but look at the
+/// compiler, and so only emit `cargo:rustc-cfg`
+const TRIVIAL_BUILD_DEPS: &[&str] = &["autocfg", "rustc_version", "version_check"];
+
+/// What a build script mentions if it does more than that
+const NONTRIVIAL_BUILD_SCRIPT: &[&str] = &["OUT_DIR", "rustc-env", "rustc-link", "rustc-cdylib"];
+
+enum Fix {
+    /// Remove the fixups.toml of a package which isn't in the dependency
+    /// graph, and its directory if nothing else is in it.
+    Remove { name: String },
+    /// Change a `version` requirement to match the package's version.
+    Version {
+        platform: Option<PlatformExpr>,
+        from: semver::VersionReq,
+        to: String,
+    },
+    /// Resolve the build script with a `rustc_flags` fixup.
+    Buildscript,
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fix::Remove { name } => {
+                write!(
+                    f,
+                    "remove fixups.toml, no package `{}` in the dependency graph",
+                    name
+                )
+            }
+            Fix::Version { platform, from, to } => {
+                if let Some(platform) = platform {
+                    write!(f, "[platform_fixup.'{}'] ", platform)?;
+                }
+                write!(f, "version: change `{}` to `{}`", from, to)
+            }
+            Fix::Buildscript => write!(f, "buildscript: run it for `rustc_flags`"),
+        }
+    }
+}
+
+pub(crate) fn audit(config: &Config, args: &Args, paths: &Paths, fix: bool) -> Result<()> {
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+
+    let mut packages: BTreeMap<&str, Vec<&Manifest>> = BTreeMap::new();
+    for pkg in &metadata.packages {
+        packages.entry(&pkg.name).or_default().push(pkg);
+    }
+
+    // Fixes by package name, with the fixup directory to make them in
+    let mut fixes: Vec<(&str, PathBuf, Fix)> = Vec::new();
+    let fixup_dirs = lint::find_fixup_dirs(config, paths)?;
+    for fixup_dir in &fixup_dirs {
+        match packages.get(fixup_dir.name.as_str()).map(Vec::as_slice) {
+            None if !fixup_dir.shared && !fixup_dir.included => {
+                let name = fixup_dir.name.clone();
+                fixes.push((&fixup_dir.name, fixup_dir.dir.clone(), Fix::Remove { name }));
+            }
+            // With several versions, it isn't clear which one a `version`
+            // was meant for.
+            Some([pkg]) => {
+                let Some(Ok((fixup_config, _included))) = &fixup_dir.parsed else {
+                    continue;
+                };
+                for (platform, fixup) in fixup_config.all_configs() {
+                    match &fixup.version {
+                        Some(req) if !req.matches(&pkg.version) => fixes.push((
+                            &fixup_dir.name,
+                            fixup_dir.dir.clone(),
+                            Fix::Version {
+                                platform: platform.cloned(),
+                                from: req.clone(),
+                                to: semver_compatible_version(&pkg.version),
+                            },
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut fixed_dirs = BTreeSet::new();
+    for pkg in &metadata.packages {
+        if metadata.workspace_default_members.contains(&pkg.id)
+            || !has_trivial_buildscript(pkg)
+            || !seen.insert((&pkg.name, &pkg.version))
+        {
+            continue;
+        }
+        let candidates = fixups::fixup_dirs(config, paths, pkg);
+        let fixup_dir = candidates
+            .iter()
+            .find(|dir| dir.join("fixups.toml").exists())
+            .unwrap_or(&candidates[0]);
+        // Versions of a package can share their fixups, which only need
+        // fixing once.
+        if is_unresolved(&fixup_dir.join("fixups.toml"))? && fixed_dirs.insert(fixup_dir.clone()) {
+            let dir = relative_path(&paths.third_party_dir, fixup_dir);
+            fixes.push((&pkg.name, dir, Fix::Buildscript));
+        }
+    }
+
+    let mut skipped = 0;
+    for (name, dir, fixup) in &fixes {
+        if config.audit.never_autofix.contains(*name) {
+            println!("{}: {} (skipped, in never_autofix)", dir.display(), fixup);
+            skipped += 1;
+            continue;
+        }
+        println!("{}: {}", dir.display(), fixup);
+        if fix {
+            apply(&paths.third_party_dir.join(dir), fixup)
+                .with_context(|| format!("fixing {}", dir.display()))?;
+        }
+    }
+
+    if !fix && fixes.len() > skipped {
+        println!("Run `reindeer audit --fix` to make these changes");
+    }
+    Ok(())
+}
+
+/// A build script which only needs the compiler and only sets cfgs, judging
+/// by what it's built with and what its source says
+fn has_trivial_buildscript(pkg: &Manifest) -> bool {
+    let Some(buildscript) = pkg.targets.iter().find(|tgt| tgt.kind_custom_build()) else {
+        return false;
+    };
+    let Ok(source) = fs::read_to_string(&buildscript.src_path) else {
+        return false;
+    };
+    pkg.links.is_none()
+        && pkg
+            .dependencies
+            .iter()
+            .filter(|dep| dep.kind == DepKind::Build)
+            .all(|dep| TRIVIAL_BUILD_DEPS.contains(&dep.name.as_str()))
+        && !NONTRIVIAL_BUILD_SCRIPT
+            .iter()
+            .any(|word| source.contains(word))
+}
+
+/// Whether a package's fixups leave its build script unresolved: there are
+/// none, or no `buildscript` entries, or an `unresolved` one.
+fn is_unresolved(fixup_path: &Path) -> Result<bool> {
+    let file = match fs::read_to_string(fixup_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err).context(format!("reading {}", fixup_path.display())),
+    };
+    let table: toml::Table = toml::from_str(&file)
+        .with_context(|| format!("Failed to parse {}", fixup_path.display()))?;
+    // Build script fixups could be in an included file.
+    if table.contains_key("include") {
+        return Ok(false);
+    }
+    Ok(match table.get("buildscript") {
+        None => true,
+        Some(toml::Value::Array(buildscript)) => buildscript.iter().any(|entry| {
+            entry
+                .as_table()
+                .is_some_and(|entry| entry.contains_key("unresolved"))
+        }),
+        Some(_) => false,
+    })
+}
+
+fn apply(fixup_dir: &Path, fix: &Fix) -> Result<()> {
+    let (platform, to) = match fix {
+        Fix::Remove { .. } => {
+            let fixup_path = fixup_dir.join("fixups.toml");
+            match fs::remove_file(&fixup_path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to remove {}", fixup_path.display()));
+                }
+            }
+            // Anything else there, like patches or a build script's captured
+            // outputs, could be someone's work in progress.
+            if fs::read_dir(fixup_dir)?.next().is_none() {
+                fs::remove_dir(fixup_dir)?;
+            } else {
+                println!("{}: kept the other files in it", fixup_dir.display());
+            }
+            return Ok(());
+        }
+        Fix::Version { platform, to, .. } => (platform.as_ref(), Some(to)),
+        Fix::Buildscript => (None, None),
+    };
+
+    let fixup_path = fixup_dir.join("fixups.toml");
+    let mut doc = match fs::read_to_string(&fixup_path) {
+        Ok(content) => content
+            .parse::<Document>()
+            .with_context(|| format!("Failed to parse {}", fixup_path.display()))?,
+        Err(err) if err.kind() == ErrorKind::NotFound => Document::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", fixup_path.display()));
+        }
+    };
+
+    let mut table = doc.as_table_mut();
+    if let Some(platform) = platform {
+        table = match table
+            .get_mut("platform_fixup")
+            .and_then(|table| table.get_mut(platform.to_string()))
+            .and_then(Item::as_table_mut)
+        {
+            Some(table) => table,
+            None => bail!("no [platform_fixup.'{}'] in fixups.toml", platform),
+        };
+    }
+
+    match to {
+        Some(to) => {
+            if !table.contains_key("version") {
+                bail!("its `version` comes from an included file");
+            }
+            // Keep any comment after the old value.
+            let Some(version) = table["version"].as_value_mut() else {
+                bail!("`version` isn't a string");
+            };
+            let decor = version.decor().clone();
+            *version = to.as_str().into();
+            *version.decor_mut() = decor;
+        }
+        None => {
+            let mut buildscript: ArrayOfTables = match table.remove("buildscript") {
+                Some(Item::ArrayOfTables(existing)) => existing
+                    .into_iter()
+                    .filter(|entry| !entry.contains_key("unresolved"))
+                    .collect(),
+                _ => ArrayOfTables::new(),
+            };
+            let mut entry = Table::new();
+            entry.set_implicit(true);
+            entry["rustc_flags"] = Item::Table(Table::new());
+            buildscript.push(entry);
+            table["buildscript"] = Item::ArrayOfTables(buildscript);
+        }
+    }
+
+    fs::create_dir_all(fixup_dir)?;
+    fs::write(&fixup_path, doc.to_string())
+        .with_context(|| format!("Failed to write {}", fixup_path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::apply;
+    use super::is_unresolved;
+    use super::Fix;
+    use crate::platform::PlatformExpr;
+
+    #[test]
+    fn removes_only_fixups() {
+        let third_party_dir = tempfile::tempdir().unwrap();
+        let remove = Fix::Remove {
+            name: "foo".to_owned(),
+        };
+
+        let fixup_dir = third_party_dir.path().join("fixups/foo");
+        fs::create_dir_all(&fixup_dir).unwrap();
+        fs::write(fixup_dir.join("fixups.toml"), "cfgs = []\n").unwrap();
+        apply(&fixup_dir, &remove).unwrap();
+        assert!(!fixup_dir.exists());
+
+        fs::create_dir_all(fixup_dir.join("patches")).unwrap();
+        fs::write(fixup_dir.join("fixups.toml"), "cfgs = []\n").unwrap();
+        fs::write(fixup_dir.join("patches/fix.patch"), "").unwrap();
+        apply(&fixup_dir, &remove).unwrap();
+        assert!(!fixup_dir.join("fixups.toml").exists());
+        assert!(fixup_dir.join("patches/fix.patch").exists());
+    }
+
+    #[test]
+    fn changes_versions() {
+        let fixup_dir = tempfile::tempdir().unwrap();
+        let fixup_path = fixup_dir.path().join("fixups.toml");
+        fs::write(
+            &fixup_path,
+            concat!(
+                "version = \"^1\"  # keep\n",
+                "\n",
+                "[platform_fixup.'cfg(windows)']\n",
+                "version = \"^1\"\n",
+            ),
+        )
+        .unwrap();
+
+        let fix = |platform: Option<&str>| Fix::Version {
+            platform: platform.map(|platform| PlatformExpr::from(platform.to_owned())),
+            from: "^1".parse().unwrap(),
+            to: "2".to_owned(),
+        };
+        apply(fixup_dir.path(), &fix(None)).unwrap();
+        apply(fixup_dir.path(), &fix(Some("cfg(windows)"))).unwrap();
+        assert_eq!(
+            fs::read_to_string(&fixup_path).unwrap(),
+            concat!(
+                "version = \"2\"  # keep\n",
+                "\n",
+                "[platform_fixup.'cfg(windows)']\n",
+                "version = \"2\"\n",
+            ),
+        );
+
+        assert!(apply(fixup_dir.path(), &fix(Some("cfg(unix)"))).is_err());
+    }
+
+    #[test]
+    fn resolves_buildscripts() {
+        let fixup_dir = tempfile::tempdir().unwrap();
+        let fixup_path = fixup_dir.path().join("fixups.toml");
+        assert!(is_unresolved(&fixup_path).unwrap());
+
+        apply(fixup_dir.path(), &Fix::Buildscript).unwrap();
+        assert!(!is_unresolved(&fixup_path).unwrap());
+        assert_eq!(
+            fs::read_to_string(&fixup_path).unwrap(),
+            "[[buildscript]]\n\n[buildscript.rustc_flags]\n",
+        );
+
+        fs::write(&fixup_path, "[[buildscript]]\n[buildscript.unresolved]\n").unwrap();
+        assert!(is_unresolved(&fixup_path).unwrap());
+        apply(fixup_dir.path(), &Fix::Buildscript).unwrap();
+        assert!(!is_unresolved(&fixup_path).unwrap());
+    }
+}
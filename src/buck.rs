@@ -13,19 +13,33 @@ use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::fmt;
 use std::fmt::Display;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
 use std::io::Write;
+use std::iter;
+use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::bail;
 use anyhow::Result;
+use fnv::FnvHasher;
+use indexmap::IndexMap;
+use rayon::iter::IntoParallelRefIterator as _;
+use rayon::iter::ParallelIterator as _;
 use semver::Version;
 use serde::ser::SerializeMap;
 use serde::ser::SerializeSeq;
+use serde::ser::SerializeTupleStruct;
 use serde::ser::Serializer;
+use serde::Deserialize;
 use serde::Serialize;
 use serde_starlark::FunctionCall;
 
 use crate::collection::SetOrMap;
 use crate::config::BuckConfig;
+use crate::config::ShardBy;
+use crate::config::SortOrder;
 use crate::platform::PlatformConfig;
 use crate::platform::PlatformExpr;
 use crate::platform::PlatformName;
@@ -86,6 +100,10 @@ impl RuleRef {
         self.platform.is_some()
     }
 
+    pub fn platform(&self) -> Option<&PlatformExpr> {
+        self.platform.as_ref()
+    }
+
     /// Return true if one of the platform_configs applies to this rule. Always returns
     /// true if this dep has no platform constraint.
     pub fn filter(&self, platform_config: &PlatformConfig) -> Result<bool, PredicateParseError> {
@@ -130,8 +148,9 @@ impl Display for BuckPath {
 
 impl Ord for BuckPath {
     fn cmp(&self, other: &Self) -> Ordering {
-        let this = self.0.to_string_lossy();
-        let other = other.0.to_string_lossy();
+        // In the order of the forward slash paths they're written as
+        let this = self.0.to_string_lossy().replace('\\', "/");
+        let other = other.0.to_string_lossy().replace('\\', "/");
         buildifier_cmp(&this, &other)
     }
 }
@@ -154,11 +173,16 @@ pub enum StringOrPath {
 pub enum SubtargetOrPath {
     Subtarget(Subtarget),
     Path(BuckPath),
+    /// An output of a rule in another package, such as `//cell/pkg:tgt[out]`
+    Label(String),
 }
 
 impl SubtargetOrPath {
     fn is_subtarget(&self) -> bool {
-        matches!(self, SubtargetOrPath::Subtarget(_))
+        matches!(
+            self,
+            SubtargetOrPath::Subtarget(_) | SubtargetOrPath::Label(_)
+        )
     }
 
     fn is_path(&self) -> bool {
@@ -185,16 +209,46 @@ pub enum Visibility {
     Custom(Vec<String>),
 }
 
-impl Serialize for Visibility {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl Visibility {
+    /// Visibility of rules which are only meant to be used from the BUCK file
+    /// they're in, unless configured otherwise.
+    pub fn private(config: &BuckConfig) -> Self {
+        if config.private_visibility.is_empty() {
+            Visibility::Private
+        } else {
+            Visibility::Custom(config.private_visibility.clone())
+        }
+    }
+}
+
+impl SerializeStyled for Visibility {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         match self {
-            Visibility::Public => ["PUBLIC"].as_slice().serialize(ser),
-            Visibility::Private => (&[] as &[&str]).serialize(ser),
-            Visibility::Custom(custom_visiblity) => custom_visiblity.serialize(ser),
+            Visibility::Public => collect_seq(ser, style, ["PUBLIC"].iter()),
+            Visibility::Private => collect_seq(ser, style, iter::empty::<&str>()),
+            Visibility::Custom(custom_visiblity) => custom_visiblity.serialize_styled(ser, style),
         }
     }
 }
 
+/// Serialize `visibility = [...]`, which `diff_friendly` leaves out when
+/// it's empty.
+fn serialize_visibility<M: SerializeMap>(
+    map: &mut M,
+    visibility: &Visibility,
+    style: Style,
+) -> Result<(), M::Error> {
+    let is_empty = match visibility {
+        Visibility::Public => false,
+        Visibility::Private => true,
+        Visibility::Custom(custom_visiblity) => custom_visiblity.is_empty(),
+    };
+    if is_empty && !style.writes_empty() {
+        return Ok(());
+    }
+    map.serialize_entry("visibility", &Styled(visibility, style))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Alias {
     pub name: Name,
@@ -203,8 +257,8 @@ pub struct Alias {
     pub visibility: Visibility,
 }
 
-impl Serialize for Alias {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for Alias {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             name,
             actual,
@@ -213,7 +267,31 @@ impl Serialize for Alias {
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
         map.serialize_entry("actual", &NameAsLabel(actual))?;
-        map.serialize_entry("visibility", visibility)?;
+        serialize_visibility(&mut map, visibility, style)?;
+        map.end()
+    }
+}
+
+/// Stands in for a package's library with a target from elsewhere, under the
+/// name of the library's rule
+#[derive(Debug)]
+pub struct Replacement {
+    pub name: Name,
+    pub actual: RuleRef,
+    pub visibility: Visibility,
+}
+
+impl SerializeStyled for Replacement {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        let Self {
+            name,
+            actual,
+            visibility,
+        } = self;
+        let mut map = ser.serialize_map(None)?;
+        map.serialize_entry("name", name)?;
+        map.serialize_entry("actual", actual)?;
+        serialize_visibility(&mut map, visibility, style)?;
         map.end()
     }
 }
@@ -237,8 +315,8 @@ pub struct HttpArchive {
     pub sort_key: Name,
 }
 
-impl Serialize for HttpArchive {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for HttpArchive {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             name,
             sha256,
@@ -253,10 +331,36 @@ impl Serialize for HttpArchive {
         map.serialize_entry("sha256", sha256)?;
         map.serialize_entry("strip_prefix", strip_prefix)?;
         if !sub_targets.is_empty() {
-            map.serialize_entry("sub_targets", sub_targets)?;
+            map.serialize_entry("sub_targets", &Styled(sub_targets, style))?;
+        }
+        map.serialize_entry("urls", &Styled(urls, style))?;
+        serialize_visibility(&mut map, visibility, style)?;
+        map.end()
+    }
+}
+
+/// A package's license files, each available as a subtarget named after its
+/// path within the package, or files which a rule refers to by location
+#[derive(Debug)]
+pub struct Filegroup {
+    pub name: Name,
+    pub srcs: BTreeMap<String, SubtargetOrPath>,
+    pub visibility: Visibility,
+}
+
+impl SerializeStyled for Filegroup {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        let Self {
+            name,
+            srcs,
+            visibility,
+        } = self;
+        let mut map = ser.serialize_map(None)?;
+        map.serialize_entry("name", name)?;
+        if !srcs.is_empty() || style.writes_empty() {
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
         }
-        map.serialize_entry("urls", urls)?;
-        map.serialize_entry("visibility", visibility)?;
+        serialize_visibility(&mut map, visibility, style)?;
         map.end()
     }
 }
@@ -269,8 +373,8 @@ pub struct GitFetch {
     pub visibility: Visibility,
 }
 
-impl Serialize for GitFetch {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for GitFetch {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             name,
             repo,
@@ -281,7 +385,7 @@ impl Serialize for GitFetch {
         map.serialize_entry("name", name)?;
         map.serialize_entry("repo", repo)?;
         map.serialize_entry("rev", rev)?;
-        map.serialize_entry("visibility", visibility)?;
+        serialize_visibility(&mut map, visibility, style)?;
         map.end()
     }
 }
@@ -290,8 +394,47 @@ impl Serialize for GitFetch {
 pub struct Common {
     pub name: Name,
     pub visibility: Visibility,
-    pub licenses: BTreeSet<BuckPath>,
+    pub licenses: BTreeSet<SubtargetOrPath>,
     pub compatible_with: Vec<RuleRef>,
+    pub comments: Comments,
+}
+
+/// Comment lines to render with a rule, before the rule and before some of
+/// its attributes by name. Only attributes at the top level of the rule can
+/// have them.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Comments {
+    pub rule: Vec<String>,
+    pub attrs: BTreeMap<String, Vec<String>>,
+}
+
+impl Comments {
+    pub fn is_empty(&self) -> bool {
+        self.rule.is_empty() && self.attrs.is_empty()
+    }
+
+    /// Add the comments to a rendered rule.
+    fn insert(&self, rendered: &str) -> String {
+        let mut out = String::new();
+        for comment in &self.rule {
+            out.push_str(&format!("# {}\n", comment));
+        }
+        for line in rendered.lines() {
+            let attr = line
+                .strip_prefix("    ")
+                .filter(|rest| !rest.starts_with(' '))
+                .and_then(|rest| rest.split_once(" = "))
+                .map(|(attr, _)| attr);
+            if let Some(comments) = attr.and_then(|attr| self.attrs.get(attr)) {
+                for comment in comments {
+                    out.push_str(&format!("    # {}\n", comment));
+                }
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
 }
 
 // Rule attributes which could be platform-specific
@@ -302,8 +445,14 @@ pub struct PlatformRustCommon {
     pub rustc_flags: Vec<String>,
     pub features: BTreeSet<String>,
     pub deps: BTreeSet<RuleRef>,
+    /// Deps added by a `select()` on each Buck config setting
+    pub select_deps: BTreeMap<String, BTreeSet<RuleRef>>,
     pub named_deps: BTreeMap<String, RuleRef>,
     pub env: BTreeMap<String, StringOrPath>,
+    pub resources: BTreeMap<BuckPath, SubtargetOrPath>,
+    /// Frameworks and system libraries to link with, by the attribute they
+    /// go in
+    pub link_libs: BTreeMap<String, BTreeSet<String>>,
 
     // This isn't really "common" (Binaries only), but does need to be platform
     pub link_style: Option<String>,
@@ -311,34 +460,37 @@ pub struct PlatformRustCommon {
     pub preferred_linkage: Option<String>,
 }
 
-impl Serialize for PlatformRustCommon {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for PlatformRustCommon {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             srcs,
             mapped_srcs,
             rustc_flags,
             features,
             deps,
+            select_deps,
             named_deps,
             env,
+            resources,
+            link_libs,
             link_style,
             preferred_linkage,
         } = self;
         let mut map = ser.serialize_map(None)?;
         if !srcs.is_empty() {
-            map.serialize_entry("srcs", srcs)?;
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
         }
         if !env.is_empty() {
             map.serialize_entry("env", env)?;
         }
         if !features.is_empty() {
-            map.serialize_entry("features", features)?;
+            map.serialize_entry("features", &Styled(features, style))?;
         }
         if let Some(link_style) = link_style {
             map.serialize_entry("link_style", link_style)?;
         }
         if !mapped_srcs.is_empty() {
-            map.serialize_entry("mapped_srcs", mapped_srcs)?;
+            map.serialize_entry("mapped_srcs", &Styled(mapped_srcs, style))?;
         }
         if !named_deps.is_empty() {
             map.serialize_entry("named_deps", named_deps)?;
@@ -346,11 +498,24 @@ impl Serialize for PlatformRustCommon {
         if let Some(preferred_linkage) = preferred_linkage {
             map.serialize_entry("preferred_linkage", preferred_linkage)?;
         }
+        if !resources.is_empty() {
+            map.serialize_entry("resources", &Styled(resources, style))?;
+        }
         if !rustc_flags.is_empty() {
-            map.serialize_entry("rustc_flags", rustc_flags)?;
+            map.serialize_entry("rustc_flags", &Styled(rustc_flags, style))?;
         }
-        if !deps.is_empty() {
-            map.serialize_entry("deps", deps)?;
+        for (attr, libs) in link_libs {
+            map.serialize_entry(attr, &Styled(libs, style))?;
+        }
+        if !deps.is_empty() || !select_deps.is_empty() {
+            map.serialize_entry(
+                "deps",
+                &Deps {
+                    deps,
+                    select_deps,
+                    style,
+                },
+            )?;
         }
         map.end()
     }
@@ -398,26 +563,64 @@ pub struct RustCommon {
 /// (e.g. `field = value`) rather than as maps with arbitrary keys
 /// (e.g. `"key": value`).
 /// ```
-fn serialize_platforms_dict<S>(
+fn serialize_platforms_dict<S, T>(
     map: &mut S,
-    platforms: &BTreeMap<PlatformName, PlatformRustCommon>,
+    platforms: &BTreeMap<PlatformName, T>,
+    style: Style,
 ) -> Result<(), S::Error>
 where
     S: SerializeMap,
+    T: SerializeStyled,
 {
-    struct Platforms<'a>(&'a BTreeMap<PlatformName, PlatformRustCommon>);
+    struct Platforms<'a, T>(&'a BTreeMap<PlatformName, T>, Style);
 
-    impl Serialize for Platforms<'_> {
+    impl<T: SerializeStyled> Serialize for Platforms<'_, T> {
         fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
             ser.collect_map(
                 self.0
                     .iter()
-                    .map(|(name, value)| (name, FunctionCall::new("dict", value))),
+                    .map(|(name, value)| (name, FunctionCall::new("dict", Styled(value, self.1)))),
             )
         }
     }
 
-    map.serialize_entry("platform", &Platforms(platforms))
+    map.serialize_entry("platform", &Platforms(platforms, style))
+}
+
+/// Serialize as `deps = [...] + select({"//config:setting": [...], "DEFAULT": []})`,
+/// with one `select()` for each config setting so that any number of them can
+/// match at once.
+struct Deps<'a> {
+    deps: &'a BTreeSet<RuleRef>,
+    select_deps: &'a BTreeMap<String, BTreeSet<RuleRef>>,
+    style: Style,
+}
+
+impl Serialize for Deps<'_> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename = "select")]
+        struct Select<'a>(BTreeMap<&'a str, Styled<'a, BTreeSet<RuleRef>>>);
+
+        if self.select_deps.is_empty() {
+            return Styled(self.deps, self.style).serialize(ser);
+        }
+
+        let empty = BTreeSet::new();
+        let len = usize::from(!self.deps.is_empty()) + self.select_deps.len();
+        let mut plus = ser.serialize_tuple_struct("+", len)?;
+        if !self.deps.is_empty() {
+            plus.serialize_field(&Styled(self.deps, self.style))?;
+        }
+        for (setting, deps) in self.select_deps {
+            let select = BTreeMap::from([
+                (setting.as_str(), Styled(deps, self.style)),
+                ("DEFAULT", Styled(&empty, self.style)),
+            ]);
+            plus.serialize_field(&Select(select))?;
+        }
+        plus.end()
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -429,8 +632,8 @@ pub struct RustLibrary {
     pub linkable_alias: Option<String>,
 }
 
-impl Serialize for RustLibrary {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for RustLibrary {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             common:
                 RustCommon {
@@ -440,6 +643,7 @@ impl Serialize for RustLibrary {
                             visibility,
                             licenses,
                             compatible_with,
+                            comments: _,
                         },
                     krate,
                     crate_root,
@@ -451,8 +655,11 @@ impl Serialize for RustLibrary {
                             rustc_flags,
                             features,
                             deps,
+                            select_deps,
                             named_deps,
                             env,
+                            resources,
+                            link_libs,
                             link_style,
                             preferred_linkage,
                         },
@@ -466,10 +673,10 @@ impl Serialize for RustLibrary {
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
         if !srcs.is_empty() {
-            map.serialize_entry("srcs", srcs)?;
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
         }
         if !compatible_with.is_empty() {
-            map.serialize_entry("compatible_with", compatible_with)?;
+            map.serialize_entry("compatible_with", &Styled(compatible_with, style))?;
         }
         map.serialize_entry("crate", krate)?;
         map.serialize_entry("crate_root", crate_root)?;
@@ -481,10 +688,10 @@ impl Serialize for RustLibrary {
             map.serialize_entry("env", env)?;
         }
         if !features.is_empty() {
-            map.serialize_entry("features", features)?;
+            map.serialize_entry("features", &Styled(features, style))?;
         }
         if !licenses.is_empty() {
-            map.serialize_entry("licenses", licenses)?;
+            map.serialize_entry("licenses", &Styled(licenses, style))?;
         }
         if let Some(link_style) = link_style {
             map.serialize_entry("link_style", link_style)?;
@@ -493,13 +700,13 @@ impl Serialize for RustLibrary {
             map.serialize_entry("linkable_alias", linkable_alias)?;
         }
         if !mapped_srcs.is_empty() {
-            map.serialize_entry("mapped_srcs", mapped_srcs)?;
+            map.serialize_entry("mapped_srcs", &Styled(mapped_srcs, style))?;
         }
         if !named_deps.is_empty() {
             map.serialize_entry("named_deps", named_deps)?;
         }
         if !platform.is_empty() {
-            serialize_platforms_dict(&mut map, platform)?;
+            serialize_platforms_dict(&mut map, platform, style)?;
         }
         if let Some(preferred_linkage) = preferred_linkage {
             map.serialize_entry("preferred_linkage", preferred_linkage)?;
@@ -510,12 +717,25 @@ impl Serialize for RustLibrary {
         if let Some(python_ext) = python_ext {
             map.serialize_entry("python_ext", python_ext)?;
         }
+        if !resources.is_empty() {
+            map.serialize_entry("resources", &Styled(resources, style))?;
+        }
         if !rustc_flags.is_empty() {
-            map.serialize_entry("rustc_flags", rustc_flags)?;
+            map.serialize_entry("rustc_flags", &Styled(rustc_flags, style))?;
         }
-        map.serialize_entry("visibility", visibility)?;
-        if !deps.is_empty() {
-            map.serialize_entry("deps", deps)?;
+        for (attr, libs) in link_libs {
+            map.serialize_entry(attr, &Styled(libs, style))?;
+        }
+        serialize_visibility(&mut map, visibility, style)?;
+        if !deps.is_empty() || !select_deps.is_empty() {
+            map.serialize_entry(
+                "deps",
+                &Deps {
+                    deps,
+                    select_deps,
+                    style,
+                },
+            )?;
         }
         map.end()
     }
@@ -524,10 +744,12 @@ impl Serialize for RustLibrary {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RustBinary {
     pub common: RustCommon,
+    /// Set on build scripts with `exec_platform`
+    pub exec_compatible_with: Vec<RuleRef>,
 }
 
-impl Serialize for RustBinary {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for RustBinary {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             common:
                 RustCommon {
@@ -537,6 +759,7 @@ impl Serialize for RustBinary {
                             visibility,
                             licenses,
                             compatible_with,
+                            comments: _,
                         },
                     krate,
                     crate_root,
@@ -548,21 +771,25 @@ impl Serialize for RustBinary {
                             rustc_flags,
                             features,
                             deps,
+                            select_deps,
                             named_deps,
                             env,
+                            resources,
+                            link_libs,
                             link_style,
                             preferred_linkage,
                         },
                     platform,
                 },
+            exec_compatible_with,
         } = self;
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
         if !srcs.is_empty() {
-            map.serialize_entry("srcs", srcs)?;
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
         }
         if !compatible_with.is_empty() {
-            map.serialize_entry("compatible_with", compatible_with)?;
+            map.serialize_entry("compatible_with", &Styled(compatible_with, style))?;
         }
         map.serialize_entry("crate", krate)?;
         map.serialize_entry("crate_root", crate_root)?;
@@ -570,33 +797,49 @@ impl Serialize for RustBinary {
         if !env.is_empty() {
             map.serialize_entry("env", env)?;
         }
+        if !exec_compatible_with.is_empty() {
+            map.serialize_entry("exec_compatible_with", &Styled(exec_compatible_with, style))?;
+        }
         if !features.is_empty() {
-            map.serialize_entry("features", features)?;
+            map.serialize_entry("features", &Styled(features, style))?;
         }
         if !licenses.is_empty() {
-            map.serialize_entry("licenses", licenses)?;
+            map.serialize_entry("licenses", &Styled(licenses, style))?;
         }
         if let Some(link_style) = link_style {
             map.serialize_entry("link_style", link_style)?;
         }
         if !mapped_srcs.is_empty() {
-            map.serialize_entry("mapped_srcs", mapped_srcs)?;
+            map.serialize_entry("mapped_srcs", &Styled(mapped_srcs, style))?;
         }
         if !named_deps.is_empty() {
             map.serialize_entry("named_deps", named_deps)?;
         }
         if !platform.is_empty() {
-            serialize_platforms_dict(&mut map, platform)?;
+            serialize_platforms_dict(&mut map, platform, style)?;
         }
         if let Some(preferred_linkage) = preferred_linkage {
             map.serialize_entry("preferred_linkage", preferred_linkage)?;
         }
+        if !resources.is_empty() {
+            map.serialize_entry("resources", &Styled(resources, style))?;
+        }
         if !rustc_flags.is_empty() {
-            map.serialize_entry("rustc_flags", rustc_flags)?;
+            map.serialize_entry("rustc_flags", &Styled(rustc_flags, style))?;
         }
-        map.serialize_entry("visibility", visibility)?;
-        if !deps.is_empty() {
-            map.serialize_entry("deps", deps)?;
+        for (attr, libs) in link_libs {
+            map.serialize_entry(attr, &Styled(libs, style))?;
+        }
+        serialize_visibility(&mut map, visibility, style)?;
+        if !deps.is_empty() || !select_deps.is_empty() {
+            map.serialize_entry(
+                "deps",
+                &Deps {
+                    deps,
+                    select_deps,
+                    style,
+                },
+            )?;
         }
         map.end()
     }
@@ -613,10 +856,11 @@ pub struct BuildscriptGenrule {
     pub env: BTreeMap<String, String>,
     pub path_env: BTreeMap<String, String>,
     pub args_env: BTreeMap<String, String>,
+    pub exec_compatible_with: Vec<RuleRef>,
 }
 
-impl Serialize for BuildscriptGenrule {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for BuildscriptGenrule {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             name,
             buildscript_rule,
@@ -627,6 +871,7 @@ impl Serialize for BuildscriptGenrule {
             env,
             path_env,
             args_env,
+            exec_compatible_with,
         } = self;
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
@@ -636,13 +881,16 @@ impl Serialize for BuildscriptGenrule {
         }
         map.serialize_entry("buildscript_rule", &NameAsLabel(buildscript_rule))?;
         if !cfgs.is_empty() {
-            map.serialize_entry("cfgs", cfgs)?;
+            map.serialize_entry("cfgs", &Styled(cfgs, style))?;
         }
         if !env.is_empty() {
             map.serialize_entry("env", env)?;
         }
+        if !exec_compatible_with.is_empty() {
+            map.serialize_entry("exec_compatible_with", &Styled(exec_compatible_with, style))?;
+        }
         if !features.is_empty() {
-            map.serialize_entry("features", features)?;
+            map.serialize_entry("features", &Styled(features, style))?;
         }
         if !path_env.is_empty() {
             map.serialize_entry("path_env", path_env)?;
@@ -652,22 +900,100 @@ impl Serialize for BuildscriptGenrule {
     }
 }
 
+/// The files a `gen_srcs` fixup declares, copied out of the `OUT_DIR` of the
+/// build script's run so that each is a named output and subtarget, which
+/// `buildscript_run` itself doesn't provide.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BuildscriptOutputs {
+    pub name: Name,
+    pub buildscript_genrule: Name,
+    pub outs: BTreeSet<String>,
+    pub visibility: Visibility,
+}
+
+impl SerializeStyled for BuildscriptOutputs {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        let Self {
+            name,
+            buildscript_genrule,
+            outs,
+            visibility,
+        } = self;
+        let outs: BTreeMap<&String, &[String]> = outs
+            .iter()
+            .map(|out| (out, std::slice::from_ref(out)))
+            .collect();
+        let mut map = ser.serialize_map(None)?;
+        map.serialize_entry("name", name)?;
+        map.serialize_entry(
+            "cmd",
+            &format!(
+                "mkdir -p $OUT && cp -R $(location :{}[out_dir])/. $OUT",
+                buildscript_genrule,
+            ),
+        )?;
+        map.serialize_entry("outs", &outs)?;
+        serialize_visibility(&mut map, visibility, style)?;
+        map.end()
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CxxLibrary {
     pub common: Common,
-    pub srcs: BTreeSet<SubtargetOrPath>,
+    pub srcs: BTreeSet<CxxSrc>,
     pub headers: BTreeSet<SubtargetOrPath>,
     pub exported_headers: SetOrMap<SubtargetOrPath>,
     pub compiler_flags: Vec<String>,
     pub preprocessor_flags: Vec<String>,
+    pub exported_preprocessor_flags: Vec<String>,
+    pub raw_headers: BTreeSet<SubtargetOrPath>,
     pub header_namespace: Option<String>,
     pub include_directories: Vec<SubtargetOrPath>,
+    pub soname: Option<String>,
     pub deps: BTreeSet<RuleRef>,
+    pub exported_deps: BTreeSet<RuleRef>,
     pub preferred_linkage: Option<String>,
+    // Platform-specific
+    pub platform: BTreeMap<PlatformName, PlatformCxxLibrary>,
+}
+
+/// A source file of a C++ library, with any flags for compiling just that
+/// file. Those with flags are written like `("foo.S", ["-Wa,--noexecstack"])`.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct CxxSrc {
+    pub path: SubtargetOrPath,
+    pub flags: Vec<String>,
 }
 
-impl Serialize for CxxLibrary {
+impl Serialize for CxxSrc {
     fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        if self.flags.is_empty() {
+            self.path.serialize(ser)
+        } else {
+            (&self.path, &self.flags).serialize(ser)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PlatformCxxLibrary {
+    pub srcs: BTreeSet<CxxSrc>,
+}
+
+impl SerializeStyled for PlatformCxxLibrary {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        let Self { srcs } = self;
+        let mut map = ser.serialize_map(None)?;
+        if !srcs.is_empty() || style.writes_empty() {
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
+        }
+        map.end()
+    }
+}
+
+impl SerializeStyled for CxxLibrary {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             common:
                 Common {
@@ -675,43 +1001,63 @@ impl Serialize for CxxLibrary {
                     visibility,
                     licenses,
                     compatible_with,
+                    comments: _,
                 },
             srcs,
             headers,
             exported_headers,
             compiler_flags,
             preprocessor_flags,
+            exported_preprocessor_flags,
+            raw_headers,
             header_namespace,
             include_directories,
+            soname,
             deps,
+            exported_deps,
             preferred_linkage,
+            platform,
         } = self;
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
-        map.serialize_entry("srcs", srcs)?;
-        map.serialize_entry("headers", headers)?;
+        if !srcs.is_empty() || style.writes_empty() {
+            map.serialize_entry("srcs", &Styled(srcs, style))?;
+        }
+        if !headers.is_empty() || style.writes_empty() {
+            map.serialize_entry("headers", &Styled(headers, style))?;
+        }
         if let Some(header_namespace) = header_namespace {
             map.serialize_entry("header_namespace", header_namespace)?;
         }
         if !exported_headers.is_empty() {
-            map.serialize_entry("exported_headers", exported_headers)?;
+            map.serialize_entry("exported_headers", &Styled(exported_headers, style))?;
+        }
+        if !exported_preprocessor_flags.is_empty() {
+            map.serialize_entry(
+                "exported_preprocessor_flags",
+                &Styled(exported_preprocessor_flags, style),
+            )?;
         }
         if !compatible_with.is_empty() {
-            map.serialize_entry("compatible_with", compatible_with)?;
+            map.serialize_entry("compatible_with", &Styled(compatible_with, style))?;
         }
         if !compiler_flags.is_empty() {
-            map.serialize_entry("compiler_flags", compiler_flags)?;
+            map.serialize_entry("compiler_flags", &Styled(compiler_flags, style))?;
         }
         if include_directories.iter().any(SubtargetOrPath::is_path) {
             map.serialize_entry(
                 "include_directories",
                 &IncludeDirectories {
                     include_directories,
+                    style,
                 },
             )?;
         }
         if !licenses.is_empty() {
-            map.serialize_entry("licenses", licenses)?;
+            map.serialize_entry("licenses", &Styled(licenses, style))?;
+        }
+        if !platform.is_empty() {
+            serialize_platforms_dict(&mut map, platform, style)?;
         }
         map.serialize_entry("preferred_linkage", preferred_linkage)?;
         if !preprocessor_flags.is_empty()
@@ -724,12 +1070,22 @@ impl Serialize for CxxLibrary {
                 &PreprocessorFlags {
                     include_directories,
                     preprocessor_flags,
+                    style,
                 },
             )?;
         }
-        map.serialize_entry("visibility", visibility)?;
+        if !raw_headers.is_empty() {
+            map.serialize_entry("raw_headers", &Styled(raw_headers, style))?;
+        }
+        if let Some(soname) = soname {
+            map.serialize_entry("soname", soname)?;
+        }
+        serialize_visibility(&mut map, visibility, style)?;
         if !deps.is_empty() {
-            map.serialize_entry("deps", deps)?;
+            map.serialize_entry("deps", &Styled(deps, style))?;
+        }
+        if !exported_deps.is_empty() {
+            map.serialize_entry("exported_deps", &Styled(exported_deps, style))?;
         }
         map.end()
     }
@@ -737,6 +1093,7 @@ impl Serialize for CxxLibrary {
 
 struct IncludeDirectories<'a> {
     include_directories: &'a [SubtargetOrPath],
+    style: Style,
 }
 
 impl<'a> Serialize for IncludeDirectories<'a> {
@@ -746,11 +1103,11 @@ impl<'a> Serialize for IncludeDirectories<'a> {
             .iter()
             .filter(|dir| dir.is_path())
             .count();
-        let mut array = serializer.serialize_seq(Some(len))?;
+        let mut array = serializer.serialize_seq(self.style.seq_len(len))?;
 
         for element in self.include_directories {
             match element {
-                SubtargetOrPath::Subtarget(_) => {
+                SubtargetOrPath::Subtarget(_) | SubtargetOrPath::Label(_) => {
                     // serialized under "preprocessor_flags" because "include_directories"
                     // does not support $(location ...) macros.
                 }
@@ -765,6 +1122,7 @@ impl<'a> Serialize for IncludeDirectories<'a> {
 struct PreprocessorFlags<'a> {
     include_directories: &'a [SubtargetOrPath],
     preprocessor_flags: &'a [String],
+    style: Style,
 }
 
 impl<'a> Serialize for PreprocessorFlags<'a> {
@@ -775,7 +1133,7 @@ impl<'a> Serialize for PreprocessorFlags<'a> {
             .filter(|dir| dir.is_subtarget())
             .count()
             + self.preprocessor_flags.len();
-        let mut array = serializer.serialize_seq(Some(len))?;
+        let mut array = serializer.serialize_seq(self.style.seq_len(len))?;
 
         for element in self.include_directories {
             // Cannot just use `array.serialize_element(format!("-I{element}"))`:
@@ -788,6 +1146,9 @@ impl<'a> Serialize for PreprocessorFlags<'a> {
                         subtarget.target, subtarget.relative,
                     ))?;
                 }
+                SubtargetOrPath::Label(label) => {
+                    array.serialize_element(&format!("-I$(location {})", label))?;
+                }
                 SubtargetOrPath::Path(_) => {
                     // serialized under "include_directories"
                 }
@@ -805,11 +1166,14 @@ impl<'a> Serialize for PreprocessorFlags<'a> {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct PrebuiltCxxLibrary {
     pub common: Common,
-    pub static_lib: SubtargetOrPath,
+    pub static_lib: Option<SubtargetOrPath>,
+    pub shared_lib: Option<SubtargetOrPath>,
+    pub import_lib: Option<SubtargetOrPath>,
+    pub preferred_linkage: Option<String>,
 }
 
-impl Serialize for PrebuiltCxxLibrary {
-    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+impl SerializeStyled for PrebuiltCxxLibrary {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
         let Self {
             common:
                 Common {
@@ -817,19 +1181,34 @@ impl Serialize for PrebuiltCxxLibrary {
                     visibility,
                     licenses,
                     compatible_with,
+                    comments: _,
                 },
             static_lib,
+            shared_lib,
+            import_lib,
+            preferred_linkage,
         } = self;
         let mut map = ser.serialize_map(None)?;
         map.serialize_entry("name", name)?;
         if !compatible_with.is_empty() {
-            map.serialize_entry("compatible_with", compatible_with)?;
+            map.serialize_entry("compatible_with", &Styled(compatible_with, style))?;
+        }
+        if let Some(import_lib) = import_lib {
+            map.serialize_entry("import_lib", import_lib)?;
         }
         if !licenses.is_empty() {
-            map.serialize_entry("licenses", licenses)?;
+            map.serialize_entry("licenses", &Styled(licenses, style))?;
+        }
+        if let Some(preferred_linkage) = preferred_linkage {
+            map.serialize_entry("preferred_linkage", preferred_linkage)?;
+        }
+        if let Some(shared_lib) = shared_lib {
+            map.serialize_entry("shared_lib", shared_lib)?;
+        }
+        if let Some(static_lib) = static_lib {
+            map.serialize_entry("static_lib", static_lib)?;
         }
-        map.serialize_entry("static_lib", static_lib)?;
-        map.serialize_entry("visibility", visibility)?;
+        serialize_visibility(&mut map, visibility, style)?;
         map.end()
     }
 }
@@ -843,9 +1222,91 @@ pub enum Rule {
     Library(RustLibrary),
     BuildscriptBinary(RustBinary),
     BuildscriptGenrule(BuildscriptGenrule),
+    BuildscriptOutputs(BuildscriptOutputs),
     CxxLibrary(CxxLibrary),
     PrebuiltCxxLibrary(PrebuiltCxxLibrary),
+    Filegroup(Filegroup),
     RootPackage(RustLibrary),
+    Doc(RustLibrary),
+    Doctest(RustLibrary),
+    Bench(RustBinary),
+    /// A package's library as given by `replace_with` in its fixups
+    Replacement(Replacement),
+    /// A rule as changed or added by a plugin
+    Plugin(PluginRule),
+}
+
+/// Any rule, as plugins see and return them: the function it's invoked with,
+/// and its attributes in order.
+#[derive(Debug, Serialize)]
+pub struct PluginRule {
+    pub rule: String,
+    #[serde(skip)]
+    pub name: Name,
+    pub attrs: IndexMap<String, AttrValue>,
+    /// The generated rule of the same name which a plugin changed, whose
+    /// comments and place among the rules this one keeps
+    #[serde(skip)]
+    pub original: Option<Box<Rule>>,
+}
+
+/// Value of a plugin rule's attribute. Unlike `serde_json::Value`, this keeps
+/// the order of dicts.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AttrValue {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<AttrValue>),
+    Dict(IndexMap<String, AttrValue>),
+}
+
+impl Serialize for AttrValue {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            AttrValue::None => ser.serialize_none(),
+            AttrValue::Bool(value) => ser.serialize_bool(*value),
+            AttrValue::Int(value) => ser.serialize_i64(*value),
+            // Starlark output has no floats, but a whole number, as some JSON
+            // libraries write integers, is fine.
+            AttrValue::Float(value) if value.fract() == 0.0 => ser.serialize_i64(*value as i64),
+            AttrValue::Float(value) => ser.serialize_f64(*value),
+            AttrValue::String(value) => ser.serialize_str(value),
+            AttrValue::List(value) => value.serialize(ser),
+            AttrValue::Dict(value) => value.serialize(ser),
+        }
+    }
+}
+
+impl SerializeStyled for AttrValue {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        match self {
+            AttrValue::List(value) => {
+                collect_seq(ser, style, value.iter().map(|value| Styled(value, style)))
+            }
+            AttrValue::Dict(value) => value.serialize_styled(ser, style),
+            _ => self.serialize(ser),
+        }
+    }
+}
+
+/// A plugin rule's attributes, or a dict in one.
+impl SerializeStyled for IndexMap<String, AttrValue> {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        let is_empty = |value: &AttrValue| match value {
+            AttrValue::List(list) => list.is_empty(),
+            AttrValue::Dict(dict) => dict.is_empty(),
+            _ => false,
+        };
+        ser.collect_map(
+            self.iter()
+                .filter(|(_, value)| style.writes_empty() || !is_empty(value))
+                .map(|(name, value)| (name, Styled(value, style))),
+        )
+    }
 }
 
 impl Eq for Rule {}
@@ -880,12 +1341,23 @@ fn rule_sort_key(rule: &Rule) -> impl Ord + '_ {
         Rule::Alias(Alias { actual, .. }) => RuleSortKey::Other(actual, 0),
         Rule::HttpArchive(HttpArchive { sort_key, .. }) => RuleSortKey::Other(sort_key, 1),
         Rule::GitFetch(GitFetch { name, .. }) => RuleSortKey::GitFetch(name),
+        Rule::Plugin(PluginRule {
+            original: Some(original),
+            ..
+        }) => rule_sort_key(original),
         Rule::Binary(_)
         | Rule::Library(_)
         | Rule::BuildscriptBinary(_)
         | Rule::BuildscriptGenrule(_)
+        | Rule::BuildscriptOutputs(_)
         | Rule::CxxLibrary(_)
-        | Rule::PrebuiltCxxLibrary(_) => RuleSortKey::Other(rule.get_name(), 2),
+        | Rule::PrebuiltCxxLibrary(_)
+        | Rule::Filegroup(_)
+        | Rule::Doc(_)
+        | Rule::Doctest(_)
+        | Rule::Bench(_)
+        | Rule::Replacement(_)
+        | Rule::Plugin(_) => RuleSortKey::Other(rule.get_name(), 2),
         Rule::RootPackage(_) => RuleSortKey::RootPackage,
     }
 }
@@ -897,11 +1369,23 @@ impl Ord for Rule {
 }
 
 impl Rule {
+    /// Compare the rules in `order`, rather than buildifier's.
+    pub fn cmp_in(&self, other: &Self, order: SortOrder) -> Ordering {
+        match order {
+            SortOrder::Buildifier => self.cmp(other),
+            SortOrder::Lexicographic => self
+                .get_name()
+                .cmp(other.get_name())
+                .then_with(|| self.cmp(other)),
+        }
+    }
+
     pub fn get_name(&self) -> &Name {
         match self {
             Rule::Alias(Alias { name, .. })
             | Rule::HttpArchive(HttpArchive { name, .. })
             | Rule::GitFetch(GitFetch { name, .. })
+            | Rule::Filegroup(Filegroup { name, .. })
             | Rule::Binary(RustBinary {
                 common:
                     RustCommon {
@@ -926,7 +1410,16 @@ impl Rule {
                     },
                 ..
             })
+            | Rule::Bench(RustBinary {
+                common:
+                    RustCommon {
+                        common: Common { name, .. },
+                        ..
+                    },
+                ..
+            })
             | Rule::BuildscriptGenrule(BuildscriptGenrule { name, .. })
+            | Rule::BuildscriptOutputs(BuildscriptOutputs { name, .. })
             | Rule::CxxLibrary(CxxLibrary {
                 common: Common { name, .. },
                 ..
@@ -942,43 +1435,296 @@ impl Rule {
                         ..
                     },
                 ..
-            }) => name,
+            })
+            | Rule::Doc(RustLibrary {
+                common:
+                    RustCommon {
+                        common: Common { name, .. },
+                        ..
+                    },
+                ..
+            })
+            | Rule::Doctest(RustLibrary {
+                common:
+                    RustCommon {
+                        common: Common { name, .. },
+                        ..
+                    },
+                ..
+            })
+            | Rule::Replacement(Replacement { name, .. })
+            | Rule::Plugin(PluginRule { name, .. }) => name,
+        }
+    }
+
+    /// The name this rule is invoked by in the generated file, according to
+    /// the config.
+    fn rule_name<'a>(&'a self, config: &'a BuckConfig) -> &'a str {
+        match self {
+            Rule::Alias(_) | Rule::Replacement(_) => &config.alias,
+            Rule::HttpArchive(_) => &config.http_archive,
+            Rule::GitFetch(_) => &config.git_fetch,
+            Rule::Binary(_) => &config.rust_binary,
+            Rule::Library(_) | Rule::RootPackage(_) => &config.rust_library,
+            Rule::BuildscriptBinary(_) => config
+                .buildscript_binary
+                .as_ref()
+                .unwrap_or(&config.rust_binary),
+            Rule::BuildscriptGenrule(_) => &config.buildscript_genrule,
+            Rule::BuildscriptOutputs(_) => &config.genrule,
+            Rule::CxxLibrary(_) => &config.cxx_library,
+            Rule::PrebuiltCxxLibrary(_) => &config.prebuilt_cxx_library,
+            Rule::Filegroup(_) => &config.filegroup,
+            Rule::Doc(_) => &config.rust_doc,
+            Rule::Doctest(_) => &config.rust_doctest,
+            Rule::Bench(_) => config.rust_bench.as_ref().unwrap_or(&config.rust_binary),
+            Rule::Plugin(rule) => &rule.rule,
         }
     }
 
     pub fn render(&self, config: &BuckConfig, out: &mut impl Write) -> Result<()> {
-        use serde_starlark::Serializer;
-        let serialized = match self {
-            Rule::Alias(alias) => FunctionCall::new(&config.alias, alias).serialize(Serializer),
-            Rule::HttpArchive(http_archive) => {
-                FunctionCall::new(&config.http_archive, http_archive).serialize(Serializer)
+        self.render_as(config, self.rule_name(config), out)
+    }
+
+    /// Render as a call to `rule_name` rather than the configured rule.
+    fn render_as(&self, config: &BuckConfig, rule_name: &str, out: &mut impl Write) -> Result<()> {
+        let serialized = FunctionCall::new(rule_name, Styled(self, Style::new(config)))
+            .serialize(serde_starlark::Serializer)?;
+        match self.comments() {
+            Some(comments) if !comments.is_empty() => {
+                out.write_all(comments.insert(&serialized).as_bytes())?;
             }
-            Rule::GitFetch(git_fetch) => {
-                FunctionCall::new(&config.git_fetch, git_fetch).serialize(Serializer)
+            _ => out.write_all(serialized.as_bytes())?,
+        }
+        Ok(())
+    }
+
+    fn comments(&self) -> Option<&Comments> {
+        match self {
+            Rule::Binary(RustBinary { common, .. })
+            | Rule::BuildscriptBinary(RustBinary { common, .. })
+            | Rule::Bench(RustBinary { common, .. })
+            | Rule::Library(RustLibrary { common, .. })
+            | Rule::RootPackage(RustLibrary { common, .. })
+            | Rule::Doc(RustLibrary { common, .. })
+            | Rule::Doctest(RustLibrary { common, .. }) => Some(&common.common.comments),
+            Rule::CxxLibrary(CxxLibrary { common, .. })
+            | Rule::PrebuiltCxxLibrary(PrebuiltCxxLibrary { common, .. }) => Some(&common.comments),
+            Rule::Plugin(PluginRule {
+                original: Some(original),
+                ..
+            }) => original.comments(),
+            _ => None,
+        }
+    }
+
+    /// This rule as a plugin sees it.
+    pub fn to_plugin_rule(&self, config: &BuckConfig) -> Result<PluginRule> {
+        // Attribute values can't be `select()`s in JSON.
+        if let Rule::Binary(RustBinary { common, .. })
+        | Rule::BuildscriptBinary(RustBinary { common, .. })
+        | Rule::Bench(RustBinary { common, .. })
+        | Rule::Library(RustLibrary { common, .. })
+        | Rule::RootPackage(RustLibrary { common, .. })
+        | Rule::Doc(RustLibrary { common, .. })
+        | Rule::Doctest(RustLibrary { common, .. }) = self
+        {
+            if iter::once(&common.base)
+                .chain(common.platform.values())
+                .any(|rule| !rule.select_deps.is_empty())
+            {
+                bail!(
+                    "{} has `select_deps`, which plugins can't be given",
+                    self.get_name()
+                );
             }
-            Rule::Binary(bin) => FunctionCall::new(&config.rust_binary, bin).serialize(Serializer),
-            Rule::Library(lib) | Rule::RootPackage(lib) => {
-                FunctionCall::new(&config.rust_library, lib).serialize(Serializer)
+        }
+        // Going through JSON text rather than `serde_json::Value` keeps the
+        // attributes in order.
+        let json = serde_json::to_string(&Styled(self, Style::new(config)))?;
+        Ok(PluginRule {
+            rule: self.rule_name(config).to_owned(),
+            name: self.get_name().clone(),
+            attrs: serde_json::from_str(&json)?,
+            original: None,
+        })
+    }
+}
+
+/// The attributes of the rule.
+impl SerializeStyled for Rule {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        match self {
+            Rule::Alias(alias) => alias.serialize_styled(ser, style),
+            Rule::HttpArchive(http_archive) => http_archive.serialize_styled(ser, style),
+            Rule::GitFetch(git_fetch) => git_fetch.serialize_styled(ser, style),
+            Rule::Binary(bin) | Rule::BuildscriptBinary(bin) | Rule::Bench(bin) => {
+                bin.serialize_styled(ser, style)
             }
-            Rule::BuildscriptBinary(bin) => {
-                let buildscript_binary = config
-                    .buildscript_binary
-                    .as_ref()
-                    .unwrap_or(&config.rust_binary);
-                FunctionCall::new(buildscript_binary, bin).serialize(Serializer)
+            Rule::Library(lib) | Rule::RootPackage(lib) | Rule::Doc(lib) | Rule::Doctest(lib) => {
+                lib.serialize_styled(ser, style)
             }
-            Rule::BuildscriptGenrule(lib) => {
-                FunctionCall::new(&config.buildscript_genrule, lib).serialize(Serializer)
+            Rule::BuildscriptGenrule(genrule) => genrule.serialize_styled(ser, style),
+            Rule::BuildscriptOutputs(outputs) => outputs.serialize_styled(ser, style),
+            Rule::CxxLibrary(lib) => lib.serialize_styled(ser, style),
+            Rule::PrebuiltCxxLibrary(lib) => lib.serialize_styled(ser, style),
+            Rule::Filegroup(filegroup) => filegroup.serialize_styled(ser, style),
+            Rule::Replacement(replacement) => replacement.serialize_styled(ser, style),
+            Rule::Plugin(rule) => rule.attrs.serialize_styled(ser, style),
+        }
+    }
+}
+
+/// How the rules are laid out, as configured in `[buck]`. serde can't pass
+/// it down to the values which depend on it, so they're wrapped in `Styled`
+/// along with it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Style {
+    pub sort_order: SortOrder,
+    pub diff_friendly: bool,
+}
+
+impl Style {
+    pub fn new(config: &BuckConfig) -> Self {
+        Style {
+            sort_order: config.sort_order,
+            diff_friendly: config.diff_friendly,
+        }
+    }
+
+    /// Length to start a list of `len` items with. Given none,
+    /// serde_starlark puts even a single item on a line of its own, so that
+    /// adding a second one only adds a line.
+    fn seq_len(self, len: usize) -> Option<usize> {
+        if self.diff_friendly {
+            None
+        } else {
+            Some(len)
+        }
+    }
+
+    /// Whether to write attributes whose value is empty, which means the same
+    /// as leaving them out.
+    fn writes_empty(self) -> bool {
+        !self.diff_friendly
+    }
+}
+
+/// Values which serialize depending on the `Style`.
+trait SerializeStyled {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error>;
+}
+
+/// Serialize a list laid out in the style.
+fn collect_seq<S, T>(
+    ser: S,
+    style: Style,
+    values: impl ExactSizeIterator<Item = T>,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    let mut seq = ser.serialize_seq(style.seq_len(values.len()))?;
+    for value in values {
+        seq.serialize_element(&value)?;
+    }
+    seq.end()
+}
+
+/// A value with the style to serialize it in.
+struct Styled<'a, T>(&'a T, Style);
+
+impl<T: SerializeStyled> Serialize for Styled<'_, T> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_styled(ser, self.1)
+    }
+}
+
+/// Values in sortable string arrays, and keys of sorted maps. They're kept in
+/// buildifier's order, and in lexicographic order compare as the strings
+/// they're written as.
+trait SortString {
+    fn sort_string(&self) -> String;
+}
+
+impl SortString for RuleRef {
+    fn sort_string(&self) -> String {
+        self.target.clone()
+    }
+}
+
+impl SortString for BuckPath {
+    fn sort_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl SortString for SubtargetOrPath {
+    fn sort_string(&self) -> String {
+        match self {
+            SubtargetOrPath::Subtarget(Subtarget { target, relative }) => {
+                format!(":{}[{}]", target, relative)
             }
-            Rule::CxxLibrary(lib) => {
-                FunctionCall::new(&config.cxx_library, lib).serialize(Serializer)
+            SubtargetOrPath::Path(path) => path.to_string(),
+            SubtargetOrPath::Label(label) => label.clone(),
+        }
+    }
+}
+
+impl SortString for CxxSrc {
+    fn sort_string(&self) -> String {
+        self.path.sort_string()
+    }
+}
+
+impl SortString for String {
+    fn sort_string(&self) -> String {
+        self.clone()
+    }
+}
+
+/// Sorted sets are written in the style's sort order.
+impl<T: SortString + Serialize> SerializeStyled for BTreeSet<T> {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        match style.sort_order {
+            SortOrder::Buildifier => collect_seq(ser, style, self.iter()),
+            SortOrder::Lexicographic => {
+                let mut values: Vec<&T> = self.iter().collect();
+                values.sort_by_cached_key(|value| value.sort_string());
+                collect_seq(ser, style, values.into_iter())
             }
-            Rule::PrebuiltCxxLibrary(lib) => {
-                FunctionCall::new(&config.prebuilt_cxx_library, lib).serialize(Serializer)
+        }
+    }
+}
+
+/// So are the keys of sorted maps.
+impl<K: SortString + Serialize, V: Serialize> SerializeStyled for BTreeMap<K, V> {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        match style.sort_order {
+            SortOrder::Buildifier => self.serialize(ser),
+            SortOrder::Lexicographic => {
+                let mut entries: Vec<(&K, &V)> = self.iter().collect();
+                entries.sort_by_cached_key(|(key, _)| key.sort_string());
+                ser.collect_map(entries)
             }
-        }?;
-        out.write_all(serialized.as_bytes())?;
-        Ok(())
+        }
+    }
+}
+
+/// Lists which keep their order.
+impl<T: Serialize> SerializeStyled for Vec<T> {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        collect_seq(ser, style, self.iter())
+    }
+}
+
+impl<T: SortString + Serialize + Ord> SerializeStyled for SetOrMap<T> {
+    fn serialize_styled<S: Serializer>(&self, ser: S, style: Style) -> Result<S::Ok, S::Error> {
+        match self {
+            SetOrMap::Set(set) => set.serialize_styled(ser, style),
+            SetOrMap::Map(map) => map.serialize_styled(ser, style),
+        }
     }
 }
 
@@ -1003,27 +1749,646 @@ fn buildifier_cmp(a: &str, b: &str) -> Ordering {
     })
 }
 
+/// Number of rules to render in parallel before writing them out.
+const RENDER_CHUNK_SIZE: usize = 256;
+
+/// Marks the start of hand-written content in a generated BUCK file.
+const BEGIN_MANUAL: &str = "# BEGIN MANUAL";
+/// Marks the end of hand-written content in a generated BUCK file.
+const END_MANUAL: &str = "# END MANUAL";
+
+/// Extract the hand-written sections of an existing BUCK file, including
+/// their marker lines, so that they can be carried over when it's regenerated.
+pub fn manual_sections(buckfile: &str) -> Result<Vec<String>> {
+    let mut sections = Vec::new();
+    let mut current: Option<String> = None;
+
+    for (lineno, line) in buckfile.lines().enumerate() {
+        let marker = line.trim_end();
+        match &mut current {
+            None if marker == BEGIN_MANUAL => current = Some(format!("{}\n", line)),
+            None if marker == END_MANUAL => {
+                bail!(
+                    "line {}: `{}` without `{}`",
+                    lineno + 1,
+                    END_MANUAL,
+                    BEGIN_MANUAL
+                );
+            }
+            None => {}
+            Some(_) if marker == BEGIN_MANUAL => {
+                bail!("line {}: nested `{}`", lineno + 1, BEGIN_MANUAL);
+            }
+            Some(section) => {
+                section.push_str(line);
+                section.push('\n');
+                if marker == END_MANUAL {
+                    sections.extend(current.take());
+                }
+            }
+        }
+    }
+
+    if current.is_some() {
+        bail!("`{}` without `{}`", BEGIN_MANUAL, END_MANUAL);
+    }
+    Ok(sections)
+}
+
+/// What the targets file says about a public library when
+/// `targets_file_metadata` is set
+pub struct TargetMetadata {
+    pub version: String,
+    pub krate: String,
+    pub features: BTreeSet<String>,
+}
+
+impl Serialize for TargetMetadata {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut map = ser.serialize_map(Some(3))?;
+        map.serialize_entry("crate", &self.krate)?;
+        map.serialize_entry("features", &self.features)?;
+        map.serialize_entry("version", &self.version)?;
+        map.end()
+    }
+}
+
+/// Write a generated .bzl file, such as the `targets_file`, which defines a
+/// single variable.
+pub fn write_bzl_variable(
+    config: &BuckConfig,
+    variable: &str,
+    value: &impl Serialize,
+    out: &mut impl Write,
+) -> Result<()> {
+    out.write_all(config.generated_file_header.as_bytes())?;
+    if !config.generated_file_header.is_empty() {
+        out.write_all(b"\n")?;
+    }
+    let value = serde_starlark::to_string(value)?;
+    writeln!(out, "{} = {}", variable, value.trim_end())?;
+    Ok(())
+}
+
+/// Names of the rules in a generated BUCK file, or in one of its shards.
+pub fn rule_names(buckfile: &str) -> BTreeSet<String> {
+    buckfile
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("name = \""))
+        .filter_map(|rest| rest.strip_suffix("\","))
+        .map(str::to_owned)
+        .collect()
+}
+
 pub fn write_buckfile<'a>(
     config: &BuckConfig,
     rules: impl Iterator<Item = &'a Rule>,
+    manual_sections: &[String],
     out: &mut impl Write,
 ) -> Result<()> {
+    write_header(config, out)?;
+
+    let rules: Vec<&Rule> = rules.collect();
+    write_loads(config, &rules, out)?;
+    let first = write_rules(config, &rules, false, out)?;
+    write_manual_sections(manual_sections, first, out)
+}
+
+/// A line of an existing BUCK file, or a whole generated rule in it.
+enum Chunk {
+    Line(String),
+    Rule { name: String, text: String },
+}
+
+/// Rewrite an existing generated BUCK file with these rules in place of the
+/// ones with the same names, adding those it doesn't have yet among the others
+/// by name. Everything else in it, hand-written sections included, stays as it
+/// is.
+pub fn splice_buckfile<'a>(
+    config: &BuckConfig,
+    existing: &str,
+    rules: impl Iterator<Item = &'a Rule>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut chunks = Vec::new();
+    let mut lines = existing.lines();
+    let mut in_manual = false;
+    while let Some(line) = lines.next() {
+        match line.trim_end() {
+            BEGIN_MANUAL => in_manual = true,
+            END_MANUAL => in_manual = false,
+            _ => {}
+        }
+        let starts_rule = !in_manual
+            && line.ends_with('(')
+            && !line.starts_with(char::is_whitespace)
+            && !line.starts_with("load(");
+        if !starts_rule {
+            chunks.push(Chunk::Line(line.to_owned()));
+            continue;
+        }
+        // Comments right above a rule, after a blank line or another rule,
+        // were rendered with it.
+        let mut text = String::new();
+        let mut start = chunks.len();
+        while let Some(Chunk::Line(line)) = start.checked_sub(1).map(|i| &chunks[i]) {
+            if !line.starts_with('#') || line == BEGIN_MANUAL || line == END_MANUAL {
+                break;
+            }
+            start -= 1;
+        }
+        let after_blank = match start.checked_sub(1).map(|i| &chunks[i]) {
+            Some(Chunk::Line(line)) => line.is_empty(),
+            Some(Chunk::Rule { .. }) => true,
+            None => false,
+        };
+        if after_blank {
+            for chunk in chunks.drain(start..) {
+                if let Chunk::Line(line) = chunk {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+            }
+        }
+        text.push_str(line);
+        text.push('\n');
+        let mut name = None;
+        for line in lines.by_ref() {
+            text.push_str(line);
+            text.push('\n');
+            if name.is_none() {
+                name = line
+                    .strip_prefix("    name = \"")
+                    .and_then(|rest| rest.strip_suffix("\","))
+                    .map(str::to_owned);
+            }
+            if line == ")" {
+                break;
+            }
+        }
+        let Some(name) = name else {
+            bail!("no name in `{}`", line);
+        };
+        chunks.push(Chunk::Rule { name, text });
+    }
+
+    let rules: Vec<&Rule> = rules.collect();
+    let mut missing_loads = BTreeMap::new();
+    for rule in &rules {
+        let mut text = Vec::new();
+        rule.render(config, &mut text)?;
+        let text = String::from_utf8(text)?;
+        let name = &rule.get_name().0;
+
+        let current = chunks.iter_mut().find_map(|chunk| match chunk {
+            Chunk::Rule { name: other, text } if other == name => Some(text),
+            _ => None,
+        });
+        if let Some(current) = current {
+            *current = text;
+            continue;
+        }
+
+        let rule_name = rule.rule_name(config);
+        let symbol = rule_name.split('.').next().unwrap();
+        if let Some(bzl) = config.loads.get(symbol) {
+            let loaded = existing.lines().any(|line| {
+                line.starts_with("load(")
+                    && line.contains(&format!("{:?}", bzl))
+                    && line.contains(&format!("{:?}", symbol))
+            });
+            if !loaded {
+                missing_loads.insert(symbol, bzl);
+            }
+        }
+
+        // Before the first rule which sorts after it, or after the last one.
+        let rule_positions: Vec<usize> = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| matches!(chunk, Chunk::Rule { .. }))
+            .map(|(i, _)| i)
+            .collect();
+        let Some(&last) = rule_positions.last() else {
+            bail!("there are no generated rules to add `{}` among", name);
+        };
+        let after = rule_positions.iter().find(|&&i| match &chunks[i] {
+            Chunk::Rule { name: other, .. } => other.as_str() > name.as_str(),
+            Chunk::Line(_) => false,
+        });
+        let new = Chunk::Rule {
+            name: name.clone(),
+            text,
+        };
+        match after {
+            Some(&i) => {
+                chunks.insert(i, Chunk::Line(String::new()));
+                chunks.insert(i, new);
+            }
+            None => {
+                chunks.insert(last + 1, new);
+                chunks.insert(last + 1, Chunk::Line(String::new()));
+            }
+        }
+    }
+
+    if !missing_loads.is_empty() {
+        let last_load = chunks
+            .iter()
+            .rposition(|chunk| matches!(chunk, Chunk::Line(line) if line.starts_with("load(")));
+        let at = match last_load {
+            Some(i) => i + 1,
+            None => {
+                let first_rule = chunks
+                    .iter()
+                    .position(|chunk| matches!(chunk, Chunk::Rule { .. }))
+                    .unwrap();
+                chunks.insert(first_rule, Chunk::Line(String::new()));
+                first_rule
+            }
+        };
+        for (symbol, bzl) in missing_loads.into_iter().rev() {
+            chunks.insert(at, Chunk::Line(format!("load({:?}, {:?})", bzl, symbol)));
+        }
+    }
+
+    for chunk in &chunks {
+        match chunk {
+            Chunk::Line(line) => writeln!(out, "{}", line)?,
+            Chunk::Rule { text, .. } => out.write_all(text.as_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// Prefix of the names of the .bzl files which hold the rules when they're
+/// sharded, and of the macros those files define.
+pub const SHARD_PREFIX: &str = "rust_shard_";
+
+/// The shard files in a third-party directory, whether or not the rules are
+/// still sharded.
+pub fn shard_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+            if file_name.starts_with(SHARD_PREFIX) && file_name.ends_with(".bzl") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Write a BUCK file which loads and calls a macro from each of the shards
+/// that `config.shard_by` splits the rules into. Returns the content of the
+/// shards, by file name.
+pub fn write_sharded_buckfile<'a>(
+    config: &BuckConfig,
+    shard_by: ShardBy,
+    rules: impl Iterator<Item = &'a Rule>,
+    manual_sections: &[String],
+    out: &mut impl Write,
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    let shard_count = config.shard_count.unwrap_or(16);
+    if shard_count == 0 {
+        bail!("`shard_count` must be at least 1");
+    }
+
+    // Rules keep their relative order within each shard.
+    let mut shards: BTreeMap<String, Vec<&Rule>> = BTreeMap::new();
+    for rule in rules {
+        let key = shard_key(rule, shard_by, shard_count);
+        shards.entry(key).or_default().push(rule);
+    }
+
+    let mut files = BTreeMap::new();
+    for (key, rules) in &shards {
+        let mut shard = Vec::new();
+        write_header(config, &mut shard)?;
+        write_loads(config, rules, &mut shard)?;
+        writeln!(shard, "def {}{}():", SHARD_PREFIX, key)?;
+        write_rules(config, rules, true, &mut shard)?;
+        files.insert(format!("{}{}.bzl", SHARD_PREFIX, key), shard);
+    }
+
+    write_header(config, out)?;
+    out.write_all(config.buckfile_imports.as_bytes())?;
+    for key in shards.keys() {
+        writeln!(out, "load(\":{0}{1}.bzl\", \"{0}{1}\")", SHARD_PREFIX, key)?;
+    }
+    if !config.buckfile_imports.is_empty() || !shards.is_empty() {
+        out.write_all(b"\n")?;
+    }
+    for key in shards.keys() {
+        writeln!(out, "{}{}()", SHARD_PREFIX, key)?;
+    }
+    write_manual_sections(manual_sections, shards.is_empty(), out)?;
+
+    Ok(files)
+}
+
+/// Which shard a rule goes in. Rules are grouped by package name, without
+/// the version, so that all of a package's rules end up together.
+fn shard_key(rule: &Rule, shard_by: ShardBy, shard_count: usize) -> String {
+    let name = match rule {
+        Rule::Alias(Alias { actual, .. }) => actual,
+        Rule::HttpArchive(HttpArchive { sort_key, .. }) => sort_key,
+        _ => rule.get_name(),
+    };
+    let package = match name
+        .0
+        .match_indices('-')
+        .find(|(i, _)| name.0[i + 1..].starts_with(|ch: char| ch.is_ascii_digit()))
+    {
+        Some((i, _)) => &name.0[..i],
+        None => &name.0,
+    };
+
+    match shard_by {
+        ShardBy::Letter => match package.chars().next() {
+            Some(ch) if ch.is_ascii_alphanumeric() => ch.to_ascii_lowercase().to_string(),
+            _ => "_".to_owned(),
+        },
+        ShardBy::Hash => {
+            // Unlike std's hashers, FNV is the same from one version of Rust
+            // to the next.
+            let mut hasher = FnvHasher::default();
+            hasher.write(package.as_bytes());
+            let width = (shard_count - 1).to_string().len();
+            format!("{:0width$}", hasher.finish() % shard_count as u64)
+        }
+    }
+}
+
+fn write_header(config: &BuckConfig, out: &mut impl Write) -> Result<()> {
     out.write_all(config.generated_file_header.as_bytes())?;
     if !config.generated_file_header.is_empty() {
         out.write_all(b"\n")?;
     }
+    Ok(())
+}
+
+fn write_loads(config: &BuckConfig, rules: &[&Rule], out: &mut impl Write) -> Result<()> {
+    // Load only the symbols that the rules in this file actually use. A rule
+    // name like `cargo.rust_library` needs `cargo` loaded.
+    let mut loads: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for rule in rules {
+        let rule_name = rule.rule_name(config);
+        let symbol = rule_name.split('.').next().unwrap();
+        if let Some(bzl) = config.loads.get(symbol) {
+            loads.entry(bzl).or_default().insert(symbol);
+        }
+    }
 
     out.write_all(config.buckfile_imports.as_bytes())?;
-    if !config.buckfile_imports.is_empty() {
+    for (bzl, symbols) in &loads {
+        write!(out, "load({:?}", bzl)?;
+        for symbol in symbols {
+            write!(out, ", {:?}", symbol)?;
+        }
+        out.write_all(b")\n")?;
+    }
+    if !config.buckfile_imports.is_empty() || !loads.is_empty() {
         out.write_all(b"\n")?;
     }
+    Ok(())
+}
 
-    for (i, rule) in rules.enumerate() {
-        if i > 0 {
-            out.write_all(b"\n")?;
+/// Write the rules, or with `in_macro`, the body of a macro which defines
+/// them. Returns whether there were none.
+fn write_rules(
+    config: &BuckConfig,
+    rules: &[&Rule],
+    in_macro: bool,
+    out: &mut impl Write,
+) -> Result<bool> {
+    // Serialize the rules in parallel, a chunk at a time so that rendered
+    // output can be written out as it's produced, in order.
+    let mut first = true;
+    for chunk in rules.chunks(RENDER_CHUNK_SIZE) {
+        let rendered = chunk
+            .par_iter()
+            .map(|rule| {
+                let mut buf = Vec::new();
+                if in_macro {
+                    render_in_macro(config, rule, &mut buf)?;
+                } else {
+                    rule.render(config, &mut buf)?;
+                }
+                Ok(buf)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for buf in rendered {
+            if !first {
+                out.write_all(b"\n")?;
+            }
+            out.write_all(&buf)?;
+            first = false;
         }
-        rule.render(config, out)?;
     }
+    Ok(first)
+}
 
+/// Render a rule indented into the body of a macro in a .bzl file, where
+/// rules that aren't loaded have to be called through `native`.
+fn render_in_macro(config: &BuckConfig, rule: &Rule, out: &mut impl Write) -> Result<()> {
+    let rule_name = rule.rule_name(config);
+    let symbol = rule_name.split('.').next().unwrap();
+    let loaded = config.loads.contains_key(symbol)
+        || config.buckfile_imports.contains(&format!("{:?}", symbol));
+
+    let mut buf = Vec::new();
+    if loaded {
+        rule.render_as(config, rule_name, &mut buf)?;
+    } else {
+        rule.render_as(config, &format!("native.{}", rule_name), &mut buf)?;
+    }
+    for line in String::from_utf8(buf)?.lines() {
+        if !line.is_empty() {
+            out.write_all(b"    ")?;
+            out.write_all(line.as_bytes())?;
+        }
+        out.write_all(b"\n")?;
+    }
     Ok(())
 }
+
+fn write_manual_sections(
+    manual_sections: &[String],
+    mut first: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    // Hand-written sections go after all the generated rules, in the order
+    // they appeared in the previous version of the file.
+    for section in manual_sections {
+        if !first {
+            out.write_all(b"\n")?;
+        }
+        out.write_all(section.as_bytes())?;
+        first = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::collections::BTreeSet;
+
+    use super::Alias;
+    use super::BuckPath;
+    use super::BuildscriptOutputs;
+    use super::Common;
+    use super::Filegroup;
+    use super::Name;
+    use super::PlatformRustCommon;
+    use super::Rule;
+    use super::RuleRef;
+    use super::RustCommon;
+    use super::RustLibrary;
+    use super::SerializeStyled;
+    use super::Style;
+    use super::Styled;
+    use super::SubtargetOrPath;
+    use super::Visibility;
+    use crate::cargo::Edition;
+    use crate::collection::SetOrMap;
+    use crate::config::BuckConfig;
+    use crate::config::SortOrder;
+
+    fn render(value: &impl SerializeStyled, sort_order: SortOrder) -> String {
+        let style = Style {
+            sort_order,
+            diff_friendly: false,
+        };
+        serde_json::to_string(&Styled(value, style)).unwrap()
+    }
+
+    #[test]
+    fn sort_orders() {
+        let deps: BTreeSet<RuleRef> = ["//foo:bar", ":foo-1.0", "bar"]
+            .map(|target| RuleRef::new(target.to_owned()))
+            .into();
+        assert_eq!(
+            render(&deps, SortOrder::Buildifier),
+            r#"["bar",":foo-1.0","//foo:bar"]"#,
+        );
+        assert_eq!(
+            render(&deps, SortOrder::Lexicographic),
+            r#"["//foo:bar",":foo-1.0","bar"]"#,
+        );
+
+        let header = SubtargetOrPath::Path(BuckPath("include/foo.h".into()));
+        let generated = SubtargetOrPath::Label("//gen:foo[bar.h]".to_owned());
+        let headers = SetOrMap::Set(BTreeSet::from([header.clone(), generated.clone()]));
+        assert_eq!(
+            render(&headers, SortOrder::Buildifier),
+            r#"["include/foo.h","//gen:foo[bar.h]"]"#,
+        );
+        assert_eq!(
+            render(&headers, SortOrder::Lexicographic),
+            r#"["//gen:foo[bar.h]","include/foo.h"]"#,
+        );
+        let headers = SetOrMap::Map(BTreeMap::from([
+            ("foo.h".to_owned(), header),
+            ("bar.h".to_owned(), generated),
+        ]));
+        for sort_order in [SortOrder::Buildifier, SortOrder::Lexicographic] {
+            assert_eq!(
+                render(&headers, sort_order),
+                r#"{"bar.h":"//gen:foo[bar.h]","foo.h":"include/foo.h"}"#,
+            );
+        }
+
+        let alias = Rule::Alias(Alias {
+            name: Name("foo".to_owned()),
+            actual: Name("zlib-1.0".to_owned()),
+            visibility: Visibility::Public,
+        });
+        let filegroup = Rule::Filegroup(Filegroup {
+            name: Name("licenses".to_owned()),
+            srcs: BTreeMap::new(),
+            visibility: Visibility::Private,
+        });
+        assert!(alias.cmp_in(&filegroup, SortOrder::Buildifier).is_gt());
+        assert!(alias.cmp_in(&filegroup, SortOrder::Lexicographic).is_lt());
+    }
+
+    #[test]
+    fn diff_friendly() {
+        let config: BuckConfig = toml::from_str("diff_friendly = true").unwrap();
+        let render = |deps: &[&str]| {
+            let rule = Rule::Library(RustLibrary {
+                common: RustCommon {
+                    common: Common {
+                        name: Name("foo-1.0".to_owned()),
+                        visibility: Visibility::Private,
+                        licenses: BTreeSet::new(),
+                        compatible_with: Vec::new(),
+                        comments: Default::default(),
+                    },
+                    krate: "foo".to_owned(),
+                    crate_root: BuckPath("foo-1.0/src/lib.rs".into()),
+                    edition: Edition::Rust2021,
+                    base: PlatformRustCommon {
+                        rustc_flags: vec![r#"--cfg=feature="a,[b]""#.to_owned()],
+                        deps: deps
+                            .iter()
+                            .map(|dep| RuleRef::new((*dep).to_owned()))
+                            .collect(),
+                        ..Default::default()
+                    },
+                    platform: BTreeMap::new(),
+                },
+                proc_macro: false,
+                dlopen_enable: false,
+                python_ext: None,
+                linkable_alias: None,
+            });
+            let mut out = Vec::new();
+            rule.render(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let before = render(&[":bar-1.0"]);
+        let after = render(&[":bar-1.0", ":baz-1.0"]);
+        assert!(!before.contains("visibility"));
+        assert!(before
+            .contains("    rustc_flags = [\n        \"--cfg=feature=\\\"a,[b]\\\"\",\n    ],\n"));
+        assert!(before.contains("    deps = [\n        \":bar-1.0\",\n    ],\n"));
+        let mut added = after.lines().collect::<Vec<_>>();
+        let line = added
+            .iter()
+            .position(|line| line.contains(":baz-1.0"))
+            .unwrap();
+        assert_eq!(added.remove(line), "        \":baz-1.0\",");
+        assert_eq!(added, before.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn buildscript_outputs() {
+        let config: BuckConfig = toml::from_str("").unwrap();
+        let rule = Rule::BuildscriptOutputs(BuildscriptOutputs {
+            name: Name("foo-1.0-build-script-run-outs".to_owned()),
+            buildscript_genrule: Name("foo-1.0-build-script-run".to_owned()),
+            outs: BTreeSet::from(["bindings.rs".to_owned(), "data/tables.bin".to_owned()]),
+            visibility: Visibility::Private,
+        });
+        let mut out = Vec::new();
+        rule.render(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "genrule(\n    \
+                 name = \"foo-1.0-build-script-run-outs\",\n    \
+                 cmd = \"mkdir -p $OUT && cp -R $(location :foo-1.0-build-script-run[out_dir])/. $OUT\",\n    \
+                 outs = {\n        \
+                     \"bindings.rs\": [\"bindings.rs\"],\n        \
+                     \"data/tables.bin\": [\"data/tables.bin\"],\n    \
+                 },\n    \
+                 visibility = [],\n\
+             )\n",
+        );
+    }
+}
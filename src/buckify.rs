@@ -11,9 +11,11 @@ use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::io;
 use std::io::Write;
+use std::iter;
 use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
@@ -24,54 +26,89 @@ use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 
+use crate::abspaths;
 use crate::buck;
 use crate::buck::Alias;
 use crate::buck::BuckPath;
+use crate::buck::Comments;
 use crate::buck::Common;
+use crate::buck::Filegroup;
 use crate::buck::GitFetch;
 use crate::buck::HttpArchive;
 use crate::buck::Name;
 use crate::buck::PlatformRustCommon;
+use crate::buck::Replacement;
 use crate::buck::Rule;
 use crate::buck::RuleRef;
 use crate::buck::RustBinary;
 use crate::buck::RustCommon;
 use crate::buck::RustLibrary;
 use crate::buck::StringOrPath;
+use crate::buck::Subtarget;
 use crate::buck::SubtargetOrPath;
+use crate::buck::TargetMetadata;
 use crate::buck::Visibility;
+use crate::cargo;
 use crate::cargo::cargo_get_lockfile_and_metadata;
 use crate::cargo::ArtifactKind;
+use crate::cargo::CrateType;
+use crate::cargo::DepKind;
 use crate::cargo::Edition;
 use crate::cargo::Manifest;
 use crate::cargo::ManifestTarget;
+use crate::cargo::NodeDepKind;
 use crate::cargo::PkgId;
 use crate::cargo::Source;
 use crate::cargo::TargetReq;
+use crate::cargo_profile::CargoProfile;
 use crate::collection::SetOrMap;
+use crate::config::BuckConfig;
 use crate::config::Config;
+use crate::config::MetadataField;
+use crate::config::RequiredFeatures;
+use crate::cycles;
+use crate::date;
+use crate::deny;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::fingerprint;
 use crate::fixups::Fixups;
-use crate::glob::Globs;
-use crate::glob::NO_EXCLUDE;
+use crate::hooks;
+use crate::hooks::BuckifySummary;
+use crate::hooks::PackageSummary;
+use crate::hooks::WrittenFile;
 use crate::index;
 use crate::lockfile::Lockfile;
 use crate::lockfile::LockfilePackage;
+use crate::msrv;
+use crate::owners;
 use crate::platform::platform_names_for_expr;
 use crate::platform::PlatformExpr;
 use crate::platform::PlatformName;
-use crate::srcfiles::crate_srcfiles;
+use crate::platform_targets;
+use crate::plugins;
+use crate::profile;
+use crate::rustc_flags::RustcFlags;
+use crate::srcs_cache::cached_crate_srcfiles;
+use crate::validate;
+use crate::yanked;
 use crate::Args;
 use crate::Paths;
 
-// normalize a/b/../c => a/c
+// normalize a/b/../c => a/c, keeping the `..`s which can't be resolved, as in
+// ../../c
 pub fn normalize_dotdot(path: &Path) -> PathBuf {
     let mut ret = PathBuf::new();
 
     for component in path.components() {
         match component {
-            Component::ParentDir if ret.parent().is_some() => {
+            Component::ParentDir
+                if matches!(ret.components().next_back(), Some(Component::Normal(_))) =>
+            {
                 ret.pop();
             }
+            // There's nothing above the root.
+            Component::ParentDir if ret.has_root() => {}
             c => ret.push(c),
         }
     }
@@ -79,13 +116,65 @@ pub fn normalize_dotdot(path: &Path) -> PathBuf {
     ret
 }
 
-// Compute a path for `to` relative to `base`.
-pub fn relative_path(mut base: &Path, to: &Path) -> PathBuf {
+// Compute a path for `to` relative to `base`, such as ../libs/foo for a path
+// dependency outside the third-party directory. If there's no way from one to
+// the other, such as from one Windows drive to another, `to` is returned as it
+// is.
+//
+// This goes by the paths as they're written first, so that a symlink within
+// the repository is followed like a directory. Only if that leads out of
+// `base` are symlinks resolved, in case `to` is reached through a link to the
+// repository, such as a symlinked home directory.
+pub fn relative_path(base: &Path, to: &Path) -> PathBuf {
+    let outside = |path: &Path| path.is_absolute() || path.starts_with("..");
+    let lexical = lexical_relative_path(base, to);
+    if !outside(&lexical) || !base.is_absolute() || !to.is_absolute() {
+        return lexical;
+    }
+    match (dunce::canonicalize(base), dunce::canonicalize(to)) {
+        (Ok(base), Ok(to)) => {
+            let resolved = lexical_relative_path(&base, &to);
+            if outside(&resolved) {
+                lexical
+            } else {
+                resolved
+            }
+        }
+        _ => lexical,
+    }
+}
+
+// The spelling of a path which compares equal to every other spelling of it:
+// without `..`, and on Windows, without the `\\?\` prefix and with an upper
+// case drive letter.
+fn comparable_path(path: &Path) -> PathBuf {
+    let path = normalize_dotdot(dunce::simplified(path));
+    if cfg!(windows) {
+        if let Some(path_str) = path.to_str() {
+            let bytes = path_str.as_bytes();
+            if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_lowercase() {
+                return PathBuf::from(path_str[..1].to_ascii_uppercase() + &path_str[1..]);
+            }
+        }
+    }
+    path
+}
+
+fn lexical_relative_path(base: &Path, to: &Path) -> PathBuf {
+    let base = comparable_path(base);
+    let to = comparable_path(to);
+    let mut base = base.as_path();
     let mut res = PathBuf::new();
 
     while !to.starts_with(base) {
+        // Going up from `..`, or from the start of a relative path to an
+        // absolute one, needs to know the current directory.
+        match base.components().next_back() {
+            Some(Component::Normal(_)) => {}
+            _ => return to,
+        }
         res.push("..");
-        base = base.parent().expect("root dir not prefix of other?");
+        base = base.parent().unwrap();
     }
 
     res.join(
@@ -110,7 +199,13 @@ fn unzip_platform<T: Clone>(
                     .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
 
                 for plat in plats {
-                    extend(perplat.entry(plat.clone()).or_default(), thing.clone())
+                    // Like platform-specific deps, anything matching the
+                    // DEFAULT platform applies to all platforms.
+                    if plat.is_default() {
+                        extend(common, thing.clone())
+                    } else {
+                        extend(perplat.entry(plat.clone()).or_default(), thing.clone())
+                    }
                 }
             }
             None => extend(common, thing),
@@ -126,7 +221,12 @@ struct RuleContext<'meta> {
     paths: &'meta Paths,
     index: index::Index<'meta>,
     lockfile: Lockfile,
+    cargo_profile: Option<CargoProfile>,
+    /// From `top_level_platforms`, for the packages it applies to
+    platforms: HashMap<&'meta PkgId, BTreeSet<&'meta PlatformName>>,
     done: Mutex<HashSet<(&'meta PkgId, TargetReq<'meta>)>>,
+    /// Packages which any rules have been generated for
+    generated: Mutex<HashSet<&'meta PkgId>>,
 }
 
 /// Generate rules for a set of dependencies
@@ -167,9 +267,13 @@ fn generate_rules<'scope>(
     }
     for tgt in &pkg.targets {
         let matching_kind = match target_req {
-            TargetReq::Lib => tgt.kind_lib() || tgt.kind_proc_macro() || tgt.kind_cdylib(),
+            TargetReq::Lib => {
+                tgt.kind_lib() || tgt.kind_proc_macro() || tgt.kind_cdylib() || tgt.kind_staticlib()
+            }
             TargetReq::Bin(required_bin) => tgt.kind_bin() && tgt.name == required_bin,
             TargetReq::EveryBin => tgt.kind_bin(),
+            TargetReq::EveryBench => tgt.kind_bench(),
+            TargetReq::EveryExample => tgt.kind_example(),
             TargetReq::BuildScript => tgt.kind_custom_build(),
             TargetReq::Staticlib => tgt.kind_staticlib(),
             TargetReq::Cdylib => tgt.kind_cdylib(),
@@ -178,7 +282,7 @@ fn generate_rules<'scope>(
         if !matching_kind {
             continue;
         }
-        match generate_target_rules(context, pkg, tgt) {
+        match generate_target_rules(context, pkg, tgt, target_req) {
             Ok((rules, _)) if rules.is_empty() => {
                 // Don't generate rules for dependencies if we're not emitting
                 // any rules for this target.
@@ -187,22 +291,27 @@ fn generate_rules<'scope>(
                 let is_private_root_pkg =
                     context.index.is_root_package(pkg) && !context.index.is_public_package(pkg);
                 if !is_private_root_pkg {
+                    let replaced = rules
+                        .iter()
+                        .any(|rule| matches!(rule, Rule::Replacement(_)));
                     for rule in rules {
                         let _ = rule_tx.send(Ok(rule));
                     }
-                    if context.config.vendor.is_none() {
+                    context.generated.lock().unwrap().insert(&pkg.id);
+                    if context.config.vendor.is_none() && !replaced {
                         deps.push((pkg, TargetReq::Sources));
                     }
                 }
                 generate_dep_rules(context, scope, rule_tx.clone(), deps);
             }
             Err(err) => {
-                log::error!(
-                    "pkg {} target {}: rule generation failed: {:?}",
-                    pkg,
-                    tgt.name,
-                    err
-                );
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("rule generation failed: {:?}", err),
+                )
+                .package(pkg)
+                .target(&tgt.name)
+                .report();
                 let _ = rule_tx.send(Err(err));
             }
         }
@@ -213,6 +322,10 @@ fn generate_nonvendored_sources_archive<'scope>(
     context: &'scope RuleContext<'scope>,
     pkg: &'scope Manifest,
 ) -> Result<Option<Rule>> {
+    // Overridden packages aren't in Cargo.lock, which is left as it was.
+    if pkg.source == Source::Local {
+        return Ok(None);
+    }
     let lockfile_package = match context.lockfile.find(pkg) {
         Some(lockfile_package) => lockfile_package,
         None => {
@@ -232,7 +345,7 @@ fn generate_nonvendored_sources_archive<'scope>(
         Source::CratesIo => generate_http_archive(context, pkg, lockfile_package).map(Some),
         Source::Git {
             repo, commit_hash, ..
-        } => generate_git_fetch(repo, commit_hash).map(Some),
+        } => generate_git_fetch(context.config, repo, commit_hash).map(Some),
         Source::Unrecognized(_) => {
             bail!(
                 "`vendor = false` mode is supported only with exclusively crates.io and https git dependencies. \"{}\" {} is coming from some other source",
@@ -268,22 +381,24 @@ fn generate_http_archive<'scope>(
         strip_prefix: format!("{}-{}", pkg.name, pkg.version),
         sub_targets: BTreeSet::new(), // populated later after all fixups are constructed
         urls: vec![format!(
-            "https://crates.io/api/v1/crates/{}/{}/download",
-            pkg.name, pkg.version,
+            "{}/{}/{}/download",
+            context.config.buck.crates_url.trim_end_matches('/'),
+            pkg.name,
+            pkg.version,
         )],
-        visibility: Visibility::Private,
+        visibility: Visibility::private(&context.config.buck),
         sort_key: Name(format!("{}-{}", pkg.name, pkg.version)),
     }))
 }
 
-fn generate_git_fetch(repo: &str, commit_hash: &str) -> Result<Rule> {
+fn generate_git_fetch(config: &Config, repo: &str, commit_hash: &str) -> Result<Rule> {
     let short_name = short_name_for_git_repo(repo)?;
 
     Ok(Rule::GitFetch(GitFetch {
         name: Name(format!("{}.git", short_name)),
         repo: repo.to_owned(),
         rev: commit_hash.to_owned(),
-        visibility: Visibility::Private,
+        visibility: Visibility::private(&config.buck),
     }))
 }
 
@@ -320,7 +435,8 @@ pub fn short_name_for_git_repo(repo: &str) -> Result<String> {
         .to_owned();
 
     let hash = {
-        use std::hash::{Hash, Hasher};
+        use std::hash::Hash;
+        use std::hash::Hasher;
         let mut hasher = fnv::FnvHasher::default();
         canonical.hash(&mut hasher);
         hasher.finish()
@@ -336,6 +452,48 @@ pub fn short_name_for_git_repo(repo: &str) -> Result<String> {
     Ok(dir_name)
 }
 
+/// The Buck package of a directory, such as `third-party/rust`, relative to
+/// the cell root the third-party directory is in.
+pub(crate) fn buck_package(third_party_dir: &Path, dir: &Path) -> Result<String> {
+    let Some(cell_root) = third_party_dir
+        .ancestors()
+        .find(|dir| dir.join(".buckconfig").exists())
+    else {
+        bail!(
+            "no .buckconfig in {} or above it, to tell which Buck package it is",
+            third_party_dir.display(),
+        );
+    };
+    let package = relative_path(cell_root, dir);
+    if package.is_absolute() || package.starts_with("..") {
+        bail!(
+            "{} is outside of the Buck cell at {}",
+            dir.display(),
+            cell_root.display(),
+        );
+    }
+    Ok(BuckPath(package).to_string())
+}
+
+/// For a path dependency outside the third-party directory, its Buck package
+/// and the label of its `path_dependency_filegroup`, such as `libs/foo` and
+/// `//libs/foo:srcs`.
+pub(crate) fn path_dependency_filegroup(
+    config: &Config,
+    third_party_dir: &Path,
+    pkg: &Manifest,
+) -> Result<Option<(String, String)>> {
+    let Some(filegroup) = &config.buck.path_dependency_filegroup else {
+        return Ok(None);
+    };
+    if pkg.source != Source::Local || pkg.manifest_dir().starts_with(third_party_dir) {
+        return Ok(None);
+    }
+    let package = buck_package(third_party_dir, pkg.manifest_dir())?;
+    let label = format!("//{}:{}", package, filegroup);
+    Ok(Some((package, label)))
+}
+
 /// Find the git repository containing the given manifest directory.
 fn find_repository_root(manifest_dir: &Path) -> Result<&Path> {
     let mut dir = manifest_dir;
@@ -353,12 +511,41 @@ fn find_repository_root(manifest_dir: &Path) -> Result<&Path> {
     }
 }
 
+/// Aliases of a public package's library. The root package is public but we
+/// don't expose it via an alias. The root package library is exposed directly.
+fn public_aliases(context: &RuleContext, pkg: &Manifest, fixups: &Fixups, rules: &mut Vec<Rule>) {
+    let RuleContext { config, index, .. } = context;
+    if !index.is_public_target(pkg, TargetReq::Lib) || index.is_root_package(pkg) {
+        return;
+    }
+
+    rules.push(Rule::Alias(Alias {
+        name: index.public_rule_name(pkg),
+        actual: index.private_rule_name(pkg),
+        visibility: fixups.public_visibility(),
+    }));
+
+    if config.semver_aliases {
+        let private_rule_name = index.private_rule_name(pkg);
+        for name in index.semver_aliases(pkg) {
+            if *name != private_rule_name {
+                rules.push(Rule::Alias(Alias {
+                    name: name.clone(),
+                    actual: private_rule_name.clone(),
+                    visibility: fixups.public_visibility(),
+                }));
+            }
+        }
+    }
+}
+
 /// Generate rules for a target. Returns the rules, and the
 /// packages we depend on for further rule generation.
 fn generate_target_rules<'scope>(
     context: &'scope RuleContext<'scope>,
     pkg: &'scope Manifest,
     tgt: &'scope ManifestTarget,
+    target_req: TargetReq<'scope>,
 ) -> Result<(Vec<Rule>, Vec<(&'scope Manifest, TargetReq<'scope>)>)> {
     let RuleContext {
         config,
@@ -368,48 +555,175 @@ fn generate_target_rules<'scope>(
     } = context;
 
     log::info!("Generating rules for package {} target {}", pkg, tgt.name);
+    let _timer = profile::time("rule generation").package(pkg, None);
 
-    let fixups = Fixups::new(config, paths, index, pkg, tgt)?;
+    let fixups = {
+        let _timer = profile::time("fixups").package(pkg, Some("rule generation"));
+        Fixups::new(config, paths, index, pkg, tgt)?
+    };
 
     if fixups.omit_target() {
         return Ok((vec![], vec![]));
     }
 
+    fixups.check_expected_version()?;
+
+    // A replaced package is nothing but its library, as an alias to the
+    // replacement, and none of its dependencies.
+    if let Some(replace_with) = fixups.replace_with() {
+        if !matches!(target_req, TargetReq::Lib) || index.is_root_package(pkg) {
+            return Ok((vec![], vec![]));
+        }
+        let mut rules = Vec::new();
+        public_aliases(context, pkg, &fixups, &mut rules);
+        rules.push(Rule::Replacement(Replacement {
+            name: index.private_rule_name(pkg),
+            actual: RuleRef::new(replace_with.to_owned()),
+            visibility: fixups.library_visibility(),
+        }));
+        return Ok((rules, vec![]));
+    }
+
+    let mut rule_platforms = match context.platforms.get(&pkg.id) {
+        Some(platforms) => platforms.clone(),
+        None => configured_platforms(config),
+    };
+    // Omitted on some platforms only, the target's rule is still generated
+    // but incompatible with those.
+    let omitted = fixups.omit_target_platforms(&rule_platforms)?;
+    if !omitted.is_empty() && omitted == rule_platforms {
+        return Ok((vec![], vec![]));
+    }
+    rule_platforms.retain(|name| !omitted.contains(name));
+
+    // Binaries which something depends on are always generated, but the
+    // package's full set of binaries is subject to config.
+    if let TargetReq::EveryBin = target_req {
+        if !fixups.include_bin() {
+            Diagnostic::new(Severity::Info, "skipping binary")
+                .package(pkg)
+                .target(&tgt.name)
+                .suggestion("list it in `bins` in the package's fixups.toml to generate it")
+                .report();
+            return Ok((vec![], vec![]));
+        }
+    }
+
+    if let TargetReq::EveryExample = target_req {
+        if !fixups.include_example() {
+            return Ok((vec![], vec![]));
+        }
+    }
+
+    // Features in `required-features` which aren't enabled on some platforms
+    let mut missing_required_features = BTreeSet::new();
+    if tgt.kind_bin() && !tgt.required_features.is_empty() {
+        let missing = missing_required_features_by_platform(
+            config,
+            &tgt.required_features,
+            &fixups.compute_features()?,
+            &rule_platforms,
+        )?;
+        missing_required_features.extend(missing.values().flatten().cloned());
+        if !missing.is_empty() && config.required_features == RequiredFeatures::Skip {
+            let message = if missing.len() == rule_platforms.len() {
+                format!(
+                    "skipping, required features {:?} not enabled",
+                    missing_required_features,
+                )
+            } else {
+                format!(
+                    "skipping on {}, required features {:?} not enabled there",
+                    missing
+                        .keys()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    missing_required_features,
+                )
+            };
+            Diagnostic::new(Severity::Info, message)
+                .package(pkg)
+                .target(&tgt.name)
+                .suggestion("enable the features, or set `required_features = \"enable\"`")
+                .report();
+            if missing.len() == rule_platforms.len() {
+                return Ok((vec![], vec![]));
+            }
+            // Like omit_targets for those platforms
+            rule_platforms.retain(|name| !missing.contains_key(name));
+            missing_required_features.clear();
+        }
+    }
+
+    let compatible_with = rule_compatible_with(config, &rule_platforms).with_context(|| {
+        format!(
+            "top_level_platforms, omit_targets or required-features for {} target {}",
+            pkg, tgt.name
+        )
+    })?;
+
     log::debug!("pkg {} target {} fixups {:#?}", pkg, tgt.name, fixups);
 
     let manifest_dir = pkg.manifest_dir();
-    let mapped_manifest_dir =
-        if context.config.vendor.is_some() || matches!(pkg.source, Source::Local) {
-            relative_path(&paths.third_party_dir, manifest_dir)
-        } else if let Source::Git { repo, .. } = &pkg.source {
-            let git_fetch = short_name_for_git_repo(repo)?;
-            let repository_root = find_repository_root(manifest_dir)?;
-            let path_within_repo = relative_path(repository_root, manifest_dir);
-            PathBuf::from(git_fetch).join(path_within_repo)
-        } else {
-            PathBuf::from(format!("{}-{}.crate", pkg.name, pkg.version))
-        };
+    let manifest_rel = relative_path(&paths.third_party_dir, manifest_dir);
+    let filegroup = path_dependency_filegroup(config, &paths.third_party_dir, pkg)?;
+    let in_place = config.vendor.is_some() || matches!(pkg.source, Source::Local);
+    // Such as on another Windows drive
+    let unreachable = in_place && filegroup.is_none() && manifest_rel.is_absolute();
+    if unreachable {
+        Diagnostic::new(
+            Severity::Warning,
+            format!(
+                "no relative path to {} from the third-party directory, so its sources \
+                are mapped in by absolute path, which Buck may not allow",
+                manifest_dir.display(),
+            ),
+        )
+        .package(pkg)
+        .target(&tgt.name)
+        .suggestion("move it under the Buck cell, and set `path_dependency_filegroup`")
+        .report();
+    }
+    let mapped_manifest_dir = if let Some((package, _)) = &filegroup {
+        // Where the filegroup's files are put for rustc
+        PathBuf::from(package)
+    } else if unreachable {
+        PathBuf::from(index.versioned_rule_name(pkg))
+    } else if in_place {
+        manifest_rel.clone()
+    } else if let Source::Git { repo, .. } = &pkg.source {
+        let git_fetch = short_name_for_git_repo(repo)?;
+        let repository_root = find_repository_root(manifest_dir)?;
+        let path_within_repo = relative_path(repository_root, manifest_dir);
+        PathBuf::from(git_fetch).join(path_within_repo)
+    } else {
+        PathBuf::from(format!("{}-{}.crate", pkg.name, pkg.version))
+    };
     let crate_root = mapped_manifest_dir.join(relative_path(manifest_dir, &tgt.src_path));
     let edition = tgt.edition.unwrap_or(pkg.edition);
 
+    let license_srcs = fixups.license_srcs()?;
     let mut licenses = BTreeSet::new();
-    if config.vendor.is_none() {
-        // The `licenses` attribute takes `attrs.source()` which is the file
-        // containing the custom license text. For `vendor = false` mode, we
-        // don't have such a file on disk, and we don't have a Buck label either
-        // that could refer to the right generated location following download
-        // because `http_archive` does not expose subtargets for each of the
-        // individual contained files.
-    } else {
-        let rel_manifest = relative_path(&paths.third_party_dir, manifest_dir);
-        let mut license_globs = Globs::new(&config.license_patterns, NO_EXCLUDE)?;
-        for path in license_globs.walk(manifest_dir) {
-            licenses.insert(BuckPath(rel_manifest.join(path)));
-        }
-        if let Some(license_file) = &pkg.license_file {
-            licenses.insert(BuckPath(rel_manifest.join(license_file)));
+    let mut licenses_filegroup = None;
+    if config.buck.license_filegroups && !license_srcs.is_empty() {
+        let name = Name(format!("{}-licenses", index.versioned_rule_name(pkg)));
+        for path in license_srcs.keys() {
+            licenses.insert(SubtargetOrPath::Subtarget(Subtarget {
+                target: name.clone(),
+                relative: BuckPath(PathBuf::from(path)),
+            }));
         }
-    };
+        licenses_filegroup = Some(Rule::Filegroup(Filegroup {
+            name,
+            srcs: license_srcs,
+            visibility: Visibility::private(&config.buck),
+        }));
+    } else {
+        // Paths into the vendor directory, or subtargets of the http_archive
+        // in `vendor = false` mode
+        licenses.extend(license_srcs.into_values());
+    }
 
     // Get a list of the most obvious sources for the crate. This is either a list of
     // filename, or a list of globs.
@@ -420,7 +734,8 @@ fn generate_target_rules<'scope>(
         && edition >= Edition::Rust2018
     {
         measure_time::trace_time!("srcfiles for {}", pkg);
-        let sources = crate_srcfiles(&tgt.src_path);
+        let _timer = profile::time("srcfiles").package(pkg, Some("rule generation"));
+        let sources = cached_crate_srcfiles(paths, manifest_dir, &tgt.src_path);
         if sources.errors.is_empty() {
             let srcs = sources
                 .files
@@ -430,7 +745,14 @@ fn generate_target_rules<'scope>(
             log::debug!("crate_srcfiles returned {:#?}", srcs);
             srcs
         } else {
-            log::info!("crate_srcfiles failed: {:?}", sources.errors);
+            Diagnostic::new(
+                Severity::Info,
+                format!("precise srcs failed, using globs: {:?}", sources.errors),
+            )
+            .package(pkg)
+            .target(&tgt.name)
+            .suggestion("set `precise_srcs = false` in the package's fixups.toml")
+            .report();
             vec![]
         }
     } else {
@@ -448,19 +770,58 @@ fn generate_target_rules<'scope>(
     // Per platform rule bits
     let mut perplat: BTreeMap<PlatformName, PlatformRustCommon> = BTreeMap::new();
 
-    unzip_platform(
-        config,
-        &mut base,
-        &mut perplat,
-        |rule, flags| {
-            log::debug!("pkg {} target {}: adding flags {:?}", pkg, tgt.name, flags);
-            rule.rustc_flags.extend(flags)
-        },
-        fixups.compute_cmdline(),
-    )
-    .context("rustc_flags")?;
+    // Flags from the Cargo profile go first, so that fixups can override them.
+    // Build scripts would follow `build-override`, which isn't supported.
+    let mut rustc_flags = RustcFlags::default();
+    if let Some(cargo_profile) = &context.cargo_profile {
+        if !tgt.kind_custom_build() {
+            let workspace_member = index.workspace_members.iter().any(|m| m.id == pkg.id);
+            let flags = cargo_profile.rustc_flags(pkg, tgt, workspace_member)?;
+            rustc_flags.add(config, None, flags, "the Cargo profile")?;
+        }
+    }
+    for (platform, flags) in fixups.compute_cmdline() {
+        log::debug!("pkg {} target {}: adding flags {:?}", pkg, tgt.name, flags);
+        let source = match &platform {
+            Some(platform) => format!("fixups for `{}`", platform),
+            None => "fixups".to_owned(),
+        };
+        rustc_flags
+            .add(config, platform.as_ref(), flags, &source)
+            .context("rustc_flags")?;
+    }
+    rustc_flags.apply(pkg, tgt, &mut base, &mut perplat);
 
-    if config.vendor.is_some() || matches!(pkg.source, Source::Local) {
+    if filegroup.is_some() || unreachable {
+        // Files from the fixups stay as they are.
+        unzip_platform(
+            config,
+            &mut base,
+            &mut perplat,
+            |rule, srcs| {
+                for src in srcs {
+                    match src.strip_prefix(&manifest_rel) {
+                        Ok(path) => {
+                            let path = BuckPath(path.to_owned());
+                            let from = match &filegroup {
+                                Some((_, filegroup)) => {
+                                    SubtargetOrPath::Label(format!("{}[{}]", filegroup, path))
+                                }
+                                None => SubtargetOrPath::Path(BuckPath(src.clone())),
+                            };
+                            rule.mapped_srcs
+                                .insert(from, BuckPath(mapped_manifest_dir.join(&path.0)));
+                        }
+                        Err(_) => {
+                            rule.srcs.insert(BuckPath(src));
+                        }
+                    }
+                }
+            },
+            fixups.compute_srcs(srcs)?,
+        )
+        .context("srcs")?;
+    } else if in_place {
         unzip_platform(
             config,
             &mut base,
@@ -473,9 +834,44 @@ fn generate_target_rules<'scope>(
         )
         .context("srcs")?;
     } else if let Source::Git { repo, .. } = &pkg.source {
+        if fixups.has_overlay() {
+            Diagnostic::new(
+                Severity::Warning,
+                "overlay is not supported for git sources with `vendor = false`",
+            )
+            .package(pkg)
+            .target(&tgt.name)
+            .report();
+        }
         let short_name = short_name_for_git_repo(repo)?;
         let git_fetch_target = format!(":{}.git", short_name);
         base.srcs.insert(BuckPath(PathBuf::from(git_fetch_target)));
+    } else if fixups.has_overlay() {
+        // The overlay's files can't be mapped over the http_archive as a
+        // whole, so map in the rest of the package's files one by one as
+        // subtargets of it.
+        let http_archive = Name(format!("{}-{}.crate", pkg.name, pkg.version));
+        unzip_platform(
+            config,
+            &mut base,
+            &mut perplat,
+            |rule, srcs| {
+                for src in srcs {
+                    // Files outside of the package aren't in the archive.
+                    if let Ok(path) = src.strip_prefix(&manifest_rel) {
+                        rule.mapped_srcs.insert(
+                            SubtargetOrPath::Subtarget(Subtarget {
+                                target: http_archive.clone(),
+                                relative: BuckPath(path.to_owned()),
+                            }),
+                            BuckPath(mapped_manifest_dir.join(path)),
+                        );
+                    }
+                }
+            },
+            fixups.compute_srcs(srcs)?,
+        )
+        .context("srcs")?;
     } else {
         let http_archive_target = format!(":{}-{}.crate", pkg.name, pkg.version);
         base.srcs
@@ -486,24 +882,86 @@ fn generate_target_rules<'scope>(
         config,
         &mut base,
         &mut perplat,
-        |rule, ()| {
+        |rule, srcs| {
             log::debug!(
-                "pkg {} target {}: adding OUT_DIR for gen_srcs",
+                "pkg {} target {}: adding prerun OUT_DIR srcs {:?}",
                 pkg,
                 tgt.name,
+                srcs
             );
-            rule.env.insert(
-                "OUT_DIR".to_owned(),
-                StringOrPath::String(format!(
-                    "$(location :{}[out_dir])",
-                    fixups.buildscript_genrule_name(),
-                )),
+            rule.srcs.extend(srcs.into_iter().map(BuckPath))
+        },
+        fixups.compute_prerun_srcs()?,
+    )
+    .context("prerun srcs")?;
+
+    unzip_platform(
+        config,
+        &mut base,
+        &mut perplat,
+        |rule, outputs| {
+            log::debug!(
+                "pkg {} target {}: adding OUT_DIR for gen_srcs {:?}",
+                pkg,
+                tgt.name,
+                outputs,
             );
+            if outputs.is_empty() {
+                rule.env.insert(
+                    "OUT_DIR".to_owned(),
+                    StringOrPath::String(format!(
+                        "$(location :{}[out_dir])",
+                        fixups.buildscript_genrule_name(),
+                    )),
+                );
+            } else {
+                // Map each declared output into the crate's sources so only
+                // those files, not the whole OUT_DIR, are dependencies.
+                let out_dir = mapped_manifest_dir.join("out_dir");
+                for output in outputs {
+                    rule.mapped_srcs.insert(
+                        SubtargetOrPath::Subtarget(Subtarget {
+                            target: fixups.buildscript_outputs_name(),
+                            relative: BuckPath(PathBuf::from(&output)),
+                        }),
+                        BuckPath(out_dir.join(output)),
+                    );
+                }
+                rule.env.insert(
+                    "OUT_DIR".to_owned(),
+                    StringOrPath::Path(BuckPath(out_dir.clone())),
+                );
+            }
         },
         fixups.compute_gen_srcs(),
     )
     .context("OUT_DIR for gen_srcs")?;
 
+    unzip_platform(
+        config,
+        &mut base,
+        &mut perplat,
+        |rule, outputs| {
+            log::debug!(
+                "pkg {} target {}: adding runtime outputs {:?}",
+                pkg,
+                tgt.name,
+                outputs,
+            );
+            for output in outputs {
+                rule.resources.insert(
+                    BuckPath(PathBuf::from(&output)),
+                    SubtargetOrPath::Subtarget(Subtarget {
+                        target: fixups.buildscript_outputs_name(),
+                        relative: BuckPath(PathBuf::from(output)),
+                    }),
+                );
+            }
+        },
+        fixups.compute_runtime_outputs(),
+    )
+    .context("runtime outputs")?;
+
     unzip_platform(
         config,
         &mut base,
@@ -534,7 +992,7 @@ fn generate_target_rules<'scope>(
             );
             rule.features.extend(features);
         },
-        fixups.compute_features()?,
+        fixups.compute_rule_features()?,
     )
     .context("features")?;
 
@@ -550,28 +1008,56 @@ fn generate_target_rules<'scope>(
     )
     .context("env")?;
 
+    unzip_platform(
+        config,
+        &mut base,
+        &mut perplat,
+        |rule, select_deps| {
+            for (setting, deps) in select_deps {
+                rule.select_deps
+                    .entry(setting)
+                    .or_default()
+                    .extend(deps.into_iter().map(RuleRef::new));
+            }
+        },
+        fixups.compute_select_deps(),
+    )
+    .context("select_deps")?;
+
     // Compute set of dependencies any rule we generate here will need. They will only
     // be emitted if we actually emit some rules below.
     let mut dep_pkgs = Vec::new();
+    // Cfgs from fixups count when deciding which platforms a dependency is for.
+    let platforms = config
+        .platform
+        .iter()
+        .map(|(name, platform)| Ok((name.clone(), fixups.platform_with_cfgs(platform)?)))
+        .collect::<Result<Vec<_>>>()?;
+    // Dependencies behind `cfg(test)` or `cfg(doctest)` are left out of the
+    // library, but kept for its doctest rule.
+    let mut test_base = PlatformRustCommon::default();
+    let mut test_perplat: BTreeMap<PlatformName, PlatformRustCommon> = BTreeMap::new();
+    let mut test_dep_pkgs = Vec::new();
     for (deppkg, dep, rename, dep_kind) in fixups.compute_deps()? {
         let target_req = dep_kind.target_req();
         if let TargetReq::Staticlib | TargetReq::Cdylib = target_req {
             let artifact = &dep_kind.artifact;
             bail!("unsupported artifact kind {artifact:?} for dependency {dep:?}");
         }
-        if let Some(compile_target) = &dep_kind.compile_target {
-            // Compile_target is not implemented yet.
-            //
-            // For example artifact dependencies allow a crate to depend on a
-            // binary built for a different architecture.
-            bail!("unsupported compile_target {compile_target:?} for dependency {dep:?}");
-        }
+        let artifact_env = if dep_kind.artifact == Some(ArtifactKind::Bin) {
+            artifact_bin_env(&dep, rename, dep_kind)?
+        } else {
+            Vec::new()
+        };
         if dep.has_platform() {
             // If this is a platform-specific dependency, find the
             // matching supported platform(s) and insert it into the appropriate
             // dependency.
             // If the name is DEFAULT_PLATFORM then just put it in the normal generic deps
-            for (name, platform) in &config.platform {
+            let mut matched = false;
+            let mut in_default = false;
+            let mut missing = Vec::new();
+            for (name, platform) in &platforms {
                 let is_default = name.is_default();
 
                 log::debug!(
@@ -584,8 +1070,14 @@ fn generate_target_rules<'scope>(
                     dep.filter(platform)
                 );
 
-                if dep.filter(platform)? {
+                if !dep.filter(platform)? {
+                    if !is_default {
+                        missing.push(name);
+                    }
+                } else {
                     let dep = dep.clone();
+                    matched = true;
+                    in_default |= is_default;
 
                     let recipient = if is_default {
                         // Just use normal deps
@@ -595,11 +1087,7 @@ fn generate_target_rules<'scope>(
                     };
 
                     if dep_kind.artifact == Some(ArtifactKind::Bin) {
-                        let target_name = dep.target.strip_prefix(':').unwrap();
-                        let bin_name = dep_kind.bin_name.as_ref().unwrap();
-                        let env = format!("{}-{}", target_name, bin_name);
-                        let location = format!("$(location {}-{}#check)", dep.target, bin_name);
-                        recipient.env.insert(env, StringOrPath::String(location));
+                        recipient.env.extend(artifact_env.iter().cloned());
                     } else if let Some(rename) = rename {
                         recipient.named_deps.insert(rename.to_owned(), dep);
                     } else {
@@ -610,15 +1098,58 @@ fn generate_target_rules<'scope>(
                     }
                 }
             }
+            // The normal deps apply to every platform, not just those the
+            // dependency is for.
+            if in_default && !missing.is_empty() {
+                let missing: Vec<String> = missing.iter().map(ToString::to_string).collect();
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!(
+                        "dependency {} for `{}` matches the DEFAULT platform, so it also applies to {}",
+                        dep.target,
+                        dep.platform().unwrap(),
+                        missing.join(", "),
+                    ),
+                )
+                .package(pkg)
+                .target(&tgt.name)
+                .suggestion("narrow down the DEFAULT platform, or set `default_platform` in reindeer.toml")
+                .report();
+            }
+            if !matched && dep_kind.artifact.is_none() {
+                for (name, platform) in &platforms {
+                    if !dep.filter(&platform.for_tests())? {
+                        continue;
+                    }
+                    log::debug!(
+                        "pkg {} target {}: dep {:?} is only for tests",
+                        pkg,
+                        tgt.name,
+                        dep,
+                    );
+                    let recipient = if name.is_default() {
+                        &mut test_base
+                    } else {
+                        test_perplat.entry(name.clone()).or_default()
+                    };
+                    match rename {
+                        Some(rename) => {
+                            recipient.named_deps.insert(rename.to_owned(), dep.clone());
+                        }
+                        None => {
+                            recipient.deps.insert(dep.clone());
+                        }
+                    }
+                    if let Some(deppkg) = deppkg {
+                        test_dep_pkgs.push((deppkg, target_req));
+                    }
+                }
+            }
         } else {
             // Otherwise this is not platform-specific and can go into the
             // generic dependencies.
             if dep_kind.artifact == Some(ArtifactKind::Bin) {
-                let target_name = dep.target.strip_prefix(':').unwrap();
-                let bin_name = dep_kind.bin_name.as_ref().unwrap();
-                let env = format!("{}-{}", target_name, bin_name);
-                let location = format!("$(location {}-{}#check)", dep.target, bin_name);
-                base.env.insert(env, StringOrPath::String(location));
+                base.env.extend(artifact_env);
             } else if let Some(rename) = rename {
                 base.named_deps.insert(rename.to_owned(), dep);
             } else {
@@ -634,6 +1165,8 @@ fn generate_target_rules<'scope>(
     let mut bin_base = base.clone();
     let mut bin_perplat = perplat.clone();
 
+    bin_base.features.extend(missing_required_features);
+
     unzip_platform(
         config,
         &mut bin_base,
@@ -668,6 +1201,42 @@ fn generate_target_rules<'scope>(
     )
     .context("preferred_linkage")?;
 
+    unzip_platform(
+        config,
+        &mut lib_base,
+        &mut lib_perplat,
+        |rule, frameworks| {
+            log::debug!(
+                "pkg {} target {}: frameworks {:?}",
+                pkg,
+                tgt.name,
+                frameworks
+            );
+            let attr = config.buck.frameworks_attr.to_string();
+            rule.link_libs.entry(attr).or_default().extend(frameworks);
+        },
+        fixups.compute_frameworks(),
+    )
+    .context("frameworks")?;
+
+    unzip_platform(
+        config,
+        &mut lib_base,
+        &mut lib_perplat,
+        |rule, system_libs| {
+            log::debug!(
+                "pkg {} target {}: system_libs {:?}",
+                pkg,
+                tgt.name,
+                system_libs
+            );
+            let attr = config.buck.system_libs_attr.to_string();
+            rule.link_libs.entry(attr).or_default().extend(system_libs);
+        },
+        fixups.compute_system_libs(),
+    )
+    .context("system_libs")?;
+
     // Standalone binary - binary for a package always takes the package's library as a dependency
     // if there is one
     if let Some(true) = pkg.dependency_target().map(ManifestTarget::kind_lib) {
@@ -676,23 +1245,23 @@ fn generate_target_rules<'scope>(
             .insert(RuleRef::from(index.private_rule_name(pkg)));
     }
 
+    // Fixups may override which crate types a library target is built as.
+    let crate_types = fixups.crate_types();
+    let crate_cdylib = crate_types.contains(&CrateType::Cdylib);
+    let crate_staticlib = crate_types.contains(&CrateType::Staticlib);
+    let crate_lib = crate_types.contains(&CrateType::Lib)
+        || crate_types.contains(&CrateType::Rlib)
+        || crate_types.contains(&CrateType::Dylib);
+
     // Generate rules appropriate to each kind of crate we want to support
-    let rules: Vec<Rule> = if (tgt.kind_lib() && tgt.crate_lib())
+    let mut rules: Vec<Rule> = if ((tgt.kind_lib() || tgt.kind_cdylib() || tgt.kind_staticlib())
+        && (crate_lib || crate_cdylib || crate_staticlib))
         || (tgt.kind_proc_macro() && tgt.crate_proc_macro())
-        || (tgt.kind_cdylib() && tgt.crate_cdylib())
     {
         // Library or procmacro
         let mut rules = vec![];
 
-        // The root package is public but we don't expose it via
-        // an alias. The root package library is exposed directly.
-        if index.is_public_target(pkg, TargetReq::Lib) && !index.is_root_package(pkg) {
-            rules.push(Rule::Alias(Alias {
-                name: index.public_rule_name(pkg),
-                actual: index.private_rule_name(pkg),
-                visibility: fixups.public_visibility(),
-            }));
-        }
+        public_aliases(context, pkg, &fixups, &mut rules);
 
         let rust_library = RustLibrary {
             common: RustCommon {
@@ -705,10 +1274,11 @@ fn generate_target_rules<'scope>(
                     visibility: if index.is_root_package(pkg) {
                         Visibility::Public
                     } else {
-                        Visibility::Private
+                        fixups.library_visibility()
                     },
                     licenses,
                     compatible_with: vec![],
+                    comments: fixups.comments(),
                 },
                 krate: tgt.name.replace('-', "_"),
                 crate_root: BuckPath(crate_root),
@@ -717,10 +1287,10 @@ fn generate_target_rules<'scope>(
                 platform: lib_perplat,
             },
             proc_macro: tgt.crate_proc_macro(),
-            dlopen_enable: tgt.kind_cdylib() && fixups.python_ext().is_none(),
+            dlopen_enable: crate_cdylib && fixups.python_ext().is_none(),
             python_ext: fixups.python_ext().map(str::to_string),
             linkable_alias: if index.is_public_target(pkg, TargetReq::Lib)
-                && (tgt.kind_cdylib() || fixups.python_ext().is_some())
+                && (crate_cdylib || crate_staticlib || fixups.python_ext().is_some())
             {
                 Some(index.public_rule_name(pkg).0)
             } else {
@@ -728,6 +1298,42 @@ fn generate_target_rules<'scope>(
             },
         };
 
+        // Documentation and doctests are built from the same sources and
+        // dependencies as the library.
+        let library_variant = |suffix: &str| {
+            let mut variant = rust_library.clone();
+            variant.common.common.name =
+                Name(format!("{}-{}", index.private_rule_name(pkg), suffix));
+            variant.common.common.visibility = fixups.public_visibility();
+            variant.dlopen_enable = false;
+            variant.python_ext = None;
+            variant.linkable_alias = None;
+            variant
+        };
+        if index.is_public_target(pkg, TargetReq::Lib) && !index.is_root_package(pkg) {
+            if config.include_docs {
+                rules.push(Rule::Doc(library_variant("doc")));
+            }
+            if config.include_doctests && !tgt.crate_proc_macro() {
+                let mut doctest = library_variant("doctest");
+                doctest
+                    .common
+                    .base
+                    .deps
+                    .insert(RuleRef::from(index.private_rule_name(pkg)));
+                let common = &mut doctest.common;
+                common.base.deps.extend(test_base.deps);
+                common.base.named_deps.extend(test_base.named_deps);
+                for (name, test) in test_perplat {
+                    let platform = common.platform.entry(name).or_default();
+                    platform.deps.extend(test.deps);
+                    platform.named_deps.extend(test.named_deps);
+                }
+                dep_pkgs.extend(test_dep_pkgs);
+                rules.push(Rule::Doctest(doctest));
+            }
+        }
+
         rules.push(if index.is_root_package(pkg) {
             Rule::RootPackage(rust_library)
         } else {
@@ -737,16 +1343,28 @@ fn generate_target_rules<'scope>(
         // Library depends on the build script (if there is one).
         dep_pkgs.push((pkg, TargetReq::BuildScript));
 
+        if config.include_benches
+            && index.is_public_target(pkg, TargetReq::Lib)
+            && !index.is_root_package(pkg)
+        {
+            dep_pkgs.push((pkg, TargetReq::EveryBench));
+        }
+
+        if fixups.has_examples() {
+            dep_pkgs.push((pkg, TargetReq::EveryExample));
+        }
+
         rules
     } else if tgt.crate_bin() && tgt.kind_custom_build() {
         // Build script
         let buildscript = RustBinary {
             common: RustCommon {
                 common: Common {
-                    name: Name(format!("{}-{}", pkg, tgt.name)),
-                    visibility: Visibility::Private,
+                    name: Name(format!("{}-{}", index.package_rule_name(pkg), tgt.name)),
+                    visibility: Visibility::private(&config.buck),
                     licenses: Default::default(),
                     compatible_with: vec![],
+                    comments: Comments::default(),
                 },
                 krate: tgt.name.replace('-', "_"),
                 crate_root: BuckPath(crate_root),
@@ -759,6 +1377,7 @@ fn generate_target_rules<'scope>(
                 },
                 platform: bin_perplat,
             },
+            exec_compatible_with: Vec::new(),
         };
         fixups.emit_buildscript_rules(buildscript, config)?
     } else if tgt.kind_bin() && tgt.crate_bin() {
@@ -777,9 +1396,10 @@ fn generate_target_rules<'scope>(
             common: RustCommon {
                 common: Common {
                     name: actual,
-                    visibility: Visibility::Private,
+                    visibility: Visibility::private(&config.buck),
                     licenses,
                     compatible_with: vec![],
+                    comments: fixups.comments(),
                 },
                 krate: tgt.name.replace('-', "_"),
                 crate_root: BuckPath(crate_root),
@@ -787,6 +1407,7 @@ fn generate_target_rules<'scope>(
                 base: bin_base,
                 platform: bin_perplat,
             },
+            exec_compatible_with: Vec::new(),
         }));
 
         // Binary depends on the library (if there is one) and build script (if
@@ -795,6 +1416,93 @@ fn generate_target_rules<'scope>(
         dep_pkgs.push((pkg, TargetReq::BuildScript));
 
         rules
+    } else if (tgt.kind_bench() && target_req == TargetReq::EveryBench)
+        || (tgt.kind_example() && target_req == TargetReq::EveryExample)
+    {
+        let kind = if tgt.kind_bench() { "bench" } else { "example" };
+
+        // Cargo only resolves the dev-dependencies of workspace members, so
+        // use whichever matching version is already in the graph.
+        for dep in &pkg.dependencies {
+            if dep.kind != DepKind::Dev {
+                continue;
+            }
+            let Some(deppkg) = index.find_unresolved_dep(dep) else {
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!(
+                        "skipping {}, dev-dependency {} {} is not in the dependency graph",
+                        kind, dep.name, dep.req,
+                    ),
+                )
+                .package(pkg)
+                .target(&tgt.name)
+                .suggestion("add it to Cargo.toml so that Cargo resolves it")
+                .report();
+                return Ok((vec![], vec![]));
+            };
+            if deppkg.id == pkg.id {
+                continue;
+            }
+            let dep_rule =
+                RuleRef::from(index.private_rule_name(deppkg)).with_platform(dep.target.as_ref());
+            let add_dep = |recipient: &mut PlatformRustCommon| match &dep.rename {
+                Some(rename) => {
+                    recipient
+                        .named_deps
+                        .insert(rename.replace('-', "_"), dep_rule.clone());
+                }
+                None => {
+                    recipient.deps.insert(dep_rule.clone());
+                }
+            };
+            if dep_rule.has_platform() {
+                for (name, platform) in &config.platform {
+                    if !dep_rule.filter(platform)? {
+                        continue;
+                    }
+                    if name.is_default() {
+                        add_dep(&mut bin_base);
+                    } else {
+                        add_dep(bin_perplat.entry(name.clone()).or_default());
+                    }
+                }
+            } else {
+                add_dep(&mut bin_base);
+            }
+            dep_pkgs.push((deppkg, TargetReq::Lib));
+        }
+
+        dep_pkgs.push((pkg, TargetReq::Lib));
+        dep_pkgs.push((pkg, TargetReq::BuildScript));
+
+        let binary = RustBinary {
+            common: RustCommon {
+                common: Common {
+                    name: Name(format!(
+                        "{}-{}-{}",
+                        index.private_rule_name(pkg),
+                        kind,
+                        tgt.name,
+                    )),
+                    visibility: fixups.public_visibility(),
+                    licenses,
+                    compatible_with: vec![],
+                    comments: fixups.comments(),
+                },
+                krate: tgt.name.replace('-', "_"),
+                crate_root: BuckPath(crate_root),
+                edition,
+                base: bin_base,
+                platform: bin_perplat,
+            },
+            exec_compatible_with: Vec::new(),
+        };
+        vec![if tgt.kind_bench() {
+            Rule::Bench(binary)
+        } else {
+            Rule::Binary(binary)
+        }]
     } else {
         // Ignore everything else for now.
         log::info!("pkg {} target {} Skipping {:?}", pkg, tgt.name, tgt.kind());
@@ -802,27 +1510,613 @@ fn generate_target_rules<'scope>(
         vec![]
     };
 
+    // Every target of the package comes with the same filegroup, and they
+    // get deduplicated by name.
+    if !rules.is_empty() {
+        rules.extend(licenses_filegroup);
+    }
+
+    for rule in &mut rules {
+        if !compatible_with.is_empty() {
+            set_compatible_with(rule, &compatible_with);
+        }
+        simplify_platforms(rule, &rule_platforms, !config.buck.diff_friendly);
+    }
+
     Ok((rules, dep_pkgs))
 }
 
-pub(crate) fn buckify(config: &Config, args: &Args, paths: &Paths, stdout: bool) -> Result<()> {
-    let (lockfile, metadata) = {
-        measure_time::trace_time!("Get cargo metadata");
-        cargo_get_lockfile_and_metadata(config, args, paths)?
-    };
-
-    if args.debug {
-        log::trace!("Metadata {:#?}", metadata);
+/// The features in `required` which aren't enabled on each of the
+/// `platforms` that's missing any, given the features enabled by platform
+/// expression.
+fn missing_required_features_by_platform<'a>(
+    config: &Config,
+    required: &BTreeSet<String>,
+    features: &HashMap<Option<PlatformExpr>, BTreeSet<String>>,
+    platforms: &BTreeSet<&'a PlatformName>,
+) -> Result<BTreeMap<&'a PlatformName, BTreeSet<String>>> {
+    let mut enabled: BTreeMap<&PlatformName, BTreeSet<&str>> = platforms
+        .iter()
+        .map(|name| (*name, BTreeSet::new()))
+        .collect();
+    for (expr, features) in features {
+        let applies = match expr {
+            None => None,
+            Some(expr) => {
+                let names = platform_names_for_expr(config, expr)
+                    .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
+                // Like in unzip_platform, matching DEFAULT means everywhere.
+                if names.iter().any(|name| name.is_default()) {
+                    None
+                } else {
+                    Some(names)
+                }
+            }
+        };
+        for (name, enabled) in &mut enabled {
+            if applies.as_ref().is_none_or(|names| names.contains(name)) {
+                enabled.extend(features.iter().map(String::as_str));
+            }
+        }
     }
 
-    let index = index::Index::new(config.include_top_level, &metadata)?;
+    Ok(enabled
+        .into_iter()
+        .filter_map(|(name, enabled)| {
+            let missing: BTreeSet<String> = required
+                .iter()
+                .filter(|feature| !enabled.contains(feature.as_str()))
+                .cloned()
+                .collect();
+            (!missing.is_empty()).then_some((name, missing))
+        })
+        .collect())
+}
+
+fn configured_platforms(config: &Config) -> BTreeSet<&PlatformName> {
+    config
+        .platform
+        .keys()
+        .filter(|name| !name.is_default())
+        .collect()
+}
+
+/// `compatible_with` for a rule generated for only some of the configured
+/// platforms, by `top_level_platforms` or `omit_targets`, so that Buck doesn't
+/// try to build it for the others. Nothing if it's generated for all of them.
+fn rule_compatible_with(
+    config: &Config,
+    rule_platforms: &BTreeSet<&PlatformName>,
+) -> Result<Vec<RuleRef>> {
+    let omitted: BTreeSet<&PlatformName> = configured_platforms(config)
+        .into_iter()
+        .filter(|name| !rule_platforms.contains(name))
+        .collect();
+    if omitted.is_empty() {
+        return Ok(Vec::new());
+    }
+    platform_targets::compatible_with(config, rule_platforms, &omitted)
+}
+
+fn set_compatible_with(rule: &mut Rule, compatible_with: &[RuleRef]) {
+    match rule {
+        Rule::Binary(RustBinary { common, .. })
+        | Rule::Bench(RustBinary { common, .. })
+        | Rule::Library(RustLibrary { common, .. })
+        | Rule::RootPackage(RustLibrary { common, .. })
+        | Rule::Doc(RustLibrary { common, .. })
+        | Rule::Doctest(RustLibrary { common, .. }) => {
+            common.common.compatible_with = compatible_with.to_vec();
+        }
+        _ => {}
+    }
+}
+
+/// Drop the entries of a rule's `platform` dict for platforms it isn't
+/// generated for, and if `hoist`, move what the entries for all of those have
+/// in common into the base attributes.
+fn simplify_platforms(rule: &mut Rule, platforms: &BTreeSet<&PlatformName>, hoist: bool) {
+    match rule {
+        Rule::Binary(RustBinary { common, .. })
+        | Rule::BuildscriptBinary(RustBinary { common, .. })
+        | Rule::Bench(RustBinary { common, .. })
+        | Rule::Library(RustLibrary { common, .. })
+        | Rule::RootPackage(RustLibrary { common, .. })
+        | Rule::Doc(RustLibrary { common, .. })
+        | Rule::Doctest(RustLibrary { common, .. }) => {
+            common.platform.retain(|name, _| platforms.contains(name));
+            if hoist && common.platform.len() == platforms.len() {
+                hoist_common_platform_attrs(common);
+            }
+        }
+        Rule::CxxLibrary(lib) => {
+            lib.platform.retain(|name, _| platforms.contains(name));
+        }
+        _ => {}
+    }
+}
+
+/// With an entry in the `platform` dict for every platform, whatever they all
+/// have applies everywhere and can go in the base attributes instead.
+fn hoist_common_platform_attrs(rule: &mut RustCommon) {
+    let base = &mut rule.base;
+    let mut entries: Vec<&mut PlatformRustCommon> = rule.platform.values_mut().collect();
+    if entries.len() < 2 {
+        return;
+    }
+
+    hoist_set(&mut base.srcs, &mut entries, |rule| &mut rule.srcs);
+    hoist_set(&mut base.features, &mut entries, |rule| &mut rule.features);
+    hoist_map(&mut base.mapped_srcs, &mut entries, |rule| {
+        &mut rule.mapped_srcs
+    });
+    hoist_map(&mut base.named_deps, &mut entries, |rule| {
+        &mut rule.named_deps
+    });
+    hoist_map(&mut base.env, &mut entries, |rule| &mut rule.env);
+    hoist_map(&mut base.resources, &mut entries, |rule| {
+        &mut rule.resources
+    });
+    hoist_map_of_sets(&mut base.select_deps, &mut entries, |rule| {
+        &mut rule.select_deps
+    });
+    hoist_map_of_sets(&mut base.link_libs, &mut entries, |rule| {
+        &mut rule.link_libs
+    });
+    hoist_option(&mut base.link_style, &mut entries, |rule| {
+        &mut rule.link_style
+    });
+    hoist_option(&mut base.preferred_linkage, &mut entries, |rule| {
+        &mut rule.preferred_linkage
+    });
+
+    // Flags only when they're all the same, to keep them in order.
+    if !entries[0].rustc_flags.is_empty()
+        && entries
+            .iter()
+            .all(|entry| entry.rustc_flags == entries[0].rustc_flags)
+    {
+        base.rustc_flags.extend(entries[0].rustc_flags.clone());
+        for entry in &mut entries {
+            entry.rustc_flags.clear();
+        }
+    }
+
+    // Platform-specific deps remember the expression they came from, which
+    // may differ between platforms, so these go by target.
+    let common_deps: Vec<String> = entries[0]
+        .deps
+        .iter()
+        .map(|dep| dep.target.clone())
+        .filter(|target| {
+            entries[1..]
+                .iter()
+                .all(|entry| entry.deps.iter().any(|dep| dep.target == *target))
+        })
+        .collect();
+    for target in common_deps {
+        for entry in &mut entries {
+            entry.deps.retain(|dep| dep.target != target);
+        }
+        if !base.deps.iter().any(|dep| dep.target == target) {
+            base.deps.insert(RuleRef::new(target));
+        }
+    }
+
+    rule.platform
+        .retain(|_, entry| *entry != PlatformRustCommon::default());
+}
+
+fn hoist_set<T: Ord + Clone>(
+    base: &mut BTreeSet<T>,
+    entries: &mut [&mut PlatformRustCommon],
+    field: fn(&mut PlatformRustCommon) -> &mut BTreeSet<T>,
+) {
+    let (first, rest) = entries.split_first_mut().unwrap();
+    let common: Vec<T> = field(first)
+        .iter()
+        .filter(|item| rest.iter_mut().all(|entry| field(entry).contains(item)))
+        .cloned()
+        .collect();
+    for item in common {
+        for entry in entries.iter_mut() {
+            field(entry).remove(&item);
+        }
+        base.insert(item);
+    }
+}
+
+fn hoist_map<K: Ord + Clone, V: PartialEq + Clone>(
+    base: &mut BTreeMap<K, V>,
+    entries: &mut [&mut PlatformRustCommon],
+    field: fn(&mut PlatformRustCommon) -> &mut BTreeMap<K, V>,
+) {
+    let (first, rest) = entries.split_first_mut().unwrap();
+    let common: Vec<(K, V)> = field(first)
+        .iter()
+        .filter(|(key, value)| {
+            base.get(key).is_none_or(|base| base == *value)
+                && rest
+                    .iter_mut()
+                    .all(|entry| field(entry).get(key) == Some(value))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    for (key, value) in common {
+        for entry in entries.iter_mut() {
+            field(entry).remove(&key);
+        }
+        base.insert(key, value);
+    }
+}
+
+fn hoist_map_of_sets<T: Ord + Clone>(
+    base: &mut BTreeMap<String, BTreeSet<T>>,
+    entries: &mut [&mut PlatformRustCommon],
+    field: fn(&mut PlatformRustCommon) -> &mut BTreeMap<String, BTreeSet<T>>,
+) {
+    let (first, rest) = entries.split_first_mut().unwrap();
+    let mut common: Vec<(String, T)> = Vec::new();
+    for (key, items) in field(first).iter() {
+        for item in items {
+            if rest.iter_mut().all(|entry| {
+                field(entry)
+                    .get(key)
+                    .is_some_and(|items| items.contains(item))
+            }) {
+                common.push((key.clone(), item.clone()));
+            }
+        }
+    }
+    for (key, item) in common {
+        for entry in entries.iter_mut() {
+            let map = field(entry);
+            if let Some(items) = map.get_mut(&key) {
+                items.remove(&item);
+                if items.is_empty() {
+                    map.remove(&key);
+                }
+            }
+        }
+        base.entry(key).or_default().insert(item);
+    }
+}
+
+fn hoist_option<T: PartialEq>(
+    base: &mut Option<T>,
+    entries: &mut [&mut PlatformRustCommon],
+    field: fn(&mut PlatformRustCommon) -> &mut Option<T>,
+) {
+    let (first, rest) = entries.split_first_mut().unwrap();
+    if base.is_some()
+        || field(first).is_none()
+        || !rest.iter_mut().all(|entry| *field(entry) == *field(first))
+    {
+        return;
+    }
+    *base = field(first).take();
+    for entry in rest {
+        *field(entry) = None;
+    }
+}
+
+/// Files a Rust rule takes from other rules, which in `vendor = false` mode
+/// may need to be subtargets of an http_archive.
+fn rust_subtargets(rule: &RustCommon) -> impl Iterator<Item = &SubtargetOrPath> {
+    let platforms = iter::once(&rule.base).chain(rule.platform.values());
+    rule.common
+        .licenses
+        .iter()
+        .chain(platforms.flat_map(|plat| plat.mapped_srcs.keys().chain(plat.resources.values())))
+}
+
+/// Environment Cargo provides for an artifact dependency on a binary:
+/// `CARGO_BIN_FILE_<DEP>_<NAME>`, plus `CARGO_BIN_FILE_<DEP>` if the binary
+/// has the same name as the dependency.
+fn artifact_bin_env(
+    dep: &RuleRef,
+    rename: Option<&str>,
+    dep_kind: &NodeDepKind,
+) -> Result<Vec<(String, StringOrPath)>> {
+    let bin_name = dep_kind.bin_name.as_deref().unwrap();
+    let Some(dep_name) = dep_kind.extern_name.as_deref().or(rename) else {
+        bail!("missing name for artifact dependency {dep:?}");
+    };
+
+    // A build script runs on the exec platform, so that's where its binary
+    // dependencies are built unless they explicitly ask for the target
+    // platform. Artifact dependencies of anything else are built for the
+    // same platform as the dependent.
+    let macro_name = match (&dep_kind.kind, dep_kind.compile_target.as_deref()) {
+        (DepKind::Build, None) => "exe",
+        (_, None | Some("target")) => "location",
+        (_, Some(compile_target)) => {
+            // Building for some other specific architecture would need a
+            // configuration transition, which we have no way to express.
+            bail!("unsupported compile_target {compile_target:?} for dependency {dep:?}");
+        }
+    };
+    let location = StringOrPath::String(format!("$({} {}-{})", macro_name, dep.target, bin_name));
+
+    let dep_name = dep_name.to_uppercase().replace('-', "_");
+    let mut env = vec![(
+        format!("CARGO_BIN_FILE_{}_{}", dep_name, bin_name),
+        location.clone(),
+    )];
+    if bin_name.to_uppercase().replace('-', "_") == dep_name {
+        env.push((format!("CARGO_BIN_FILE_{}", dep_name), location));
+    }
+    Ok(env)
+}
+
+/// The metadata file's entry for every third-party package, by the name its
+/// rules start with.
+fn package_metadata(
+    config: &Config,
+    paths: &Paths,
+    index: &index::Index,
+    kept_packages: &[&Manifest],
+) -> Result<BTreeMap<String, BTreeMap<String, serde_json::Value>>> {
+    let mut packages = BTreeMap::new();
+    for &pkg in kept_packages {
+        if index.workspace_members.contains(&pkg) {
+            continue;
+        }
+
+        let mut entry = BTreeMap::new();
+        for field in &config.buck.metadata_fields.0 {
+            let value = match field {
+                MetadataField::Name => Some(pkg.name.clone().into()),
+                MetadataField::Version => Some(pkg.version.to_string().into()),
+                MetadataField::License => pkg.license.clone().map(Into::into),
+                MetadataField::Description => pkg.description.clone().map(Into::into),
+                MetadataField::Repository => pkg.repository.clone().map(Into::into),
+                MetadataField::Authors => {
+                    (!pkg.authors.is_empty()).then(|| pkg.authors.clone().into())
+                }
+                // From the fixups, below
+                MetadataField::Buildscript => None,
+            };
+            if let Some(value) = value {
+                let key = serde_json::to_value(field)?;
+                entry.insert(key.as_str().unwrap().to_owned(), value);
+            }
+        }
+
+        // Any target other than a build script will do for reading fixups,
+        // which wouldn't write out a template for it.
+        if let Some(tgt) = pkg.targets.iter().find(|tgt| !tgt.kind_custom_build()) {
+            let fixups = Fixups::new(config, paths, index, pkg, tgt)?;
+            if config
+                .buck
+                .metadata_fields
+                .0
+                .contains(&MetadataField::Buildscript)
+            {
+                if let Some(handling) = fixups.buildscript_handling() {
+                    entry.insert("buildscript".to_owned(), handling.into_iter().collect());
+                }
+            }
+            for (key, value) in fixups.metadata() {
+                entry.insert(key.clone(), value.clone().into());
+            }
+        }
+
+        packages.insert(index.package_rule_name(pkg).to_owned(), entry);
+    }
+    Ok(packages)
+}
+
+/// Record how each package's build script is dealt with, so that what was
+/// left out can be checked in the diagnostics.
+fn report_buildscripts(
+    config: &Config,
+    paths: &Paths,
+    index: &index::Index,
+    kept_packages: &[&Manifest],
+) -> Result<()> {
+    for &pkg in kept_packages {
+        if index.workspace_members.contains(&pkg) {
+            continue;
+        }
+        let Some(tgt) = pkg.targets.iter().find(|tgt| !tgt.kind_custom_build()) else {
+            continue;
+        };
+        let fixups = Fixups::new(config, paths, index, pkg, tgt)?;
+        if let Some(handling) = fixups.buildscript_handling() {
+            let handling: Vec<&str> = handling.into_iter().collect();
+            Diagnostic::new(
+                Severity::Info,
+                format!("build script: {}", handling.join(", ")),
+            )
+            .package(pkg)
+            .report();
+        }
+    }
+    Ok(())
+}
+
+/// The public libraries, by the name of their alias.
+fn public_library_metadata(
+    index: &index::Index,
+    kept_packages: &[&Manifest],
+) -> BTreeMap<Name, TargetMetadata> {
+    let mut targets = BTreeMap::new();
+    for &pkg in kept_packages {
+        if !index.is_public_target(pkg, TargetReq::Lib) || index.is_root_package(pkg) {
+            continue;
+        }
+        let Some(tgt) = pkg.dependency_target() else {
+            continue;
+        };
+        targets.insert(
+            index.public_rule_name(pkg),
+            TargetMetadata {
+                version: pkg.version.to_string(),
+                krate: tgt.name.replace('-', "_"),
+                features: index.resolved_features(pkg).map(str::to_owned).collect(),
+            },
+        );
+    }
+    targets
+}
+
+/// The configured `generated_file_header`, with its placeholders filled in.
+fn generated_file_header(
+    config: &Config,
+    paths: &Paths,
+    packages: &BTreeSet<Manifest>,
+) -> Result<String> {
+    let mut header = config
+        .buck
+        .generated_file_header
+        .replace("{reindeer_version}", env!("CARGO_PKG_VERSION"));
+
+    if header.contains(fingerprint::PLACEHOLDER) {
+        let fingerprint = fingerprint::fingerprint(config, paths, packages)?;
+        header = header.replace(fingerprint::PLACEHOLDER, &fingerprint);
+    }
+
+    if header.contains("{cargo_lock_hash}") {
+        // Every workspace's lockfile, so that a change to any of them shows.
+        let workspaces = config
+            .workspaces
+            .iter()
+            .map(|manifest_path| paths.third_party_dir.join(manifest_path));
+        let mut lockfiles = Vec::new();
+        for lockfile_path in iter::once(paths.lockfile_path.clone())
+            .chain(workspaces.map(|manifest_path| manifest_path.with_file_name("Cargo.lock")))
+        {
+            if !lockfiles.is_empty() {
+                lockfiles.push(b'\0');
+            }
+            let lockfile = fs::read(&lockfile_path)
+                .with_context(|| format!("reading {}", lockfile_path.display()))?;
+            lockfiles.extend(lockfile);
+        }
+        let hash = format!("{:016x}", cargo::fnv_hash(&lockfiles));
+        header = header.replace("{cargo_lock_hash}", &hash);
+    }
+
+    if header.contains("{date}") {
+        header = header.replace("{date}", &date::civil_date(date::today()?));
+    }
+
+    Ok(header)
+}
+
+pub(crate) fn buckify(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    stdout: bool,
+    packages: &[String],
+) -> Result<()> {
+    hooks::run_pre_buckify(&config.hooks.pre_buckify, paths)?;
+
+    let (lockfile, metadata) = {
+        measure_time::trace_time!("Get cargo metadata");
+        cargo_get_lockfile_and_metadata(config, args, paths)?
+    };
+
+    if args.debug {
+        log::trace!("Metadata {:#?}", metadata);
+    }
+
+    let index = index::Index::new(
+        config.include_top_level,
+        config.naming,
+        config.collision_suffix,
+        &metadata,
+    )?;
+    for pkg in index.suffixed_packages() {
+        Diagnostic::new(
+            Severity::Warning,
+            format!(
+                "rule names: another package is also {}, so this one from {} has rules named `{}`",
+                pkg,
+                pkg.id,
+                index.package_rule_name(pkg),
+            ),
+        )
+        .package(pkg)
+        .report();
+    }
+    deny::check_denied(config, &index)?;
+    msrv::check_rust_version(config, &index)?;
+    yanked::check_yanked(config, paths, &index)?;
+
+    let cargo_profile = match &config.profile {
+        Some(profile) => Some(CargoProfile::read(paths, &profile.name)?),
+        None => None,
+    };
+
+    for (name, platforms) in &config.top_level_platforms {
+        if !index.public_packages().any(|pkg| pkg.name == *name) {
+            Diagnostic::new(
+                Severity::Warning,
+                format!("top_level_platforms: `{}` is not a public package", name),
+            )
+            .report();
+        }
+        if let Some(platform) = platforms
+            .iter()
+            .find(|platform| !config.platform.contains_key(*platform))
+        {
+            bail!(
+                "top_level_platforms: `{}` is not a configured platform",
+                platform
+            );
+        }
+    }
+    let platforms = index.restricted_platforms(&config.top_level_platforms);
+
+    if config.omit_features.keys().any(|name| name != "*") {
+        let names: HashSet<&str> = index
+            .dependency_chains(|_| false)
+            .packages()
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+        for name in config.omit_features.keys() {
+            if name != "*" && !names.contains(name.as_str()) {
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!("omit_features: no package named `{}`", name),
+                )
+                .report();
+            }
+        }
+    }
+
+    // With `--package`, only the rules of those packages and what they depend
+    // on are generated, to be spliced into the existing BUCK file.
+    let roots: Vec<&Manifest> = if packages.is_empty() {
+        index.workspace_members.clone()
+    } else {
+        if config.buck.shard_by.is_some() {
+            bail!("`--package` can't be used with `shard_by`");
+        }
+        let all: Vec<&Manifest> = index.dependency_chains(|_| false).packages().collect();
+        let mut roots = Vec::new();
+        for name in packages {
+            let len = roots.len();
+            roots.extend(all.iter().filter(|pkg| pkg.name == *name));
+            if roots.len() == len {
+                bail!("Package `{}` is not in the dependency graph", name);
+            }
+        }
+        roots
+    };
 
     let context = &RuleContext {
         config,
         paths,
         index,
         lockfile,
+        cargo_profile,
+        platforms,
         done: Mutex::new(HashSet::new()),
+        generated: Mutex::new(HashSet::new()),
     };
 
     let (tx, rx) = mpsc::channel();
@@ -830,15 +2124,12 @@ pub(crate) fn buckify(config: &Config, args: &Args, paths: &Paths, stdout: bool)
     {
         measure_time::trace_time!("generate_dep_rules");
         rayon::scope(move |scope| {
-            for &workspace_member in &context.index.workspace_members {
+            for root in roots {
                 generate_dep_rules(
                     context,
                     scope,
                     tx.clone(),
-                    [
-                        (workspace_member, TargetReq::Lib),
-                        (workspace_member, TargetReq::EveryBin),
-                    ],
+                    [(root, TargetReq::Lib), (root, TargetReq::EveryBin)],
                 );
             }
         });
@@ -866,34 +2157,41 @@ pub(crate) fn buckify(config: &Config, args: &Args, paths: &Paths, stdout: bool)
             if let SubtargetOrPath::Subtarget(subtarget) = subtarget_or_path {
                 need_subtargets
                     .entry(subtarget.target.clone())
-                    .or_insert_with(BTreeSet::new)
+                    .or_default()
                     .insert(subtarget.relative.clone());
             }
         };
 
         for rule in &rules {
             match rule {
-                Rule::Binary(rule) | Rule::BuildscriptBinary(rule) => {
-                    rule.common.base.mapped_srcs.keys().for_each(&mut insert);
-                    for plat in rule.common.platform.values() {
-                        plat.mapped_srcs.keys().for_each(&mut insert);
-                    }
+                Rule::Binary(rule) | Rule::BuildscriptBinary(rule) | Rule::Bench(rule) => {
+                    rust_subtargets(&rule.common).for_each(&mut insert);
                 }
-                Rule::Library(rule) => {
-                    rule.common.base.mapped_srcs.keys().for_each(&mut insert);
-                    for plat in rule.common.platform.values() {
-                        plat.mapped_srcs.keys().for_each(&mut insert);
-                    }
+                Rule::Library(rule)
+                | Rule::RootPackage(rule)
+                | Rule::Doc(rule)
+                | Rule::Doctest(rule) => {
+                    rust_subtargets(&rule.common).for_each(&mut insert);
                 }
                 Rule::CxxLibrary(rule) => {
-                    rule.srcs.iter().for_each(&mut insert);
+                    rule.srcs.iter().for_each(|src| insert(&src.path));
+                    for plat in rule.platform.values() {
+                        plat.srcs.iter().for_each(|src| insert(&src.path));
+                    }
+                    rule.common.licenses.iter().for_each(&mut insert);
                     rule.headers.iter().for_each(&mut insert);
+                    rule.raw_headers.iter().for_each(&mut insert);
                     match &rule.exported_headers {
                         SetOrMap::Set(set) => set.iter().for_each(&mut insert),
                         SetOrMap::Map(map) => map.values().for_each(&mut insert),
                     }
                 }
-                Rule::PrebuiltCxxLibrary(rule) => insert(&rule.static_lib),
+                Rule::Filegroup(rule) => rule.srcs.values().for_each(&mut insert),
+                Rule::PrebuiltCxxLibrary(rule) => {
+                    let libs = [&rule.static_lib, &rule.shared_lib, &rule.import_lib];
+                    libs.into_iter().flatten().for_each(&mut insert);
+                    rule.common.licenses.iter().for_each(&mut insert);
+                }
                 _ => {}
             }
         }
@@ -911,26 +2209,150 @@ pub(crate) fn buckify(config: &Config, args: &Args, paths: &Paths, stdout: bool)
             .collect();
     }
 
+    cycles::check_cycles(config, paths, &context.index, &mut rules)?;
+    abspaths::check_absolute_paths(config, paths, &mut rules)?;
+
+    // Packages which fixups cut off from the public targets, such as by
+    // omitting the only dependency on them, get no rules, and are left out of
+    // the other generated files as well.
+    let mut kept_packages: Vec<&Manifest> = context
+        .index
+        .dependency_chains(|_| false)
+        .packages()
+        .collect();
+    if packages.is_empty() {
+        let generated = context.generated.lock().unwrap();
+        kept_packages.retain(|pkg| {
+            let kept = generated.contains(&pkg.id) || context.index.workspace_members.contains(pkg);
+            if !kept {
+                Diagnostic::new(
+                    Severity::Info,
+                    "pruned, not reachable from any public target once fixups are applied",
+                )
+                .package(pkg)
+                .report();
+            }
+            kept
+        });
+    }
+
+    let mut rules: Vec<Rule> = rules.into_iter().collect();
+    rules.sort_by(|a, b| a.cmp_in(b, config.buck.sort_order));
+    let rules: Vec<Rule> = if config.plugins.is_empty() {
+        rules
+    } else {
+        plugins::run_plugins(&config.plugins, &config.buck, paths, rules)?
+    };
+
+    // A checkout which converts line endings mustn't change the output.
+    let newlines = |text: String| {
+        if config.buck.diff_friendly {
+            text.replace("\r\n", "\n")
+        } else {
+            text
+        }
+    };
+    let buck_config = BuckConfig {
+        generated_file_header: newlines(generated_file_header(config, paths, &metadata.packages)?)
+            .into(),
+        buckfile_imports: newlines(config.buck.buckfile_imports.to_string()).into(),
+        ..config.buck.clone()
+    };
+
+    let buckpath = paths.third_party_dir.join(&config.buck.file_name);
+    let existing = match fs::read_to_string(&buckpath) {
+        Ok(existing) => Some(newlines(existing)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).context(format!("reading {}", buckpath.display())),
+    };
+    let manual_sections = match &existing {
+        Some(existing) => buck::manual_sections(existing)
+            .with_context(|| format!("reading hand-written sections of {}", buckpath.display()))?,
+        None => Vec::new(),
+    };
+    let spliced = if packages.is_empty() {
+        None
+    } else {
+        let Some(existing) = &existing else {
+            bail!(
+                "`--package` needs an existing {} to update, run a full `reindeer buckify` first",
+                buckpath.display()
+            );
+        };
+        let mut out = Vec::new();
+        buck::splice_buckfile(&buck_config, existing, rules.iter(), &mut out)
+            .with_context(|| format!("updating {}", buckpath.display()))?;
+        Some(out)
+    };
+
     // Emit build rules to stdout
     if stdout {
-        let mut out = Vec::new();
-        buck::write_buckfile(&config.buck, rules.iter(), &mut out).context("writing buck file")?;
+        let out = match spliced {
+            Some(spliced) => spliced,
+            None => {
+                let mut out = Vec::new();
+                let _timer = profile::time("serialization");
+                buck::write_buckfile(&buck_config, rules.iter(), &manual_sections, &mut out)
+                    .context("writing buck file")?;
+                out
+            }
+        };
         // Ignore error, for example pipe closed resulting from
         // `reindeer buckify --stdout | head`.
         let _ = io::stdout().write_all(&out);
         return Ok(());
     }
 
+    let mut files = Vec::new();
+    let mut write_file = |path: &Path, content: Vec<u8>| -> Result<()> {
+        let changed = !fs::read(path).is_ok_and(|x| x == content);
+        if changed {
+            fs::write(path, content).with_context(|| format!("write {} file", path.display()))?;
+        }
+        files.push(WrittenFile {
+            path: relative_path(&paths.third_party_dir, path),
+            changed,
+        });
+        Ok(())
+    };
+
     // Write build rules to file
-    let buckpath = paths.third_party_dir.join(&config.buck.file_name);
     {
         measure_time::trace_time!("Write build rules to file");
 
         let mut out = Vec::new();
-        buck::write_buckfile(&config.buck, rules.iter(), &mut out).context("writing buck file")?;
-        if !fs::read(&buckpath).is_ok_and(|x| x == out) {
-            fs::write(&buckpath, out)
-                .with_context(|| format!("write {} file", buckpath.display()))?;
+        let mut shards = BTreeMap::new();
+        if let Some(spliced) = spliced {
+            out = spliced;
+        } else {
+            let _timer = profile::time("serialization");
+            match config.buck.shard_by {
+                None => {
+                    buck::write_buckfile(&buck_config, rules.iter(), &manual_sections, &mut out)
+                }
+                Some(shard_by) => buck::write_sharded_buckfile(
+                    &buck_config,
+                    shard_by,
+                    rules.iter(),
+                    &manual_sections,
+                    &mut out,
+                )
+                .map(|files| shards = files),
+            }
+            .context("writing buck file")?;
+        }
+        write_file(&buckpath, out)?;
+
+        // Shards which no longer have any rules, or all of them if the rules
+        // are no longer sharded, would otherwise be left behind.
+        for path in buck::shard_files(&paths.third_party_dir)? {
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            if !shards.contains_key(&*file_name) {
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+            }
+        }
+        for (file_name, content) in shards {
+            write_file(&paths.third_party_dir.join(file_name), content)?;
         }
     }
 
@@ -940,12 +2362,222 @@ pub(crate) fn buckify(config: &Config, args: &Args, paths: &Paths, stdout: bool)
         buckpath.display()
     );
 
+    if let Some(validate_command) = &config.buck.validate_command {
+        validate::validate(validate_command, paths, &context.index)?;
+    }
+
+    if let Some(owners_config) = &config.owners {
+        for (path, content) in
+            owners::owners_files(config, owners_config, paths, &context.index, &kept_packages)?
+        {
+            write_file(&path, content.into_bytes())?;
+        }
+    }
+
+    report_buildscripts(config, paths, &context.index, &kept_packages)?;
+
+    if let Some(metadata_file) = &config.buck.metadata_file {
+        let packages = package_metadata(config, paths, &context.index, &kept_packages)?;
+        let mut out = Vec::new();
+        buck::write_bzl_variable(&buck_config, "METADATA", &packages, &mut out)
+            .context("writing metadata file")?;
+        write_file(&paths.third_party_dir.join(metadata_file), out)?;
+    }
+
+    if let Some(targets_file) = &config.buck.targets_file {
+        let targets = public_library_metadata(&context.index, &kept_packages);
+        let mut out = Vec::new();
+        if config.buck.targets_file_metadata {
+            buck::write_bzl_variable(&buck_config, "RUST_TARGETS", &targets, &mut out)
+        } else {
+            let names: Vec<&Name> = targets.keys().collect();
+            buck::write_bzl_variable(&buck_config, "RUST_TARGETS", &names, &mut out)
+        }
+        .context("writing targets file")?;
+        write_file(&paths.third_party_dir.join(targets_file), out)?;
+    }
+
+    if !config.hooks.post_buckify.is_empty() {
+        let summary = BuckifySummary {
+            third_party_dir: &paths.third_party_dir,
+            files,
+            packages: kept_packages
+                .iter()
+                .map(|pkg| PackageSummary {
+                    name: &pkg.name,
+                    version: pkg.version.to_string(),
+                    rule: context.index.package_rule_name(pkg),
+                })
+                .collect(),
+        };
+        hooks::run_post_buckify(&config.hooks.post_buckify, paths, &summary)?;
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeSet;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use super::configured_platforms;
+    use super::missing_required_features_by_platform;
+    use super::normalize_dotdot;
+    use super::relative_path;
+    use super::rule_compatible_with;
     use super::short_name_for_git_repo;
+    use crate::platform::PlatformExpr;
+
+    #[test]
+    fn compatible_with_retained_platforms() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("reindeer.toml"), "").unwrap();
+        let config = crate::config::read_config(tmp.path()).unwrap();
+
+        let all = configured_platforms(&config);
+        assert!(rule_compatible_with(&config, &all).unwrap().is_empty());
+
+        let linux = all
+            .iter()
+            .copied()
+            .filter(|name| name.to_string().starts_with("linux-"))
+            .collect();
+        let compatible_with: Vec<String> = rule_compatible_with(&config, &linux)
+            .unwrap()
+            .into_iter()
+            .map(|rule| rule.target)
+            .collect();
+        assert_eq!(compatible_with, ["prelude//os:linux"]);
+    }
+
+    #[test]
+    fn required_features_by_platform() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("reindeer.toml"), "").unwrap();
+        let config = crate::config::read_config(tmp.path()).unwrap();
+        let platforms = configured_platforms(&config);
+
+        let required = BTreeSet::from(["a".to_owned(), "b".to_owned()]);
+        let features = HashMap::from([
+            (None, BTreeSet::from(["a".to_owned()])),
+            (
+                Some(PlatformExpr::from("cfg(target_os = \"linux\")".to_owned())),
+                BTreeSet::from(["b".to_owned()]),
+            ),
+        ]);
+        let missing =
+            missing_required_features_by_platform(&config, &required, &features, &platforms)
+                .unwrap();
+        let missing_on: Vec<String> = missing.keys().map(ToString::to_string).collect();
+        assert_eq!(
+            missing_on,
+            ["macos-arm64", "macos-x86_64", "windows-gnu", "windows-msvc"],
+        );
+        assert!(missing
+            .values()
+            .all(|missing| *missing == BTreeSet::from(["b".to_owned()])));
+    }
+
+    #[test]
+    fn normalizes_dotdot() {
+        assert_eq!(normalize_dotdot(Path::new("a/b/../c")), Path::new("a/c"));
+        assert_eq!(normalize_dotdot(Path::new("../../c")), Path::new("../../c"));
+        assert_eq!(normalize_dotdot(Path::new("a/../../c")), Path::new("../c"));
+        assert_eq!(normalize_dotdot(Path::new("/../c")), Path::new("/c"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        let third_party = Path::new("/repo/third-party/rust");
+        assert_eq!(
+            relative_path(third_party, Path::new("/repo/third-party/rust/vendor/foo")),
+            Path::new("vendor/foo"),
+        );
+        assert_eq!(
+            relative_path(third_party, Path::new("/repo/libs/foo")),
+            Path::new("../../libs/foo"),
+        );
+        assert_eq!(
+            relative_path(
+                third_party,
+                Path::new("/repo/third-party/rust/../../libs/foo")
+            ),
+            Path::new("../../libs/foo"),
+        );
+        assert_eq!(relative_path(third_party, third_party), Path::new(""));
+        assert_eq!(
+            relative_path(
+                Path::new("../libs/foo"),
+                Path::new("../libs/foo/src/lib.rs")
+            ),
+            Path::new("src/lib.rs"),
+        );
+        // No way from a relative path to an absolute one or out of `..`
+        assert_eq!(
+            relative_path(Path::new("vendor/foo"), Path::new("/repo/libs/foo")),
+            Path::new("/repo/libs/foo"),
+        );
+        assert_eq!(
+            relative_path(Path::new("../libs"), Path::new("fixups/foo")),
+            Path::new("fixups/foo"),
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_paths_through_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let real = tmp.path().join("real");
+        std::fs::create_dir_all(real.join("third-party/vendor/foo")).unwrap();
+        std::os::unix::fs::symlink(&real, tmp.path().join("link")).unwrap();
+        let third_party = dunce::canonicalize(real.join("third-party")).unwrap();
+
+        // Reached through a link to the repository
+        assert_eq!(
+            relative_path(
+                &third_party,
+                &tmp.path().join("link/third-party/vendor/foo")
+            ),
+            Path::new("vendor/foo"),
+        );
+        // A symlink within it is left alone.
+        std::os::unix::fs::symlink(
+            third_party.join("vendor/foo"),
+            third_party.join("vendor/bar"),
+        )
+        .unwrap();
+        assert_eq!(
+            relative_path(&third_party, &third_party.join("vendor/bar")),
+            Path::new("vendor/bar"),
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn relative_paths_on_windows() {
+        let third_party = Path::new(r"C:\repo\third-party");
+        assert_eq!(
+            relative_path(
+                third_party,
+                Path::new(r"\\?\C:\repo\third-party\vendor\foo")
+            ),
+            Path::new(r"vendor\foo"),
+        );
+        assert_eq!(
+            relative_path(third_party, Path::new(r"c:\repo\libs\foo")),
+            Path::new(r"..\libs\foo"),
+        );
+        assert_eq!(
+            relative_path(third_party, Path::new(r"D:\libs\foo")),
+            Path::new(r"D:\libs\foo"),
+        );
+        assert_eq!(
+            relative_path(third_party, Path::new(r"\\server\share\foo")),
+            Path::new(r"\\server\share\foo"),
+        );
+    }
 
     #[test]
     fn hashes_with_same_repo_variations() {
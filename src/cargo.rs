@@ -16,14 +16,19 @@ use std::collections::BTreeSet;
 use std::env;
 use std::fmt;
 use std::fmt::Display;
+use std::fs;
+use std::hash::Hasher;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::ErrorKind;
+use std::mem;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::Stdio;
 use std::thread;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use serde::de::DeserializeOwned;
@@ -32,8 +37,13 @@ use serde::Deserializer;
 use serde::Serialize;
 
 use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::diagnostics::Severity;
 use crate::lockfile::Lockfile;
 use crate::platform::PlatformExpr;
+use crate::profile;
 use crate::Args;
 use crate::Paths;
 
@@ -42,14 +52,139 @@ pub fn cargo_get_lockfile_and_metadata(
     args: &Args,
     paths: &Paths,
 ) -> Result<(Lockfile, Metadata)> {
+    let (mut lockfile, mut metadata) = match &args.metadata_json {
+        Some(metadata_json) => {
+            if !config.workspaces.is_empty() {
+                bail!("--metadata-json can't be used together with `workspaces`");
+            }
+            if !config.overrides.is_empty() {
+                bail!("--metadata-json can't be used together with overrides");
+            }
+            let json = fs::read(metadata_json)
+                .with_context(|| format!("reading {}", metadata_json.display()))?;
+            let metadata: Metadata = serde_json::from_slice(&json)
+                .with_context(|| format!("parsing metadata {}", metadata_json.display()))?;
+            (Lockfile::load(&paths.lockfile_path)?, metadata)
+        }
+        None => cargo_get_workspace_lockfile_and_metadata(
+            config,
+            args,
+            paths,
+            &paths.manifest_path,
+            &paths.lockfile_path,
+        )?,
+    };
+
+    if config.workspaces.is_empty() {
+        check_overrides(config, paths, &metadata)?;
+        return Ok((lockfile, metadata));
+    }
+
+    // First-order dependencies seen so far, by package name. These become the
+    // public targets of the shared BUCK file, so two workspaces asking for
+    // different versions of the same crate would be ambiguous.
+    let mut public_deps = BTreeMap::new();
+    let members = metadata.workspace_default_members.clone();
+    record_public_deps(&mut public_deps, &metadata, &members, &paths.manifest_path)?;
+
+    for manifest_path in &config.workspaces {
+        let manifest_path = paths.third_party_dir.join(manifest_path);
+        let lockfile_path = manifest_path.with_file_name("Cargo.lock");
+        let (other_lockfile, other_metadata) = cargo_get_workspace_lockfile_and_metadata(
+            config,
+            args,
+            paths,
+            &manifest_path,
+            &lockfile_path,
+        )
+        .with_context(|| format!("getting metadata for {}", manifest_path.display()))?;
+
+        let members = other_metadata.workspace_default_members.clone();
+        metadata.merge(other_metadata);
+        record_public_deps(&mut public_deps, &metadata, &members, &manifest_path)?;
+        lockfile.merge(other_lockfile);
+    }
+
+    check_overrides(config, paths, &metadata)?;
+    Ok((lockfile, metadata))
+}
+
+/// The overridden crates, with the canonical path of each one's directory.
+fn override_paths<'a>(config: &'a Config, paths: &Paths) -> Result<Vec<(&'a str, PathBuf)>> {
+    config
+        .overrides
+        .iter()
+        .map(|(name, path)| {
+            let path = dunce::canonicalize(paths.third_party_dir.join(path))
+                .with_context(|| format!("override of `{}`: {}", name, path.display()))?;
+            Ok((name.as_str(), path))
+        })
+        .collect()
+}
+
+/// Fail unless each override took the place of its crate, which Cargo only
+/// does if the version in the directory matches what depends on it.
+fn check_overrides(config: &Config, paths: &Paths, metadata: &Metadata) -> Result<()> {
+    for (name, path) in override_paths(config, paths)? {
+        let Some(pkg) = metadata.packages.iter().find(|pkg| {
+            pkg.name == name && pkg.source == Source::Local && pkg.manifest_path.starts_with(&path)
+        }) else {
+            bail!(
+                "override of `{}` with {} wasn't used; its version needs to match the \
+                dependencies on it",
+                name,
+                path.display(),
+            );
+        };
+        Diagnostic::new(
+            Severity::Warning,
+            format!("resolved from {} by override", path.display()),
+        )
+        .package(pkg)
+        .report();
+    }
+    Ok(())
+}
+
+fn cargo_get_workspace_lockfile_and_metadata(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    manifest_path: &Path,
+    lockfile_path: &Path,
+) -> Result<(Lockfile, Metadata)> {
+    let _timer = profile::time("cargo metadata");
+
     let mut cargo_flags = vec![
         "metadata",
         "--format-version",
         "1",
         "--manifest-path",
-        paths.manifest_path.to_str().unwrap(),
+        manifest_path.to_str().unwrap(),
     ];
 
+    // Each override becomes a `[patch.crates-io]` entry, which changes what
+    // Cargo.lock would say. It's put back the way it was afterwards.
+    let mut patches = Vec::new();
+    for (name, path) in override_paths(config, paths)? {
+        let path = toml::Value::String(path.to_string_lossy().into_owned());
+        patches.push(format!("patch.crates-io.{}.path={}", name, path));
+    }
+    for patch in &patches {
+        cargo_flags.extend(["--config", patch]);
+    }
+    let saved_lockfile = if patches.is_empty() {
+        None
+    } else if args.offline || config.cargo.offline {
+        bail!("overrides need to update Cargo.lock for a while, which offline mode doesn't allow");
+    } else {
+        match fs::read(lockfile_path) {
+            Ok(content) => Some(Some(content)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Some(None),
+            Err(err) => return Err(err).context(format!("reading {}", lockfile_path.display())),
+        }
+    };
+
     let cargo_home;
     let lockfile;
     if config.vendor.is_none() {
@@ -67,25 +202,218 @@ pub fn cargo_get_lockfile_and_metadata(
         // We must not change it during buckify or else we'd be generating Buck
         // targets for not the same crate versions that were put in the vendor
         // directory.
-        cargo_flags.extend(["--frozen", "--locked", "--offline"]);
-        lockfile = Some(Lockfile::load(paths)?);
+        if saved_lockfile.is_some() {
+            cargo_flags.push("--offline");
+        } else {
+            cargo_flags.extend(["--frozen", "--locked", "--offline"]);
+        }
+        lockfile = Some(Lockfile::load(lockfile_path)?);
+    };
+
+    let metadata = match &config.cargo.metadata_cache {
+        Some(_) if patches.is_empty() => cargo_metadata_cached(
+            config,
+            cargo_home,
+            args,
+            paths,
+            &cargo_flags,
+            manifest_path,
+            lockfile_path,
+        ),
+        _ => run_cargo_json(
+            config,
+            cargo_home,
+            &paths.third_party_dir,
+            args,
+            &cargo_flags,
+        ),
     };
+    if let Some(saved_lockfile) = saved_lockfile {
+        match saved_lockfile {
+            Some(content) => fs::write(lockfile_path, content),
+            None => fs::remove_file(lockfile_path),
+        }
+        .with_context(|| format!("restoring {}", lockfile_path.display()))?;
+    }
+    let metadata: Metadata = metadata.context("parsing metadata")?;
+
+    let lockfile = match lockfile {
+        Some(existing_lockfile) => existing_lockfile,
+        None => Lockfile::load(lockfile_path)?,
+    };
+
+    Ok((lockfile, metadata))
+}
+
+/// `cargo metadata` output saved along with a fingerprint of its inputs
+#[derive(Deserialize)]
+struct MetadataCache {
+    fingerprint: String,
+    metadata: Metadata,
+}
+
+/// Like running `cargo metadata` with `run_cargo_json`, but reuse its previous
+/// output from the `metadata_cache` directory if nothing that went into it has
+/// changed since.
+fn cargo_metadata_cached(
+    config: &Config,
+    cargo_home: Option<&Path>,
+    args: &Args,
+    paths: &Paths,
+    cargo_flags: &[&str],
+    manifest_path: &Path,
+    lockfile_path: &Path,
+) -> Result<Metadata> {
+    let cache_dir = paths
+        .third_party_dir
+        .join(config.cargo.metadata_cache.as_ref().unwrap());
+    let cache_path = cache_dir.join(format!(
+        "metadata-{:016x}.json",
+        fnv_hash(manifest_path.to_string_lossy().as_bytes())
+    ));
+
+    match fs::read(&cache_path) {
+        Ok(cached) => match serde_json::from_slice::<MetadataCache>(&cached) {
+            Ok(cache) => {
+                let fingerprint = metadata_fingerprint(
+                    args,
+                    cargo_home,
+                    cargo_flags,
+                    manifest_path,
+                    lockfile_path,
+                    &cache.metadata,
+                )?;
+                if cache.fingerprint == fingerprint {
+                    log::debug!("Using cached metadata {}", cache_path.display());
+                    return Ok(cache.metadata);
+                }
+                log::debug!("Cached metadata {} is stale", cache_path.display());
+            }
+            Err(err) => log::warn!("Ignoring cached metadata {}: {}", cache_path.display(), err),
+        },
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context(format!("reading {}", cache_path.display())),
+    }
 
-    let metadata: Metadata = run_cargo_json(
+    let json = run_cargo(
         config,
         cargo_home,
         &paths.third_party_dir,
         args,
-        &cargo_flags,
+        cargo_flags,
     )
-    .context("parsing metadata")?;
+    .context("running cargo")?;
+    let metadata: Metadata = serde_json::from_slice(&json).context("deserializing json")?;
 
-    let lockfile = match lockfile {
-        Some(existing_lockfile) => existing_lockfile,
-        None => Lockfile::load(paths)?,
-    };
+    // Computed after running cargo, which may have updated the lockfile.
+    let fingerprint = metadata_fingerprint(
+        args,
+        cargo_home,
+        cargo_flags,
+        manifest_path,
+        lockfile_path,
+        &metadata,
+    )?;
+    let mut cache = format!("{{\"fingerprint\":{:?},\"metadata\":", fingerprint).into_bytes();
+    cache.extend_from_slice(&json);
+    cache.push(b'}');
+    fs::create_dir_all(&cache_dir)?;
+    fs::write(&cache_path, cache).with_context(|| format!("writing {}", cache_path.display()))?;
+
+    Ok(metadata)
+}
 
-    Ok((lockfile, metadata))
+/// Hash of everything `cargo metadata` output depends on that might change
+/// between runs: the lockfile, the workspace's manifests, the cargo flags and
+/// options, and the Cargo home whose config points crates.io at the vendor
+/// directory in vendoring mode.
+fn metadata_fingerprint(
+    args: &Args,
+    cargo_home: Option<&Path>,
+    cargo_flags: &[&str],
+    manifest_path: &Path,
+    lockfile_path: &Path,
+    metadata: &Metadata,
+) -> Result<String> {
+    let mut inputs = vec![lockfile_path.to_owned(), manifest_path.to_owned()];
+    if let Some(cargo_home) = cargo_home {
+        inputs.push(cargo_home.join("config.toml"));
+        inputs.push(cargo_home.join("config"));
+    }
+    inputs.extend(
+        metadata
+            .packages
+            .iter()
+            .filter(|pkg| metadata.workspace_default_members.contains(&pkg.id))
+            .map(|pkg| pkg.manifest_path.clone()),
+    );
+
+    let mut content = args.cargo_options.join("\0").into_bytes();
+    content.push(0);
+    content.extend(cargo_flags.join("\0").into_bytes());
+    content.push(0);
+    if let Some(cargo_home) = cargo_home {
+        content.extend(cargo_home.to_string_lossy().as_bytes());
+    }
+    for path in inputs {
+        content.push(0);
+        match fs::read(&path) {
+            Ok(file) => content.extend(file),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(format!("reading {}", path.display())),
+        }
+    }
+
+    Ok(format!("{:016x}", fnv_hash(&content)))
+}
+
+pub(crate) fn fnv_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Check the first-order dependencies of one workspace's members against those
+/// of previously merged workspaces, and record them.
+fn record_public_deps(
+    public_deps: &mut BTreeMap<String, (PkgId, PathBuf)>,
+    metadata: &Metadata,
+    members: &[PkgId],
+    manifest_path: &Path,
+) -> Result<()> {
+    let packages: BTreeMap<&PkgId, &Manifest> =
+        metadata.packages.iter().map(|pkg| (&pkg.id, pkg)).collect();
+
+    for member in members {
+        let Some(node) = metadata
+            .resolve
+            .nodes
+            .iter()
+            .find(|node| node.id == *member)
+        else {
+            continue;
+        };
+        for dep in &node.deps {
+            let pkg = packages[&dep.pkg];
+            let (pkgid, workspace) = public_deps
+                .entry(pkg.name.clone())
+                .or_insert_with(|| (dep.pkg.clone(), manifest_path.to_owned()));
+            // Within one workspace Cargo is in charge of which versions get
+            // used, only disagreements between workspaces are a problem.
+            if *pkgid != dep.pkg && workspace != manifest_path {
+                bail!(
+                    "{} depends on {} but {} depends on {}; workspaces sharing a \
+                     third-party directory must agree on the versions of their \
+                     direct dependencies",
+                    workspace.display(),
+                    packages[&*pkgid],
+                    manifest_path.display(),
+                    pkg,
+                );
+            }
+        }
+    }
+    Ok(())
 }
 
 // Run a cargo command
@@ -102,6 +430,14 @@ pub(crate) fn run_cargo(
         .map(String::as_str)
         .chain(opts.iter().cloned())
         .collect();
+    let offline = args.offline || config.cargo.offline;
+    if offline {
+        for flag in ["--frozen", "--locked", "--offline"] {
+            if !cmdline.contains(&flag) {
+                cmdline.push(flag);
+            }
+        }
+    }
     let mut envs = Vec::new();
     if config.cargo.bindeps {
         cmdline.push("-Zbindeps");
@@ -156,9 +492,12 @@ pub(crate) fn run_cargo(
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    let mut child = cargo_command
-        .spawn()
-        .with_context(|| format!("Failed to execute `{:?}`", cargo_command))?;
+    let mut child = cargo_command.spawn().with_context(|| {
+        Failure::new(
+            FailureKind::Cargo,
+            format!("Failed to execute `{:?}`", cargo_command),
+        )
+    })?;
 
     let stdout_thr = thread::spawn({
         let stdout = BufReader::new(child.stdout.take().unwrap());
@@ -193,7 +532,16 @@ pub(crate) fn run_cargo(
     let stderr = stderr_thr.join().expect("stderr thread join failed");
 
     if !child.wait()?.success() {
-        anyhow::bail!("`{:?}` failed:\n{}", cargo_command, stderr);
+        let message = if offline {
+            format!(
+                "`{:?}` failed in offline mode, Cargo.lock or the vendored sources may be \
+                 out of date:\n{}",
+                cargo_command, stderr,
+            )
+        } else {
+            format!("`{:?}` failed:\n{}", cargo_command, stderr)
+        };
+        return Err(Failure::new(FailureKind::Cargo, message).into());
     }
 
     Ok(stdout.into_bytes())
@@ -226,7 +574,35 @@ where
     Ok(Option::deserialize(deserializer)?.unwrap_or_default())
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+/// Parse a Rust version such as `1.70`, in which the patch version may be
+/// left out.
+pub fn parse_rust_version(version: &str) -> Result<semver::Version> {
+    let parts: Vec<&str> = version.split('.').collect();
+    let part = |i: usize| parts.get(i).map_or(Ok(0), |part| part.parse::<u64>());
+    match (parts.len(), part(0), part(1), part(2)) {
+        (1..=3, Ok(major), Ok(minor), Ok(patch)) => Ok(semver::Version::new(major, minor, patch)),
+        _ => bail!(
+            "invalid Rust version `{}`, expected something like 1.70",
+            version,
+        ),
+    }
+}
+
+pub fn deserialize_rust_version<'de, D>(
+    deserializer: D,
+) -> Result<Option<semver::Version>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(version) => parse_rust_version(&version)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct PkgId(pub String);
 
 impl Display for PkgId {
@@ -246,6 +622,40 @@ pub struct Metadata {
     pub resolve: Resolve,
 }
 
+impl Metadata {
+    /// Union in the dependency graph of another workspace. The root package,
+    /// if any, stays the one from `self`. Packages resolved in both
+    /// workspaces get the union of their features and dependencies.
+    pub fn merge(&mut self, other: Metadata) {
+        let mut nodes: BTreeMap<PkgId, Node> = mem::take(&mut self.resolve.nodes)
+            .into_iter()
+            .map(|node| (node.id.clone(), node))
+            .collect();
+        for node in other.resolve.nodes {
+            match nodes.get_mut(&node.id) {
+                Some(existing) => {
+                    existing.deps.extend(node.deps);
+                    existing.features.extend(node.features);
+                }
+                None => {
+                    nodes.insert(node.id.clone(), node);
+                }
+            }
+        }
+        self.resolve.nodes = nodes.into_values().collect();
+
+        for member in other.workspace_default_members {
+            if !self.workspace_default_members.contains(&member) {
+                self.workspace_default_members.push(member);
+            }
+        }
+
+        // Packages already present are kept as they are.
+        self.packages.extend(other.packages);
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum VecStringOrBool {
@@ -255,6 +665,7 @@ pub enum VecStringOrBool {
 
 /// Package manifest
 // https://doc.rust-lang.org/cargo/reference/manifest.html#the-package-section
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Manifest {
     /// Package name
@@ -296,6 +707,9 @@ pub struct Manifest {
     pub keywords: BTreeSet<String>,
     /// Path to README file
     pub readme: Option<PathBuf>,
+    /// Oldest version of Rust the package supports
+    #[serde(default, deserialize_with = "deserialize_rust_version")]
+    pub rust_version: Option<semver::Version>,
     /// Source repository
     pub repository: Option<String>,
     /// Default edition for the package (if targets don't have it)
@@ -335,7 +749,7 @@ impl Ord for Manifest {
 
 impl PartialOrd for Manifest {
     fn partial_cmp(&self, other: &Manifest) -> Option<std::cmp::Ordering> {
-        self.id.partial_cmp(&other.id)
+        Some(self.cmp(other))
     }
 }
 
@@ -477,22 +891,6 @@ impl ManifestTarget {
         self.kind_staticlib() || self.kind_cdylib()
     }
 
-    pub fn crate_rlib(&self) -> bool {
-        self.crate_types.contains(&CrateType::Rlib)
-    }
-
-    pub fn crate_dylib(&self) -> bool {
-        self.crate_types.contains(&CrateType::Dylib)
-    }
-
-    pub fn crate_cdylib(&self) -> bool {
-        self.crate_types.contains(&CrateType::Cdylib)
-    }
-
-    pub fn crate_lib(&self) -> bool {
-        self.crate_rlib() || self.crate_dylib() || self.crate_types.contains(&CrateType::Lib)
-    }
-
     pub fn crate_bin(&self) -> bool {
         self.crate_types.contains(&CrateType::Bin)
     }
@@ -578,7 +976,7 @@ impl NodeDepKind {
         bin_name: None,
     };
 
-    pub fn target_req(&self) -> TargetReq {
+    pub fn target_req(&self) -> TargetReq<'_> {
         match self.artifact {
             None => TargetReq::Lib,
             Some(ArtifactKind::Bin) => {
@@ -599,12 +997,15 @@ pub enum TargetReq<'a> {
     Lib,
     Bin(&'a str),
     EveryBin,
+    EveryBench,
+    EveryExample,
     BuildScript,
     Staticlib,
     Cdylib,
     Sources,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub enum CompileMode {
@@ -613,7 +1014,17 @@ pub enum CompileMode {
     Test,
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Deserialize,
+    Serialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd
+)]
 #[serde(rename_all = "kebab-case")]
 pub enum CrateType {
     Bin,
@@ -652,6 +1063,7 @@ pub enum TargetKind {
     Cdylib,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Eq, PartialEq)]
 pub enum BuildKind {
     Host,
@@ -735,8 +1147,31 @@ fn parse_source(source: &str) -> Option<Source> {
 
 #[cfg(test)]
 mod test {
+    use std::fs;
+
+    use structopt::StructOpt;
+
+    use super::metadata_fingerprint;
+    use super::parse_rust_version;
     use super::parse_source;
+    use super::Metadata;
     use super::Source;
+    use crate::Args;
+
+    #[test]
+    fn test_parse_rust_version() {
+        assert_eq!(
+            parse_rust_version("1.70").unwrap(),
+            semver::Version::new(1, 70, 0),
+        );
+        assert_eq!(
+            parse_rust_version("1.70.1").unwrap(),
+            semver::Version::new(1, 70, 1),
+        );
+        assert!(parse_rust_version("1.70.0-nightly").is_err());
+        assert!(parse_rust_version("1.70.0.0").is_err());
+        assert!(parse_rust_version("").is_err());
+    }
 
     #[test]
     fn test_parses_source_git() {
@@ -750,4 +1185,58 @@ mod test {
             }),
         );
     }
+
+    #[test]
+    fn test_metadata_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("Cargo.toml");
+        let lockfile_path = dir.path().join("Cargo.lock");
+        let cargo_home = dir.path().join(".cargo");
+        fs::write(&manifest_path, "[package]\nname = \"rust-third-party\"\n").unwrap();
+        fs::write(&lockfile_path, "version = 3\n").unwrap();
+        fs::create_dir(&cargo_home).unwrap();
+        fs::write(
+            cargo_home.join("config.toml"),
+            "[source.crates-io]\nreplace-with = \"vendored-sources\"\n",
+        )
+        .unwrap();
+
+        let args = Args::from_iter(["reindeer", "buckify"]);
+        let metadata: Metadata = serde_json::from_value(serde_json::json!({
+            "packages": [],
+            "version": 1,
+            "workspace_default_members": [],
+            "resolve": {"root": null, "nodes": []},
+        }))
+        .unwrap();
+        let flags = ["metadata", "--manifest-path", "Cargo.toml"];
+        let vendored_flags = [
+            "metadata",
+            "--manifest-path",
+            "Cargo.toml",
+            "--frozen",
+            "--locked",
+            "--offline",
+        ];
+        let fingerprint = |cargo_home, cargo_flags: &[&str]| {
+            metadata_fingerprint(
+                &args,
+                cargo_home,
+                cargo_flags,
+                &manifest_path,
+                &lockfile_path,
+                &metadata,
+            )
+            .unwrap()
+        };
+
+        let unvendored = fingerprint(None, &flags);
+        let vendored = fingerprint(Some(&cargo_home), &vendored_flags);
+        assert_eq!(unvendored, fingerprint(None, &flags));
+        assert_ne!(unvendored, vendored);
+        assert_ne!(vendored, fingerprint(Some(&cargo_home), &flags));
+
+        fs::write(cargo_home.join("config.toml"), "").unwrap();
+        assert_ne!(vendored, fingerprint(Some(&cargo_home), &vendored_flags));
+    }
 }
@@ -0,0 +1,259 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Settings from a `[profile.<name>]` section of the workspace's Cargo.toml,
+//! turned into rustc flags for the generated rules. Only settings which the
+//! manifest spells out are passed on, so that Buck's own build modes decide
+//! everything else.
+
+use std::fs;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::cargo::Manifest;
+use crate::cargo::ManifestTarget;
+use crate::Paths;
+
+/// The settings Reindeer understands, as Cargo spells them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProfileSettings {
+    opt_level: Option<toml::Value>,
+    debug: Option<toml::Value>,
+    panic: Option<String>,
+    lto: Option<toml::Value>,
+    inherits: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProfileSection {
+    #[serde(flatten)]
+    settings: ProfileSettings,
+    /// Overrides for dependencies, by package name, `name@version`, or `*`
+    /// for every package outside the workspace
+    #[serde(default)]
+    package: toml::Table,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CargoManifest {
+    #[serde(default)]
+    profile: toml::Table,
+}
+
+#[derive(Debug)]
+pub struct CargoProfile {
+    settings: ProfileSettings,
+    packages: Vec<(String, ProfileSettings)>,
+}
+
+impl CargoProfile {
+    /// Read the named profile from the workspace's Cargo.toml, following its
+    /// `inherits`.
+    pub fn read(paths: &Paths, name: &str) -> Result<Self> {
+        let manifest = fs::read_to_string(&paths.manifest_path)
+            .with_context(|| format!("reading {}", paths.manifest_path.display()))?;
+        let manifest: CargoManifest = toml::from_str(&manifest)
+            .with_context(|| format!("parsing {}", paths.manifest_path.display()))?;
+        Self::from_profiles(&manifest.profile, name)
+            .with_context(|| format!("reading profile `{}`", name))
+    }
+
+    fn from_profiles(profiles: &toml::Table, name: &str) -> Result<Self> {
+        let mut chain = Vec::new();
+        let mut next = Some(name.to_owned());
+        while let Some(name) = next.take() {
+            if chain.iter().any(|(seen, _)| *seen == name) {
+                bail!("profile `{}` inherits from itself", name);
+            }
+            let section: ProfileSection = match profiles.get(&name) {
+                Some(section) => section.clone().try_into()?,
+                None if name == "dev" || name == "release" => ProfileSection::default(),
+                None => bail!("no profile `{}` in Cargo.toml", name),
+            };
+            next = section.settings.inherits.clone();
+            chain.push((name, section));
+        }
+
+        // Apply the chain from the bottom up, like Cargo does.
+        let mut settings = ProfileSettings::default();
+        let mut packages: Vec<(String, ProfileSettings)> = Vec::new();
+        for (_, section) in chain.into_iter().rev() {
+            settings = section.settings.or(&settings);
+            for (spec, package) in section.package {
+                let package: ProfileSettings = package.try_into()?;
+                if package.panic.is_some() || package.lto.is_some() {
+                    bail!(
+                        "`panic` and `lto` can't be overridden for package `{}`",
+                        spec
+                    );
+                }
+                match packages.iter_mut().find(|(existing, _)| *existing == spec) {
+                    Some((_, existing)) => *existing = package.or(existing),
+                    None => packages.push((spec, package)),
+                }
+            }
+        }
+
+        Ok(CargoProfile { settings, packages })
+    }
+
+    /// Flags for one of a package's targets other than its build script.
+    pub fn rustc_flags(
+        &self,
+        pkg: &Manifest,
+        tgt: &ManifestTarget,
+        workspace_member: bool,
+    ) -> Result<Vec<String>> {
+        let spec = format!("{}@{}", pkg.name, pkg.version);
+        let mut settings = self.settings_for(&pkg.name, &spec, workspace_member);
+        if tgt.crate_proc_macro() {
+            // Proc macros are loaded into rustc, so Cargo always builds them
+            // to unwind.
+            settings.panic = None;
+        }
+        // Only linking does anything with `lto`.
+        settings
+            .flags(tgt.crate_bin())
+            .with_context(|| format!("profile settings for {}", spec))
+    }
+
+    fn settings_for(&self, name: &str, spec: &str, workspace_member: bool) -> ProfileSettings {
+        // Like Cargo, a package named outright beats `*`.
+        let mut settings = self.settings.clone();
+        if !workspace_member {
+            if let Some((_, package)) = self.packages.iter().find(|(key, _)| key == "*") {
+                settings = package.clone().or(&settings);
+            }
+        }
+        if let Some((_, package)) = self
+            .packages
+            .iter()
+            .find(|(key, _)| key == name || key == spec)
+        {
+            settings = package.clone().or(&settings);
+        }
+        settings
+    }
+}
+
+impl ProfileSettings {
+    /// These settings, with any unset ones taken from `base`.
+    fn or(self, base: &ProfileSettings) -> ProfileSettings {
+        ProfileSettings {
+            opt_level: self.opt_level.or_else(|| base.opt_level.clone()),
+            debug: self.debug.or_else(|| base.debug.clone()),
+            panic: self.panic.or_else(|| base.panic.clone()),
+            lto: self.lto.or_else(|| base.lto.clone()),
+            inherits: None,
+        }
+    }
+
+    fn flags(&self, binary: bool) -> Result<Vec<String>> {
+        let mut flags = Vec::new();
+        if let Some(opt_level) = &self.opt_level {
+            let opt_level = match opt_level {
+                toml::Value::Integer(level @ 0..=3) => level.to_string(),
+                toml::Value::String(level) if level == "s" || level == "z" => level.clone(),
+                other => bail!("unsupported opt-level {}", other),
+            };
+            flags.push(format!("-Copt-level={}", opt_level));
+        }
+        if let Some(debug) = &self.debug {
+            let debuginfo = match debug {
+                toml::Value::Boolean(false) | toml::Value::Integer(0) => "0",
+                toml::Value::Integer(1) => "1",
+                toml::Value::Boolean(true) | toml::Value::Integer(2) => "2",
+                toml::Value::String(level) => match level.as_str() {
+                    "none" => "0",
+                    "line-directives-only" => "line-directives-only",
+                    "line-tables-only" => "line-tables-only",
+                    "limited" => "1",
+                    "full" => "2",
+                    _ => bail!("unsupported debug setting {:?}", level),
+                },
+                other => bail!("unsupported debug setting {}", other),
+            };
+            flags.push(format!("-Cdebuginfo={}", debuginfo));
+        }
+        if let Some(panic) = &self.panic {
+            flags.push(format!("-Cpanic={}", panic));
+        }
+        if let Some(lto) = self.lto.as_ref().filter(|_| binary) {
+            // `lto = false` is Cargo's default of thin local LTO, which needs
+            // no flag.
+            let lto = match lto {
+                toml::Value::Boolean(false) => None,
+                toml::Value::Boolean(true) => Some("fat"),
+                toml::Value::String(lto) => match lto.as_str() {
+                    "fat" | "thin" | "off" => Some(lto.as_str()),
+                    _ => bail!("unsupported lto setting {:?}", lto),
+                },
+                other => bail!("unsupported lto setting {}", other),
+            };
+            flags.extend(lto.map(|lto| format!("-Clto={}", lto)));
+        }
+        Ok(flags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CargoProfile;
+
+    fn flags(manifest: &str, profile: &str, name: &str, workspace_member: bool) -> Vec<String> {
+        let manifest: toml::Table = toml::from_str(manifest).unwrap();
+        let profiles = manifest["profile"].as_table().unwrap();
+        let profile = CargoProfile::from_profiles(profiles, profile).unwrap();
+        let spec = format!("{}@1.0.0", name);
+        profile
+            .settings_for(name, &spec, workspace_member)
+            .flags(true)
+            .unwrap()
+    }
+
+    #[test]
+    fn inherits_and_overrides() {
+        let manifest = r#"
+            [profile.release]
+            opt-level = 3
+            panic = "abort"
+
+            [profile.release.package."*"]
+            debug = false
+
+            [profile.release.package.ring]
+            opt-level = "s"
+
+            [profile.dist]
+            inherits = "release"
+            lto = "thin"
+        "#;
+
+        assert_eq!(
+            flags(manifest, "dist", "libc", false),
+            [
+                "-Copt-level=3",
+                "-Cdebuginfo=0",
+                "-Cpanic=abort",
+                "-Clto=thin"
+            ],
+        );
+        assert_eq!(
+            flags(manifest, "release", "ring", false),
+            ["-Copt-level=s", "-Cdebuginfo=0", "-Cpanic=abort"],
+        );
+        assert_eq!(
+            flags(manifest, "release", "member", true),
+            ["-Copt-level=3", "-Cpanic=abort"],
+        );
+        assert!(flags(manifest, "dev", "libc", false).is_empty());
+    }
+}
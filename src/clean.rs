@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Remove files left behind by packages which are no longer in Cargo.lock
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::lockfile::Lockfile;
+use crate::Paths;
+
+pub(crate) fn clean(config: &Config, paths: &Paths, dry_run: bool) -> Result<()> {
+    let mut lockfile = Lockfile::load(&paths.lockfile_path)?;
+    for manifest_path in &config.workspaces {
+        let manifest_path = paths.third_party_dir.join(manifest_path);
+        lockfile.merge(Lockfile::load(&manifest_path.with_file_name("Cargo.lock"))?);
+    }
+
+    let names: BTreeSet<&str> = lockfile
+        .packages
+        .iter()
+        .map(|pkg| pkg.name.as_str())
+        .collect();
+    let versioned_names: BTreeSet<String> = lockfile
+        .packages
+        .iter()
+        .map(|pkg| format!("{}-{}", pkg.name, pkg.version))
+        .collect();
+
+    let mut stale = Vec::new();
+
+    // `cargo vendor --versioned-dirs` names each directory after the package
+    // and version. Without `--versioned-dirs` some are named after just the
+    // package.
+    for dir in subdirs(&paths.third_party_dir.join("vendor"))? {
+        let name = dir.file_name().unwrap().to_string_lossy();
+        let current = if config.naming.versioned_dirs() {
+            versioned_names.contains(name.as_ref())
+        } else {
+            versioned_names.contains(name.as_ref()) || names.contains(name.as_ref())
+        };
+        if !current {
+            stale.push(dir);
+        }
+    }
+
+    // Only fixups which are still exactly what `fixup_templates` generated are
+    // removed. Anything written by hand is left alone, `reindeer lint` will
+    // point those out.
+    let mut fixup_dirs = subdirs(&paths.third_party_dir.join("fixups"))?;
+    if let Some(staging) = &config.fixup_templates_dir {
+        fixup_dirs.extend(subdirs(&paths.third_party_dir.join(staging))?);
+    }
+    for dir in fixup_dirs {
+        let name = dir.file_name().unwrap().to_string_lossy();
+        if !names.contains(name.as_ref()) && is_fixup_template(&dir)? {
+            stale.push(dir);
+        }
+    }
+
+    for dir in &stale {
+        let relpath = dir.strip_prefix(&paths.third_party_dir).unwrap();
+        if dry_run {
+            println!("Would remove {}", relpath.display());
+        } else {
+            println!("Removing {}", relpath.display());
+            fs::remove_dir_all(dir).with_context(|| format!("removing {}", dir.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn subdirs(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(err) => return Err(err).context(format!("reading {}", dir.display())),
+    };
+
+    let mut subdirs = BTreeSet::new();
+    for entry in entries {
+        let entry = entry?;
+        // Following symlinks, which is what the vendor directory consists
+        // of with a `store`
+        if fs::metadata(entry.path())?.is_dir() {
+            subdirs.insert(entry.path());
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Whether a fixup directory holds nothing but a fixups.toml with an
+/// unresolved build script, as generated by `fixup_templates`.
+fn is_fixup_template(dir: &Path) -> Result<bool> {
+    let mut files = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    if files.len() != 1 || files.pop().unwrap().file_name() != "fixups.toml" {
+        return Ok(false);
+    }
+
+    let fixup_path = dir.join("fixups.toml");
+    let file = fs::read_to_string(&fixup_path)
+        .with_context(|| format!("reading {}", fixup_path.display()))?;
+    let Ok(table) = toml::from_str::<toml::Table>(&file) else {
+        return Ok(false);
+    };
+
+    Ok(match table.get("buildscript") {
+        Some(toml::Value::Array(buildscript)) if table.len() == 1 => {
+            buildscript.iter().all(|entry| {
+                entry
+                    .as_table()
+                    .is_some_and(|entry| entry.len() == 1 && entry.contains_key("unresolved"))
+            })
+        }
+        _ => false,
+    })
+}
@@ -7,9 +7,11 @@
 
 //! Global third-party config
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Write as _;
@@ -20,16 +22,24 @@ use std::ops::Deref;
 use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use globset::Glob;
+use globset::GlobMatcher;
 use monostate::MustBe;
 use serde::de::value::MapAccessDeserializer;
 use serde::de::Deserializer;
+use serde::de::IntoDeserializer;
 use serde::de::MapAccess;
 use serde::de::Visitor;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::cargo::deserialize_rust_version;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::fixups::config::CargoEnvs;
 use crate::platform::PlatformConfig;
 use crate::platform::PlatformName;
 
@@ -40,6 +50,11 @@ pub struct Config {
     #[serde(skip)]
     pub config_path: PathBuf,
 
+    /// reindeer.toml with the files it includes merged in and environment
+    /// variables substituted, as TOML, or empty if there's none
+    #[serde(skip)]
+    pub resolved: String,
+
     /// Try to compute a precise list of sources rather than using globbing
     #[serde(default)]
     pub precise_srcs: bool,
@@ -52,6 +67,23 @@ pub struct Config {
     #[serde(default)]
     pub fixup_templates: bool,
 
+    /// Stage fixup templates in this directory, relative to the third-party
+    /// directory, rather than in `fixups`, until `reindeer accept-fixups`
+    #[serde(default)]
+    pub fixup_templates_dir: Option<PathBuf>,
+
+    /// Also look for a package's fixups.toml in this directory within the
+    /// package, when it's vendored or local. Any in `fixups` go on top of it.
+    #[serde(default)]
+    pub package_fixups_dir: Option<PathBuf>,
+
+    /// More directories laid out like `fixups`, relative to the third-party
+    /// directory, such as fixups shared between repositories. A package's
+    /// fixups in each go on top of those in the ones after it, with `fixups`
+    /// itself on top of them all.
+    #[serde(default)]
+    pub extra_fixup_dirs: Vec<PathBuf>,
+
     /// Fail buckify if there are unresolved fixups
     #[serde(default)]
     pub unresolved_fixup_error: bool,
@@ -68,6 +100,102 @@ pub struct Config {
     #[serde(default)]
     pub strict_globs: bool,
 
+    /// Cargo environment variables to set on the rules of every package,
+    /// like `cargo_env` in fixups. A package's own `cargo_env` replaces this.
+    #[serde(default)]
+    pub cargo_env: CargoEnvs,
+
+    /// Generate rules for the binaries of top-level packages. Individual
+    /// crates can override this with `bins` in their fixups.
+    #[serde(default = "default_true")]
+    pub include_bins: bool,
+
+    /// Also generate a documentation rule for each public library, using the
+    /// `rust_doc` rule from the `[buck]` section
+    #[serde(default)]
+    pub include_docs: bool,
+
+    /// Also generate a rule to run the doctests of each public library other
+    /// than proc macros, using the `rust_doctest` rule from the `[buck]`
+    /// section
+    #[serde(default)]
+    pub include_doctests: bool,
+
+    /// Also generate rules for the benchmarks of public packages, as
+    /// binaries using the `rust_bench` rule from the `[buck]` section
+    #[serde(default)]
+    pub include_benches: bool,
+
+    /// Leave out the bench and doctest rules which are part of a dependency
+    /// cycle, through their dev-dependencies, rather than failing
+    #[serde(default)]
+    pub break_dev_dependency_cycles: bool,
+
+    /// What to do with binaries whose `required-features` are not all enabled
+    #[serde(default)]
+    pub required_features: RequiredFeatures,
+
+    /// Features to leave out of every package with a given name, or of every
+    /// package at all under `"*"`, as `omit_features` in their fixups would
+    #[serde(default)]
+    pub omit_features: BTreeMap<String, BTreeSet<String>>,
+
+    /// How much of a package's version goes into the names of its rules and
+    /// its vendor directory
+    #[serde(default)]
+    pub naming: Naming,
+
+    /// What's added to the rule names of packages with the same name and
+    /// version as another from a different source
+    #[serde(default)]
+    pub collision_suffix: CollisionSuffix,
+
+    /// Also give public libraries aliases like `foo-1` and `foo`, for the
+    /// newest public version which is compatible
+    #[serde(default)]
+    pub semver_aliases: bool,
+
+    /// Visibility of the aliases and libraries of crates whose names match a
+    /// glob, from the `[visibility]` section
+    #[serde(default)]
+    pub visibility: VisibilityPatterns,
+
+    /// Crates which buckify refuses to allow anywhere in the dependency graph
+    #[serde(default)]
+    pub deny: Vec<DenyConfig>,
+
+    /// Version of the Rust toolchain which builds the generated rules, for
+    /// checking packages' `rust-version` against
+    #[serde(default, deserialize_with = "deserialize_rust_version")]
+    pub rust_version: Option<semver::Version>,
+
+    /// Whether a package needing a newer Rust than `rust_version` is an error
+    #[serde(default)]
+    pub rust_version_check: CheckLevel,
+
+    /// What to do about packages locked to a version which has been yanked
+    /// from its registry
+    #[serde(default)]
+    pub yanked: CheckLevel,
+
+    /// Dependencies to buckify, in the same form as the `[dependencies]` of a
+    /// Cargo.toml. When present, Reindeer generates the third-party Cargo.toml
+    /// instead of it being written by hand.
+    #[serde(default)]
+    pub dependencies: Option<toml::Table>,
+
+    /// Additional Cargo workspaces, as paths to their Cargo.toml relative to
+    /// this file. Their dependencies are merged with those of the Cargo.toml
+    /// in this directory into a single set of Buck rules.
+    #[serde(default)]
+    pub workspaces: Vec<PathBuf>,
+
+    /// Crates to resolve from a local directory instead of crates.io, from
+    /// the `[overrides]` section and `--override`, as paths relative to this
+    /// file
+    #[serde(default)]
+    pub overrides: BTreeMap<String, PathBuf>,
+
     #[serde(default)]
     pub cargo: CargoConfig,
 
@@ -83,8 +211,221 @@ pub struct Config {
     #[serde(default)]
     pub audit: AuditConfig,
 
+    /// Commands which get the generated rules as JSON and return them with
+    /// changes, in turn, before they're written
+    #[serde(default)]
+    pub plugins: Vec<Vec<String>>,
+
+    /// Commands to run around `reindeer buckify`, from the `[hooks]` section
+    #[serde(default)]
+    pub hooks: HooksConfig,
+
+    /// Write a file naming the owners of each vendored package into its
+    /// directory, for routing reviews of third-party changes
+    #[serde(default)]
+    pub owners: Option<OwnersConfig>,
+
+    /// Pass the settings of one of the workspace's Cargo profiles on to the
+    /// generated rules as rustc flags
+    #[serde(default)]
+    pub profile: Option<ProfileConfig>,
+
     #[serde(default = "default_platforms")]
     pub platform: HashMap<PlatformName, PlatformConfig>,
+
+    /// What the DEFAULT platform, whose dependencies go in the normal `deps`
+    /// of every platform, stands for
+    #[serde(default, deserialize_with = "deserialize_default_platform")]
+    pub default_platform: DefaultPlatform,
+
+    /// Platforms to generate the rules of a public package for, by package
+    /// name, instead of every configured platform. Packages which only such
+    /// public packages depend on get the platforms of those.
+    #[serde(default)]
+    pub top_level_platforms: BTreeMap<String, BTreeSet<PlatformName>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DefaultPlatform {
+    /// `default_platform = true`: the `[platform.DEFAULT]` table, if any
+    #[default]
+    Table,
+    /// `default_platform = "linux-x86_64"`: the same as another platform
+    Platform(PlatformName),
+    /// `default_platform = false`: nothing, ignoring any `[platform.DEFAULT]`
+    Disabled,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequiredFeatures {
+    /// Don't generate rules for the binary, like Cargo
+    #[default]
+    Skip,
+    /// Generate the binary with its required features added to it
+    Enable,
+}
+
+/// Packages whose names would collide under the chosen scheme keep their full
+/// version.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Naming {
+    /// `foo-1.2.3`
+    #[default]
+    Versioned,
+    /// `foo-1`, or `foo-0.2` for versions before 1.0
+    Major,
+    /// `foo`
+    Unversioned,
+}
+
+impl Naming {
+    /// Whether `cargo vendor` gets `--versioned-dirs`. Only with no version in
+    /// the rule names are the directories of packages with a single version
+    /// named after just the package.
+    pub fn versioned_dirs(self) -> bool {
+        self != Naming::Unversioned
+    }
+}
+
+/// How packages whose rule names would be the same as another package's from
+/// a different source, such as a git fork of a crates.io package, are told
+/// apart. Packages from crates.io keep their names.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionSuffix {
+    /// `foo-1.2.3-git-repo` for a git repository, the registry's host for
+    /// other registries, or `foo-1.2.3-local` for a path dependency
+    #[default]
+    Source,
+    /// A hash of the package ID, `foo-1.2.3-0123abcd`
+    Hash,
+}
+
+/// How rules, and arrays of strings such as `srcs` and `deps`, are ordered in
+/// the generated files.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Like buildifier: local `:targets` after plain strings, then `//`
+    /// labels, comparing the parts between `:` and `.`, and a package's alias
+    /// before its other rules
+    #[default]
+    Buildifier,
+    /// Plain string order, of values and of rule names
+    Lexicographic,
+}
+
+/// How the rules are split across files when `shard_by` is set.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShardBy {
+    /// One file per first character of the rule names, `rust_shard_a.bzl`
+    Letter,
+    /// `shard_count` files, `rust_shard_00.bzl` and so on, picked by a hash
+    /// of the package name
+    Hash,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VisibilityPatterns(Vec<(String, GlobMatcher, Vec<String>)>);
+
+impl VisibilityPatterns {
+    /// The visibility for a crate. If several patterns match its name, the
+    /// longest one applies.
+    pub fn get(&self, crate_name: &str) -> Option<&[String]> {
+        self.0
+            .iter()
+            .filter(|(_, matcher, _)| matcher.is_match(crate_name))
+            .max_by_key(|(pattern, _, _)| pattern.len())
+            .map(|(_, _, visibility)| visibility.as_slice())
+    }
+}
+
+impl<'de> Deserialize<'de> for VisibilityPatterns {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let patterns: BTreeMap<String, Vec<String>> = Deserialize::deserialize(deserializer)?;
+        let mut compiled = Vec::new();
+        for (pattern, visibility) in patterns {
+            let matcher = Glob::new(&pattern)
+                .map_err(serde::de::Error::custom)?
+                .compile_matcher();
+            compiled.push((pattern, matcher, visibility));
+        }
+        Ok(VisibilityPatterns(compiled))
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataField {
+    Name,
+    Version,
+    License,
+    Description,
+    Repository,
+    Authors,
+    /// How the package's build script is dealt with
+    Buildscript,
+}
+
+/// The package fields in the metadata file, by default its name, version,
+/// license and repository
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct MetadataFields(pub BTreeSet<MetadataField>);
+
+impl Default for MetadataFields {
+    fn default() -> Self {
+        MetadataFields(BTreeSet::from([
+            MetadataField::Name,
+            MetadataField::Version,
+            MetadataField::License,
+            MetadataField::Repository,
+        ]))
+    }
+}
+
+/// How to treat problems found by an optional check
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckLevel {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+/// One `[[deny]]` entry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DenyConfig {
+    /// Package name
+    pub name: String,
+    /// Only deny versions matching this requirement. All versions if unset.
+    #[serde(default)]
+    pub version: Option<semver::VersionReq>,
+    /// Why, or what to use instead
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Packages allowed to bring it in. A dependency path from a workspace
+    /// member which goes through one of these is exempt.
+    #[serde(default)]
+    pub allow_via: BTreeSet<String>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -98,6 +439,15 @@ pub struct CargoConfig {
     /// Support Cargo's unstable "artifact dependencies" functionality, RFC 3028.
     #[serde(default)]
     pub bindeps: bool,
+    /// Run every Cargo command with `--frozen --locked --offline`, so that
+    /// nothing is fetched from the network and Cargo.lock is never changed
+    #[serde(default)]
+    pub offline: bool,
+    /// Directory, relative to this file, in which to keep `cargo metadata`
+    /// output. It's reused for as long as the lockfile and workspace
+    /// manifests are unchanged.
+    #[serde(default)]
+    pub metadata_cache: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -112,10 +462,138 @@ pub struct BuckConfig {
     pub generated_file_header:
         StringWithDefault<MustBe!("# \x40generated by `reindeer buckify`\n")>,
 
+    /// Order of the rules and of arrays of strings in the generated files
+    #[serde(default)]
+    pub sort_order: SortOrder,
+
+    /// Comment each Rust rule whose package has fixups with the path of its
+    /// fixups.toml, and each of the rule's attributes with the fixups which
+    /// changed it
+    #[serde(default)]
+    pub fixup_comments: bool,
+
+    /// Lay out the generated files so that changes to them make the smallest
+    /// diffs, and the same ones on any OS: lists of one item on several lines
+    /// like longer ones, no attributes with empty values, platform-specific
+    /// attributes kept per platform, and only `\n` line endings in the BUCK
+    /// file
+    #[serde(default)]
+    pub diff_friendly: bool,
+
     /// Front matter for the generated BUCK file
     #[serde(default)]
     pub buckfile_imports: StringWithDefault<MustBe!("")>,
 
+    /// Where to load the rules from, as a map from the symbol a rule name
+    /// starts with (`cargo` for `cargo.rust_library`) to a .bzl file. A
+    /// `load()` is generated for each symbol the BUCK file uses.
+    #[serde(default)]
+    pub loads: BTreeMap<String, String>,
+
+    /// Visibility of the aliases for public crates whose fixups don't set
+    /// `visibility`. Unset means `["PUBLIC"]`.
+    #[serde(default)]
+    pub public_visibility: Option<Vec<String>>,
+
+    /// Visibility of every other generated rule. Empty means only rules in
+    /// the same BUCK file can use them.
+    #[serde(default)]
+    pub private_visibility: Vec<String>,
+
+    /// Also write a .bzl file of this name, such as `RUST_TARGETS.bzl`,
+    /// defining `RUST_TARGETS` as the names of the public library targets
+    #[serde(default)]
+    pub targets_file: Option<String>,
+
+    /// Make `RUST_TARGETS` a dict from each target name to its package's
+    /// version, its crate name and its enabled features, instead of a list
+    #[serde(default)]
+    pub targets_file_metadata: bool,
+
+    /// Command to check the generated rules with after writing them, such as
+    /// `["buck2", "uquery", "//third-party/rust/..."]`. It runs in this
+    /// directory, and its errors are reported against the packages whose
+    /// rules they name.
+    #[serde(default)]
+    pub validate_command: Option<Vec<String>>,
+
+    /// Command for `reindeer unused` which prints the labels of the targets
+    /// first-party code depends on, one per line, such as `["buck2",
+    /// "uquery", "deps(//... - //third-party/rust/..., 1)"]`. It runs in this
+    /// directory.
+    #[serde(default)]
+    pub unused_query: Option<Vec<String>>,
+
+    /// Name of a filegroup of its sources, such as `srcs`, which each path
+    /// dependency outside this directory defines in its own BUCK file. Its
+    /// rules then take the sources from there, as Buck doesn't allow `../`
+    /// paths.
+    #[serde(default)]
+    pub path_dependency_filegroup: Option<String>,
+
+    /// What to do about absolute paths left in the generated rules' `env`,
+    /// `rustc_flags` and sources once those to files in this directory have
+    /// been replaced by locations. They tie the rules to this machine, which defeats
+    /// remote execution and caching.
+    #[serde(default)]
+    pub absolute_paths: CheckLevel,
+
+    /// Put the rules in several .bzl files next to the BUCK file, each
+    /// defining a macro which the BUCK file loads and calls, rather than all
+    /// in the BUCK file itself. They're still all in the one Buck package.
+    #[serde(default)]
+    pub shard_by: Option<ShardBy>,
+
+    /// Number of files for `shard_by = "hash"`. Unset means 16.
+    #[serde(default)]
+    pub shard_count: Option<usize>,
+
+    /// Also write a .bzl file of this name, such as `METADATA.bzl`, defining
+    /// `METADATA` as a dict of information about every third-party package
+    #[serde(default)]
+    pub metadata_file: Option<String>,
+
+    /// Which fields from each package's manifest go in the `metadata_file`.
+    /// Fixups can add more with their `metadata` table.
+    #[serde(default)]
+    pub metadata_fields: MetadataFields,
+
+    /// Attribute of library rules for the Apple frameworks from fixups'
+    /// `frameworks`
+    #[serde(default)]
+    pub frameworks_attr: StringWithDefault<MustBe!("frameworks")>,
+
+    /// Attribute of library rules for the system libraries from fixups'
+    /// `system_libs`
+    #[serde(default)]
+    pub system_libs_attr: StringWithDefault<MustBe!("system_libs")>,
+
+    /// Put each package's license files in a filegroup rule named
+    /// `<package>-<version>-licenses` and have `licenses` refer to them
+    /// through it, rather than to the files themselves
+    #[serde(default)]
+    pub license_filegroups: bool,
+
+    /// Where `http_archive` rules download packages from crates.io, as
+    /// `<crates_url>/<name>/<version>/download`. `reindeer export-mirror`
+    /// writes a directory to serve here.
+    #[serde(default)]
+    pub crates_url: StringWithDefault<MustBe!("https://crates.io/api/v1/crates")>,
+
+    /// Constraint values for the `platform()` of each platform in the file
+    /// from `reindeer platforms emit`, by platform name. Platforms not listed
+    /// get the prelude's `os` and `cpu` constraints for their `target_os` and
+    /// `target_arch`.
+    #[serde(default)]
+    pub platform_constraints: BTreeMap<PlatformName, Vec<String>>,
+
+    /// Platform which build scripts are built and run on. Their rules get
+    /// `exec_compatible_with` its constraints, as in `platform_constraints`,
+    /// so that remote execution picks a matching machine when
+    /// cross-compiling.
+    #[serde(default)]
+    pub exec_platform: Option<PlatformName>,
+
     /// Rule name for alias
     #[serde(default)]
     pub alias: StringWithDefault<MustBe!("alias")>,
@@ -131,17 +609,32 @@ pub struct BuckConfig {
     /// Rule name for rust_binary
     #[serde(default)]
     pub rust_binary: StringWithDefault<MustBe!("rust_binary")>,
+    /// Rule name for the documentation of a library
+    #[serde(default)]
+    pub rust_doc: StringWithDefault<MustBe!("rust_doc")>,
+    /// Rule name for running the doctests of a library
+    #[serde(default)]
+    pub rust_doctest: StringWithDefault<MustBe!("rust_doctest")>,
+    /// Rule name for a benchmark, by default the same as for rust_binary
+    pub rust_bench: Option<String>,
     /// Rule name for cxx_library
     #[serde(default)]
     pub cxx_library: StringWithDefault<MustBe!("cxx_library")>,
     /// Rule name for prebuilt_cxx_library
     #[serde(default)]
     pub prebuilt_cxx_library: StringWithDefault<MustBe!("prebuilt_cxx_library")>,
+    /// Rule name for filegroup
+    #[serde(default)]
+    pub filegroup: StringWithDefault<MustBe!("filegroup")>,
     /// Rule name for the rust_binary of a build script
     pub buildscript_binary: Option<String>,
     /// Rule name for a build script invocation
     #[serde(default)]
     pub buildscript_genrule: StringWithDefault<MustBe!("buildscript_run")>,
+    /// Rule name for genrule, which copies the outputs a `gen_srcs` fixup
+    /// declares out of the build script's `OUT_DIR`
+    #[serde(default)]
+    pub genrule: StringWithDefault<MustBe!("genrule")>,
 }
 
 #[derive(Debug, Default, Clone, Deserialize)]
@@ -154,16 +647,75 @@ pub struct VendorConfig {
     /// Set of globs to remove from Cargo's checksun files in vendored dirs
     #[serde(default)]
     pub checksum_exclude: HashSet<String>,
+    /// How the vendored files relate to Cargo's own extracted copy of each
+    /// package
+    #[serde(default)]
+    pub link: VendorLink,
+    /// Keep the vendored packages in this directory, relative to this config
+    /// file, each named after its checksum, and have the vendor directory
+    /// consist of symlinks to them
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VendorLink {
+    /// Separate copies, as `cargo vendor` leaves them
+    #[default]
+    Copy,
+    /// Copy-on-write clones of the files Cargo extracted, wherever the
+    /// filesystem supports them
+    Reflink,
+    /// Hardlinks to copies of the files Cargo extracted, which Reindeer keeps
+    /// in `.reindeer-cache`, wherever the filesystem allows
+    Hardlink,
 }
 
 #[derive(Debug, Default, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AuditConfig {
-    /// List of package names to never attempt to autofix
+    /// List of package names to never attempt to autofix, either their
+    /// advisories or their fixups
     #[serde(default)]
     pub never_autofix: HashSet<String>,
 }
 
+/// Each command is a program and its arguments, run in this directory.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Commands to run before collecting metadata, such as generating code
+    /// which a workspace member needs
+    #[serde(default)]
+    pub pre_buckify: Vec<Vec<String>>,
+    /// Commands to run after writing all the files, with a JSON summary of
+    /// what was written on stdin
+    #[serde(default)]
+    pub post_buckify: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OwnersConfig {
+    /// Name of the file in each vendored package's directory
+    #[serde(default)]
+    pub file_name: StringWithDefault<MustBe!("OWNERS")>,
+    /// Owners of the packages whose fixups don't name their own
+    #[serde(default)]
+    pub default: Vec<String>,
+    /// Contents of the file, with `{owners}` replaced by one owner per line
+    /// and `{package}` by the package's name and version. By default just
+    /// the owners.
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Name of the profile in Cargo.toml, such as `release`
+    pub name: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct StringWithDefault<T> {
     pub value: String,
@@ -228,6 +780,10 @@ impl<T> From<String> for StringWithDefault<T> {
     }
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_vendor_config() -> Option<VendorConfig> {
     Some(VendorConfig::default())
 }
@@ -278,29 +834,83 @@ where
     deserializer.deserialize_any(VendorConfigVisitor)
 }
 
+fn deserialize_default_platform<'de, D>(deserializer: D) -> Result<DefaultPlatform, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DefaultPlatformVisitor;
+
+    impl<'de> Visitor<'de> for DefaultPlatformVisitor {
+        type Value = DefaultPlatform;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a platform name or a boolean")
+        }
+
+        fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(if value {
+                DefaultPlatform::Table
+            } else {
+                DefaultPlatform::Disabled
+            })
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            let name = PlatformName::deserialize(value.into_deserializer())?;
+            Ok(DefaultPlatform::Platform(name))
+        }
+    }
+
+    deserializer.deserialize_any(DefaultPlatformVisitor)
+}
+
 pub fn read_config(dir: &Path) -> Result<Config> {
     let reindeer_toml = dir.join("reindeer.toml");
     let mut config = try_read_config(&reindeer_toml)?;
 
     config.config_path = dir.to_path_buf();
 
+    match &config.default_platform {
+        DefaultPlatform::Table => {}
+        DefaultPlatform::Platform(name) => {
+            let Some(platform) = config.platform.get(name).filter(|_| !name.is_default()) else {
+                let message = format!("default_platform `{}` is not a configured platform", name);
+                return Err(invalid(message).into());
+            };
+            let platform = platform.clone();
+            config
+                .platform
+                .insert(PlatformName::default_platform(), platform);
+        }
+        DefaultPlatform::Disabled => {
+            config.platform.retain(|name, _| !name.is_default());
+        }
+    }
+
     if config.buck.buckfile_imports.is_default {
         // Fill in some prelude imports so Reindeer generates working targets
         // out of the box.
-        let mut buckfile_imports = String::new();
+        let loads = &mut config.buck.loads;
 
         if config.buck.buildscript_genrule.is_default {
-            buckfile_imports
-                .push_str("load(\"@prelude//rust:cargo_buildscript.bzl\", \"buildscript_run\")\n");
+            loads
+                .entry("buildscript_run".to_owned())
+                .or_insert_with(|| "@prelude//rust:cargo_buildscript.bzl".to_owned());
         }
 
         if config.buck.rust_library.is_default && config.buck.rust_binary.is_default {
-            buckfile_imports.push_str("load(\"@prelude//rust:cargo_package.bzl\", \"cargo\")\n");
+            loads
+                .entry("cargo".to_owned())
+                .or_insert_with(|| "@prelude//rust:cargo_package.bzl".to_owned());
             config.buck.rust_library = "cargo.rust_library".to_owned().into();
             config.buck.rust_binary = "cargo.rust_binary".to_owned().into();
         }
-
-        config.buck.buckfile_imports = buckfile_imports.into();
     }
 
     Ok(config)
@@ -313,13 +923,198 @@ fn try_read_config(path: &Path) -> Result<Config> {
             let empty_config = toml::Table::new();
             return Ok(Config::deserialize(empty_config).unwrap());
         }
-        Err(err) => return Err(err).context(format!("Failed to read config {}", path.display())),
+        Err(err) => {
+            return Err(err).context(invalid(format!("Failed to read config {}", path.display())))
+        }
     };
 
-    let config: Config =
-        toml::from_str(&file).context(format!("Failed to parse {}", path.display()))?;
+    let table: toml::Table =
+        toml::from_str(&file).context(invalid(format!("Failed to parse {}", path.display())))?;
+    let mut table = resolve_includes(
+        path,
+        table,
+        &mut vec![path.to_owned()],
+        &mut Vec::new(),
+        merge_config_table,
+    )?;
+    interpolate_env_table(&mut table)
+        .context(invalid(format!("Failed to parse {}", path.display())))?;
+    let resolved = toml::to_string(&table)?;
+
+    let mut config = Config::deserialize(table)
+        .context(invalid(format!("Failed to parse {}", path.display())))?;
+    config.resolved = resolved;
 
     log::debug!("Read config {:#?}", config);
 
     Ok(config)
 }
+
+/// Context for errors in the config, which end the run as config errors.
+fn invalid(message: String) -> Failure {
+    Failure::new(FailureKind::Config, message)
+}
+
+/// Layer the contents of the files named by a config's `include` list
+/// underneath it with `merge`. Includes are relative to the file mentioning
+/// them, may themselves include other files, and later ones override earlier
+/// ones.
+pub(crate) fn resolve_includes(
+    path: &Path,
+    mut table: toml::Table,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    merge: fn(&mut toml::Table, toml::Table),
+) -> Result<toml::Table> {
+    let includes: Vec<PathBuf> = match table.remove("include") {
+        None => return Ok(table),
+        Some(includes) => includes.try_into().context(invalid(format!(
+            "`include` in {} must be a list of paths",
+            path.display()
+        )))?,
+    };
+
+    let mut merged = toml::Table::new();
+    for include in includes {
+        let include = path.parent().unwrap().join(include);
+        if stack.contains(&include) {
+            let message = format!("Config {} includes itself", include.display());
+            return Err(invalid(message).into());
+        }
+
+        let file = fs::read_to_string(&include).context(invalid(format!(
+            "Failed to read config {}",
+            include.display()
+        )))?;
+        let included: toml::Table = toml::from_str(&file)
+            .context(invalid(format!("Failed to parse {}", include.display())))?;
+
+        files.push(include.clone());
+        stack.push(include.clone());
+        let included = resolve_includes(&include, included, stack, files, merge)?;
+        stack.pop();
+
+        merge(&mut merged, included);
+    }
+
+    merge(&mut merged, table);
+    Ok(merged)
+}
+
+/// Overlay `table` onto `base`. Tables are merged key by key, anything else
+/// (including arrays) is replaced wholesale.
+fn merge_config_table(base: &mut toml::Table, table: toml::Table) {
+    for (key, value) in table {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(table)) => {
+                merge_config_table(base, table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn interpolate_env_table(table: &mut toml::Table) -> Result<()> {
+    for (key, value) in table.iter_mut() {
+        interpolate_env_value(value).with_context(|| format!("in `{}`", key))?;
+    }
+    Ok(())
+}
+
+fn interpolate_env_value(value: &mut toml::Value) -> Result<()> {
+    match value {
+        toml::Value::String(string) => *string = interpolate_env(string)?,
+        toml::Value::Array(array) => {
+            for value in array {
+                interpolate_env_value(value)?;
+            }
+        }
+        toml::Value::Table(table) => interpolate_env_table(table)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` from the environment. `$${` is a
+/// literal `${`; a `$` not followed by `{` is left alone.
+fn interpolate_env(string: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = string;
+    while let Some(dollar) = rest.find('$') {
+        result.push_str(&rest[..dollar]);
+        rest = &rest[dollar..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                bail!("unterminated `${{` in {:?}", string);
+            };
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            match (env::var(name), default) {
+                (Ok(value), _) => result.push_str(&value),
+                (Err(env::VarError::NotPresent), Some(default)) => result.push_str(default),
+                (Err(err), _) => {
+                    return Err(err).context(format!("environment variable {}", name));
+                }
+            }
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::interpolate_env;
+    use super::Naming;
+
+    #[test]
+    fn test_interpolate_env() {
+        env::set_var("REINDEER_TEST_INTERPOLATE", "/opt/bin");
+        env::remove_var("REINDEER_TEST_INTERPOLATE_UNSET");
+
+        assert_eq!(
+            interpolate_env("${REINDEER_TEST_INTERPOLATE}/cargo").unwrap(),
+            "/opt/bin/cargo",
+        );
+        assert_eq!(
+            interpolate_env("${REINDEER_TEST_INTERPOLATE_UNSET:-cargo}").unwrap(),
+            "cargo",
+        );
+        assert_eq!(
+            interpolate_env("${REINDEER_TEST_INTERPOLATE:-cargo}").unwrap(),
+            "/opt/bin",
+        );
+        assert_eq!(
+            interpolate_env("$(location :x) $${HOME} $").unwrap(),
+            "$(location :x) ${HOME} $",
+        );
+        assert!(interpolate_env("${REINDEER_TEST_INTERPOLATE_UNSET}").is_err());
+        assert!(interpolate_env("${REINDEER_TEST_INTERPOLATE").is_err());
+    }
+
+    #[test]
+    fn versioned_dirs_by_naming() {
+        #[derive(serde::Deserialize)]
+        struct Table {
+            naming: Naming,
+        }
+        let naming = |toml: &str| toml::from_str::<Table>(toml).unwrap().naming;
+
+        assert!(naming("naming = \"versioned\"").versioned_dirs());
+        assert!(naming("naming = \"major\"").versioned_dirs());
+        assert!(!naming("naming = \"unversioned\"").versioned_dirs());
+    }
+}
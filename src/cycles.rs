@@ -0,0 +1,376 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Find dependency cycles among the generated rules.
+//!
+//! Cargo doesn't let packages depend on each other in a cycle, but fixups can:
+//! an `extra_deps` entry on a package which itself depends on the package with
+//! the fixup closes a loop. Buck would only report it once something builds,
+//! naming rules rather than the fixups responsible.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::iter;
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::buck::Name;
+use crate::buck::PlatformRustCommon;
+use crate::buck::Rule;
+use crate::buck::RuleRef;
+use crate::buck::RustBinary;
+use crate::buck::RustCommon;
+use crate::buck::RustLibrary;
+use crate::buck::StringOrPath;
+use crate::buck::SubtargetOrPath;
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::fixups::Fixups;
+use crate::index::Index;
+use crate::Paths;
+
+/// Fail if the rules depend on each other in a cycle, or with
+/// `break_dev_dependency_cycles`, leave out the bench and doctest rules which
+/// do.
+pub(crate) fn check_cycles(
+    config: &Config,
+    paths: &Paths,
+    index: &Index,
+    rules: &mut BTreeSet<Rule>,
+) -> Result<()> {
+    let by_name: BTreeMap<&str, &Rule> = rules
+        .iter()
+        .map(|rule| (rule.get_name().0.as_str(), rule))
+        .collect();
+    let graph: BTreeMap<&str, BTreeSet<&str>> = by_name
+        .iter()
+        .map(|(&name, rule)| (name, local_deps(rule).collect()))
+        .collect();
+
+    let is_dev_rule = |name: &str| matches!(by_name[name], Rule::Bench(_) | Rule::Doctest(_));
+    let found = break_cycles(&graph, |name| {
+        config.break_dev_dependency_cycles && is_dev_rule(name)
+    });
+
+    let mut left_out = BTreeSet::new();
+    let mut cycles = 0;
+    for (cycle, dev_rule) in found {
+        let steps: Vec<String> = cycle
+            .iter()
+            .zip(cycle.iter().cycle().skip(1))
+            .map(|(from, to)| {
+                let origin = dep_origin(config, paths, index, by_name[from], to)?;
+                Ok(format!("`{}` -> `{}` ({})", from, to, origin))
+            })
+            .collect::<Result<_>>()?;
+        let message = format!("dependency cycle: {}", steps.join(", "));
+
+        match dev_rule {
+            Some(dev_rule) => {
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!("{}; leaving out `{}`", message, dev_rule),
+                )
+                .report();
+                left_out.insert(dev_rule.to_string());
+            }
+            None => {
+                cycles += 1;
+                let mut diagnostic = Diagnostic::new(Severity::Error, message)
+                    .suggestion("remove one of the fixups which add these dependencies");
+                if cycle.iter().any(|name| is_dev_rule(name)) {
+                    diagnostic = diagnostic
+                        .suggestion("set break_dev_dependency_cycles to leave out the dev rule");
+                }
+                diagnostic.report();
+            }
+        }
+    }
+
+    rules.retain(|rule| !left_out.contains(&rule.get_name().0));
+    if cycles > 0 {
+        bail!(
+            "{} dependency cycle{} among the generated rules",
+            cycles,
+            if cycles == 1 { "" } else { "s" },
+        );
+    }
+    Ok(())
+}
+
+/// The rules in the same BUCK file which a rule refers to.
+fn local_deps(rule: &Rule) -> Box<dyn Iterator<Item = &str> + '_> {
+    fn local(rule_ref: &RuleRef) -> Option<&str> {
+        rule_ref.target.strip_prefix(':')
+    }
+    fn subtarget(src: &SubtargetOrPath) -> Option<&str> {
+        match src {
+            SubtargetOrPath::Subtarget(subtarget) => Some(&subtarget.target.0),
+            _ => None,
+        }
+    }
+
+    match rule {
+        Rule::Alias(alias) => Box::new(iter::once(alias.actual.0.as_str())),
+        Rule::BuildscriptGenrule(genrule) => {
+            Box::new(iter::once(genrule.buildscript_rule.0.as_str()))
+        }
+        Rule::BuildscriptOutputs(outputs) => {
+            Box::new(iter::once(outputs.buildscript_genrule.0.as_str()))
+        }
+        Rule::Binary(RustBinary { common, .. })
+        | Rule::BuildscriptBinary(RustBinary { common, .. })
+        | Rule::Bench(RustBinary { common, .. })
+        | Rule::Library(RustLibrary { common, .. })
+        | Rule::RootPackage(RustLibrary { common, .. })
+        | Rule::Doc(RustLibrary { common, .. })
+        | Rule::Doctest(RustLibrary { common, .. }) => {
+            let RustCommon { base, platform, .. } = common;
+            Box::new(iter::once(base).chain(platform.values()).flat_map(
+                move |plat: &PlatformRustCommon| {
+                    let deps = plat
+                        .deps
+                        .iter()
+                        .chain(plat.select_deps.values().flatten())
+                        .chain(plat.named_deps.values())
+                        .filter_map(local);
+                    let srcs = plat
+                        .mapped_srcs
+                        .keys()
+                        .chain(plat.resources.values())
+                        .filter_map(subtarget);
+                    let env = plat.env.values().flat_map(|value| match value {
+                        StringOrPath::String(value) => macro_targets(value),
+                        StringOrPath::Path(_) => Vec::new(),
+                    });
+                    deps.chain(srcs).chain(env)
+                },
+            ))
+        }
+        Rule::CxxLibrary(lib) => {
+            Box::new(lib.deps.iter().chain(&lib.exported_deps).filter_map(local))
+        }
+        _ => Box::new(iter::empty()),
+    }
+}
+
+/// Local targets of `$(location :foo[out])` and `$(exe :foo)` macros.
+fn macro_targets(value: &str) -> Vec<&str> {
+    value
+        .split("$(")
+        .skip(1)
+        .filter_map(|rest| {
+            let (_macro, arg) = rest.split_once(' ')?;
+            let target = arg.strip_prefix(':')?;
+            let end = target.find(['[', ')']).unwrap_or(target.len());
+            Some(&target[..end])
+        })
+        .collect()
+}
+
+/// The cycles in `graph`, each with the rule left out to break it, if any of
+/// its rules `can_leave_out`. Breaking the one cycle found through a group of
+/// rules can leave others through them, so the rest of the graph is searched
+/// again until no more rules are left out.
+fn break_cycles<'a>(
+    graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    can_leave_out: impl Fn(&str) -> bool,
+) -> Vec<(Vec<&'a str>, Option<&'a str>)> {
+    let mut graph = graph.clone();
+    let mut seen = BTreeSet::new();
+    let mut found = Vec::new();
+    loop {
+        let mut left_out = Vec::new();
+        for cycle in find_cycles(&graph) {
+            if !seen.insert(cycle.clone()) {
+                continue;
+            }
+            let dev_rule = cycle.iter().copied().find(|name| can_leave_out(name));
+            left_out.extend(dev_rule);
+            found.push((cycle, dev_rule));
+        }
+        if left_out.is_empty() {
+            return found;
+        }
+        for name in left_out {
+            graph.remove(name);
+        }
+    }
+}
+
+/// At least one cycle through every group of rules which depend on each
+/// other, each as the rules along it from the one which sorts first.
+fn find_cycles<'a>(graph: &BTreeMap<&'a str, BTreeSet<&'a str>>) -> Vec<Vec<&'a str>> {
+    #[derive(PartialEq)]
+    enum State {
+        OnPath,
+        Done,
+    }
+
+    let mut state: HashMap<&str, State> = HashMap::new();
+    let mut seen = BTreeSet::new();
+    let mut cycles = Vec::new();
+    for (&root, deps) in graph {
+        if state.contains_key(root) {
+            continue;
+        }
+        state.insert(root, State::OnPath);
+        let mut path = vec![root];
+        let mut stack = vec![deps.iter()];
+        while let Some(deps) = stack.last_mut() {
+            let Some(&dep) = deps.next() else {
+                stack.pop();
+                state.insert(path.pop().unwrap(), State::Done);
+                continue;
+            };
+            match state.get(dep) {
+                None => {
+                    state.insert(dep, State::OnPath);
+                    path.push(dep);
+                    stack.push(
+                        graph
+                            .get(dep)
+                            .map_or_else(Default::default, |deps| deps.iter()),
+                    );
+                }
+                Some(State::OnPath) => {
+                    let start = path.iter().position(|&name| name == dep).unwrap();
+                    let mut cycle = path[start..].to_vec();
+                    let first = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+                    cycle.rotate_left(first);
+                    if seen.insert(cycle.clone()) {
+                        cycles.push(cycle);
+                    }
+                }
+                Some(State::Done) => {}
+            }
+        }
+    }
+    cycles
+}
+
+/// Why one rule depends on another, naming the fixups responsible if any.
+fn dep_origin(
+    config: &Config,
+    paths: &Paths,
+    index: &Index,
+    from: &Rule,
+    to: &str,
+) -> Result<String> {
+    match from {
+        Rule::Alias(_) => return Ok("alias".to_owned()),
+        Rule::BuildscriptGenrule(_) | Rule::BuildscriptOutputs(_) => {
+            return Ok("build script".to_owned());
+        }
+        _ => {}
+    }
+    if let Some(pkg) = package_of(index, from.get_name()) {
+        if let Some(tgt) = pkg.targets.first() {
+            let fixups = Fixups::new(config, paths, index, pkg, tgt)?;
+            let fields = fixups.fields_adding_dep(&format!(":{}", to));
+            if !fields.is_empty() {
+                let fields: Vec<&str> = fields.into_iter().collect();
+                return Ok(format!(
+                    "{} in {}",
+                    fields.join(", "),
+                    fixups.fixup_path().display(),
+                ));
+            }
+        }
+    }
+    Ok("Cargo.toml".to_owned())
+}
+
+/// The package whose rule this is, going by the start of its name.
+fn package_of<'meta>(index: &Index<'meta>, name: &Name) -> Option<&'meta Manifest> {
+    index
+        .dependency_chains(|_| false)
+        .packages()
+        .filter(|pkg| {
+            let prefix = index.package_rule_name(pkg);
+            name.0 == prefix
+                || name.0 == index.private_rule_name(pkg).0
+                || name
+                    .0
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+        .max_by_key(|pkg| index.package_rule_name(pkg).len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::collections::BTreeSet;
+
+    use super::break_cycles;
+    use super::find_cycles;
+
+    fn graph<'a>(edges: &[(&'a str, &'a str)]) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+        let mut graph: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (from, to) in edges {
+            graph.entry(from).or_default().insert(to);
+            graph.entry(to).or_default();
+        }
+        graph
+    }
+
+    #[test]
+    fn test_no_cycles() {
+        let graph = graph(&[("a", "b"), ("b", "c"), ("a", "c")]);
+        assert!(find_cycles(&graph).is_empty());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let graph = graph(&[
+            ("a", "b"),
+            ("b", "c"),
+            ("c", "a"),
+            ("c", "d"),
+            ("d", "d"),
+            ("e", "c"),
+        ]);
+        assert_eq!(find_cycles(&graph), vec![vec!["a", "b", "c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn test_cycle_found_from_the_middle() {
+        let graph = graph(&[("a", "c"), ("c", "b"), ("b", "c")]);
+        assert_eq!(find_cycles(&graph), vec![vec!["b", "c"]]);
+    }
+
+    #[test]
+    fn test_break_cycles() {
+        // Two cycles through `a` and `d`, of which one search only finds the
+        // first.
+        let graph = graph(&[("a", "b"), ("a", "c"), ("b", "d"), ("c", "d"), ("d", "a")]);
+        assert_eq!(find_cycles(&graph), vec![vec!["a", "b", "d"]]);
+        assert_eq!(
+            break_cycles(&graph, |name| name == "b"),
+            vec![
+                (vec!["a", "b", "d"], Some("b")),
+                (vec!["a", "c", "d"], None),
+            ],
+        );
+        assert_eq!(
+            break_cycles(&graph, |name| name == "b" || name == "c"),
+            vec![
+                (vec!["a", "b", "d"], Some("b")),
+                (vec!["a", "c", "d"], Some("c")),
+            ],
+        );
+        assert_eq!(
+            break_cycles(&graph, |_| false),
+            vec![(vec!["a", "b", "d"], None)],
+        );
+    }
+}
@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Dates as days since 1970-01-01, in the proleptic Gregorian calendar, using
+//! Howard Hinnant's algorithms:
+//! http://howardhinnant.github.io/date_algorithms.html
+
+use std::env;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use anyhow::Result;
+
+/// Today, in UTC. Like other tools with reproducible output, let
+/// SOURCE_DATE_EPOCH pin it.
+pub fn today() -> Result<u64> {
+    let secs = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .parse()
+            .with_context(|| format!("SOURCE_DATE_EPOCH={:?} isn't a timestamp", epoch))?,
+        Err(_) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    Ok(secs / 86400)
+}
+
+/// `YYYY-MM-DD` of the day this many days after 1970-01-01.
+pub fn civil_date(days: u64) -> String {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Days from 1970-01-01 to the date at the start of an RFC 3339 timestamp.
+pub fn days_since_epoch(timestamp: &str) -> Option<u64> {
+    let date = timestamp.get(..10)?;
+    let mut parts = date.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    u64::try_from(era * 146097 + day_of_era - 719468).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::civil_date;
+    use super::days_since_epoch;
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(11016), "2000-02-29");
+        assert_eq!(civil_date(19783), "2024-03-01");
+    }
+
+    #[test]
+    fn test_days_since_epoch() {
+        assert_eq!(days_since_epoch("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(days_since_epoch("2000-03-01T00:00:00Z"), Some(11017));
+        assert_eq!(days_since_epoch("2023-01-15T12:34:56Z"), Some(19372));
+        assert_eq!(days_since_epoch("not a date"), None);
+        for days in [0, 11016, 19783] {
+            let timestamp = format!("{}T00:00:00Z", civil_date(days));
+            assert_eq!(days_since_epoch(&timestamp), Some(days));
+        }
+    }
+}
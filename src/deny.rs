@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Enforce the `[[deny]]` list of packages which must not be depended on.
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::config::DenyConfig;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::index::display_chain;
+use crate::index::Index;
+
+/// Fail if any denied package is reachable from a workspace member, other
+/// than through one of the packages its entry allows it via.
+pub(crate) fn check_denied(config: &Config, index: &Index) -> Result<()> {
+    let mut denied = 0;
+
+    for deny in &config.deny {
+        let chains = index.dependency_chains(|pkg| deny.allow_via.contains(&pkg.name));
+        for pkg in chains.packages().filter(|pkg| is_denied(deny, pkg)) {
+            denied += 1;
+
+            let chain = chains.chain(pkg);
+            let mut message = format!("denied package, reached through {}", display_chain(&chain));
+            if let Some(reason) = &deny.message {
+                message.push_str(": ");
+                message.push_str(reason);
+            }
+
+            let mut diagnostic = Diagnostic::new(Severity::Error, message).package(pkg);
+            if let [.., dependent, _] = chain.as_slice() {
+                diagnostic = diagnostic.suggestion(format!(
+                    "remove the dependency, or add \"{}\" to allow_via for {}",
+                    dependent.name, deny.name,
+                ));
+            }
+            diagnostic.report();
+        }
+    }
+
+    if denied > 0 {
+        bail!(
+            "{} denied package{} in the dependency graph",
+            denied,
+            if denied == 1 { "" } else { "s" },
+        );
+    }
+    Ok(())
+}
+
+fn is_denied(deny: &DenyConfig, pkg: &Manifest) -> bool {
+    pkg.name == deny.name
+        && deny
+            .version
+            .as_ref()
+            .is_none_or(|req| req.matches(&pkg.version))
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Problems found along the way, logged as they happen and also kept for
+//! writing out as JSON with `--diagnostics-json`. Also the kinds of failure
+//! which end a run, each with its own exit code.
+
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cargo::Manifest;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Something was deliberately left out, such as a skipped target
+    Info,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Package name and version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub message: String,
+    /// What the user can do about it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+static DIAGNOSTICS: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            package: None,
+            target: None,
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    pub fn package(mut self, package: &Manifest) -> Self {
+        self.package = Some(package.to_string());
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_owned());
+        self
+    }
+
+    pub fn suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// Keep the diagnostic for `write_json` without logging it.
+    pub fn record(self) {
+        DIAGNOSTICS.lock().unwrap().push(self);
+    }
+
+    /// Log the diagnostic and keep it for `write_json`.
+    pub fn report(self) {
+        let location = match (&self.package, &self.target) {
+            (Some(package), Some(target)) => format!("pkg {} target {}: ", package, target),
+            (Some(package), None) => format!("pkg {}: ", package),
+            (None, _) => String::new(),
+        };
+        let hint = match &self.suggestion {
+            Some(suggestion) => format!("\n  hint: {}", suggestion),
+            None => String::new(),
+        };
+        match self.severity {
+            Severity::Error => log::error!("{}{}{}", location, self.message, hint),
+            Severity::Warning => log::warn!("{}{}{}", location, self.message, hint),
+            Severity::Info => log::info!("{}{}{}", location, self.message, hint),
+        }
+        self.record();
+    }
+}
+
+/// Write out every diagnostic reported so far.
+pub fn write_json(path: &Path) -> Result<()> {
+    let diagnostics = DIAGNOSTICS.lock().unwrap();
+    let json = serde_json::to_string_pretty(&*diagnostics)?;
+    fs::write(path, json).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Why a run failed, for wrappers which need to tell failures apart by exit
+/// code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureKind {
+    /// Anything not covered below, including bugs in Reindeer
+    Internal,
+    /// reindeer.toml or a fixups.toml can't be read or is invalid
+    Config,
+    /// Build scripts with no fixups, with `unresolved_fixup_error` set
+    UnresolvedFixups,
+    /// Vendoring the sources failed
+    Vendor,
+    /// A Cargo command failed
+    Cargo,
+    /// `lint` found problems, or `status` found the BUCK file out of date
+    Check,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Internal => 1,
+            FailureKind::Config => 2,
+            FailureKind::UnresolvedFixups => 3,
+            FailureKind::Vendor => 4,
+            FailureKind::Cargo => 5,
+            FailureKind::Check => 6,
+        }
+    }
+
+    /// The kind of an error, decided by the outermost `Failure` in its chain.
+    pub fn of(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<Failure>() {
+            Some(failure) => failure.kind,
+            None => FailureKind::Internal,
+        }
+    }
+}
+
+/// An error, or context added to one, which says what kind of failure it is.
+#[derive(Debug)]
+pub struct Failure {
+    kind: FailureKind,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Failure {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// How the error which ended a run is printed.
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorFormat {
+    Human,
+    /// A JSON object on stderr with the failure's `kind`, `exit_code`,
+    /// `message` and `causes`
+    Json,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => anyhow::bail!("unknown error format `{}`, expected human or json", s),
+        }
+    }
+}
+
+/// Report the error which ended a run, returning the exit code to end with.
+pub fn report_failure(err: &anyhow::Error, format: ErrorFormat) -> i32 {
+    let kind = FailureKind::of(err);
+    match format {
+        ErrorFormat::Human => Diagnostic::new(Severity::Error, format!("{:?}", err)).report(),
+        ErrorFormat::Json => {
+            #[derive(Serialize)]
+            struct Output {
+                kind: FailureKind,
+                exit_code: i32,
+                message: String,
+                causes: Vec<String>,
+            }
+            let output = Output {
+                kind,
+                exit_code: kind.exit_code(),
+                message: err.to_string(),
+                causes: err.chain().skip(1).map(ToString::to_string).collect(),
+            };
+            eprintln!("{}", serde_json::to_string(&output).unwrap());
+            Diagnostic::new(Severity::Error, format!("{:?}", err)).record();
+        }
+    }
+    kind.exit_code()
+}
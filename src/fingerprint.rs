@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A hash of the files in the third-party directory which buckify's output
+//! depends on. Put in the BUCK file's header with `{fingerprint}`, it lets
+//! `reindeer status` tell whether the BUCK file is stale without running Cargo
+//! or generating any rules.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use walkdir::WalkDir;
+
+use crate::buckify::relative_path;
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::fixups::fixup_dirs;
+use crate::Args;
+use crate::Paths;
+
+/// Placeholder in `generated_file_header` for the fingerprint.
+pub(crate) const PLACEHOLDER: &str = "{fingerprint}";
+
+/// Hash of the version of Reindeer, the config as it's read, after includes
+/// and environment variables, the workspaces' Cargo.toml and Cargo.lock, and
+/// the fixups. Only fixups in the packages' own directories need the
+/// `packages`.
+pub(crate) fn fingerprint<'a>(
+    config: &Config,
+    paths: &Paths,
+    packages: impl IntoIterator<Item = &'a Manifest>,
+) -> Result<String> {
+    let mut inputs: Vec<PathBuf> = vec![paths.manifest_path.clone()];
+    inputs.push(paths.lockfile_path.clone());
+    for manifest_path in &config.workspaces {
+        let manifest_path = paths.third_party_dir.join(manifest_path);
+        inputs.push(manifest_path.with_file_name("Cargo.lock"));
+        inputs.push(manifest_path);
+    }
+
+    // Everywhere fixups are looked for, whole directories at a time so that
+    // fixups for a new package count too.
+    let mut fixups_dirs: BTreeSet<PathBuf> = ["fixups".as_ref()]
+        .into_iter()
+        .chain(config.extra_fixup_dirs.iter().map(PathBuf::as_path))
+        .chain(config.fixup_templates_dir.as_deref())
+        .map(|dir| paths.third_party_dir.join(dir))
+        .collect();
+    for package in packages {
+        for dir in fixup_dirs(config, paths, package) {
+            if !fixups_dirs
+                .iter()
+                .any(|fixups_dir| dir.starts_with(fixups_dir))
+            {
+                fixups_dirs.insert(dir);
+            }
+        }
+    }
+    for fixups_dir in fixups_dirs {
+        for entry in WalkDir::new(&fixups_dir).sort_by_file_name() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) if err.io_error().map(|err| err.kind()) == Some(ErrorKind::NotFound) => {
+                    break;
+                }
+                Err(err) => return Err(err).context(format!("reading {}", fixups_dir.display())),
+            };
+            if entry.file_type().is_file() {
+                inputs.push(entry.into_path());
+            }
+        }
+    }
+
+    let mut content = env!("CARGO_PKG_VERSION").as_bytes().to_vec();
+    content.push(0);
+    content.extend(config.resolved.bytes());
+    // Overrides can come from the command line as well as the config.
+    for (name, path) in &config.overrides {
+        content.push(0);
+        content.extend(format!("{}={}", name, path.display()).bytes());
+    }
+    for path in inputs {
+        // Paths are part of the hash so that moving a fixup from one package
+        // to another counts as a change.
+        content.push(0);
+        content.extend(
+            relative_path(&paths.third_party_dir, &path)
+                .to_string_lossy()
+                .bytes(),
+        );
+        content.push(0);
+        match fs::read(&path) {
+            Ok(file) => content.extend(file),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(format!("reading {}", path.display())),
+        }
+    }
+
+    Ok(format!("{:016x}", cargo::fnv_hash(&content)))
+}
+
+/// Check whether the BUCK file was generated from the current inputs, by
+/// looking for their fingerprint in its header.
+pub(crate) fn status(config: &Config, args: &Args, paths: &Paths) -> Result<()> {
+    // Cargo knows where the packages are, for fixups in their directories.
+    let packages = if config.package_fixups_dir.is_some() {
+        let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+        metadata.packages
+    } else {
+        BTreeSet::new()
+    };
+    check_status(config, paths, &packages)
+}
+
+fn check_status(config: &Config, paths: &Paths, packages: &BTreeSet<Manifest>) -> Result<()> {
+    if !config.buck.generated_file_header.contains(PLACEHOLDER) {
+        return Err(Failure::new(
+            FailureKind::Config,
+            format!(
+                "`generated_file_header` doesn't include `{}`, so there's nothing to check",
+                PLACEHOLDER,
+            ),
+        )
+        .into());
+    }
+
+    let buckpath = paths.third_party_dir.join(&config.buck.file_name);
+    let buckfile = match fs::read_to_string(&buckpath) {
+        Ok(buckfile) => buckfile,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(Failure::new(
+                FailureKind::Check,
+                format!(
+                    "{} doesn't exist, run `reindeer buckify`",
+                    buckpath.display()
+                ),
+            )
+            .into());
+        }
+        Err(err) => return Err(err).context(format!("reading {}", buckpath.display())),
+    };
+
+    let fingerprint = fingerprint(config, paths, packages)?;
+    let up_to_date = buckfile
+        .lines()
+        .take_while(|line| line.is_empty() || line.starts_with('#'))
+        .any(|line| line.contains(&fingerprint));
+    if !up_to_date {
+        return Err(Failure::new(
+            FailureKind::Check,
+            format!(
+                "{} is out of date, run `reindeer buckify`",
+                buckpath.display()
+            ),
+        )
+        .into());
+    }
+
+    println!("{} is up to date", buckpath.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::fs;
+
+    use super::check_status;
+    use super::fingerprint;
+    use crate::cargo::Manifest;
+    use crate::config::read_config;
+    use crate::diagnostics::FailureKind;
+    use crate::Paths;
+
+    #[test]
+    fn status_sees_package_fixups() {
+        let dir = tempfile::tempdir().unwrap();
+        let third_party_dir = dir.path().to_owned();
+        fs::write(
+            third_party_dir.join("reindeer.toml"),
+            "package_fixups_dir = \"reindeer\"\n\
+            [buck]\n\
+            generated_file_header = \"# {fingerprint}\"\n",
+        )
+        .unwrap();
+        let package_fixups = third_party_dir.join("vendor/foo-1.0.0/reindeer");
+        fs::create_dir_all(&package_fixups).unwrap();
+        fs::write(package_fixups.join("fixups.toml"), "cfgs = [\"a\"]\n").unwrap();
+
+        let config = read_config(&third_party_dir).unwrap();
+        let paths = Paths {
+            manifest_path: third_party_dir.join("Cargo.toml"),
+            lockfile_path: third_party_dir.join("Cargo.lock"),
+            cargo_home: third_party_dir.join(".cargo"),
+            third_party_dir: third_party_dir.clone(),
+        };
+        let package: Manifest = serde_json::from_value(serde_json::json!({
+            "name": "foo",
+            "version": "1.0.0",
+            "id": "registry+https://github.com/rust-lang/crates.io-index#foo@1.0.0",
+            "license": null,
+            "license_file": null,
+            "description": null,
+            "source": "registry+https://github.com/rust-lang/crates.io-index",
+            "dependencies": [],
+            "targets": [],
+            "features": {},
+            "manifest_path": third_party_dir.join("vendor/foo-1.0.0/Cargo.toml"),
+            "metadata": null,
+            "authors": [],
+            "categories": [],
+            "keywords": [],
+            "readme": null,
+            "repository": null,
+            "edition": "2021",
+            "links": null,
+            "publish": null,
+        }))
+        .unwrap();
+        let packages = BTreeSet::from([package]);
+
+        let header = format!("# {}\n", fingerprint(&config, &paths, &packages).unwrap());
+        fs::write(third_party_dir.join("BUCK"), header).unwrap();
+        check_status(&config, &paths, &packages).unwrap();
+
+        fs::write(package_fixups.join("fixups.toml"), "cfgs = [\"b\"]\n").unwrap();
+        let err = check_status(&config, &paths, &packages).unwrap_err();
+        assert!(err.to_string().contains("out of date"), "{}", err);
+        assert_eq!(FailureKind::of(&err), FailureKind::Check);
+    }
+
+    #[test]
+    fn hashes_interpolated_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let third_party_dir = dir.path().to_owned();
+        fs::write(
+            third_party_dir.join("reindeer.toml"),
+            "[cargo]\ncargo = \"${REINDEER_TEST_FINGERPRINT_CARGO}\"\n",
+        )
+        .unwrap();
+        let paths = Paths {
+            manifest_path: third_party_dir.join("Cargo.toml"),
+            lockfile_path: third_party_dir.join("Cargo.lock"),
+            cargo_home: third_party_dir.join(".cargo"),
+            third_party_dir: third_party_dir.clone(),
+        };
+        let fingerprint_with = |cargo: &str| {
+            env::set_var("REINDEER_TEST_FINGERPRINT_CARGO", cargo);
+            let config = read_config(&third_party_dir).unwrap();
+            fingerprint(&config, &paths, []).unwrap()
+        };
+
+        let before = fingerprint_with("/opt/a/cargo");
+        assert_eq!(before, fingerprint_with("/opt/a/cargo"));
+        assert_ne!(before, fingerprint_with("/opt/b/cargo"));
+    }
+}
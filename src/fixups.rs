@@ -13,6 +13,7 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
 use std::fs;
+use std::iter;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -20,12 +21,16 @@ use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use walkdir::WalkDir;
 
 use crate::buck;
 use crate::buck::Alias;
 use crate::buck::BuckPath;
 use crate::buck::BuildscriptGenrule;
+use crate::buck::BuildscriptOutputs;
+use crate::buck::Comments;
 use crate::buck::Common;
+use crate::buck::CxxSrc;
 use crate::buck::Name;
 use crate::buck::Rule;
 use crate::buck::RuleRef;
@@ -35,35 +40,121 @@ use crate::buck::Subtarget;
 use crate::buck::SubtargetOrPath;
 use crate::buck::Visibility;
 use crate::buckify::normalize_dotdot;
+use crate::buckify::path_dependency_filegroup;
 use crate::buckify::relative_path;
 use crate::buckify::short_name_for_git_repo;
+use crate::cargo::CrateType;
 use crate::cargo::Manifest;
 use crate::cargo::ManifestTarget;
 use crate::cargo::NodeDepKind;
 use crate::cargo::Source;
 use crate::collection::SetOrMap;
 use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::diagnostics::Severity;
+use crate::glob::closest_paths;
 use crate::glob::Globs;
 use crate::glob::SerializableGlobSet as GlobSet;
 use crate::glob::NO_EXCLUDE;
 use crate::index::Index;
 use crate::index::ResolvedDep;
 use crate::platform::platform_names_for_expr;
+use crate::platform::PlatformConfig;
 use crate::platform::PlatformExpr;
+use crate::platform::PlatformName;
 use crate::platform::PlatformPredicate;
+use crate::platform_targets;
 use crate::Paths;
 
-mod buildscript;
-mod config;
+pub(crate) mod buildscript;
+pub(crate) mod config;
+pub(crate) mod staging;
 
 use buildscript::BuildscriptFixup;
 use buildscript::CxxLibraryFixup;
 use buildscript::GenSrcs;
 use buildscript::PrebuiltCxxLibraryFixup;
+use buildscript::Prerun;
 use buildscript::RustcFlags;
 use config::CargoEnv;
+use config::CargoEnvs;
 use config::FixupConfigFile;
 
+/// A reference in a fixup to another rule's output, like `:foo[bar]` or
+/// `//cell/pkg:tgt[out]`, rather than to a file in the package.
+fn rule_output(value: &str) -> Option<SubtargetOrPath> {
+    let (target, relative) = value.strip_suffix(']')?.split_once('[')?;
+    if let Some(name) = target.strip_prefix(':') {
+        Some(SubtargetOrPath::Subtarget(Subtarget {
+            target: Name(name.to_owned()),
+            relative: BuckPath(PathBuf::from(relative)),
+        }))
+    } else if target.contains("//") && target.contains(':') {
+        Some(SubtargetOrPath::Label(value.to_owned()))
+    } else {
+        None
+    }
+}
+
+/// A library file from a `prebuilt_cxx_library` fixup, with the import
+/// library which goes with it if it's a shared library on Windows.
+struct PrebuiltLib {
+    path: PathBuf,
+    shared: bool,
+    import_lib: Option<PathBuf>,
+}
+
+/// Where a package's fixups might be, from the package's own to the most
+/// widely shared
+pub(crate) fn fixup_dirs(config: &Config, paths: &Paths, package: &Manifest) -> Vec<PathBuf> {
+    let mut fixup_dirs = vec![paths.third_party_dir.join("fixups").join(&package.name)];
+    // Next to the package's sources, when those are in the repository.
+    if let Some(dir) = &config.package_fixups_dir {
+        if config.vendor.is_some() || package.source == Source::Local {
+            fixup_dirs.push(package.manifest_dir().join(dir));
+        }
+    }
+    for dir in &config.extra_fixup_dirs {
+        fixup_dirs.push(paths.third_party_dir.join(dir).join(&package.name));
+    }
+    // Fixups still being worked on in the staging directory apply until
+    // they've been accepted.
+    if let Some(dir) = &config.fixup_templates_dir {
+        fixup_dirs.push(paths.third_party_dir.join(dir).join(&package.name));
+    }
+    fixup_dirs
+}
+
+/// The directories of `fixup_dirs` with a fixups.toml in them, whose fixups
+/// all apply, each going on top of the ones after it. Templates still in the
+/// staging directory only apply to packages without other fixups.
+pub(crate) fn fixup_layers(config: &Config, paths: &Paths, package: &Manifest) -> Vec<PathBuf> {
+    let mut layers: Vec<PathBuf> = Vec::new();
+    for dir in fixup_dirs(config, paths, package) {
+        if dir.join("fixups.toml").exists() && !layers.contains(&dir) {
+            layers.push(dir);
+        }
+    }
+    if let Some(dir) = &config.fixup_templates_dir {
+        let staged = paths.third_party_dir.join(dir).join(&package.name);
+        if layers.len() > 1 && layers.last() == Some(&staged) {
+            layers.pop();
+        }
+    }
+    layers
+}
+
+/// The directory of the fixups.toml which goes on top of a package's others,
+/// or `fixups/<package>` if there are none yet.
+pub(crate) fn primary_fixup_dir(config: &Config, paths: &Paths, package: &Manifest) -> PathBuf {
+    fixup_layers(config, paths, package)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| paths.third_party_dir.join("fixups").join(&package.name))
+}
+
 /// Fixups for a specific package & target
 pub struct Fixups<'meta> {
     config: &'meta Config,
@@ -98,16 +189,31 @@ impl<'meta> Fixups<'meta> {
         package: &'meta Manifest,
         target: &'meta ManifestTarget,
     ) -> Result<Self> {
-        let fixup_dir = paths.third_party_dir.join("fixups").join(&package.name);
+        let central_dir = paths.third_party_dir.join("fixups").join(&package.name);
+        // New templates go to the staging directory, if there is one.
+        let template_dir = match &config.fixup_templates_dir {
+            Some(dir) => paths.third_party_dir.join(dir).join(&package.name),
+            None => central_dir.clone(),
+        };
+        // Paths in all of the fixups are relative to the topmost directory,
+        // as they are in files the fixups.toml includes.
+        let layers = fixup_layers(config, paths, package);
+        let fixup_dir = layers.first().cloned().unwrap_or(central_dir);
         let fixup_path = fixup_dir.join("fixups.toml");
 
-        let fixup_config: FixupConfigFile = if let Ok(file) = fs::read_to_string(&fixup_path) {
-            log::debug!("read fixups from {}", fixup_path.display());
-            toml::from_str(&file).context(format!("Failed to parse {}", fixup_path.display()))?
+        let fixup_config: FixupConfigFile = if !layers.is_empty() {
+            let fixup_paths: Vec<PathBuf> =
+                layers.iter().map(|dir| dir.join("fixups.toml")).collect();
+            log::debug!("read fixups from {:?}", fixup_paths);
+            FixupConfigFile::parse_layers(&fixup_paths).context(Failure::new(
+                FailureKind::Config,
+                format!("Failed to parse the fixups of {}", package),
+            ))?
         } else {
             log::debug!("no fixups at {}", fixup_path.display());
             let fixup = FixupConfigFile::template(&paths.third_party_dir, target);
             if config.fixup_templates && target.kind_custom_build() {
+                let fixup_path = template_dir.join("fixups.toml");
                 log::info!(
                     "Writing template for {} to {}",
                     package,
@@ -157,7 +263,7 @@ impl<'meta> Fixups<'meta> {
             (Some([]), None) | (None, None) => false,
             (Some(tgts), _) => tgts.iter().any(|(kind, name)| {
                 self.target.kind.contains(kind)
-                    && name.as_ref().map_or(true, |name| &self.target.name == name)
+                    && name.as_ref().is_none_or(|name| &self.target.name == name)
             }),
         }
     }
@@ -180,19 +286,239 @@ impl<'meta> Fixups<'meta> {
         }
     }
 
+    /// The package's license files, by their path within the package.
+    pub fn license_srcs(&self) -> Result<BTreeMap<String, SubtargetOrPath>> {
+        let mut srcs = BTreeMap::new();
+        if self.config.vendor.is_none() && self.package.source != Source::CratesIo {
+            // Only an http_archive can expose the files it contains.
+            return Ok(srcs);
+        }
+        let mut license_globs = Globs::new(&self.config.license_patterns, NO_EXCLUDE)?;
+        let license_file = self.package.license_file.clone();
+        for path in license_globs.walk(self.manifest_dir).chain(license_file) {
+            let src = self.subtarget_or_path(&path);
+            srcs.insert(BuckPath(path).to_string(), src);
+        }
+        Ok(srcs)
+    }
+
     pub fn public_visibility(&self) -> Visibility {
         match self.fixup_config.custom_visibility.as_deref() {
             Some(visibility) => Visibility::Custom(visibility.to_vec()),
-            None => Visibility::Public,
+            None => match self.config.visibility.get(&self.package.name) {
+                Some(visibility) => Visibility::Custom(visibility.to_vec()),
+                None => match &self.config.buck.public_visibility {
+                    Some(visibility) => Visibility::Custom(visibility.clone()),
+                    None => Visibility::Public,
+                },
+            },
+        }
+    }
+
+    /// Visibility of the package's library rule, which is normally only used
+    /// through its alias.
+    pub fn library_visibility(&self) -> Visibility {
+        match self.config.visibility.get(&self.package.name) {
+            Some(visibility) => Visibility::Custom(visibility.to_vec()),
+            None => Visibility::private(&self.config.buck),
         }
     }
 
+    /// Extra entries for the package in the metadata file
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.fixup_config.metadata
+    }
+
+    /// Owners of the package, if they differ from the default ones
+    pub fn owners(&self) -> Option<&[String]> {
+        self.fixup_config.owners.as_deref()
+    }
+
     pub fn python_ext(&self) -> Option<&str> {
         self.fixup_config.python_ext.as_deref()
     }
 
+    /// Crate types for the target, taking any fixup override into account.
+    /// The override only applies to the library target.
+    pub fn crate_types(&self) -> &BTreeSet<CrateType> {
+        let is_lib_target =
+            self.target.kind_lib() || self.target.kind_cdylib() || self.target.kind_staticlib();
+        match &self.fixup_config.crate_types {
+            Some(crate_types) if is_lib_target => crate_types,
+            _ => &self.target.crate_types,
+        }
+    }
+
+    /// Whether any of the package's examples are to be generated
+    pub fn has_examples(&self) -> bool {
+        !self.fixup_config.examples.is_empty()
+    }
+
+    /// Whether to generate a rule for this example, by being listed in the
+    /// package's `examples` fixup.
+    pub fn include_example(&self) -> bool {
+        self.fixup_config.examples.contains(&self.target.name)
+    }
+
+    /// Whether to generate a rule for this binary as one of the package's
+    /// binaries, rather than because something depends on it.
+    pub fn include_bin(&self) -> bool {
+        match &self.fixup_config.bins {
+            Some(bins) => bins.contains(&self.target.name),
+            None => self.config.include_bins,
+        }
+    }
+
     pub fn omit_target(&self) -> bool {
-        self.fixup_config.omit_targets.contains(&self.target.name)
+        self.fixup_config
+            .base(&self.package.version)
+            .is_some_and(|base| base.omit_targets.contains(&self.target.name))
+    }
+
+    /// How the package's build script is dealt with, by the kinds of its
+    /// `buildscript` fixups for any platform, or `omitted` if it isn't at all.
+    /// None if there's no build script.
+    pub fn buildscript_handling(&self) -> Option<BTreeSet<&'static str>> {
+        let buildscript = self.buildscript_target()?;
+        let mut kinds: BTreeSet<&str> = self
+            .fixup_config
+            .configs(&self.package.version)
+            .flat_map(|(_platform, fixup)| fixup.buildscript.iter())
+            .map(BuildscriptFixup::kind)
+            .collect();
+        let omitted = self
+            .fixup_config
+            .base(&self.package.version)
+            .is_some_and(|base| base.omit_targets.contains(&buildscript.name));
+        if omitted || kinds.is_empty() {
+            kinds = BTreeSet::from(["omitted"]);
+        }
+        Some(kinds)
+    }
+
+    /// The target to use instead of the package's library, if any
+    pub fn replace_with(&self) -> Option<&str> {
+        self.fixup_config.replace_with.as_deref()
+    }
+
+    /// Fail if the package's version isn't one its fixups expect.
+    pub fn check_expected_version(&self) -> Result<()> {
+        if let Some(req) = &self.fixup_config.expect_version {
+            if !req.matches(&self.package.version) {
+                bail!(
+                    "{} is locked to {}, but its fixups expect `{}`",
+                    self.package.name,
+                    self.package.version,
+                    req,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Comments for the target's rule saying which fixups file it comes
+    /// from, and which attributes have fixups in them, if `fixup_comments`
+    /// asks for them.
+    pub fn comments(&self) -> Comments {
+        let fixup_path = self.fixup_dir.join("fixups.toml");
+        if !self.config.buck.fixup_comments || !fixup_path.exists() {
+            return Comments::default();
+        }
+
+        let mut fields: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (_platform, fixup) in self.fixup_config.configs(&self.package.version) {
+            let mut add = |attr, field, present| {
+                if present {
+                    fields.entry(attr).or_default().insert(field);
+                }
+            };
+            add("deps", "extra_deps", !fixup.extra_deps.is_empty());
+            add("deps", "omit_deps", !fixup.omit_deps.is_empty());
+            add("deps", "select_deps", !fixup.select_deps.is_empty());
+            add(
+                "named_deps",
+                "extra_named_deps",
+                !fixup.extra_named_deps.is_empty(),
+            );
+            add("rustc_flags", "rustc_flags", !fixup.rustc_flags.is_empty());
+            add("rustc_flags", "cfgs", !fixup.cfgs.is_empty());
+            add("features", "features", !fixup.features.is_empty());
+            add("features", "omit_features", !fixup.omit_features.is_empty());
+            add("env", "env", !fixup.env.is_empty());
+            add("srcs", "extra_srcs", !fixup.extra_srcs.is_empty());
+            add(
+                "mapped_srcs",
+                "extra_mapped_srcs",
+                !fixup.extra_mapped_srcs.is_empty(),
+            );
+        }
+
+        Comments {
+            rule: vec![format!("fixups: {}", BuckPath(self.fixup_path()))],
+            attrs: fields
+                .into_iter()
+                .map(|(attr, fields)| {
+                    let fields: Vec<&str> = fields.into_iter().collect();
+                    (
+                        attr.to_owned(),
+                        vec![format!("with {} from fixups", fields.join(", "))],
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The package's fixups.toml, relative to the third-party directory,
+    /// whether or not there is one
+    pub fn fixup_path(&self) -> PathBuf {
+        relative_path(&self.third_party_dir, &self.fixup_dir.join("fixups.toml"))
+    }
+
+    /// Which fields of the fixups, for any platform, add a dependency on
+    /// `label`
+    pub fn fields_adding_dep(&self, label: &str) -> BTreeSet<&'static str> {
+        let mut fields = BTreeSet::new();
+        for (_platform, fixup) in self.fixup_config.configs(&self.package.version) {
+            if fixup.extra_deps.contains(label) {
+                fields.insert("extra_deps");
+            }
+            if fixup.extra_named_deps.values().any(|dep| dep == label) {
+                fields.insert("extra_named_deps");
+            }
+            if fixup.select_deps.values().any(|deps| deps.contains(label)) {
+                fields.insert("select_deps");
+            }
+        }
+        fields
+    }
+
+    /// Which of `platforms` a `platform_fixup` omits the target on
+    pub fn omit_target_platforms<'a>(
+        &self,
+        platforms: &BTreeSet<&'a PlatformName>,
+    ) -> Result<BTreeSet<&'a PlatformName>> {
+        let mut omitted = BTreeSet::new();
+        for (expr, fixup) in self.fixup_config.platform_configs(&self.package.version) {
+            if !fixup.omit_targets.contains(&self.target.name) {
+                continue;
+            }
+            let pred = PlatformPredicate::parse(expr)
+                .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
+            omitted.extend(
+                platforms
+                    .iter()
+                    .filter(|name| pred.eval(&self.config.platform[**name])),
+            );
+        }
+        Ok(omitted)
+    }
+
+    /// Whether an `overlay` replaces some of the package's files, on any
+    /// platform.
+    pub fn has_overlay(&self) -> bool {
+        self.fixup_config
+            .configs(&self.package.version)
+            .any(|(_platform, config)| config.overlay.is_some())
     }
 
     pub fn precise_srcs(&self) -> bool {
@@ -224,9 +550,21 @@ impl<'meta> Fixups<'meta> {
         name
     }
 
+    /// The genrule with the outputs `gen_srcs` declares, each a subtarget
+    pub fn buildscript_outputs_name(&self) -> Name {
+        let mut name = self.buildscript_genrule_name();
+        name.0.push_str("-outs");
+        name
+    }
+
     fn buildscript_rule_name(&self) -> Option<Name> {
-        self.buildscript_target()
-            .map(|tgt| Name(format!("{}-{}", self.package, tgt.name)))
+        self.buildscript_target().map(|tgt| {
+            Name(format!(
+                "{}-{}",
+                self.index.package_rule_name(self.package),
+                tgt.name
+            ))
+        })
     }
 
     /// Return buildscript-related rules
@@ -234,7 +572,7 @@ impl<'meta> Fixups<'meta> {
     /// dependencies referencing them are conditional).
     pub fn emit_buildscript_rules(
         &self,
-        buildscript: RustBinary,
+        mut buildscript: RustBinary,
         config: &'meta Config,
     ) -> Result<Vec<Rule>> {
         let mut res = Vec::new();
@@ -243,10 +581,13 @@ impl<'meta> Fixups<'meta> {
 
         let buildscript_rule_name = match self.buildscript_rule_name() {
             None => {
-                log::warn!(
-                    "Package {} doesn't have a build script to fix up",
-                    self.package
-                );
+                Diagnostic::new(Severity::Warning, "no build script to fix up")
+                    .package(self.package)
+                    .suggestion(format!(
+                        "remove `buildscript` from {}/fixups.toml",
+                        rel_fixup.display(),
+                    ))
+                    .report();
                 return Ok(res);
             }
             Some(name) => name,
@@ -276,7 +617,25 @@ impl<'meta> Fixups<'meta> {
             .configs(&self.package.version)
             .flat_map(|(_platform, fixup)| fixup.buildscript.iter());
 
+        // Binaries from artifact build-dependencies are looked up by the build
+        // script at runtime, not compile time. Like the features, those of
+        // platform-specific build-dependencies are merged in, since the
+        // genrule has no per-platform attributes.
+        let artifact_env: BTreeMap<String, String> = iter::once(&buildscript.common.base)
+            .chain(buildscript.common.platform.values())
+            .flat_map(|plat| &plat.env)
+            .filter(|(k, _)| k.starts_with("CARGO_BIN_FILE_"))
+            .filter_map(|(k, v)| match v {
+                StringOrPath::String(v) => Some((k.clone(), v.clone())),
+                StringOrPath::Path(_) => None,
+            })
+            .collect();
+
+        let exec_compatible_with = platform_targets::exec_compatible_with(config)?;
+        buildscript.exec_compatible_with = exec_compatible_with.clone();
+
         let mut buildscript_run = None;
+        let mut outs = BTreeSet::new();
         let default_genrule = || BuildscriptGenrule {
             name: self.buildscript_genrule_name(),
             buildscript_rule: buildscript_rule_name.clone(),
@@ -284,9 +643,10 @@ impl<'meta> Fixups<'meta> {
             version: self.package.version.clone(),
             features: features.clone(),
             cfgs: Vec::new(),
-            env: BTreeMap::new(),
+            env: artifact_env.clone(),
             path_env: BTreeMap::new(),
             args_env: BTreeMap::new(),
+            exec_compatible_with: exec_compatible_with.clone(),
         };
 
         for fix in fixes {
@@ -329,9 +689,11 @@ impl<'meta> Fixups<'meta> {
                 // Generated source files - given a list, set up rules to extract them from
                 // the buildscript.
                 BuildscriptFixup::GenSrcs(GenSrcs {
-                    env,      // env set while running
-                    path_env, // env pointing to pathnames set while running
-                    args_env, // space-separated args like CFLAGS
+                    env,             // env set while running
+                    path_env,        // env pointing to pathnames set while running
+                    args_env,        // space-separated args like CFLAGS
+                    outputs,         // declared files generated into OUT_DIR
+                    runtime_outputs, // declared files needed at runtime
                     ..
                 }) => {
                     // Emit the build script itself
@@ -342,6 +704,8 @@ impl<'meta> Fixups<'meta> {
                     buildscript_run.env.extend(env.clone());
                     buildscript_run.path_env.extend(path_env.clone());
                     buildscript_run.args_env.extend(args_env.clone());
+                    outs.extend(outputs.iter().cloned());
+                    outs.extend(runtime_outputs.iter().cloned());
                 }
 
                 // Emit a C++ library build rule (elsewhere - add a dependency to it)
@@ -356,9 +720,15 @@ impl<'meta> Fixups<'meta> {
                     exclude,
                     compiler_flags,
                     preprocessor_flags,
+                    exported_preprocessor_flags,
+                    raw_headers,
                     header_namespace,
+                    soname,
                     deps,
+                    exported_deps,
                     compatible_with,
+                    platform_srcs,
+                    src_flags,
                     ..
                 }) => {
                     let actual = Name(format!(
@@ -380,29 +750,25 @@ impl<'meta> Fixups<'meta> {
                         res.push(rule);
                     }
 
-                    let rule = buck::CxxLibrary {
+                    let src_flags = src_flags
+                        .iter()
+                        .map(|(glob, flags)| Ok((Globs::new([glob], NO_EXCLUDE)?, flags)))
+                        .collect::<Result<Vec<_>>>()
+                        .context("C++ source flags")?;
+                    let mut rule = buck::CxxLibrary {
                         common: Common {
                             name: actual,
-                            visibility: Visibility::Private,
+                            visibility: Visibility::private(&self.config.buck),
                             licenses: Default::default(),
                             compatible_with: compatible_with
                                 .iter()
                                 .cloned()
                                 .map(RuleRef::new)
                                 .collect(),
+                            comments: Comments::default(),
                         },
                         // Just collect the sources, excluding things in the exclude list
-                        srcs: {
-                            let mut globs = Globs::new(srcs, exclude).context("C++ sources")?;
-                            let srcs = globs
-                                .walk(self.manifest_dir)
-                                .map(|path| self.subtarget_or_path(&path))
-                                .collect();
-                            if self.config.strict_globs {
-                                globs.check_all_globs_used()?;
-                            }
-                            srcs
-                        },
+                        srcs: self.cxx_srcs("cxx_library srcs", srcs, exclude, &src_flags)?,
                         // Collect the nominated headers, plus everything in the fixup include
                         // path(s).
                         headers: {
@@ -433,7 +799,11 @@ impl<'meta> Fixups<'meta> {
                                     .map(|path| self.subtarget_or_path(&path))
                                     .collect();
                                 if self.config.strict_globs {
-                                    exported_header_globs.check_all_globs_used()?;
+                                    self.report_unmatched_globs(
+                                        "cxx_library exported_headers",
+                                        &exported_header_globs.unmatched(),
+                                        self.manifest_dir,
+                                    )?;
                                 }
                                 SetOrMap::Set(exported_headers)
                             }
@@ -457,30 +827,69 @@ impl<'meta> Fixups<'meta> {
                             .collect(),
                         compiler_flags: compiler_flags.clone(),
                         preprocessor_flags: preprocessor_flags.clone(),
+                        exported_preprocessor_flags: exported_preprocessor_flags.clone(),
+                        raw_headers: {
+                            let mut globs =
+                                Globs::new(raw_headers, exclude).context("C++ raw headers")?;
+                            let raw_headers = globs
+                                .walk(self.manifest_dir)
+                                .map(|path| self.subtarget_or_path(&path))
+                                .collect();
+                            if self.config.strict_globs {
+                                self.report_unmatched_globs(
+                                    "cxx_library raw_headers",
+                                    &globs.unmatched(),
+                                    self.manifest_dir,
+                                )?;
+                            }
+                            raw_headers
+                        },
                         header_namespace: header_namespace.clone(),
+                        soname: soname.clone(),
                         deps: deps.iter().cloned().map(RuleRef::new).collect(),
+                        exported_deps: exported_deps.iter().cloned().map(RuleRef::new).collect(),
                         preferred_linkage: Some("static".to_string()),
+                        platform: BTreeMap::new(),
                     };
 
+                    for (expr, platform_srcs) in platform_srcs {
+                        let what = format!("cxx_library platform_srcs for `{}`", expr);
+                        let platform_srcs =
+                            self.cxx_srcs(&what, platform_srcs, exclude, &src_flags)?;
+                        let platnames = platform_names_for_expr(self.config, expr)
+                            .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
+                        for platname in platnames {
+                            let srcs = platform_srcs.iter().cloned();
+                            // Sources for the DEFAULT platform are for all of them.
+                            if platname.is_default() {
+                                rule.srcs.extend(srcs);
+                            } else {
+                                let platform = rule.platform.entry(platname.clone()).or_default();
+                                platform.srcs.extend(srcs);
+                            }
+                        }
+                    }
+
                     res.push(Rule::CxxLibrary(rule));
                 }
 
-                // Emit a prebuilt C++ library rule for each static library (elsewhere - add dependencies to them)
-                BuildscriptFixup::PrebuiltCxxLibrary(PrebuiltCxxLibraryFixup {
-                    name,
-                    static_libs,
-                    public,
-                    compatible_with,
-                    ..
-                }) => {
-                    let mut static_lib_globs =
-                        Globs::new(static_libs, NO_EXCLUDE).context("Static libraries")?;
-                    for static_lib in static_lib_globs.walk(self.manifest_dir) {
+                // Emit a prebuilt C++ library rule for each static or shared library (elsewhere - add
+                // dependencies to them)
+                BuildscriptFixup::PrebuiltCxxLibrary(
+                    fixup @ PrebuiltCxxLibraryFixup {
+                        name,
+                        public,
+                        compatible_with,
+                        ..
+                    },
+                ) => {
+                    for lib in self.prebuilt_libs(fixup, true)? {
+                        let file_name = lib.path.file_name().unwrap().to_string_lossy();
                         let actual = Name(format!(
                             "{}-{}-{}",
                             self.index.private_rule_name(self.package),
                             name,
-                            static_lib.file_name().unwrap().to_string_lossy(),
+                            file_name,
                         ));
 
                         if *public {
@@ -489,7 +898,7 @@ impl<'meta> Fixups<'meta> {
                                     "{}-{}-{}",
                                     self.index.public_rule_name(self.package),
                                     name,
-                                    static_lib.file_name().unwrap().to_string_lossy(),
+                                    file_name,
                                 )),
                                 actual: actual.clone(),
                                 visibility: self.public_visibility(),
@@ -497,45 +906,80 @@ impl<'meta> Fixups<'meta> {
                             res.push(rule);
                         }
 
+                        let lib_path = self.subtarget_or_path(&lib.path);
+                        let (static_lib, shared_lib) = if lib.shared {
+                            (None, Some(lib_path))
+                        } else {
+                            (Some(lib_path), None)
+                        };
                         let rule = buck::PrebuiltCxxLibrary {
                             common: Common {
                                 name: actual,
-                                visibility: Visibility::Private,
+                                visibility: Visibility::private(&self.config.buck),
                                 licenses: Default::default(),
                                 compatible_with: compatible_with
                                     .iter()
                                     .cloned()
                                     .map(RuleRef::new)
                                     .collect(),
+                                comments: Comments::default(),
                             },
-                            static_lib: self.subtarget_or_path(&static_lib),
+                            static_lib,
+                            shared_lib,
+                            import_lib: lib.import_lib.map(|path| self.subtarget_or_path(&path)),
+                            preferred_linkage: lib.shared.then(|| "shared".to_owned()),
                         };
                         res.push(Rule::PrebuiltCxxLibrary(rule));
                     }
-                    if self.config.strict_globs {
-                        static_lib_globs.check_all_globs_used()?;
-                    }
                 }
 
+                // Outputs were captured ahead of time and are applied directly
+                // to the target, so there's nothing to build or run.
+                BuildscriptFixup::Prerun(_) => {}
+
                 // Complain and omit
                 BuildscriptFixup::Unresolved(msg) => {
-                    let unresolved_package_msg = format!(
-                        "{} has a build script, but I don't know what to do with it: {}",
-                        self.package, msg
-                    );
-                    if config.unresolved_fixup_error {
-                        log::error!("{}", unresolved_package_msg);
-                        return Err(anyhow!(
-                            "Unresolved fix up errors, fix them and rerun buckify."
-                        ));
+                    let severity = if config.unresolved_fixup_error {
+                        Severity::Error
                     } else {
-                        log::warn!("{}", unresolved_package_msg);
+                        Severity::Warning
+                    };
+                    Diagnostic::new(
+                        severity,
+                        format!(
+                            "has a build script, but I don't know what to do with it: {}",
+                            msg
+                        ),
+                    )
+                    .package(self.package)
+                    .target(&self.target.name)
+                    .suggestion(format!(
+                        "describe the build script in {}/fixups.toml, or capture its \
+                         outputs with `reindeer run-buildscripts {}`",
+                        rel_fixup.display(),
+                        self.package.name,
+                    ))
+                    .report();
+                    if config.unresolved_fixup_error {
+                        return Err(Failure::new(
+                            FailureKind::UnresolvedFixups,
+                            "Unresolved fix up errors, fix them and rerun buckify.",
+                        )
+                        .into());
                     }
                 }
             }
         }
 
         if let Some(buildscript_run) = buildscript_run {
+            if !outs.is_empty() {
+                res.push(Rule::BuildscriptOutputs(BuildscriptOutputs {
+                    name: self.buildscript_outputs_name(),
+                    buildscript_genrule: buildscript_run.name.clone(),
+                    outs,
+                    visibility: Visibility::private(&self.config.buck),
+                }));
+            }
             res.push(Rule::BuildscriptGenrule(buildscript_run));
         }
 
@@ -548,6 +992,16 @@ impl<'meta> Fixups<'meta> {
         let mut ret = HashMap::new();
 
         let mut platform_omits = HashMap::new();
+        let global_omits = ["*", self.package.name.as_str()]
+            .into_iter()
+            .filter_map(|name| self.config.omit_features.get(name))
+            .flatten();
+        for feature in global_omits {
+            platform_omits
+                .entry(feature.as_str())
+                .or_insert_with(HashSet::new)
+                .insert(None);
+        }
         for (platform, fixup) in self.fixup_config.configs(&self.package.version) {
             // Group by feature which platforms omit it.
             for feature in &fixup.omit_features {
@@ -579,16 +1033,14 @@ impl<'meta> Fixups<'meta> {
             }
 
             let mut excludes = vec![];
-            for platform in omitted_platforms {
-                if let Some(platform_expr) = platform {
-                    // If a platform filters a feature added by the base,
-                    // we need to filter it from the base and add it to all
-                    // other platforms. Create a predicate that excludes all
-                    // filtered platforms. This will be the "all other
-                    // platforms".
-                    let platform_pred = PlatformPredicate::parse(platform_expr)?;
-                    excludes.push(PlatformPredicate::Not(Box::new(platform_pred)));
-                }
+            for platform_expr in omitted_platforms.iter().flatten() {
+                // If a platform filters a feature added by the base,
+                // we need to filter it from the base and add it to all
+                // other platforms. Create a predicate that excludes all
+                // filtered platforms. This will be the "all other
+                // platforms".
+                let platform_pred = PlatformPredicate::parse(platform_expr)?;
+                excludes.push(PlatformPredicate::Not(Box::new(platform_pred)));
             }
 
             assert!(!excludes.is_empty());
@@ -603,6 +1055,27 @@ impl<'meta> Fixups<'meta> {
         Ok(ret)
     }
 
+    /// The features for the target's rule: those of `compute_features`, as
+    /// renamed by `feature_map`. Build scripts keep Cargo's names, because
+    /// their rule's features are what `CARGO_FEATURE_*` are made from.
+    pub fn compute_rule_features(&self) -> Result<HashMap<Option<PlatformExpr>, BTreeSet<String>>> {
+        let features = self.compute_features()?;
+        let feature_map = &self.fixup_config.feature_map;
+        if feature_map.is_empty() || self.target.kind_custom_build() {
+            return Ok(features);
+        }
+        Ok(features
+            .into_iter()
+            .map(|(platform, features)| {
+                let features = features
+                    .into_iter()
+                    .map(|feature| feature_map.get(&feature).cloned().unwrap_or(feature))
+                    .collect();
+                (platform, features)
+            })
+            .collect())
+    }
+
     fn buildscript_rustc_flags(&self) -> Vec<(Option<PlatformExpr>, Vec<String>)> {
         let mut ret = vec![];
         if self.buildscript_target().is_none() {
@@ -616,11 +1089,15 @@ impl<'meta> Fixups<'meta> {
                 if !self.target_match(buildscript) {
                     continue;
                 }
-                if let BuildscriptFixup::RustcFlags(_) = buildscript {
-                    flags.push(format!(
+                match buildscript {
+                    BuildscriptFixup::RustcFlags(_) => flags.push(format!(
                         "@$(location :{}[rustc_flags])",
                         self.buildscript_genrule_name()
-                    ))
+                    )),
+                    BuildscriptFixup::Prerun(Prerun { cfgs, .. }) => {
+                        flags.extend(cfgs.iter().map(|cfg| format!("--cfg={}", cfg)))
+                    }
+                    _ => {}
                 }
             }
 
@@ -632,6 +1109,27 @@ impl<'meta> Fixups<'meta> {
         ret
     }
 
+    /// The platform as this package sees it, with the cfgs its fixups pass to
+    /// rustc set, so that dependencies behind `cfg(tokio_unstable)` and the
+    /// like are picked up when the cfg is.
+    pub fn platform_with_cfgs(&self, platform: &PlatformConfig) -> Result<PlatformConfig> {
+        let mut cfgs = Vec::new();
+        for (expr, config) in self.fixup_config.configs(&self.package.version) {
+            if config.cfgs.is_empty() {
+                continue;
+            }
+            if let Some(expr) = expr {
+                let pred = PlatformPredicate::parse(expr)
+                    .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
+                if !pred.eval(platform) {
+                    continue;
+                }
+            }
+            cfgs.extend(config.cfgs.iter().map(String::as_str));
+        }
+        Ok(platform.with_cfgs(cfgs))
+    }
+
     /// Return extra command-line options, with platform annotation if needed
     pub fn compute_cmdline(&self) -> Vec<(Option<PlatformExpr>, Vec<String>)> {
         let mut ret = vec![];
@@ -662,7 +1160,7 @@ impl<'meta> Fixups<'meta> {
         Vec<(
             Option<&'meta Manifest>,
             RuleRef,
-            Option<&'meta str>,
+            Option<&str>,
             &'meta NodeDepKind,
         )>,
     > {
@@ -773,6 +1271,14 @@ impl<'meta> Fixups<'meta> {
                     &NodeDepKind::ORDINARY,
                 )
             }));
+            ret.extend(config.extra_named_deps.iter().map(|(rename, dep)| {
+                (
+                    None,
+                    RuleRef::new(dep.to_string()).with_platform(platform),
+                    Some(rename.as_str()),
+                    &NodeDepKind::ORDINARY,
+                )
+            }));
             for buildscript in &config.buildscript {
                 if !self.target_match(buildscript) {
                     continue;
@@ -795,23 +1301,22 @@ impl<'meta> Fixups<'meta> {
                         &NodeDepKind::ORDINARY,
                     ));
                 }
-                if let BuildscriptFixup::PrebuiltCxxLibrary(PrebuiltCxxLibraryFixup {
-                    add_dep: true,
-                    name,
-                    static_libs,
-                    ..
-                }) = buildscript
+                if let BuildscriptFixup::PrebuiltCxxLibrary(
+                    fixup @ PrebuiltCxxLibraryFixup {
+                        add_dep: true,
+                        name,
+                        ..
+                    },
+                ) = buildscript
                 {
-                    let mut static_lib_globs =
-                        Globs::new(static_libs, NO_EXCLUDE).context("Prebuilt C++ libraries")?;
-                    for static_lib in static_lib_globs.walk(self.manifest_dir) {
+                    for lib in self.prebuilt_libs(fixup, false)? {
                         ret.push((
                             None,
                             RuleRef::new(format!(
                                 ":{}-{}-{}",
                                 self.index.private_rule_name(self.package),
                                 name,
-                                static_lib.file_name().unwrap().to_string_lossy(),
+                                lib.path.file_name().unwrap().to_string_lossy(),
                             ))
                             .with_platform(platform),
                             None,
@@ -835,53 +1340,47 @@ impl<'meta> Fixups<'meta> {
             let mut map: BTreeMap<String, StringOrPath> = config
                 .env
                 .iter()
-                .map(|(k, v)| (k.clone(), StringOrPath::String(v.clone())))
+                .map(|(k, v)| {
+                    let v = match rule_output(v) {
+                        Some(_) => format!("$(location {})", v),
+                        None => v.clone(),
+                    };
+                    (k.clone(), StringOrPath::String(v))
+                })
                 .collect();
 
-            for cargo_env in config.cargo_env.iter() {
-                let v = match cargo_env {
-                    CargoEnv::CARGO_MANIFEST_DIR => {
-                        if self.config.vendor.is_some()
-                            || matches!(self.package.source, Source::Local)
-                        {
+            for buildscript in &config.buildscript {
+                if let BuildscriptFixup::Prerun(prerun) = buildscript {
+                    if !self.target_match(buildscript) {
+                        continue;
+                    }
+                    map.extend(
+                        prerun
+                            .env
+                            .iter()
+                            .map(|(k, v)| (k.clone(), StringOrPath::String(v.clone()))),
+                    );
+                    if let Some(out_dir) = &prerun.out_dir {
+                        map.insert(
+                            "OUT_DIR".to_owned(),
                             StringOrPath::Path(BuckPath(relative_path(
                                 &self.third_party_dir,
-                                self.manifest_dir,
-                            )))
-                        } else if let Source::Git { repo, .. } = &self.package.source {
-                            let short_name = short_name_for_git_repo(repo)?;
-                            StringOrPath::String(short_name.to_owned())
-                        } else {
-                            StringOrPath::String(format!(
-                                "{}-{}.crate",
-                                self.package.name, self.package.version,
-                            ))
-                        }
-                    }
-                    CargoEnv::CARGO_PKG_AUTHORS => {
-                        StringOrPath::String(self.package.authors.join(":"))
-                    }
-                    CargoEnv::CARGO_PKG_DESCRIPTION => {
-                        StringOrPath::String(self.package.description.clone().unwrap_or_default())
-                    }
-                    CargoEnv::CARGO_PKG_REPOSITORY => {
-                        StringOrPath::String(self.package.repository.clone().unwrap_or_default())
-                    }
-                    CargoEnv::CARGO_PKG_VERSION => {
-                        StringOrPath::String(self.package.version.to_string())
-                    }
-                    CargoEnv::CARGO_PKG_VERSION_MAJOR => {
-                        StringOrPath::String(self.package.version.major.to_string())
-                    }
-                    CargoEnv::CARGO_PKG_VERSION_MINOR => {
-                        StringOrPath::String(self.package.version.minor.to_string())
-                    }
-                    CargoEnv::CARGO_PKG_VERSION_PATCH => {
-                        StringOrPath::String(self.package.version.patch.to_string())
+                                &self.fixup_dir.join(out_dir),
+                            ))),
+                        );
                     }
-                    CargoEnv::CARGO_PKG_NAME => StringOrPath::String(self.package.name.clone()),
-                };
-                map.insert(cargo_env.to_string(), v);
+                }
+            }
+
+            // The package's own `cargo_env` replaces the global one, which
+            // only goes with the package as a whole.
+            let cargo_env = match (&config.cargo_env, platform) {
+                (Some(cargo_env), _) => cargo_env,
+                (None, None) => &self.config.cargo_env,
+                (None, Some(_)) => &CargoEnvs::None,
+            };
+            for cargo_env in cargo_env.iter() {
+                map.insert(cargo_env.to_string(), self.cargo_env_value(cargo_env)?);
             }
 
             if !map.is_empty() {
@@ -889,9 +1388,68 @@ impl<'meta> Fixups<'meta> {
             }
         }
 
+        if self.fixup_config.base(&self.package.version).is_none() {
+            let map = self
+                .config
+                .cargo_env
+                .iter()
+                .map(|cargo_env| Ok((cargo_env.to_string(), self.cargo_env_value(cargo_env)?)))
+                .collect::<Result<BTreeMap<_, _>>>()?;
+            if !map.is_empty() {
+                ret.push((None, map));
+            }
+        }
+
         Ok(ret)
     }
 
+    /// Value of one of the Cargo environment variables for this package.
+    fn cargo_env_value(&self, cargo_env: CargoEnv) -> Result<StringOrPath> {
+        let value = match cargo_env {
+            CargoEnv::CARGO_MANIFEST_DIR => {
+                if let Some((_, filegroup)) =
+                    path_dependency_filegroup(self.config, &self.third_party_dir, self.package)?
+                {
+                    StringOrPath::String(format!("$(location {})", filegroup))
+                } else if self.config.vendor.is_some()
+                    || matches!(self.package.source, Source::Local)
+                {
+                    StringOrPath::Path(BuckPath(relative_path(
+                        &self.third_party_dir,
+                        self.manifest_dir,
+                    )))
+                } else if let Source::Git { repo, .. } = &self.package.source {
+                    let short_name = short_name_for_git_repo(repo)?;
+                    StringOrPath::String(short_name.to_owned())
+                } else {
+                    StringOrPath::String(format!(
+                        "{}-{}.crate",
+                        self.package.name, self.package.version,
+                    ))
+                }
+            }
+            CargoEnv::CARGO_PKG_AUTHORS => StringOrPath::String(self.package.authors.join(":")),
+            CargoEnv::CARGO_PKG_DESCRIPTION => {
+                StringOrPath::String(self.package.description.clone().unwrap_or_default())
+            }
+            CargoEnv::CARGO_PKG_REPOSITORY => {
+                StringOrPath::String(self.package.repository.clone().unwrap_or_default())
+            }
+            CargoEnv::CARGO_PKG_VERSION => StringOrPath::String(self.package.version.to_string()),
+            CargoEnv::CARGO_PKG_VERSION_MAJOR => {
+                StringOrPath::String(self.package.version.major.to_string())
+            }
+            CargoEnv::CARGO_PKG_VERSION_MINOR => {
+                StringOrPath::String(self.package.version.minor.to_string())
+            }
+            CargoEnv::CARGO_PKG_VERSION_PATCH => {
+                StringOrPath::String(self.package.version.patch.to_string())
+            }
+            CargoEnv::CARGO_PKG_NAME => StringOrPath::String(self.package.name.clone()),
+        };
+        Ok(value)
+    }
+
     /// Given a glob for the srcs, walk the filesystem to get the full set.
     /// `srcs` is the normal source glob rooted at the package's manifest dir.
     pub fn compute_srcs(
@@ -900,9 +1458,13 @@ impl<'meta> Fixups<'meta> {
     ) -> Result<Vec<(Option<PlatformExpr>, BTreeSet<PathBuf>)>> {
         let mut ret: Vec<(Option<PlatformExpr>, BTreeSet<PathBuf>)> = vec![];
 
-        // This function is only used in vendoring mode, so it's guaranteed that
-        // manifest_dir is a subdirectory of third_party_dir.
-        assert!(self.config.vendor.is_some() || matches!(self.package.source, Source::Local));
+        // This function is only used in vendoring mode, or for an http_archive
+        // whose files need to be listed one by one to make room for an overlay.
+        assert!(
+            self.config.vendor.is_some()
+                || matches!(self.package.source, Source::Local)
+                || self.has_overlay()
+        );
         let manifest_rel = relative_path(&self.third_party_dir, self.manifest_dir);
 
         let srcs_globs: Vec<String> = srcs
@@ -1017,54 +1579,134 @@ impl<'meta> Fixups<'meta> {
         let mut unmatched_globs = Vec::new();
 
         for glob in globs {
-            // The extra_srcs are allowed to be located outside this crate's
-            // manifest dir, i.e. starting with "../". For example libstd refers
-            // to files from portable-simd and stdarch, which are located in
-            // sibling directories. Thus doing a WalkDir over manifest_dir is
-            // not sufficient; here we pick the right directory to walk for this
-            // glob.
-            let mut dir_containing_extra_srcs = self.manifest_dir.to_owned();
-            let mut rest_of_glob = Path::new(glob).components();
-            while let Some(component) = rest_of_glob.as_path().components().next() {
-                if component.as_os_str().to_string_lossy().contains('*') {
-                    // Ready to do globby stuff.
-                    break;
-                } else {
-                    rest_of_glob.next().unwrap();
-                    dir_containing_extra_srcs.push(component);
-                }
+            let matches = extra_srcs_matches(self.manifest_dir, glob)?;
+            if matches.is_empty() {
+                unmatched_globs.push(glob.as_str());
             }
-
-            let len_before = extra_srcs.len();
-            let mut insert = |absolute_path: &Path| {
-                let tp_rel_path = relative_path(&self.third_party_dir, absolute_path);
+            for absolute_path in matches {
+                let tp_rel_path = relative_path(&self.third_party_dir, &absolute_path);
                 extra_srcs.insert(normalize_dotdot(&tp_rel_path));
-            };
-
-            let rest_of_glob = rest_of_glob.as_path();
-            if rest_of_glob.as_os_str().is_empty() {
-                // None of the components contained glob so this extra_src
-                // refers to a specific file.
-                if dir_containing_extra_srcs.is_file() {
-                    insert(&dir_containing_extra_srcs);
-                }
-            } else {
-                let glob = rest_of_glob.to_string_lossy();
-                for path in Globs::new([glob], NO_EXCLUDE)?.walk(&dir_containing_extra_srcs) {
-                    insert(&dir_containing_extra_srcs.join(path));
-                }
             }
+        }
+
+        self.report_unmatched_globs("extra_srcs", &unmatched_globs, self.manifest_dir)?;
+        Ok(extra_srcs)
+    }
 
-            if extra_srcs.len() == len_before {
-                unmatched_globs.push(glob);
+    /// Report each glob from the fixups which matched no files in `dir`, with
+    /// where in fixups.toml it came from and the closest paths that do exist.
+    /// The library files of a `prebuilt_cxx_library` fixup, relative to the
+    /// manifest dir.
+    fn prebuilt_libs(
+        &self,
+        fixup: &PrebuiltCxxLibraryFixup,
+        report_unmatched: bool,
+    ) -> Result<Vec<PrebuiltLib>> {
+        let report_unmatched = report_unmatched && self.config.strict_globs;
+        let walk = |field: &str, globs: &[String]| -> Result<Vec<PathBuf>> {
+            let mut globs = Globs::new(globs, NO_EXCLUDE).context("Prebuilt C++ libraries")?;
+            let paths = globs.walk(self.manifest_dir).collect();
+            if report_unmatched {
+                self.report_unmatched_globs(
+                    &format!("prebuilt_cxx_library {}", field),
+                    &globs.unmatched(),
+                    self.manifest_dir,
+                )?;
             }
+            Ok(paths)
+        };
+
+        let static_libs = walk("static_libs", &fixup.static_libs)?;
+        let shared_libs = walk("shared_libs", &fixup.shared_libs)?;
+        let import_libs = walk("import_libs", &fixup.import_libs)?;
+
+        let mut libs: Vec<PrebuiltLib> = static_libs
+            .into_iter()
+            .map(|path| PrebuiltLib {
+                path,
+                shared: false,
+                import_lib: None,
+            })
+            .collect();
+        for path in shared_libs {
+            let import_lib = import_libs
+                .iter()
+                .find(|import_lib| import_lib.file_stem() == path.file_stem())
+                .cloned();
+            libs.push(PrebuiltLib {
+                path,
+                shared: true,
+                import_lib,
+            });
         }
+        Ok(libs)
+    }
 
-        if unmatched_globs.is_empty() {
-            Ok(extra_srcs)
-        } else {
-            bail!("Unmatched globs in extra_srcs: {:?}", unmatched_globs);
+    /// The C++ sources matching `srcs`, each with the flags from any of
+    /// `src_flags` whose glob it matches.
+    fn cxx_srcs(
+        &self,
+        what: &str,
+        srcs: &[String],
+        exclude: &[String],
+        src_flags: &[(Globs, &Vec<String>)],
+    ) -> Result<BTreeSet<CxxSrc>> {
+        let mut globs = Globs::new(srcs, exclude).context("C++ sources")?;
+        let paths: Vec<PathBuf> = globs.walk(self.manifest_dir).collect();
+        if self.config.strict_globs {
+            self.report_unmatched_globs(what, &globs.unmatched(), self.manifest_dir)?;
+        }
+
+        Ok(paths
+            .into_iter()
+            .map(|path| CxxSrc {
+                flags: src_flags
+                    .iter()
+                    .filter(|(globs, _)| globs.is_match(&path))
+                    .flat_map(|(_, flags)| flags.iter().cloned())
+                    .collect(),
+                path: self.subtarget_or_path(&path),
+            })
+            .collect())
+    }
+
+    fn report_unmatched_globs(&self, field: &str, unmatched: &[&str], dir: &Path) -> Result<()> {
+        if unmatched.is_empty() {
+            return Ok(());
+        }
+
+        let fixup_path = self.fixup_dir.join("fixups.toml");
+        let fixup_file = fs::read_to_string(&fixup_path).unwrap_or_default();
+        let fixup_path = relative_path(&self.third_party_dir, &fixup_path);
+
+        for glob in unmatched {
+            let quoted = [format!("\"{}\"", glob), format!("'{}'", glob)];
+            let location = match fixup_file
+                .lines()
+                .position(|line| quoted.iter().any(|quoted| line.contains(quoted)))
+            {
+                Some(idx) => format!("{}:{}", fixup_path.display(), idx + 1),
+                None => fixup_path.display().to_string(),
+            };
+
+            let mut diagnostic = Diagnostic::new(
+                Severity::Error,
+                format!("{} `{}` at {} matches no files", field, glob, location),
+            )
+            .package(self.package)
+            .target(&self.target.name);
+            let closest = closest_paths(glob, dir);
+            if !closest.is_empty() {
+                let closest: Vec<String> = closest
+                    .iter()
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect();
+                diagnostic = diagnostic.suggestion(format!("did you mean {}?", closest.join(", ")));
+            }
+            diagnostic.report();
         }
+
+        bail!("Unmatched globs in {}: {:?}", field, unmatched);
     }
 
     pub fn compute_mapped_srcs(
@@ -1078,7 +1720,7 @@ impl<'meta> Fixups<'meta> {
 
             for (k, v) in &config.extra_mapped_srcs {
                 map.insert(
-                    self.subtarget_or_path(Path::new(k)),
+                    rule_output(k).unwrap_or_else(|| self.subtarget_or_path(Path::new(k))),
                     BuckPath(mapped_manifest_dir.join(v)),
                 );
             }
@@ -1087,6 +1729,13 @@ impl<'meta> Fixups<'meta> {
                 let overlay_dir = self.fixup_dir.join(overlay);
                 let relative_overlay_dir = relative_path(&self.third_party_dir, &overlay_dir);
                 let overlay_files = config.overlay_files(&self.fixup_dir)?;
+                if self.config.strict_globs && overlay_files.is_empty() {
+                    self.report_unmatched_globs(
+                        "overlay",
+                        &[&overlay.to_string_lossy()],
+                        &self.fixup_dir,
+                    )?;
+                }
 
                 log::debug!(
                     "pkg {} target {} overlay_dir {} overlay_files {:?}",
@@ -1111,8 +1760,47 @@ impl<'meta> Fixups<'meta> {
         Ok(ret)
     }
 
-    /// Return mapping from rules of generated source to local name.
-    pub fn compute_gen_srcs(&self) -> Vec<(Option<PlatformExpr>, ())> {
+    /// Files from a prerun build script's committed OUT_DIR, which need to be
+    /// present for `include!(concat!(env!("OUT_DIR"), ...))` to work.
+    pub fn compute_prerun_srcs(&self) -> Result<Vec<(Option<PlatformExpr>, BTreeSet<PathBuf>)>> {
+        let mut ret = vec![];
+
+        for (platform, config) in self.fixup_config.configs(&self.package.version) {
+            let mut set = BTreeSet::new();
+
+            for buildscript in &config.buildscript {
+                let BuildscriptFixup::Prerun(Prerun {
+                    out_dir: Some(out_dir),
+                    ..
+                }) = buildscript
+                else {
+                    continue;
+                };
+                if !self.target_match(buildscript) {
+                    continue;
+                }
+                let out_dir = self.fixup_dir.join(out_dir);
+                for entry in WalkDir::new(&out_dir) {
+                    let entry = entry
+                        .with_context(|| format!("Walking prerun out_dir {}", out_dir.display()))?;
+                    if entry.file_type().is_file() {
+                        set.insert(relative_path(&self.third_party_dir, entry.path()));
+                    }
+                }
+            }
+
+            if !set.is_empty() {
+                ret.push((platform.cloned(), set));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Return the generated sources for each platform: either the declared
+    /// outputs of the buildscript genrule, or an empty set if the target needs
+    /// the whole OUT_DIR.
+    pub fn compute_gen_srcs(&self) -> Vec<(Option<PlatformExpr>, BTreeSet<String>)> {
         let mut ret = vec![];
 
         if self.buildscript_rule_name().is_none() {
@@ -1121,12 +1809,54 @@ impl<'meta> Fixups<'meta> {
         }
 
         for (platform, config) in self.fixup_config.configs(&self.package.version) {
-            if config
-                .buildscript
-                .iter()
-                .any(|fix| self.target_match(fix) && matches!(fix, BuildscriptFixup::GenSrcs(_)))
-            {
-                ret.push((platform.cloned(), ()));
+            let mut outputs = BTreeSet::new();
+            let mut whole_out_dir = false;
+            let mut any = false;
+
+            for fix in &config.buildscript {
+                if let BuildscriptFixup::GenSrcs(gen_srcs) = fix {
+                    if !self.target_match(fix) {
+                        continue;
+                    }
+                    any = true;
+                    whole_out_dir |= gen_srcs.outputs.is_empty();
+                    outputs.extend(gen_srcs.outputs.iter().cloned());
+                }
+            }
+
+            if any {
+                if whole_out_dir {
+                    outputs.clear();
+                }
+                ret.push((platform.cloned(), outputs));
+            }
+        }
+
+        ret
+    }
+
+    /// Return the files from the buildscript genrule which the target needs at
+    /// runtime, for each platform.
+    pub fn compute_runtime_outputs(&self) -> Vec<(Option<PlatformExpr>, BTreeSet<String>)> {
+        let mut ret = vec![];
+
+        if self.buildscript_rule_name().is_none() {
+            return ret;
+        }
+
+        for (platform, config) in self.fixup_config.configs(&self.package.version) {
+            let mut outputs = BTreeSet::new();
+
+            for fix in &config.buildscript {
+                if let BuildscriptFixup::GenSrcs(gen_srcs) = fix {
+                    if self.target_match(fix) {
+                        outputs.extend(gen_srcs.runtime_outputs.iter().cloned());
+                    }
+                }
+            }
+
+            if !outputs.is_empty() {
+                ret.push((platform.cloned(), outputs));
             }
         }
 
@@ -1145,6 +1875,44 @@ impl<'meta> Fixups<'meta> {
         ret
     }
 
+    /// Compute the deps selected by Buck config settings
+    pub fn compute_select_deps(
+        &self,
+    ) -> Vec<(Option<PlatformExpr>, BTreeMap<String, BTreeSet<String>>)> {
+        let mut ret = Vec::new();
+        for (platform, config) in self.fixup_config.configs(&self.package.version) {
+            if !config.select_deps.is_empty() {
+                ret.push((platform.cloned(), config.select_deps.clone()));
+            }
+        }
+
+        ret
+    }
+
+    /// Compute the Apple frameworks to link with
+    pub fn compute_frameworks(&self) -> Vec<(Option<PlatformExpr>, BTreeSet<String>)> {
+        let mut ret = Vec::new();
+        for (platform, config) in self.fixup_config.configs(&self.package.version) {
+            if !config.frameworks.is_empty() {
+                ret.push((platform.cloned(), config.frameworks.clone()));
+            }
+        }
+
+        ret
+    }
+
+    /// Compute the system libraries to link with
+    pub fn compute_system_libs(&self) -> Vec<(Option<PlatformExpr>, BTreeSet<String>)> {
+        let mut ret = Vec::new();
+        for (platform, config) in self.fixup_config.configs(&self.package.version) {
+            if !config.system_libs.is_empty() {
+                ret.push((platform.cloned(), config.system_libs.clone()));
+            }
+        }
+
+        ret
+    }
+
     /// Compute preferred_linkage (how dependents should link you)
     pub fn compute_preferred_linkage(&self) -> Vec<(Option<PlatformExpr>, String)> {
         let mut ret = Vec::new();
@@ -1157,3 +1925,40 @@ impl<'meta> Fixups<'meta> {
         ret
     }
 }
+
+/// Absolute paths of the files an `extra_srcs` glob refers to.
+pub(crate) fn extra_srcs_matches(manifest_dir: &Path, glob: &str) -> Result<Vec<PathBuf>> {
+    // The extra_srcs are allowed to be located outside this crate's
+    // manifest dir, i.e. starting with "../". For example libstd refers
+    // to files from portable-simd and stdarch, which are located in
+    // sibling directories. Thus doing a WalkDir over manifest_dir is
+    // not sufficient; here we pick the right directory to walk for this
+    // glob.
+    let mut dir_containing_extra_srcs = manifest_dir.to_owned();
+    let mut rest_of_glob = Path::new(glob).components();
+    while let Some(component) = rest_of_glob.as_path().components().next() {
+        if component.as_os_str().to_string_lossy().contains('*') {
+            // Ready to do globby stuff.
+            break;
+        } else {
+            rest_of_glob.next().unwrap();
+            dir_containing_extra_srcs.push(component);
+        }
+    }
+
+    let rest_of_glob = rest_of_glob.as_path();
+    if rest_of_glob.as_os_str().is_empty() {
+        // None of the components contained glob so this extra_src
+        // refers to a specific file.
+        if dir_containing_extra_srcs.is_file() {
+            return Ok(vec![dir_containing_extra_srcs]);
+        }
+        return Ok(vec![]);
+    }
+
+    let glob = rest_of_glob.to_string_lossy();
+    Ok(Globs::new([glob], NO_EXCLUDE)?
+        .walk(&dir_containing_extra_srcs)
+        .map(|path| dir_containing_extra_srcs.join(path))
+        .collect())
+}
@@ -6,14 +6,18 @@
  */
 
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::path::PathBuf;
 
 use anyhow::Result;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::value::SeqAccessDeserializer;
 use serde::de::Error as DeError;
 use serde::de::MapAccess;
+use serde::de::SeqAccess;
 use serde::de::Visitor;
 use serde::ser::SerializeMap;
 use serde::Deserialize;
@@ -23,6 +27,7 @@ use serde::Serializer;
 
 use crate::cargo::TargetKind;
 use crate::collection::SetOrMap;
+use crate::platform::PlatformExpr;
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct BuildscriptFixups(pub Vec<BuildscriptFixup>);
@@ -51,6 +56,7 @@ impl Deref for BuildscriptFixups {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Eq, PartialEq)]
 pub enum BuildscriptFixup {
     /// Unresolved build script (string with helpful message)
@@ -64,9 +70,24 @@ pub enum BuildscriptFixup {
     CxxLibrary(CxxLibraryFixup),
     /// Generate a prebuilt C++ library rule
     PrebuiltCxxLibrary(PrebuiltCxxLibraryFixup),
+    /// Use outputs captured by `reindeer run-buildscripts` instead of running
+    /// the build script at build time
+    Prerun(Prerun),
 }
 
 impl BuildscriptFixup {
+    /// The key the fixup goes under in fixups.toml
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BuildscriptFixup::Unresolved(_) => "unresolved",
+            BuildscriptFixup::RustcFlags(_) => "rustc_flags",
+            BuildscriptFixup::GenSrcs(_) => "gen_srcs",
+            BuildscriptFixup::CxxLibrary(_) => "cxx_library",
+            BuildscriptFixup::PrebuiltCxxLibrary(_) => "prebuilt_cxx_library",
+            BuildscriptFixup::Prerun(_) => "prerun",
+        }
+    }
+
     pub fn targets(&self) -> Option<&[(TargetKind, Option<String>)]> {
         let targets = match self {
             BuildscriptFixup::RustcFlags(RustcFlags { targets, .. }) => targets,
@@ -75,6 +96,7 @@ impl BuildscriptFixup {
             BuildscriptFixup::PrebuiltCxxLibrary(PrebuiltCxxLibraryFixup { targets, .. }) => {
                 targets
             }
+            BuildscriptFixup::Prerun(Prerun { targets, .. }) => targets,
             BuildscriptFixup::Unresolved(_) => return None,
         };
 
@@ -97,13 +119,23 @@ pub struct RustcFlags {
     pub path_env: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct GenSrcs {
     // Which targets are we generating source for. List in the form
     // of kind and name (eg `["bin","cargo"]`). Empty means apply to main lib target.
     #[serde(default)]
     pub targets: Vec<(TargetKind, Option<String>)>,
+    // Exact set of files the build script writes to OUT_DIR. If given, each is
+    // a named output of the genrule and mapped individually into the target's
+    // sources, rather than depending on the whole OUT_DIR.
+    #[serde(default)]
+    pub outputs: BTreeSet<String>,
+    // Files the build script writes to OUT_DIR which the target reads when it
+    // runs rather than when it's compiled. Each is a named output of the
+    // genrule and one of the target's `resources`.
+    #[serde(default)]
+    pub runtime_outputs: BTreeSet<String>,
     // Runtime environment for the gensrc program
     #[serde(default)]
     pub env: BTreeMap<String, String>,
@@ -129,6 +161,47 @@ pub struct GenSrcs {
     pub args_env: BTreeMap<String, String>,
 }
 
+/// `gen_srcs` is either the full table, or a shorthand list of outputs:
+/// `gen_srcs = ["bindings.rs"]`.
+struct GenSrcsOrOutputs(GenSrcs);
+
+struct GenSrcsVisitor;
+
+impl<'de> Visitor<'de> for GenSrcsVisitor {
+    type Value = GenSrcsOrOutputs;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("gen_srcs table or list of generated files")
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let outputs = BTreeSet::deserialize(SeqAccessDeserializer::new(seq))?;
+        Ok(GenSrcsOrOutputs(GenSrcs {
+            outputs,
+            ..GenSrcs::default()
+        }))
+    }
+
+    fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        GenSrcs::deserialize(MapAccessDeserializer::new(map)).map(GenSrcsOrOutputs)
+    }
+}
+
+impl<'de> Deserialize<'de> for GenSrcsOrOutputs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(GenSrcsVisitor)
+    }
+}
+
 fn set_true() -> bool {
     true
 }
@@ -160,18 +233,39 @@ pub struct CxxLibraryFixup {
     pub compiler_flags: Vec<String>,
     #[serde(default)]
     pub preprocessor_flags: Vec<String>,
+    #[serde(default)]
+    pub exported_preprocessor_flags: Vec<String>, // also for dependents
+    #[serde(default)]
+    pub raw_headers: Vec<String>, // header globs, included by path as they are
     pub header_namespace: Option<String>,
+    pub soname: Option<String>,
     #[serde(default)]
     pub deps: Vec<String>,
     #[serde(default)]
+    pub exported_deps: Vec<String>, // deps which dependents also get
+    #[serde(default)]
     pub compatible_with: Vec<String>,
+    // Extra src globs for the platforms matching each expression, such as
+    // the assembly for one architecture
+    #[serde(default)]
+    pub platform_srcs: BTreeMap<PlatformExpr, Vec<String>>,
+    // Compiler flags for just the srcs matching each glob
+    #[serde(default)]
+    pub src_flags: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct PrebuiltCxxLibraryFixup {
-    pub name: String,             // rule basename
+    pub name: String, // rule basename
+    #[serde(default)]
     pub static_libs: Vec<String>, // static lib globs
+    #[serde(default)]
+    pub shared_libs: Vec<String>, // shared lib globs
+    // Import library globs for the shared libs on Windows, each going with
+    // the shared lib of the same file stem
+    #[serde(default)]
+    pub import_libs: Vec<String>,
     #[serde(default = "set_true")]
     pub add_dep: bool, // add to dependencies
     // Which targets are we a dependency for. List in the form
@@ -184,6 +278,24 @@ pub struct PrebuiltCxxLibraryFixup {
     pub compatible_with: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Prerun {
+    // Which targets are the outputs for. List in the form of kind and name
+    // (eg `["bin","cargo"]`). Empty means apply to main lib target.
+    #[serde(default)]
+    pub targets: Vec<(TargetKind, Option<String>)>,
+    // Directory, relative to the fixup dir, holding the committed contents of
+    // the build script's OUT_DIR. Absent if the build script wrote nothing.
+    pub out_dir: Option<PathBuf>,
+    // `cargo:rustc-cfg` values printed by the build script
+    #[serde(default)]
+    pub cfgs: BTreeSet<String>,
+    // `cargo:rustc-env` values printed by the build script
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
 #[derive(Deserialize)]
 struct Empty {}
 
@@ -204,6 +316,7 @@ impl Serialize for BuildscriptFixup {
             BuildscriptFixup::PrebuiltCxxLibrary(prebuilt_lib) => {
                 map.serialize_entry("prebuilt_prebcxx_library", prebuilt_lib)?
             }
+            BuildscriptFixup::Prerun(prerun) => map.serialize_entry("prerun", prerun)?,
         }
         map.end()
     }
@@ -226,11 +339,12 @@ impl<'de> Visitor<'de> for BuildscriptFixupVisitor<'de> {
             let res = match key.as_str() {
                 "unresolved" => BuildscriptFixup::Unresolved(access.next_value()?),
                 "rustc_flags" => BuildscriptFixup::RustcFlags(access.next_value()?),
-                "gen_srcs" => BuildscriptFixup::GenSrcs(access.next_value()?),
+                "gen_srcs" => BuildscriptFixup::GenSrcs(access.next_value::<GenSrcsOrOutputs>()?.0),
                 "cxx_library" => BuildscriptFixup::CxxLibrary(access.next_value()?),
                 "prebuilt_cxx_library" => {
                     BuildscriptFixup::PrebuiltCxxLibrary(access.next_value()?)
                 }
+                "prerun" => BuildscriptFixup::Prerun(access.next_value()?),
                 other => {
                     // other keys are unit, which map to an empty map
                     let _ = access.next_value::<Empty>()?;
@@ -9,9 +9,12 @@ use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashSet;
 use std::fmt;
+use std::fs;
+use std::iter;
 use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::Context;
 use anyhow::Result;
 use serde::de::value::SeqAccessDeserializer;
 use serde::de::SeqAccess;
@@ -24,7 +27,9 @@ use strum::IntoEnumIterator as _;
 use walkdir::WalkDir;
 
 use crate::buckify::relative_path;
+use crate::cargo::CrateType;
 use crate::cargo::ManifestTarget;
+use crate::config::resolve_includes;
 use crate::fixups::buildscript::BuildscriptFixup;
 use crate::fixups::buildscript::BuildscriptFixups;
 use crate::glob::SerializableGlobSet as GlobSet;
@@ -41,9 +46,14 @@ pub struct FixupConfigFile {
     #[serde(default, rename = "visibility")]
     pub custom_visibility: Option<Vec<String>>,
 
-    /// Omit a target
+    /// Only generate rules for these binaries of the package. Overrides the
+    /// global config `include_bins` for this crate.
+    pub bins: Option<BTreeSet<String>>,
+
+    /// Generate rules for these examples of the package, which are otherwise
+    /// skipped.
     #[serde(default)]
-    pub omit_targets: BTreeSet<String>,
+    pub examples: BTreeSet<String>,
 
     /// Skip precise srcs detection and fallback to `**/*.rs`.
     /// Overrides the global config `precise_srcs` for this crate.
@@ -57,6 +67,36 @@ pub struct FixupConfigFile {
     /// rule so it can be mapped to the right underlying rule.
     pub python_ext: Option<String>,
 
+    /// Replace the crate types Cargo reports for the package's library
+    /// target, for example `["rlib", "cdylib"]` for an FFI crate that's
+    /// consumed by both Rust and C++. This decides whether the library is
+    /// `dlopen_enable`d and linkable from non-Rust rules.
+    pub crate_types: Option<BTreeSet<CrateType>>,
+
+    /// Rename features in what the package's targets are compiled with, from
+    /// Cargo's name to the one to pass to rustc. Cargo's names still decide
+    /// dependency resolution, `required-features`, and build scripts'
+    /// `CARGO_FEATURE_*` environment.
+    #[serde(default)]
+    pub feature_map: BTreeMap<String, String>,
+
+    /// Use this Buck target instead of generating rules for the package's
+    /// library, such as an internal fork of it
+    pub replace_with: Option<String>,
+
+    /// Versions of the package the fixups are written for. Buckify fails if
+    /// Cargo resolves it to anything else.
+    pub expect_version: Option<semver::VersionReq>,
+
+    /// Extra entries for the package in the `metadata_file`, such as the
+    /// owning team or a link to a security review
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+
+    /// Owners of the package, instead of the configured default ones, for
+    /// the file written into its vendor directory
+    pub owners: Option<Vec<String>>,
+
     /// Common config
     #[serde(flatten)]
     base: FixupConfig,
@@ -66,7 +106,63 @@ pub struct FixupConfigFile {
     platform_fixup: BTreeMap<PlatformExpr, FixupConfig>,
 }
 
+/// Overlay `table` onto `base` for fixups' includes, and for a package's own
+/// fixups on top of shared ones. Tables are merged key by key, and arrays of
+/// tables such as `[[buildscript]]` are appended to, so a package can add its
+/// own build script fixups to shared ones. Anything else is replaced.
+fn merge_fixup_table(base: &mut toml::Table, table: toml::Table) {
+    for (key, value) in table {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(table)) => {
+                merge_fixup_table(base, table);
+            }
+            (Some(toml::Value::Array(base)), toml::Value::Array(array))
+                if base.iter().chain(&array).all(toml::Value::is_table) =>
+            {
+                base.extend(array);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 impl FixupConfigFile {
+    /// Parse a fixups.toml, with whatever it names in `include` underneath it.
+    /// Also returns the paths of the included files.
+    pub fn parse(path: &Path, file: &str) -> Result<(Self, Vec<PathBuf>)> {
+        let (table, included) = Self::parse_table(path, file)?;
+        Ok((FixupConfigFile::deserialize(table)?, included))
+    }
+
+    /// Parse the fixups.toml files of several fixup directories, each going on
+    /// top of the ones after it the same way as on top of what it includes.
+    pub fn parse_layers(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().rev() {
+            let file = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let (table, _included) = Self::parse_table(path, &file)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            merge_fixup_table(&mut merged, table);
+        }
+        Ok(FixupConfigFile::deserialize(merged)?)
+    }
+
+    fn parse_table(path: &Path, file: &str) -> Result<(toml::Table, Vec<PathBuf>)> {
+        let table: toml::Table = toml::from_str(file)?;
+        let mut included = Vec::new();
+        let table = resolve_includes(
+            path,
+            table,
+            &mut vec![path.to_owned()],
+            &mut included,
+            merge_fixup_table,
+        )?;
+        Ok((table, included))
+    }
+
     /// Generate a template for a fixup.toml as a starting point.
     pub fn template(third_party_path: &Path, target: &ManifestTarget) -> Self {
         if !target.kind_custom_build() {
@@ -99,16 +195,25 @@ impl FixupConfigFile {
     pub fn platform_configs<'a>(
         &'a self,
         version: &'a semver::Version,
-    ) -> impl Iterator<Item = (&PlatformExpr, &FixupConfig)> + 'a {
+    ) -> impl Iterator<Item = (&'a PlatformExpr, &'a FixupConfig)> + 'a {
         self.platform_fixup
             .iter()
             .filter(move |(_, cfg)| cfg.version_applies(version))
     }
 
+    /// Every config in the file, regardless of which versions it applies to.
+    pub fn all_configs(&self) -> impl Iterator<Item = (Option<&PlatformExpr>, &FixupConfig)> {
+        iter::once((None, &self.base)).chain(
+            self.platform_fixup
+                .iter()
+                .map(|(plat, cfg)| (Some(plat), cfg)),
+        )
+    }
+
     pub fn configs<'a>(
         &'a self,
         version: &'a semver::Version,
-    ) -> impl Iterator<Item = (Option<&PlatformExpr>, &FixupConfig)> + 'a {
+    ) -> impl Iterator<Item = (Option<&'a PlatformExpr>, &'a FixupConfig)> + 'a {
         self.base(version)
             .into_iter()
             .map(|base| (None, base))
@@ -136,6 +241,10 @@ pub struct FixupConfig {
     /// Extra configs
     #[serde(default)]
     pub cfgs: BTreeSet<String>,
+    /// Omit a target. In a `platform_fixup`, the target's rule is made
+    /// incompatible with the matching platforms instead.
+    #[serde(default)]
+    pub omit_targets: BTreeSet<String>,
     /// Extra features
     #[serde(default)]
     pub features: BTreeSet<String>,
@@ -153,8 +262,8 @@ pub struct FixupConfig {
     /// `true` means add all Cargo environment variables.
     /// `false` means add none.
     /// A list of environment variables names adds only those.
-    #[serde(default)]
-    pub cargo_env: CargoEnvs,
+    /// Unset means `cargo_env` from reindeer.toml.
+    pub cargo_env: Option<CargoEnvs>,
     /// Path relative to fixups_dir with overlay filesystem
     /// Files in overlay logically add to or replace files in
     /// manifest dir, and therefore have the same directory
@@ -164,17 +273,32 @@ pub struct FixupConfig {
     pub link_style: Option<String>,
     /// Rust library preferred linkage (how dependents should link you)
     pub preferred_linkage: Option<String>,
+    /// Apple frameworks the library links with, such as `Security`
+    #[serde(default)]
+    pub frameworks: BTreeSet<String>,
+    /// System libraries the library links with, such as `advapi32`
+    #[serde(default)]
+    pub system_libs: BTreeSet<String>,
 
     // Table/map-like values must come after everything else
     /// Additional env variables
     #[serde(default)]
     pub env: BTreeMap<String, String>,
+    /// Additional Buck dependencies under a crate name other than their own,
+    /// from the name the crate's code uses to the Buck target
+    #[serde(default)]
+    pub extra_named_deps: BTreeMap<String, String>,
     /// How to handle a build-script, if present
     #[serde(default)]
     pub buildscript: BuildscriptFixups,
     /// Extra mapped srcs
     #[serde(default)]
     pub extra_mapped_srcs: BTreeMap<String, PathBuf>,
+    /// Additional Buck dependencies for when a Buck config setting matches,
+    /// such as `"//build_modes:asan" = ["//third-party/asan:runtime"]`, each
+    /// in a `select()` added on to `deps`
+    #[serde(default)]
+    pub select_deps: BTreeMap<String, BTreeSet<String>>,
 }
 
 impl FixupConfig {
@@ -207,14 +331,14 @@ impl FixupConfig {
 
     /// Return true if config applies to given version
     pub fn version_applies(&self, ver: &semver::Version) -> bool {
-        self.version.as_ref().map_or(true, |req| req.matches(ver))
+        self.version.as_ref().is_none_or(|req| req.matches(ver))
     }
 }
 
 /// `cargo_env` selection.
 ///
 /// Deserializes from `true`, `false` or `["CARGO_MANIFEST_DIR", ...]`.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub enum CargoEnvs {
     All,
     #[default]
@@ -315,3 +439,48 @@ impl Serialize for CargoEnvs {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::FixupConfigFile;
+
+    #[test]
+    fn layers_package_fixups_over_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared/foo/fixups.toml");
+        let local = dir.path().join("fixups/foo/fixups.toml");
+        fs::create_dir_all(shared.parent().unwrap()).unwrap();
+        fs::create_dir_all(local.parent().unwrap()).unwrap();
+        fs::write(
+            &shared,
+            "cfgs = [\"shared\"]\n\
+             [env]\n\
+             A = \"1\"\n\
+             B = \"1\"\n\
+             [[buildscript]]\n\
+             [buildscript.rustc_flags]\n",
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "[env]\n\
+             B = \"2\"\n\
+             [[buildscript]]\n\
+             [buildscript.gen_srcs]\n",
+        )
+        .unwrap();
+
+        let fixups = FixupConfigFile::parse_layers(&[local, shared]).unwrap();
+        let base = &fixups.base;
+        assert_eq!(base.cfgs, ["shared".to_owned()].into());
+        assert_eq!(
+            base.env,
+            [("A", "1"), ("B", "2")]
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .into(),
+        );
+        assert_eq!(base.buildscript.len(), 2);
+    }
+}
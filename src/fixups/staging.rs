@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Promote fixups from `fixup_templates_dir` into `fixups` once they're done
+//!
+//! Keeping generated templates out of `fixups` until then means a half-edited
+//! one can't be committed along with everything else by accident.
+
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buckify::relative_path;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::Paths;
+
+pub(crate) fn accept_fixups(
+    config: &Config,
+    paths: &Paths,
+    packages: &[String],
+    dry_run: bool,
+) -> Result<()> {
+    let Some(staging) = &config.fixup_templates_dir else {
+        bail!("There's no `fixup_templates_dir` in reindeer.toml to accept fixups from");
+    };
+    let staging = paths.third_party_dir.join(staging);
+
+    let mut staged = Vec::new();
+    match fs::read_dir(&staging) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    staged.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context(format!("reading {}", staging.display())),
+    }
+    staged.sort();
+
+    if let Some(name) = packages.iter().find(|name| !staged.contains(name)) {
+        bail!("There are no staged fixups for `{}`", name);
+    }
+
+    for name in staged {
+        if !packages.is_empty() && !packages.contains(&name) {
+            continue;
+        }
+        let from = staging.join(&name);
+        let to = paths.third_party_dir.join("fixups").join(&name);
+        let fixup_path = from.join("fixups.toml");
+        let fixups = fs::read_to_string(&fixup_path)
+            .with_context(|| format!("reading {}", fixup_path.display()))?;
+
+        // The directory may already be there for the outputs of
+        // `reindeer run-buildscripts`, without any fixups.
+        if to.join("fixups.toml").exists() {
+            Diagnostic::new(
+                Severity::Warning,
+                format!(
+                    "{}/fixups.toml already exists, leaving the staged fixups for `{}`",
+                    relative_path(&paths.third_party_dir, &to).display(),
+                    name,
+                ),
+            )
+            .report();
+            continue;
+        }
+        if is_unfinished(&fixups) {
+            Diagnostic::new(
+                Severity::Warning,
+                format!("staged fixups for `{}` are unfinished", name),
+            )
+            .suggestion(format!(
+                "describe the build script in place of `unresolved` in {}",
+                relative_path(&paths.third_party_dir, &fixup_path).display()
+            ))
+            .report();
+            continue;
+        }
+
+        let relpath = |path| {
+            relative_path(&paths.third_party_dir, path)
+                .display()
+                .to_string()
+        };
+        if dry_run {
+            println!("Would move {} to {}:", relpath(&from), relpath(&to));
+            print!("{}", fixups);
+        } else {
+            println!("Moving {} to {}", relpath(&from), relpath(&to));
+            fs::create_dir_all(&to)?;
+            for entry in fs::read_dir(&from)? {
+                let entry = entry?;
+                let dest = to.join(entry.file_name());
+                if dest.exists() {
+                    bail!("{} is already there", dest.display());
+                }
+                fs::rename(entry.path(), &dest).with_context(|| {
+                    format!("moving {} to {}", entry.path().display(), dest.display())
+                })?;
+            }
+            fs::remove_dir(&from).with_context(|| format!("removing {}", from.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a fixups.toml still has a build script which the template left
+/// `unresolved`.
+fn is_unfinished(fixups: &str) -> bool {
+    let Ok(table) = toml::from_str::<toml::Table>(fixups) else {
+        return false;
+    };
+    match table.get("buildscript") {
+        Some(toml::Value::Array(buildscript)) => buildscript.iter().any(|entry| {
+            entry
+                .as_table()
+                .is_some_and(|entry| entry.contains_key("unresolved"))
+        }),
+        _ => false,
+    }
+}
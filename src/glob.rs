@@ -11,7 +11,6 @@ use std::iter::Empty;
 use std::path::Path;
 use std::path::PathBuf;
 
-use anyhow::bail;
 use anyhow::Result;
 use globset::Glob;
 use globset::GlobBuilder;
@@ -131,16 +130,69 @@ impl Globs {
             .into_iter()
     }
 
-    pub fn check_all_globs_used(&self) -> Result<()> {
-        if self.globs_used.len() == self.globset.len() {
-            return Ok(());
+    /// Whether a path, relative to the directory it's in, matches.
+    pub fn is_match(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.globset.is_match(path) && !self.exceptset.is_match(path)
+    }
+
+    /// Patterns which haven't matched any path walked so far.
+    pub fn unmatched(&self) -> Vec<&str> {
+        self.original_globs
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !self.globs_used.contains(idx))
+            .map(|(_, original)| original.as_str())
+            .collect()
+    }
+}
+
+/// Files in `dir` whose relative paths are closest to `glob` by edit distance,
+/// for suggesting what a glob which matched nothing may have meant.
+pub fn closest_paths(glob: &str, dir: &Path) -> Vec<PathBuf> {
+    const MAX_SUGGESTIONS: usize = 3;
+    let max_distance = (glob.len() / 4).max(2);
+
+    let mut candidates: Vec<(usize, PathBuf)> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(dir).ok()?;
+            let distance = edit_distance(glob, &path.to_string_lossy());
+            (distance <= max_distance).then(|| (distance, path.to_owned()))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Levenshtein distance, where `*` in the pattern can stand in for any run of
+/// characters.
+fn edit_distance(pattern: &str, path: &str) -> usize {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    // Distances from the previous row of the pattern to each prefix of path.
+    let mut prev: Vec<usize> = (0..=path.len()).collect();
+    for (i, &p) in pattern.iter().enumerate() {
+        let mut row = vec![i + 1; path.len() + 1];
+        if p == '*' {
+            row[0] = prev[0];
         }
-        let mut unmatched = Vec::new();
-        for (idx, original) in self.original_globs.iter().enumerate() {
-            if !self.globs_used.contains(&idx) {
-                unmatched.push(original);
-            }
+        for (j, &c) in path.iter().enumerate() {
+            row[j + 1] = if p == '*' {
+                prev[j + 1].min(row[j])
+            } else {
+                let substitute = prev[j] + usize::from(p != c);
+                substitute.min(prev[j + 1] + 1).min(row[j] + 1)
+            };
         }
-        bail!("Unmatched globs: {:?}", unmatched);
+        prev = row;
     }
+    prev[path.len()]
 }
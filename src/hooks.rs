@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Commands from the `[hooks]` config which run before buckify collects
+//! metadata, and after it has written its files.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::Paths;
+
+/// What buckify did, given to the post-buckify hooks as JSON on stdin.
+#[derive(Debug, Serialize)]
+pub struct BuckifySummary<'a> {
+    pub third_party_dir: &'a PathBuf,
+    /// Every file buckify generated, relative to the third-party directory
+    pub files: Vec<WrittenFile>,
+    pub packages: Vec<PackageSummary<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WrittenFile {
+    pub path: PathBuf,
+    /// Whether the content differs from before
+    pub changed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageSummary<'a> {
+    pub name: &'a str,
+    pub version: String,
+    pub rule: &'a str,
+}
+
+pub(crate) fn run_pre_buckify(commands: &[Vec<String>], paths: &Paths) -> Result<()> {
+    for command in commands {
+        run(command, paths, None).context("running pre_buckify hook")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn run_post_buckify(
+    commands: &[Vec<String>],
+    paths: &Paths,
+    summary: &BuckifySummary,
+) -> Result<()> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let stdin = serde_json::to_vec_pretty(summary)?;
+    for command in commands {
+        run(command, paths, Some(&stdin)).context("running post_buckify hook")?;
+    }
+    Ok(())
+}
+
+fn run(command: &[String], paths: &Paths, stdin: Option<&[u8]>) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("empty command");
+    };
+
+    log::info!("Running hook {:?}", command);
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&paths.third_party_dir)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn()
+        .with_context(|| format!("running {}", program))?;
+    if let Some(stdin) = stdin {
+        // A hook which doesn't read its input closes the pipe early, which
+        // isn't a failure of the hook.
+        let _ = child.stdin.take().unwrap().write_all(stdin);
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("`{}` failed ({})", command.join(" "), status);
+    }
+    Ok(())
+}
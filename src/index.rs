@@ -7,25 +7,38 @@
 
 //! Index for Cargo metadata, and various useful traversals.
 
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 
+use anyhow::bail;
 use anyhow::Context as _;
 use anyhow::Result;
+use semver::VersionReq;
 use serde::Deserialize;
 
 use crate::buck::Name;
+use crate::cargo::fnv_hash;
 use crate::cargo::DepKind;
 use crate::cargo::Manifest;
+use crate::cargo::ManifestDep;
 use crate::cargo::ManifestTarget;
 use crate::cargo::Metadata;
 use crate::cargo::Node;
 use crate::cargo::NodeDep;
 use crate::cargo::NodeDepKind;
 use crate::cargo::PkgId;
+use crate::cargo::Source;
 use crate::cargo::TargetReq;
+use crate::config::CollisionSuffix;
+use crate::config::Naming;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
 use crate::platform::PlatformExpr;
+use crate::platform::PlatformName;
 
 /// Index for interesting things in Cargo metadata
 pub struct Index<'meta> {
@@ -43,9 +56,18 @@ pub struct Index<'meta> {
     /// - root_pkg, if it is being made public (aka "real", and not just a pseudo package)
     /// - first-order dependencies of root_pkg, including artifact dependencies
     public_targets: BTreeMap<(&'meta PkgId, TargetReq<'meta>), Option<&'meta str>>,
+    /// Package name and as much of the version as the naming scheme calls
+    /// for, used as the start of its rule names
+    package_names: HashMap<&'meta PkgId, String>,
+    /// Additional alias names for public libraries, by semver compatibility
+    semver_aliases: HashMap<&'meta PkgId, Vec<Name>>,
+    /// Packages whose rule names have a suffix to tell them apart from
+    /// another package's
+    suffixed: BTreeSet<&'meta Manifest>,
 }
 
 /// Extra per-package metadata to be kept in sync with the package list
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct ExtraMetadata {
     pub oncall: String, // oncall shortname for use as maintainer
@@ -63,7 +85,12 @@ impl<'meta> Index<'meta> {
     /// Construct an index for a set of Cargo metadata to allow convenient and efficient
     /// queries. The metadata represents a top level package and all its transitive
     /// dependencies.
-    pub fn new(root_is_real: bool, metadata: &'meta Metadata) -> Result<Index<'meta>> {
+    pub fn new(
+        root_is_real: bool,
+        naming: Naming,
+        collision_suffix: CollisionSuffix,
+        metadata: &'meta Metadata,
+    ) -> Result<Index<'meta>> {
         let pkgid_to_pkg: HashMap<_, _> = metadata.packages.iter().map(|m| (&m.id, m)).collect();
 
         let root_pkg = metadata.resolve.root.as_ref().map(|root_pkgid| {
@@ -95,43 +122,123 @@ impl<'meta> Index<'meta> {
             workspace_members,
             public_packages: BTreeSet::new(),
             public_targets: BTreeMap::new(),
+            package_names: HashMap::new(),
+            semver_aliases: HashMap::new(),
+            suffixed: BTreeSet::new(),
         };
 
-        // Keep an index of renamed crates, mapping from _ normalized name to actual name.
-        // Only the root package's renames matter. We don't attempt to merge different
-        // rename choices made by different workspace members.
-        let dep_renamed: HashMap<String, &'meta str> = root_pkg
-            .iter()
-            .flat_map(|root_pkg| &root_pkg.dependencies)
-            .filter_map(|dep| {
-                let rename = dep.rename.as_deref()?;
-                Some((rename.replace('-', "_"), rename))
-            })
+        // Only the root package comes first, so that its choices win.
+        let members: Vec<&Manifest> = root_pkg
+            .into_iter()
+            .chain(
+                tmp.workspace_members
+                    .iter()
+                    .copied()
+                    .filter(|member| root_pkg.is_none_or(|root| root.id != member.id)),
+            )
             .collect();
 
+        // Keep an index of renamed crates, mapping from _ normalized name to actual name.
+        // Renames come from every workspace member, not just the root package,
+        // because with `[workspace.dependencies]` inheritance a virtual manifest's
+        // members all carry the workspace's renames. If members disagree about
+        // the spelling of a rename the root package's choice wins. Features
+        // need nothing of the kind: inherited ones are already merged into the
+        // resolved graph by Cargo.
+        let mut dep_renamed: HashMap<String, &'meta str> = HashMap::new();
+        for member in &members {
+            for dep in &member.dependencies {
+                if let Some(rename) = dep.rename.as_deref() {
+                    dep_renamed
+                        .entry(rename.replace('-', "_"))
+                        .or_insert(rename);
+                }
+            }
+        }
+
         // Compute public set, with pkgid mapped to rename if it has one. Public set is
         // anything in top_levels, or first-order dependencies of any workspace member.
-        let public_targets = tmp
-            .workspace_members
-            .iter()
-            .flat_map(|member| tmp.resolved_deps(member))
-            .flat_map(|(rename, dep_kind, pkg)| {
-                let target_req = dep_kind.target_req();
-                let opt_rename = dep_renamed.get(rename).cloned();
-                vec![((&pkg.id, target_req), opt_rename)]
-            })
-            .chain(top_levels.iter().flat_map(|pkgid| {
-                [
-                    ((*pkgid, TargetReq::Lib), None),
-                    ((*pkgid, TargetReq::EveryBin), None),
-                ]
-            }))
-            .collect::<BTreeMap<_, _>>();
+        // A package only gets one alias, so when it is renamed differently
+        // for different targets or by different members, the first name wins,
+        // in the order of the members and then of the names.
+        let mut public_targets: BTreeMap<(&PkgId, TargetReq), Option<&str>> = BTreeMap::new();
+        for member in &members {
+            for (rename, dep_kind, pkg) in tmp.resolved_deps(member) {
+                let opt_rename = dep_renamed.get(rename).copied();
+                match public_targets.entry((&pkg.id, dep_kind.target_req())) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(opt_rename);
+                    }
+                    Entry::Occupied(entry) => {
+                        let alias = entry.get().unwrap_or(&pkg.name);
+                        if alias != opt_rename.unwrap_or(&pkg.name) {
+                            Diagnostic::new(
+                                Severity::Warning,
+                                format!(
+                                    "depended on as both `{}` and `{}`, \
+                                     only `{}` gets an alias",
+                                    alias,
+                                    opt_rename.unwrap_or(rename),
+                                    alias,
+                                ),
+                            )
+                            .package(pkg)
+                            .report();
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(pkgid) = top_levels {
+            public_targets.insert((pkgid, TargetReq::Lib), None);
+            public_targets.insert((pkgid, TargetReq::EveryBin), None);
+        }
 
         for (pkg, _kind) in public_targets.keys() {
             tmp.public_packages.insert(pkg);
         }
 
+        // Two libraries with the same alias would leave one of them out.
+        let mut aliased: HashMap<&str, &Manifest> = HashMap::new();
+        for ((pkgid, target_req), rename) in &public_targets {
+            if *target_req != TargetReq::Lib {
+                continue;
+            }
+            let pkg = tmp.pkgid_to_pkg[pkgid];
+            let name = rename.unwrap_or(&pkg.name);
+            if let Some(other) = aliased.insert(name, pkg) {
+                if other.id != pkg.id {
+                    bail!(
+                        "`{}` would be the alias of both {} ({}) and {} ({}), \
+                         rename one of them in the workspace's dependencies",
+                        name,
+                        other,
+                        other.id,
+                        pkg,
+                        pkg.id,
+                    );
+                }
+            }
+        }
+
+        // Public packages' aliases already go without a version, so their
+        // rules can't take the same name.
+        let public_names: HashSet<&str> = public_targets
+            .iter()
+            .map(|((pkgid, _), rename)| rename.unwrap_or(&tmp.pkgid_to_pkg[pkgid].name))
+            .collect();
+        tmp.package_names = package_names(naming, &metadata.packages, &public_names);
+        tmp.suffixed =
+            add_collision_suffixes(collision_suffix, &metadata.packages, &mut tmp.package_names);
+
+        let public_libs = public_targets
+            .keys()
+            .filter(|(pkgid, target_req)| {
+                *target_req == TargetReq::Lib && root_pkg.is_none_or(|root| root.id != **pkgid)
+            })
+            .map(|(pkgid, _)| tmp.pkgid_to_pkg[pkgid]);
+        tmp.semver_aliases = semver_aliases(public_libs, &public_names);
+
         Ok(Index {
             public_targets,
             ..tmp
@@ -156,11 +263,41 @@ impl<'meta> Index<'meta> {
         self.public_targets.contains_key(&(&pkg.id, target_req))
     }
 
+    /// Return the package's name and version as they appear in its rule
+    /// names, such as `foo-1.2.3`.
+    pub fn package_rule_name(&self, pkg: &Manifest) -> &str {
+        &self.package_names[&pkg.id]
+    }
+
+    /// Return the package's name and full version, such as `foo-1.2.3`, with
+    /// the suffix which tells it apart from another package if it has one.
+    pub fn versioned_rule_name(&self, pkg: &Manifest) -> String {
+        if self.suffixed.contains(pkg) {
+            self.package_rule_name(pkg).to_owned()
+        } else {
+            pkg.to_string()
+        }
+    }
+
+    /// Return the packages whose rule names have a suffix because another
+    /// package's would have been the same.
+    pub fn suffixed_packages(&self) -> impl Iterator<Item = &'meta Manifest> + '_ {
+        self.suffixed.iter().copied()
+    }
+
+    /// Return the names of `alias` rules such as `foo-1` and `foo` which
+    /// point at this package, as the newest public version of its name
+    /// compatible with the version in the alias.
+    pub fn semver_aliases(&self, pkg: &Manifest) -> &[Name] {
+        self.semver_aliases.get(&pkg.id).map_or(&[], Vec::as_slice)
+    }
+
     /// Return the private package rule name.
     pub fn private_rule_name(&self, pkg: &Manifest) -> Name {
+        let package_rule_name = self.package_rule_name(pkg);
         Name(match self.public_targets.get(&(&pkg.id, TargetReq::Lib)) {
-            Some(None) | None => pkg.to_string(), // Full version info
-            Some(Some(rename)) => format!("{}-{}", pkg, rename), // Rename
+            Some(None) | None => package_rule_name.to_owned(), // Version info
+            Some(Some(rename)) => format!("{}-{}", package_rule_name, rename), // Rename
         })
     }
 
@@ -172,6 +309,18 @@ impl<'meta> Index<'meta> {
         })
     }
 
+    /// Find the newest package in the graph which satisfies a dependency that
+    /// Cargo didn't resolve, such as a dev-dependency of a third-party
+    /// package.
+    pub fn find_unresolved_dep(&self, dep: &ManifestDep) -> Option<&'meta Manifest> {
+        let req = VersionReq::parse(&dep.req).ok()?;
+        self.pkgid_to_pkg
+            .values()
+            .copied()
+            .filter(|pkg| pkg.name == dep.name && req.matches(&pkg.version))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+
     /// Return the set of features resolved for a particular package
     pub fn resolved_features(&self, pkg: &Manifest) -> impl Iterator<Item = &'meta str> {
         self.pkgid_to_node
@@ -182,6 +331,34 @@ impl<'meta> Index<'meta> {
             .map(String::as_str)
     }
 
+    /// Find the shortest dependency chain from a workspace member to every
+    /// package in the graph, counting dependencies of any kind and on any
+    /// platform. Dependencies of packages for which `stop` is true aren't
+    /// followed.
+    pub fn dependency_chains(&self, stop: impl Fn(&Manifest) -> bool) -> DependencyChains<'meta> {
+        let mut reached_from = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        for &member in &self.workspace_members {
+            if reached_from.insert(member, None).is_none() {
+                queue.push_back(member);
+            }
+        }
+
+        while let Some(pkg) = queue.pop_front() {
+            if stop(pkg) {
+                continue;
+            }
+            for (_, _, dep) in self.resolved_deps(pkg) {
+                if let Entry::Vacant(entry) = reached_from.entry(dep) {
+                    entry.insert(Some(pkg));
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        DependencyChains { reached_from }
+    }
+
     /// Return the resolved dependencies for a package
     /// This should generally be filtered by a target, but for the top-level we don't really care
     fn resolved_deps(
@@ -224,7 +401,12 @@ impl<'meta> Index<'meta> {
         for (rename, dep_kind, dep) in self.resolved_deps(pkg) {
             if match dep_kind.kind {
                 DepKind::Normal => {
-                    tgt.kind_lib() || tgt.kind_proc_macro() || tgt.kind_bin() || tgt.kind_cdylib()
+                    tgt.kind_lib()
+                        || tgt.kind_proc_macro()
+                        || tgt.kind_bin()
+                        || tgt.kind_cdylib()
+                        || tgt.kind_bench()
+                        || tgt.kind_example()
                 }
                 DepKind::Dev => tgt.kind_bench() || tgt.kind_test() || tgt.kind_example(),
                 DepKind::Build => tgt.kind_custom_build(),
@@ -281,4 +463,349 @@ impl<'meta> Index<'meta> {
                 dep_kind,
             })
     }
+
+    /// Packages with at least one public target
+    pub fn public_packages(&self) -> impl Iterator<Item = &'meta Manifest> + '_ {
+        self.public_packages
+            .iter()
+            .map(|pkgid| self.pkgid_to_pkg[pkgid])
+    }
+
+    /// Platforms to generate each package for, as far as `top_level_platforms`
+    /// narrows them down. Packages which any public package without an entry
+    /// there depends on are needed on every platform, and left out.
+    pub fn restricted_platforms(
+        &self,
+        top_level_platforms: &'meta BTreeMap<String, BTreeSet<PlatformName>>,
+    ) -> HashMap<&'meta PkgId, BTreeSet<&'meta PlatformName>> {
+        let restricted: HashMap<&PkgId, &BTreeSet<PlatformName>> = self
+            .public_packages
+            .iter()
+            .filter_map(|pkgid| {
+                let platforms = top_level_platforms.get(&self.pkgid_to_pkg[pkgid].name)?;
+                Some((*pkgid, platforms))
+            })
+            .collect();
+
+        // Everything reachable other than through the restricted packages
+        let mut unrestricted = HashSet::new();
+        let mut queue = VecDeque::new();
+        for &member in &self.workspace_members {
+            if unrestricted.insert(&member.id) {
+                queue.push_back(member);
+            }
+        }
+        while let Some(pkg) = queue.pop_front() {
+            let is_member = self.workspace_members.contains(&pkg);
+            for (_, _, dep) in self.resolved_deps(pkg) {
+                if is_member && restricted.contains_key(&dep.id) {
+                    continue;
+                }
+                if unrestricted.insert(&dep.id) {
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        let mut platforms: HashMap<&PkgId, BTreeSet<&PlatformName>> = HashMap::new();
+        for (&pkgid, restriction) in &restricted {
+            let mut seen = HashSet::from([pkgid]);
+            let mut queue = VecDeque::from([self.pkgid_to_pkg[pkgid]]);
+            while let Some(pkg) = queue.pop_front() {
+                if !unrestricted.contains(&pkg.id) {
+                    platforms
+                        .entry(&pkg.id)
+                        .or_default()
+                        .extend(restriction.iter());
+                }
+                for (_, _, dep) in self.resolved_deps(pkg) {
+                    if seen.insert(&dep.id) {
+                        queue.push_back(dep);
+                    }
+                }
+            }
+        }
+        platforms
+    }
+}
+
+/// Result of `Index::dependency_chains`
+pub struct DependencyChains<'meta> {
+    /// The package through which each one was first reached, or None for
+    /// workspace members
+    reached_from: BTreeMap<&'meta Manifest, Option<&'meta Manifest>>,
+}
+
+impl<'meta> DependencyChains<'meta> {
+    pub fn packages(&self) -> impl Iterator<Item = &'meta Manifest> + '_ {
+        self.reached_from.keys().copied()
+    }
+
+    /// Packages from a workspace member down to `pkg`, inclusive.
+    pub fn chain(&self, pkg: &'meta Manifest) -> Vec<&'meta Manifest> {
+        let mut chain = vec![pkg];
+        let mut next = self.reached_from[pkg];
+        while let Some(dependent) = next {
+            chain.push(dependent);
+            next = self.reached_from[dependent];
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+/// Render a dependency chain as `a-1.0.0 -> b-2.0.0`.
+pub fn display_chain(chain: &[&Manifest]) -> String {
+    let names: Vec<String> = chain.iter().map(ToString::to_string).collect();
+    names.join(" -> ")
+}
+
+/// The part of a version which semver considers breaking changes to, such as
+/// `1` for 1.2.3 or `0.2` for 0.2.3.
+pub fn semver_compatible_version(version: &semver::Version) -> String {
+    if version.major != 0 {
+        version.major.to_string()
+    } else if version.minor != 0 {
+        format!("0.{}", version.minor)
+    } else {
+        format!("0.0.{}", version.patch)
+    }
+}
+
+fn package_names<'meta>(
+    naming: Naming,
+    packages: &'meta BTreeSet<Manifest>,
+    public_names: &HashSet<&str>,
+) -> HashMap<&'meta PkgId, String> {
+    let short_name = |pkg: &Manifest| match naming {
+        Naming::Versioned => pkg.to_string(),
+        Naming::Major => format!("{}-{}", pkg.name, semver_compatible_version(&pkg.version)),
+        Naming::Unversioned => pkg.name.clone(),
+    };
+
+    let mut count: HashMap<String, usize> = HashMap::new();
+    for pkg in packages {
+        *count.entry(short_name(pkg)).or_default() += 1;
+    }
+
+    packages
+        .iter()
+        .map(|pkg| {
+            let name = short_name(pkg);
+            if count[&name] == 1 && !public_names.contains(name.as_str()) {
+                (&pkg.id, name)
+            } else {
+                (&pkg.id, pkg.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Tell apart packages with the same name and version from different
+/// sources, by adding a suffix to the names of those not from crates.io.
+/// Returns the packages which got one.
+fn add_collision_suffixes<'meta>(
+    collision_suffix: CollisionSuffix,
+    packages: &'meta BTreeSet<Manifest>,
+    names: &mut HashMap<&'meta PkgId, String>,
+) -> BTreeSet<&'meta Manifest> {
+    let mut suffixed = BTreeSet::new();
+    // Sources can look alike too, such as two commits of one git repository,
+    // which the hash of the package ID tells apart.
+    for scheme in [collision_suffix, CollisionSuffix::Hash] {
+        let mut by_name: HashMap<&str, Vec<&Manifest>> = HashMap::new();
+        for pkg in packages {
+            by_name.entry(&names[&pkg.id]).or_default().push(pkg);
+        }
+        let colliding: Vec<&Manifest> = by_name
+            .into_values()
+            .filter(|pkgs| pkgs.len() > 1)
+            .flatten()
+            .filter(|pkg| pkg.source != Source::CratesIo)
+            .collect();
+        for pkg in colliding {
+            let name = names.get_mut(&pkg.id).unwrap();
+            name.push('-');
+            name.push_str(&source_suffix(scheme, pkg));
+            suffixed.insert(pkg);
+        }
+    }
+    suffixed
+}
+
+fn source_suffix(scheme: CollisionSuffix, pkg: &Manifest) -> String {
+    let hash = || format!("{:08x}", fnv_hash(pkg.id.0.as_bytes()) as u32);
+    if scheme == CollisionSuffix::Hash {
+        return hash();
+    }
+    match &pkg.source {
+        Source::Local => "local".to_owned(),
+        Source::CratesIo => String::new(),
+        Source::Git { repo, .. } => {
+            let repo = repo.trim_end_matches('/').trim_end_matches(".git");
+            format!("git-{}", repo.rsplit('/').next().unwrap_or(repo))
+        }
+        // Such as `sparse+https://registry.example.com/index/`
+        Source::Unrecognized(source) => {
+            let url = source
+                .split_once('+')
+                .map_or(source.as_str(), |(_, url)| url);
+            match url::Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_owned))
+            {
+                Some(host) => host.replace('.', "-"),
+                None => hash(),
+            }
+        }
+    }
+}
+
+fn semver_aliases<'meta>(
+    public_libs: impl Iterator<Item = &'meta Manifest>,
+    public_names: &HashSet<&str>,
+) -> HashMap<&'meta PkgId, Vec<Name>> {
+    let mut newest: BTreeMap<String, &Manifest> = BTreeMap::new();
+    for pkg in public_libs {
+        let compatible = format!("{}-{}", pkg.name, semver_compatible_version(&pkg.version));
+        for name in [compatible, pkg.name.clone()] {
+            // Existing public aliases, such as renames, take precedence.
+            if public_names.contains(name.as_str()) {
+                continue;
+            }
+            let entry = newest.entry(name).or_insert(pkg);
+            if pkg.version > entry.version {
+                *entry = pkg;
+            }
+        }
+    }
+
+    let mut aliases: HashMap<&PkgId, Vec<Name>> = HashMap::new();
+    for (name, pkg) in newest {
+        aliases.entry(&pkg.id).or_default().push(Name(name));
+    }
+    aliases
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use serde_json::Value;
+
+    use super::Index;
+    use crate::cargo::Metadata;
+    use crate::config::CollisionSuffix;
+    use crate::config::Naming;
+
+    fn package(name: &str, id: &str, dependencies: Value) -> Value {
+        json!({
+            "name": name,
+            "version": "1.0.0",
+            "id": id,
+            "source": null,
+            "dependencies": dependencies,
+            "targets": [{
+                "name": name.replace('-', "_"),
+                "kind": ["lib"],
+                "crate_types": ["lib"],
+                "src_path": format!("/{}/src/lib.rs", name),
+                "doctest": true,
+            }],
+            "features": {},
+            "manifest_path": format!("/{}/Cargo.toml", name),
+            "metadata": null,
+            "authors": [],
+            "categories": [],
+            "keywords": [],
+            "edition": "2021",
+            "links": null,
+        })
+    }
+
+    fn dependency(rename: &str, target: &str) -> Value {
+        json!({
+            "name": "foo",
+            "source": null,
+            "req": "^1",
+            "rename": rename,
+            "kind": null,
+            "optional": false,
+            "uses_default_features": true,
+            "features": [],
+            "target": target,
+        })
+    }
+
+    fn node_dep(name: &str, target: &str) -> Value {
+        json!({
+            "pkg": "foo",
+            "name": name,
+            "dep_kinds": [{"kind": null, "target": target}],
+        })
+    }
+
+    #[test]
+    fn renamed_per_target() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "packages": [
+                package(
+                    "rust-third-party",
+                    "root",
+                    json!([
+                        dependency("foo-windows", "cfg(windows)"),
+                        dependency("foo-unix", "cfg(unix)"),
+                    ]),
+                ),
+                package("foo", "foo", json!([])),
+            ],
+            "version": 1,
+            "workspace_default_members": ["root"],
+            "resolve": {
+                "root": "root",
+                "nodes": [
+                    {
+                        "id": "root",
+                        "deps": [
+                            node_dep("foo_windows", "cfg(windows)"),
+                            node_dep("foo_unix", "cfg(unix)"),
+                        ],
+                        "features": [],
+                    },
+                    {"id": "foo", "deps": [], "features": []},
+                ],
+            },
+        }))
+        .unwrap();
+
+        let index =
+            Index::new(false, Naming::Versioned, CollisionSuffix::Source, &metadata).unwrap();
+        let root = metadata
+            .packages
+            .iter()
+            .find(|pkg| pkg.id.0 == "root")
+            .unwrap();
+        let foo = metadata
+            .packages
+            .iter()
+            .find(|pkg| pkg.id.0 == "foo")
+            .unwrap();
+
+        // Each platform's dependency keeps its own name.
+        let mut renames: Vec<(String, &str)> = index
+            .resolved_deps_for_target(root, root.targets.first().unwrap())
+            .map(|dep| (dep.platform.unwrap().to_string(), dep.rename))
+            .collect();
+        renames.sort();
+        assert_eq!(
+            renames,
+            [
+                ("cfg(unix)".to_owned(), "foo_unix"),
+                ("cfg(windows)".to_owned(), "foo_windows"),
+            ],
+        );
+
+        // The package only gets one alias, the first name as the manifest
+        // spells it.
+        assert_eq!(index.public_rule_name(foo).to_string(), "foo-unix");
+    }
 }
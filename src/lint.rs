@@ -0,0 +1,386 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Check fixups for things that no longer apply to the dependency graph
+//!
+//! Fixups are keyed by package name and refer to dependencies, versions and
+//! platforms which can all change underneath them when Cargo.lock is updated.
+//! None of that is an error for buckify, so stale entries otherwise go
+//! unnoticed.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buckify::normalize_dotdot;
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::cargo::Metadata;
+use crate::config::Config;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::fixups::buildscript::BuildscriptFixup;
+use crate::fixups::config::CargoEnvs;
+use crate::fixups::config::FixupConfigFile;
+use crate::fixups::extra_srcs_matches;
+use crate::platform::platform_names_for_expr;
+use crate::platform::PlatformExpr;
+use crate::platform::PlatformPredicate;
+use crate::Args;
+use crate::Paths;
+
+pub(crate) fn lint(config: &Config, args: &Args, paths: &Paths) -> Result<()> {
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+
+    let mut packages: BTreeMap<&str, Vec<&Manifest>> = BTreeMap::new();
+    for pkg in &metadata.packages {
+        packages.entry(&pkg.name).or_default().push(pkg);
+    }
+
+    let mut problems = 0;
+    for fixup_dir in find_fixup_dirs(config, paths)? {
+        let mut report = |message: String| {
+            println!("{}: {}", fixup_dir.dir.display(), message);
+            problems += 1;
+        };
+
+        let Some(versions) = packages.get(fixup_dir.name.as_str()) else {
+            if !fixup_dir.shared && !fixup_dir.included {
+                report(format!(
+                    "no package `{}` in the dependency graph",
+                    fixup_dir.name
+                ));
+            }
+            continue;
+        };
+
+        let fixup_config = match fixup_dir.parsed {
+            None => continue,
+            Some(Ok((fixup_config, _included))) => fixup_config,
+            Some(Err(err)) => {
+                report(format!("failed to parse: {:#}", err));
+                continue;
+            }
+        };
+
+        lint_fixup(config, &metadata, versions, &fixup_config, &mut report);
+    }
+
+    if problems != 0 {
+        return Err(Failure::new(
+            FailureKind::Check,
+            format!("{} problems found in fixups", problems),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A package's directory in `fixups` or one of the `extra_fixup_dirs`
+pub(crate) struct FixupDir {
+    /// Relative to the third-party directory
+    pub dir: PathBuf,
+    pub name: String,
+    /// In one of the `extra_fixup_dirs`, which are expected to have fixups
+    /// for packages which this dependency graph doesn't use
+    pub shared: bool,
+    /// Holds files which other fixups include, rather than being for a
+    /// package
+    pub included: bool,
+    /// Its fixups.toml, if it has one
+    pub parsed: Option<Result<(FixupConfigFile, Vec<PathBuf>)>>,
+}
+
+pub(crate) fn find_fixup_dirs(config: &Config, paths: &Paths) -> Result<Vec<FixupDir>> {
+    let mut found = Vec::new();
+    let fixups_dirs = iter::once((Path::new("fixups"), false)).chain(
+        config
+            .extra_fixup_dirs
+            .iter()
+            .map(|dir| (dir.as_path(), true)),
+    );
+    for (fixups_dir, shared) in fixups_dirs {
+        let entries = match fs::read_dir(paths.third_party_dir.join(fixups_dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).context(format!("reading {}", fixups_dir.display())),
+        };
+
+        let mut fixup_dirs = BTreeSet::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                fixup_dirs.insert(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        for name in fixup_dirs {
+            let fixup_dir = fixups_dir.join(&name);
+            let fixup_path = paths.third_party_dir.join(&fixup_dir).join("fixups.toml");
+            let parsed = match fs::read_to_string(&fixup_path) {
+                Ok(file) => Some(FixupConfigFile::parse(&fixup_path, &file)),
+                Err(err) if err.kind() == ErrorKind::NotFound => None,
+                Err(err) => return Err(err).context(format!("reading {}", fixup_path.display())),
+            };
+            found.push(FixupDir {
+                dir: fixup_dir,
+                name,
+                shared,
+                included: false,
+                parsed,
+            });
+        }
+    }
+
+    let mut included_dirs = BTreeSet::new();
+    for fixup_dir in &found {
+        if let Some(Ok((_, included))) = &fixup_dir.parsed {
+            for path in included {
+                included_dirs.insert(normalize_dotdot(path.parent().unwrap()));
+            }
+        }
+    }
+    for fixup_dir in &mut found {
+        fixup_dir.included = included_dirs.contains(&paths.third_party_dir.join(&fixup_dir.dir));
+    }
+    Ok(found)
+}
+
+fn lint_fixup(
+    config: &Config,
+    metadata: &Metadata,
+    versions: &[&Manifest],
+    fixup_config: &FixupConfigFile,
+    report: &mut impl FnMut(String),
+) {
+    // The names every version of the package knows its dependencies by, which
+    // is what `omit_deps` refers to.
+    let mut dep_names = BTreeSet::new();
+    for pkg in versions {
+        if let Some(node) = metadata.resolve.nodes.iter().find(|node| node.id == pkg.id) {
+            for dep in &node.deps {
+                let names = dep
+                    .dep_kinds
+                    .iter()
+                    .filter_map(|dep_kind| dep_kind.extern_name.as_deref());
+                dep_names.extend(dep.name.as_deref().into_iter().chain(names));
+            }
+        }
+    }
+
+    if let Some(replace_with) = &fixup_config.replace_with {
+        if !is_target_label(replace_with) {
+            report(format!(
+                "replace_with: `{}` is not a Buck target",
+                replace_with
+            ));
+        }
+    }
+
+    for feature in fixup_config.feature_map.keys() {
+        let known = versions
+            .iter()
+            .any(|pkg| pkg.features.contains_key(feature))
+            || fixup_config
+                .all_configs()
+                .any(|(_, fixup)| fixup.features.contains(feature));
+        if !known {
+            report(format!("feature_map: no feature named `{}`", feature));
+        }
+    }
+
+    // `all_configs` always starts with the base config.
+    let (_, base) = fixup_config.all_configs().next().unwrap();
+
+    for (platform, fixup) in fixup_config.all_configs() {
+        let section = match platform {
+            None => String::new(),
+            Some(platform) => format!("[platform_fixup.'{}'] ", platform),
+        };
+
+        if let Some(platform) = platform {
+            match platform_names_for_expr(config, platform) {
+                // Such as `cfg(test)`, whose extra deps go to doctest rules
+                Ok(names) if names.is_empty() && only_for_tests(config, platform) => {}
+                Ok(names) if names.is_empty() => {
+                    report(format!(
+                        "{}matches none of the configured platforms",
+                        section
+                    ));
+                }
+                Ok(_) => {}
+                Err(err) => report(format!("{}bad platform expression: {}", section, err)),
+            }
+        }
+
+        if let Some(req) = &fixup.version {
+            if !versions.iter().any(|pkg| req.matches(&pkg.version)) {
+                report(format!(
+                    "{}version `{}` matches none of {}",
+                    section,
+                    req,
+                    versions
+                        .iter()
+                        .map(|pkg| pkg.version.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ));
+            }
+        }
+
+        for omit in &fixup.omit_deps {
+            if !dep_names.contains(omit.as_str()) {
+                report(format!(
+                    "{}omit_deps: no dependency named `{}`",
+                    section, omit
+                ));
+            }
+        }
+
+        let applicable: Vec<&Manifest> = versions
+            .iter()
+            .copied()
+            .filter(|pkg| fixup.version_applies(&pkg.version) && pkg.manifest_dir().exists())
+            .collect();
+        for glob in &fixup.extra_srcs {
+            let mut matched = false;
+            for pkg in &applicable {
+                match extra_srcs_matches(pkg.manifest_dir(), glob) {
+                    Ok(matches) => matched |= !matches.is_empty(),
+                    Err(err) => {
+                        report(format!(
+                            "{}extra_srcs: bad glob `{}`: {}",
+                            section, glob, err
+                        ));
+                        matched = true;
+                        break;
+                    }
+                }
+            }
+            if !matched && !applicable.is_empty() {
+                report(format!(
+                    "{}extra_srcs: `{}` matches no files",
+                    section, glob
+                ));
+            }
+        }
+
+        // `env` entries which something else in the same section replaces.
+        let cargo_env = match (&fixup.cargo_env, platform) {
+            (Some(cargo_env), _) => cargo_env,
+            (None, None) => &config.cargo_env,
+            (None, Some(_)) => &CargoEnvs::None,
+        };
+        let cargo_envs: BTreeSet<String> = cargo_env
+            .iter()
+            .map(|cargo_env| cargo_env.to_string())
+            .collect();
+        let prerun_envs: BTreeSet<&str> = fixup
+            .buildscript
+            .iter()
+            .filter_map(|buildscript| match buildscript {
+                BuildscriptFixup::Prerun(prerun) => Some(prerun.env.keys().map(String::as_str)),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        for (name, value) in &fixup.env {
+            if cargo_envs.contains(name) {
+                report(format!(
+                    "{}env: `{}` is overridden by cargo_env",
+                    section, name
+                ));
+            } else if prerun_envs.contains(name.as_str()) {
+                report(format!(
+                    "{}env: `{}` is overridden by the prerun build script",
+                    section, name
+                ));
+            } else if platform.is_some() && base.env.get(name) == Some(value) {
+                report(format!(
+                    "{}env: `{}` is already set to the same value without a platform",
+                    section, name
+                ));
+            }
+        }
+
+        let extra_deps = fixup
+            .extra_deps
+            .iter()
+            .chain(fixup.extra_named_deps.values());
+        for dep in extra_deps {
+            if !is_target_label(dep) {
+                report(format!(
+                    "{}extra_deps: `{}` is not a Buck target",
+                    section, dep
+                ));
+            }
+        }
+        for (setting, deps) in &fixup.select_deps {
+            for label in iter::once(setting).chain(deps) {
+                if !is_target_label(label) {
+                    report(format!(
+                        "{}select_deps: `{}` is not a Buck target",
+                        section, label
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Whether `label` has the shape of a Buck target: `:name`, `//path:name`,
+/// `cell//path:name`, or `//path/name` as shorthand for `//path/name:name`.
+fn is_target_label(label: &str) -> bool {
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && !name
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, ':' | '/' | '"'))
+    };
+
+    if let Some(name) = label.strip_prefix(':') {
+        return valid_name(name);
+    }
+
+    let Some((cell, rest)) = label.split_once("//") else {
+        return false;
+    };
+    let cell_ok = cell
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let (package, name) = match rest.split_once(':') {
+        Some((package, name)) => (package, Some(name)),
+        None => (rest, None),
+    };
+    let package_ok = !package.starts_with('/')
+        && !package.ends_with('/')
+        && !package.contains("//")
+        && !package.chars().any(char::is_whitespace);
+    let name_ok = match name {
+        Some(name) => valid_name(name),
+        None => !package.is_empty(),
+    };
+    cell_ok && package_ok && name_ok
+}
+
+/// Whether a platform expression matches some platform once `test` and
+/// `doctest` are set.
+fn only_for_tests(config: &Config, expr: &PlatformExpr) -> bool {
+    PlatformPredicate::parse(expr).is_ok_and(|pred| {
+        config
+            .platform
+            .values()
+            .any(|platform| pred.eval(&platform.for_tests()))
+    })
+}
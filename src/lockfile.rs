@@ -6,6 +6,7 @@
  */
 
 use std::fs;
+use std::path::Path;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -14,30 +15,42 @@ use serde::Deserializer;
 
 use crate::cargo::Manifest;
 use crate::cargo::Source;
-use crate::Paths;
 
 #[derive(Deserialize, Debug)]
 pub struct Lockfile {
+    #[allow(dead_code)]
     pub version: Hopefully3,
     #[serde(rename = "package")]
     pub packages: Vec<LockfilePackage>,
 }
 
 impl Lockfile {
-    pub fn load(paths: &Paths) -> Result<Self> {
-        let cargo_lock_content = fs::read_to_string(&paths.lockfile_path)
-            .with_context(|| format!("Failed to load {}", paths.lockfile_path.display()))?;
+    pub fn load(lockfile_path: &Path) -> Result<Self> {
+        let cargo_lock_content = fs::read_to_string(lockfile_path)
+            .with_context(|| format!("Failed to load {}", lockfile_path.display()))?;
 
         let mut lockfile: Lockfile = toml::from_str(&cargo_lock_content)
-            .with_context(|| format!("Failed to parse {}", paths.lockfile_path.display()))?;
+            .with_context(|| format!("Failed to parse {}", lockfile_path.display()))?;
 
-        lockfile.packages.sort_by(|a, b| {
+        lockfile.sort();
+
+        Ok(lockfile)
+    }
+
+    /// Add the packages of another workspace's lockfile.
+    pub fn merge(&mut self, other: Lockfile) {
+        self.packages.extend(other.packages);
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.packages.sort_by(|a, b| {
             let a = (&a.name, &a.version, &a.source);
             let b = (&b.name, &b.version, &b.source);
             a.cmp(&b)
         });
-
-        Ok(lockfile)
+        self.packages
+            .dedup_by(|a, b| (&a.name, &a.version, &a.source) == (&b.name, &b.version, &b.source));
     }
 
     pub fn find(&self, manifest: &Manifest) -> Option<&LockfilePackage> {
@@ -20,26 +20,59 @@
 //!
 //! (TBD - rest of it)
 
+#![allow(clippy::type_complexity)]
+
+use std::iter;
 use std::path::PathBuf;
 
+use anyhow::Context;
 use anyhow::Result;
 use structopt::StructOpt;
 
+use crate::platform::PlatformExpr;
+
+mod abspaths;
+mod audit;
 mod audit_sec;
 mod buck;
 mod buckify;
 mod cargo;
+mod cargo_profile;
 mod cfg;
+mod clean;
 mod collection;
 mod config;
+mod cycles;
+mod date;
+mod deny;
+mod diagnostics;
+mod fingerprint;
 mod fixups;
 mod glob;
+mod hooks;
 mod index;
+mod lint;
 mod lockfile;
+mod mirror;
+mod msrv;
+mod nonvendored;
+mod owners;
 mod platform;
+mod platform_targets;
+mod plugins;
+mod profile;
+mod pseudo_package;
 mod remap;
+mod report;
+mod run_buildscripts;
+mod rustc_flags;
 mod srcfiles;
+mod srcs_cache;
+mod unused;
+mod update;
+mod validate;
 mod vendor;
+mod yanked;
 
 #[derive(Debug, StructOpt)]
 #[structopt(bin_name = "reindeer")]
@@ -56,9 +89,39 @@ pub struct Args {
     /// Extra cargo options
     #[structopt(long)]
     cargo_options: Vec<String>,
+    /// Write the warnings and errors found to this file as JSON
+    #[structopt(long)]
+    diagnostics_json: Option<PathBuf>,
+    /// Never access the network or change Cargo.lock; fail instead
+    #[structopt(long)]
+    offline: bool,
+    /// Use this output of `cargo metadata --format-version 1` instead of
+    /// running it
+    #[structopt(long)]
+    metadata_json: Option<PathBuf>,
+    /// Write how long each phase took, and the slowest packages, to this
+    /// file
+    #[structopt(long)]
+    profile: Option<PathBuf>,
+    /// Format of the `--profile` output: json, or folded stacks for
+    /// flamegraph tools
+    #[structopt(long, default_value = "json")]
+    profile_format: profile::ProfileFormat,
+    /// How to print the error if the run fails: human, or json for
+    /// automation. The exit code also tells what kind of failure it was.
+    #[structopt(long, default_value = "human")]
+    error_format: diagnostics::ErrorFormat,
     /// Path to third-party dir
     #[structopt(long, default_value = ".")]
     third_party_dir: PathBuf,
+    /// Resolve a crate from a local directory instead of crates.io, as
+    /// `<crate>=<path>`, like an entry in the `[overrides]` section
+    #[structopt(
+        long = "override",
+        number_of_values = 1,
+        parse(try_from_str = parse_override)
+    )]
+    overrides: Vec<(String, PathBuf)>,
     #[structopt(subcommand)]
     subcommand: SubCommand,
 }
@@ -66,7 +129,30 @@ pub struct Args {
 #[derive(Debug, StructOpt)]
 enum SubCommand {
     /// Update Cargo.lock with new dependencies
-    Update {},
+    Update {
+        /// Instead update just these packages, like `cargo update -p`, then
+        /// vendor and buckify and show what changed
+        packages: Vec<String>,
+    },
+    /// Add a dependency to Cargo.toml, then vendor and buckify
+    Add {
+        /// Name of the package
+        package: String,
+        /// Version requirement. By default, the version Cargo picks.
+        #[structopt(long)]
+        version: Option<String>,
+        /// Features to enable, separated by commas or given several times
+        #[structopt(long, short = "F", use_delimiter = true)]
+        features: Vec<String>,
+        /// Disable the package's default features
+        #[structopt(long)]
+        no_default_features: bool,
+    },
+    /// Remove dependencies from Cargo.toml, then vendor and buckify
+    Remove {
+        #[structopt(required = true)]
+        packages: Vec<String>,
+    },
     /// Vendor crate needed for build
     Vendor {
         /// Don't delete older crates in the vendor directory
@@ -86,6 +172,10 @@ enum SubCommand {
         /// Suppresses generation of other output files.
         #[structopt(long)]
         stdout: bool,
+        /// Only regenerate the rules of these packages and their dependencies,
+        /// updating them in the existing BUCK file.
+        #[structopt(long = "package")]
+        packages: Vec<String>,
     },
     /// Show security report for vendored crates
     Auditsec {
@@ -96,6 +186,139 @@ enum SubCommand {
         #[structopt(long)]
         autofix: bool,
     },
+    /// List problems with fixups which can be fixed automatically: fixups of
+    /// packages no longer in the dependency graph, `version`s which no longer
+    /// match, and unresolved build scripts which only detect the compiler
+    Audit {
+        /// Make the changes, except to packages in `never_autofix`
+        #[structopt(long)]
+        fix: bool,
+    },
+    /// Move finished fixups from `fixup_templates_dir` into `fixups`
+    AcceptFixups {
+        /// Only these packages' fixups, rather than all that are finished
+        packages: Vec<String>,
+        /// Show which fixups would be moved, and their contents, without
+        /// moving them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Remove vendored sources and generated fixup templates of packages no
+    /// longer in Cargo.lock
+    Clean {
+        /// List what would be removed without removing it
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Remove the files matching `checksum_exclude` and
+    /// `gitignore_checksum_exclude` from the vendored packages' checksum
+    /// files, as `reindeer vendor` does, and list them for each package
+    FilterChecksums {
+        /// Only list them, leaving the checksum files alone
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Check fixups for entries which no longer match the dependency graph
+    Lint {},
+    /// List the public crates which no first-party package or target uses,
+    /// as candidates for removal
+    Unused {
+        /// Only look at the first-party packages in the dependency graph,
+        /// not at Buck's `unused_query`
+        #[structopt(long)]
+        cargo_only: bool,
+    },
+    /// Summarize every crate in the dependency graph: the age of its version,
+    /// its dependents, build script, unsafe code, license and other versions
+    Report {
+        /// text, or json or csv for spreadsheets and other tools
+        #[structopt(long, default_value = "text")]
+        format: report::ReportFormat,
+        /// Write the report to this file rather than stdout
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Report packages and fixups which wouldn't work with `vendor = false`
+    CheckNonvendored {},
+    /// Copy the .crate archive of every package from crates.io into a
+    /// directory, laid out to be served as the `crates_url` of the generated
+    /// rules
+    ExportMirror {
+        /// Directory to write `<name>/<version>/download` files to
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// Work with the platforms configured in reindeer.toml
+    Platforms {
+        #[structopt(subcommand)]
+        command: PlatformsCommand,
+    },
+    /// Check whether the BUCK file is up to date with Cargo.lock, the config
+    /// and the fixups, by the fingerprint in its header
+    Status {},
+    /// Run build scripts now and commit their outputs as fixups, so they
+    /// don't need to run at build time
+    RunBuildscripts {
+        /// Packages whose build scripts to run
+        #[structopt(required = true)]
+        packages: Vec<String>,
+        /// Record the outputs only for this platform expression, eg
+        /// `cfg(target_os = "linux")`. Use `--cargo-options=--target=...` to
+        /// run the build script for the matching target.
+        #[structopt(long)]
+        platform: Option<String>,
+    },
+}
+
+impl SubCommand {
+    /// Whether the command has Cargo look at the third-party Cargo.toml, which
+    /// with `[dependencies]` in reindeer.toml has to be generated first.
+    fn runs_cargo(&self) -> bool {
+        match self {
+            SubCommand::Vendor { .. }
+            | SubCommand::Update { .. }
+            | SubCommand::Add { .. }
+            | SubCommand::Remove { .. }
+            | SubCommand::Buckify { .. }
+            | SubCommand::Lint { .. }
+            | SubCommand::Audit { .. }
+            | SubCommand::Unused { .. }
+            | SubCommand::Report { .. }
+            | SubCommand::CheckNonvendored { .. }
+            | SubCommand::ExportMirror { .. }
+            | SubCommand::RunBuildscripts { .. } => true,
+            SubCommand::Auditsec { .. }
+            | SubCommand::AcceptFixups { .. }
+            | SubCommand::Clean { .. }
+            | SubCommand::FilterChecksums { .. }
+            | SubCommand::Platforms { .. }
+            | SubCommand::Status { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum PlatformsCommand {
+    /// Write a .bzl file defining a Buck constraint_value and platform() for
+    /// each platform
+    Emit {
+        /// File to write. By default, platforms.bzl in the third-party
+        /// directory.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Write it to stdout instead
+        #[structopt(long, conflicts_with = "output")]
+        stdout: bool,
+    },
+}
+
+fn parse_override(arg: &str) -> Result<(String, PathBuf)> {
+    match arg.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_owned(), PathBuf::from(path)))
+        }
+        _ => anyhow::bail!("expected `<crate>=<path>`, not `{}`", arg),
+    }
 }
 
 /// Computed paths
@@ -107,11 +330,15 @@ pub struct Paths {
     cargo_home: PathBuf,
 }
 
-fn try_main() -> Result<()> {
-    let args = Args::from_args();
-
+fn try_main(args: &Args) -> Result<()> {
     let third_party_dir = dunce::canonicalize(&args.third_party_dir)?;
     let mut config = config::read_config(&third_party_dir)?;
+    for (name, path) in &args.overrides {
+        // Relative to where Reindeer runs, unlike those in the config.
+        let path = dunce::canonicalize(path)
+            .with_context(|| format!("--override {}={}", name, path.display()))?;
+        config.overrides.insert(name.clone(), path);
+    }
 
     let paths = Paths {
         manifest_path: third_party_dir.join("Cargo.toml"),
@@ -122,40 +349,137 @@ fn try_main() -> Result<()> {
 
     log::debug!("Args = {:#?}, paths {:#?}", args, paths);
 
+    if let Some(dependencies) = &config.dependencies {
+        if args.subcommand.runs_cargo() {
+            pseudo_package::write_cargo_toml(dependencies, &paths)?;
+        }
+    }
+
     match &args.subcommand {
         SubCommand::Vendor {
             no_delete,
             audit_sec,
             no_fetch,
         } => {
-            vendor::cargo_vendor(&config, *no_delete, *audit_sec, *no_fetch, &args, &paths)?;
+            vendor::cargo_vendor(&config, *no_delete, *audit_sec, *no_fetch, args, &paths)?;
         }
 
         SubCommand::Auditsec { no_fetch, autofix } => {
             audit_sec::audit_sec(&config, &paths, *no_fetch, *autofix)?;
         }
 
+        SubCommand::Update { packages } if !packages.is_empty() => {
+            update::update_packages(&config, args, &paths, packages)?;
+        }
+
+        SubCommand::Add {
+            package,
+            version,
+            features,
+            no_default_features,
+        } => {
+            let dep = update::NewDependency {
+                package,
+                version: version.as_deref(),
+                features,
+                no_default_features: *no_default_features,
+            };
+            update::add_package(&config, args, &paths, &dep)?;
+        }
+
+        SubCommand::Remove { packages } => {
+            update::remove_packages(&config, args, &paths, packages)?;
+        }
+
         SubCommand::Update { .. } => {
-            let _ = cargo::run_cargo(
-                &config,
-                Some(&paths.cargo_home),
-                &paths.third_party_dir,
-                &args,
-                &[
-                    "generate-lockfile",
-                    "--manifest-path",
-                    paths.manifest_path.to_str().unwrap(),
-                ],
-            )?;
-        }
-
-        SubCommand::Buckify { stdout } => {
+            let workspaces = config
+                .workspaces
+                .iter()
+                .map(|manifest_path| paths.third_party_dir.join(manifest_path));
+            for manifest_path in iter::once(paths.manifest_path.clone()).chain(workspaces) {
+                let _ = cargo::run_cargo(
+                    &config,
+                    Some(&paths.cargo_home),
+                    &paths.third_party_dir,
+                    args,
+                    &[
+                        "generate-lockfile",
+                        "--manifest-path",
+                        manifest_path.to_str().unwrap(),
+                    ],
+                )?;
+            }
+        }
+
+        SubCommand::Buckify { stdout, packages } => {
             if config.vendor.is_some() && !vendor::is_vendored(&paths)? {
+                if args.offline || config.cargo.offline {
+                    // Falling back to downloading sources at build time is not
+                    // what anybody asking to stay offline wants.
+                    anyhow::bail!("Sources have not been vendored, run `reindeer vendor`");
+                }
                 // If you ran `reindeer buckify` without `reindeer vendor`, then
                 // default to generating non-vendored targets.
                 config.vendor = None;
             }
-            buckify::buckify(&config, &args, &paths, *stdout)?;
+            buckify::buckify(&config, args, &paths, *stdout, packages)?;
+        }
+
+        SubCommand::AcceptFixups { packages, dry_run } => {
+            fixups::staging::accept_fixups(&config, &paths, packages, *dry_run)?;
+        }
+
+        SubCommand::Clean { dry_run } => {
+            clean::clean(&config, &paths, *dry_run)?;
+        }
+
+        SubCommand::FilterChecksums { dry_run } => {
+            vendor::filter_checksums(&config, &paths, *dry_run)?;
+        }
+
+        SubCommand::Lint { .. } => {
+            if config.vendor.is_some() && !vendor::is_vendored(&paths)? {
+                config.vendor = None;
+            }
+            lint::lint(&config, args, &paths)?;
+        }
+
+        SubCommand::Audit { fix } => {
+            if config.vendor.is_some() && !vendor::is_vendored(&paths)? {
+                config.vendor = None;
+            }
+            audit::audit(&config, args, &paths, *fix)?;
+        }
+
+        SubCommand::Unused { cargo_only } => {
+            unused::unused(&config, args, &paths, *cargo_only)?;
+        }
+
+        SubCommand::Report { format, output } => {
+            report::report(&config, args, &paths, *format, output.as_deref())?;
+        }
+
+        SubCommand::CheckNonvendored { .. } => {
+            nonvendored::check_nonvendored(&config, args, &paths)?;
+        }
+
+        SubCommand::ExportMirror { dir } => {
+            mirror::export_mirror(&config, args, &paths, dir)?;
+        }
+
+        SubCommand::Platforms {
+            command: PlatformsCommand::Emit { output, stdout },
+        } => {
+            platform_targets::emit_platforms(&config, &paths, output.as_deref(), *stdout)?;
+        }
+
+        SubCommand::Status { .. } => {
+            fingerprint::status(&config, args, &paths)?;
+        }
+
+        SubCommand::RunBuildscripts { packages, platform } => {
+            let platform = platform.clone().map(PlatformExpr::from);
+            run_buildscripts::run_buildscripts(&config, args, &paths, packages, platform.as_ref())?;
         }
     }
 
@@ -167,8 +491,28 @@ fn main() {
         .format_timestamp(None)
         .init();
 
-    if let Err(err) = try_main() {
-        log::error!("{:?}", err);
-        std::process::exit(1);
+    let args = Args::from_args();
+    let result = try_main(&args);
+    let exit_code = match &result {
+        Ok(()) => 0,
+        Err(err) => diagnostics::report_failure(err, args.error_format),
+    };
+
+    if let Some(diagnostics_json) = &args.diagnostics_json {
+        if let Err(err) = diagnostics::write_json(diagnostics_json) {
+            log::error!("{:?}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(profile) = &args.profile {
+        if let Err(err) = profile::write(profile, args.profile_format) {
+            log::error!("{:?}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
     }
 }
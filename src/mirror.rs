@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Export the .crate archives of every package from crates.io to a directory,
+//! to be served as the `crates_url` of the generated `http_archive` rules
+//!
+//! Each archive goes to `<name>/<version>/download`, the same layout as
+//! `https://crates.io/api/v1/crates`, so the directory can be uploaded as it
+//! is to static hosting such as an S3 bucket.
+
+use std::collections::HashMap;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use tempfile::TempDir;
+
+use crate::cargo;
+use crate::cargo::Source;
+use crate::config::Config;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::yanked;
+use crate::Args;
+use crate::Paths;
+
+pub(crate) fn export_mirror(config: &Config, args: &Args, paths: &Paths, dir: &Path) -> Result<()> {
+    let (lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+    let (_cargo_home, cached) = fetch_archives(config, args, paths)?;
+
+    let mut exported = 0;
+    let mut existing = 0;
+    for pkg in &metadata.packages {
+        if pkg.source != Source::CratesIo {
+            continue;
+        }
+        if lockfile
+            .find(pkg)
+            .and_then(|pkg| pkg.checksum.as_ref())
+            .is_none()
+        {
+            // Buckify refuses these too.
+            bail!(
+                "No sha256 checksum for \"{}\" {} in lockfile",
+                pkg.name,
+                pkg.version
+            );
+        }
+
+        let file_name = format!("{}-{}.crate", pkg.name, pkg.version);
+        let Some(archive) = cached.get(&file_name) else {
+            bail!(
+                "{} is missing from the Cargo cache after fetching",
+                file_name
+            );
+        };
+        let dest = dir
+            .join(&pkg.name)
+            .join(pkg.version.to_string())
+            .join("download");
+        // Published versions never change, so one already there is the same.
+        if dest.exists() {
+            existing += 1;
+            continue;
+        }
+        // Copy under another name first, so that an interrupted export doesn't
+        // leave a truncated archive which the next one would skip.
+        fs::create_dir_all(dest.parent().unwrap())?;
+        let partial = dest.with_extension("partial");
+        fs::copy(archive, &partial)
+            .with_context(|| format!("copying {} to {}", archive.display(), partial.display()))?;
+        fs::rename(&partial, &dest)?;
+        exported += 1;
+    }
+
+    log::info!(
+        "Exported {} crate archives to {}, {} were already there",
+        exported,
+        dir.display(),
+        existing,
+    );
+    Ok(())
+}
+
+/// Download the archives of all the packages into a scratch Cargo home, and
+/// return it with the archives in it. Cargo checks each download against its
+/// checksum in Cargo.lock.
+///
+/// Neither Reindeer's Cargo home, which in vendoring mode replaces crates.io
+/// with the vendor directory, nor the user's, nor any `.cargo/config.toml`
+/// above the third-party directory may apply, since with crates.io replaced
+/// `cargo fetch` doesn't download anything. So Cargo runs in the scratch
+/// directory too.
+fn fetch_archives(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+) -> Result<(TempDir, HashMap<String, PathBuf>)> {
+    let cargo_home = tempfile::tempdir().context("creating scratch Cargo home")?;
+    let workspaces = config
+        .workspaces
+        .iter()
+        .map(|manifest_path| paths.third_party_dir.join(manifest_path));
+    for manifest_path in iter::once(paths.manifest_path.clone()).chain(workspaces) {
+        cargo::run_cargo(
+            config,
+            Some(cargo_home.path()),
+            cargo_home.path(),
+            args,
+            &["fetch", "--manifest-path", manifest_path.to_str().unwrap()],
+        )
+        .context(Failure::new(
+            FailureKind::Cargo,
+            "Failed to fetch crate archives",
+        ))?;
+    }
+    let cached = cached_archives(cargo_home.path())?;
+    Ok((cargo_home, cached))
+}
+
+/// Cargo's downloaded archives from crates.io, from
+/// `registry/cache/<index>/<name>-<version>.crate`. Other registries' archives
+/// can have the same names, but aren't the same packages.
+fn cached_archives(cargo_home: &Path) -> Result<HashMap<String, PathBuf>> {
+    let hosts = yanked::registry_hosts(&Source::CratesIo).unwrap_or_default();
+    let mut cached = HashMap::new();
+    if let Ok(indexes) = fs::read_dir(cargo_home.join("registry").join("cache")) {
+        for index in indexes {
+            let index = index?;
+            let is_crates_io = index.file_name().to_str().is_some_and(|dir_name| {
+                hosts
+                    .iter()
+                    .any(|host| dir_name.starts_with(&format!("{}-", host)))
+            });
+            if !is_crates_io {
+                continue;
+            }
+            for entry in fs::read_dir(index.path())? {
+                let entry = entry?;
+                if let Some(file_name) = entry.file_name().to_str() {
+                    cached.insert(file_name.to_owned(), entry.path());
+                }
+            }
+        }
+    }
+    Ok(cached)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use structopt::StructOpt;
+
+    use super::cached_archives;
+    use super::fetch_archives;
+    use crate::config::read_config;
+    use crate::Args;
+    use crate::Paths;
+
+    #[cfg(unix)]
+    #[test]
+    fn fetches_without_vendored_sources() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let third_party_dir = dir.path().join("third-party");
+        let cargo_home = third_party_dir.join(".cargo");
+        fs::create_dir_all(&cargo_home).unwrap();
+        fs::write(third_party_dir.join("reindeer.toml"), "[vendor]\n").unwrap();
+        // What `reindeer vendor` leaves behind.
+        fs::write(
+            cargo_home.join("config.toml"),
+            "[source.crates-io]\nreplace-with = \"vendored-sources\"\n",
+        )
+        .unwrap();
+
+        // Stands in for Cargo, downloading only with crates.io in place.
+        let cargo = dir.path().join("cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh\n\
+             set -e\n\
+             test ! -e \"$CARGO_HOME/config.toml\"\n\
+             test ! -e .cargo/config.toml\n\
+             cache=\"$CARGO_HOME/registry/cache/index.crates.io-6f17d22bba15001f\"\n\
+             mkdir -p \"$cache\"\n\
+             touch \"$cache/foo-1.0.0.crate\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = read_config(&third_party_dir).unwrap();
+        assert!(config.vendor.is_some());
+        let args = Args::from_iter([
+            "reindeer".as_ref(),
+            "--cargo-path".as_ref(),
+            cargo.as_os_str(),
+            "buckify".as_ref(),
+        ]);
+        let paths = Paths {
+            manifest_path: third_party_dir.join("Cargo.toml"),
+            lockfile_path: third_party_dir.join("Cargo.lock"),
+            cargo_home,
+            third_party_dir: third_party_dir.clone(),
+        };
+
+        let (_cargo_home, cached) = fetch_archives(&config, &args, &paths).unwrap();
+        assert!(cached.contains_key("foo-1.0.0.crate"));
+    }
+
+    #[test]
+    fn only_crates_io_archives() {
+        let cargo_home = tempfile::tempdir().unwrap();
+        let cache = cargo_home.path().join("registry/cache");
+        for index in [
+            "index.crates.io-6f17d22bba15001f",
+            "my-registry.example.com-0123456789abcdef",
+        ] {
+            fs::create_dir_all(cache.join(index)).unwrap();
+            fs::write(cache.join(index).join("foo-1.0.0.crate"), index).unwrap();
+        }
+        fs::write(
+            cache.join("my-registry.example.com-0123456789abcdef/bar-1.0.0.crate"),
+            "",
+        )
+        .unwrap();
+
+        let cached = cached_archives(cargo_home.path()).unwrap();
+        assert_eq!(cached.len(), 1);
+        assert!(
+            cached["foo-1.0.0.crate"].starts_with(cache.join("index.crates.io-6f17d22bba15001f"))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Check packages' `rust-version` against the toolchain in `rust_version`.
+//!
+//! Cargo would refuse to build such packages, but Buck doesn't look at
+//! `rust-version`, so without this they'd only be noticed when they fail to
+//! compile.
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::config::CheckLevel;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::index::display_chain;
+use crate::index::Index;
+
+pub(crate) fn check_rust_version(config: &Config, index: &Index) -> Result<()> {
+    let Some(toolchain) = &config.rust_version else {
+        return Ok(());
+    };
+
+    let severity = match config.rust_version_check {
+        CheckLevel::Ignore => return Ok(()),
+        CheckLevel::Warn => Severity::Warning,
+        CheckLevel::Error => Severity::Error,
+    };
+
+    let chains = index.dependency_chains(|_| false);
+    let mut too_new = 0;
+    for pkg in chains.packages() {
+        let Some(rust_version) = &pkg.rust_version else {
+            continue;
+        };
+        if rust_version <= toolchain {
+            continue;
+        }
+        too_new += 1;
+        Diagnostic::new(
+            severity,
+            format!(
+                "requires Rust {}, newer than the configured {} (reached through {})",
+                rust_version,
+                toolchain,
+                display_chain(&chains.chain(pkg)),
+            ),
+        )
+        .package(pkg)
+        .suggestion(
+            "use an older version of the package, or update rust_version along with the toolchain",
+        )
+        .report();
+    }
+
+    if too_new > 0 && config.rust_version_check == CheckLevel::Error {
+        bail!(
+            "{} package{} requiring a newer Rust than {}",
+            too_new,
+            if too_new == 1 { "" } else { "s" },
+            toolchain,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Check what would stop working with `vendor = false`
+//!
+//! Without vendoring, a package's sources come from an `http_archive` of its
+//! crates.io download, or from a `git_fetch` of its repository, and fixups
+//! can only refer to files inside those. Fixups written against the vendor
+//! directory don't always stay within that.
+
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buckify::normalize_dotdot;
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::cargo::Source;
+use crate::config::Config;
+use crate::fixups::buildscript::BuildscriptFixup;
+use crate::fixups::config::FixupConfigFile;
+use crate::fixups::extra_srcs_matches;
+use crate::fixups::fixup_layers;
+use crate::Args;
+use crate::Paths;
+
+pub(crate) fn check_nonvendored(config: &Config, args: &Args, paths: &Paths) -> Result<()> {
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+
+    let mut problems = 0;
+    for pkg in &metadata.packages {
+        let mut report = |message: String| {
+            println!("{}: {}", pkg, message);
+            problems += 1;
+        };
+
+        match &pkg.source {
+            Source::Local | Source::CratesIo | Source::Git { .. } => {}
+            Source::Unrecognized(source) => {
+                report(format!(
+                    "comes from {}, but only crates.io and git are supported",
+                    source
+                ));
+                continue;
+            }
+        }
+
+        // Wherever the package's fixups are, such as shared ones.
+        let fixup_paths: Vec<PathBuf> = fixup_layers(config, paths, pkg)
+            .iter()
+            .map(|dir| dir.join("fixups.toml"))
+            .collect();
+        if fixup_paths.is_empty() {
+            continue;
+        }
+        let fixup_config = FixupConfigFile::parse_layers(&fixup_paths)
+            .with_context(|| format!("parsing the fixups of {}", pkg))?;
+
+        check_fixup(pkg, &fixup_config, &mut report)?;
+    }
+
+    if problems != 0 {
+        bail!("{} problems found with `vendor = false`", problems);
+    }
+    Ok(())
+}
+
+fn check_fixup(
+    pkg: &Manifest,
+    fixup_config: &FixupConfigFile,
+    report: &mut impl FnMut(String),
+) -> Result<()> {
+    // Path dependencies are used in place either way.
+    if pkg.source == Source::Local {
+        return Ok(());
+    }
+    let from_git = matches!(pkg.source, Source::Git { .. });
+
+    for (platform, fixup) in fixup_config.configs(&pkg.version) {
+        let section = match platform {
+            None => String::new(),
+            Some(platform) => format!("[platform_fixup.'{}'] ", platform),
+        };
+
+        if from_git && fixup.overlay.is_some() {
+            report(format!(
+                "{}overlay: not supported for packages from git",
+                section
+            ));
+        }
+
+        if from_git {
+            for buildscript in &fixup.buildscript {
+                let kind = match buildscript {
+                    BuildscriptFixup::CxxLibrary(_) => "buildscript.cxx_library",
+                    BuildscriptFixup::PrebuiltCxxLibrary(_) => "buildscript.prebuilt_cxx_library",
+                    _ => continue,
+                };
+                report(format!(
+                    "{}{}: not supported for packages from git",
+                    section, kind
+                ));
+            }
+        }
+
+        // Nothing outside the package is in its archive.
+        for glob in &fixup.extra_srcs {
+            let matches = extra_srcs_matches(pkg.manifest_dir(), glob)
+                .with_context(|| format!("{}extra_srcs `{}`", section, glob))?;
+            if matches
+                .iter()
+                .any(|path| !normalize_dotdot(path).starts_with(pkg.manifest_dir()))
+            {
+                report(format!(
+                    "{}extra_srcs: `{}` matches files outside the package",
+                    section, glob
+                ));
+            }
+        }
+        for src in fixup.extra_mapped_srcs.keys() {
+            if escapes_package(Path::new(src)) {
+                report(format!(
+                    "{}extra_mapped_srcs: `{}` is outside the package",
+                    section, src
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a path relative to the package's manifest dir leads out of it.
+fn escapes_package(path: &Path) -> bool {
+    // Labels of other rules' outputs aren't paths at all.
+    if path.to_string_lossy().contains("//") || path.starts_with(":") {
+        return false;
+    }
+    matches!(
+        normalize_dotdot(path).components().next(),
+        Some(Component::ParentDir | Component::RootDir)
+    )
+}
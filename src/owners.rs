@@ -0,0 +1,73 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Files naming each vendored package's owners, written into its directory so
+//! that code review tools can route changes to third-party code.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::config::OwnersConfig;
+use crate::fixups::Fixups;
+use crate::index::Index;
+use crate::Paths;
+
+/// The path and content of the owners file of every vendored package which
+/// has owners.
+pub(crate) fn owners_files(
+    config: &Config,
+    owners_config: &OwnersConfig,
+    paths: &Paths,
+    index: &Index,
+    packages: &[&Manifest],
+) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    let vendor_dir = paths.third_party_dir.join("vendor");
+    for pkg in packages {
+        let Some(package_dir) = pkg.manifest_path.parent() else {
+            continue;
+        };
+        if !package_dir.starts_with(&vendor_dir) {
+            continue;
+        }
+
+        // Any target other than a build script will do for reading fixups,
+        // which wouldn't write out a template for it.
+        let fixups = match pkg.targets.iter().find(|tgt| !tgt.kind_custom_build()) {
+            Some(tgt) => Some(Fixups::new(config, paths, index, pkg, tgt)?),
+            None => None,
+        };
+        let owners = fixups
+            .as_ref()
+            .and_then(Fixups::owners)
+            .unwrap_or(&owners_config.default);
+        if owners.is_empty() {
+            continue;
+        }
+
+        let content = render(owners_config, &pkg.to_string(), owners);
+        files.push((package_dir.join(&owners_config.file_name), content));
+    }
+    Ok(files)
+}
+
+fn render(owners_config: &OwnersConfig, package: &str, owners: &[String]) -> String {
+    let mut lines = String::new();
+    for owner in owners {
+        lines.push_str(owner);
+        lines.push('\n');
+    }
+    match &owners_config.template {
+        Some(template) => template
+            .replace("{package}", package)
+            .replace("{owners}", lines.trim_end()),
+        None => lines,
+    }
+}
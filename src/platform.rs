@@ -25,6 +25,49 @@ use crate::config::Config;
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct PlatformConfig(HashMap<String, HashSet<String>>);
 
+impl PlatformConfig {
+    /// This platform with extra cfgs set, each written the way rustc's
+    /// `--cfg` takes it: `tokio_unstable` or `key="value"`.
+    pub fn with_cfgs<'a>(&self, cfgs: impl IntoIterator<Item = &'a str>) -> PlatformConfig {
+        let mut config = self.clone();
+        for cfg in cfgs {
+            match cfg.split_once('=') {
+                Some((key, value)) => {
+                    let value = value.trim().trim_matches('"');
+                    config
+                        .0
+                        .entry(key.trim().to_owned())
+                        .or_default()
+                        .insert(value.to_owned());
+                }
+                None => {
+                    config.0.entry(cfg.trim().to_owned()).or_default();
+                }
+            }
+        }
+        config
+    }
+
+    /// This platform as seen by tests, which have `test` and `doctest` set on
+    /// top of its usual cfgs.
+    pub fn for_tests(&self) -> PlatformConfig {
+        self.with_cfgs(["test", "doctest"])
+    }
+
+    /// The values of a cfg key on this platform, such as its `target_os`
+    pub fn values(&self, key: &str) -> Vec<&str> {
+        let mut values: Vec<&str> = self
+            .0
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        values.sort_unstable();
+        values
+    }
+}
+
 pub fn platform_names_for_expr<'config>(
     config: &'config Config,
     expr: &PlatformExpr,
@@ -50,6 +93,10 @@ const DEFAULT_PLATFORM: &str = "DEFAULT";
 pub struct PlatformName(String);
 
 impl PlatformName {
+    pub fn default_platform() -> Self {
+        PlatformName(DEFAULT_PLATFORM.to_owned())
+    }
+
     pub fn is_default(&self) -> bool {
         self.0 == DEFAULT_PLATFORM
     }
@@ -137,7 +184,7 @@ impl<'a> PlatformPredicate<'a> {
                 // [target.'cfg(feature = "...")'.dependencies] never get applied by Cargo
                 false
             }
-            Value { key, value } => config.0.get(*key).map_or(false, |set| set.contains(*value)),
+            Value { key, value } => config.0.get(*key).is_some_and(|set| set.contains(*value)),
             Not(pred) => !pred.eval(config),
             Any(preds) => preds.iter().any(|pred| pred.eval(config)),
             All(preds) => preds.iter().all(|pred| pred.eval(config)),
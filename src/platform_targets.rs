@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Generate the Buck side of the platforms in reindeer.toml
+//!
+//! The `platform` dicts of the generated rules are keyed by the names of the
+//! platforms in reindeer.toml, and something in Buck has to decide which of
+//! them applies to a build. `reindeer platforms emit` writes a .bzl file with
+//! a `constraint_value` and a `platform()` for each one, from the same config,
+//! so that the two can't drift apart.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buck::RuleRef;
+use crate::config::Config;
+use crate::platform::PlatformName;
+use crate::Paths;
+
+const MACRO: &str = r#"
+def reindeer_platforms(constraint_setting = "reindeer_platform"):
+    """
+    Define a constraint_value named after each platform in reindeer.toml,
+    under a new constraint_setting, and a `<platform>-platform` platform()
+    with that and the constraints in REINDEER_PLATFORMS.
+    """
+    native.constraint_setting(name = constraint_setting)
+    for name, constraint_values in REINDEER_PLATFORMS.items():
+        native.constraint_value(
+            name = name,
+            constraint_setting = ":" + constraint_setting,
+        )
+        native.platform(
+            name = name + "-platform",
+            constraint_values = [":" + name] + constraint_values,
+        )
+"#;
+
+pub(crate) fn emit_platforms(
+    config: &Config,
+    paths: &Paths,
+    output: Option<&Path>,
+    stdout: bool,
+) -> Result<()> {
+    let mut out = Vec::new();
+    write_platforms_bzl(config, &mut out)?;
+
+    if stdout {
+        std::io::stdout().write_all(&out)?;
+    } else {
+        let path = match output {
+            Some(output) => output.to_owned(),
+            None => paths.third_party_dir.join("platforms.bzl"),
+        };
+        fs::write(&path, out).with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn write_platforms_bzl(config: &Config, out: &mut impl Write) -> Result<()> {
+    let platforms: BTreeMap<&PlatformName, Vec<String>> = config
+        .platform
+        .keys()
+        .filter(|name| !name.is_default())
+        .map(|name| (name, constraint_values(config, name)))
+        .collect();
+
+    let header = &config.buck.generated_file_header;
+    out.write_all(header.as_bytes())?;
+    if !header.is_empty() {
+        out.write_all(b"\n")?;
+    }
+    writeln!(
+        out,
+        "# Constraint values implied by each platform in reindeer.toml"
+    )?;
+    let platforms = serde_starlark::to_string(&platforms)?;
+    writeln!(out, "REINDEER_PLATFORMS = {}", platforms.trim_end())?;
+    out.write_all(MACRO.as_bytes())?;
+    Ok(())
+}
+
+/// Constraints of the `exec_platform`, for the rules of build scripts
+pub(crate) fn exec_compatible_with(config: &Config) -> Result<Vec<RuleRef>> {
+    let Some(name) = &config.buck.exec_platform else {
+        return Ok(Vec::new());
+    };
+    if !config.platform.contains_key(name) {
+        bail!("exec_platform `{}` is not a configured platform", name);
+    }
+    Ok(constraint_values(config, name)
+        .into_iter()
+        .map(RuleRef::new)
+        .collect())
+}
+
+/// `compatible_with` for a rule built for `platforms` but not `omitted`. Buck
+/// takes the rule to be compatible if any one of these constraints matches, so
+/// each platform contributes one of its constraints which none of the omitted
+/// platforms have.
+pub(crate) fn compatible_with(
+    config: &Config,
+    platforms: &BTreeSet<&PlatformName>,
+    omitted: &BTreeSet<&PlatformName>,
+) -> Result<Vec<RuleRef>> {
+    let omitted_constraints: BTreeSet<String> = omitted
+        .iter()
+        .flat_map(|name| constraint_values(config, name))
+        .collect();
+    let mut compatible_with = BTreeSet::new();
+    for name in platforms {
+        if omitted.contains(name) {
+            continue;
+        }
+        let Some(constraint) = constraint_values(config, name)
+            .into_iter()
+            .find(|constraint| !omitted_constraints.contains(constraint))
+        else {
+            bail!(
+                "platform `{}` has no constraint to tell it apart from {}, \
+                 give it one in `platform_constraints`",
+                name,
+                omitted
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        };
+        compatible_with.insert(constraint);
+    }
+    Ok(compatible_with.into_iter().map(RuleRef::new).collect())
+}
+
+/// The `platform_constraints` given for a platform in the config, or else
+/// the prelude's `os` and `cpu` constraints matching its `target_os` and
+/// `target_arch`, for whichever of them has exactly one value.
+fn constraint_values(config: &Config, name: &PlatformName) -> Vec<String> {
+    if let Some(constraints) = config.buck.platform_constraints.get(name) {
+        return constraints.clone();
+    }
+
+    let platform = &config.platform[name];
+    let mut constraints = Vec::new();
+    if let [os] = platform.values("target_os")[..] {
+        let os = match os {
+            "ios" => "iphoneos",
+            os => os,
+        };
+        // Such as SGX, which the prelude has nothing for
+        if os != "unknown" {
+            constraints.push(format!("prelude//os:{}", os));
+        }
+    }
+    if let [arch] = platform.values("target_arch")[..] {
+        let cpu = match arch {
+            "aarch64" => "arm64",
+            "arm" => "arm32",
+            "x86" => "x86_32",
+            arch => arch,
+        };
+        constraints.push(format!("prelude//cpu:{}", cpu));
+    }
+    constraints
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Plugins post-process the generated rules, for needs too particular to a
+//! repo to be config options. A plugin is a command which reads
+//! `{"rules": [...]}` as JSON on stdin and writes the same back to stdout,
+//! with rules changed, added or removed. Each rule is
+//! `{"rule": "rust_library", "attrs": {"name": ..., ...}}`. Rules which come
+//! back as they went in stay as they were generated.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::buck::AttrValue;
+use crate::buck::Name;
+use crate::buck::PluginRule;
+use crate::buck::Rule;
+use crate::config::BuckConfig;
+use crate::profile;
+use crate::Paths;
+
+#[derive(Serialize)]
+struct PluginInput<'a> {
+    rules: &'a [PluginRule],
+}
+
+#[derive(Deserialize)]
+struct PluginOutput {
+    rules: Vec<PluginOutputRule>,
+}
+
+#[derive(Deserialize)]
+struct PluginOutputRule {
+    rule: String,
+    attrs: IndexMap<String, AttrValue>,
+}
+
+/// Pass the rules through each plugin in turn, returning them in the order
+/// the last one left them.
+pub(crate) fn run_plugins(
+    commands: &[Vec<String>],
+    config: &BuckConfig,
+    paths: &Paths,
+    rules: impl IntoIterator<Item = Rule>,
+) -> Result<Vec<Rule>> {
+    let _timer = profile::time("plugins");
+    // Each generated rule with the JSON the plugins were given for it.
+    let mut originals: BTreeMap<Name, (String, Rule)> = BTreeMap::new();
+    let mut plugin_rules = Vec::new();
+    for rule in rules {
+        let plugin_rule = rule.to_plugin_rule(config)?;
+        let json = serde_json::to_string(&plugin_rule)?;
+        originals.insert(plugin_rule.name.clone(), (json, rule));
+        plugin_rules.push(plugin_rule);
+    }
+
+    for command in commands {
+        plugin_rules = run_plugin(command, paths, &plugin_rules)
+            .with_context(|| format!("running plugin `{}`", command.join(" ")))?;
+    }
+
+    plugin_rules
+        .into_iter()
+        .map(|mut plugin_rule| {
+            Ok(match originals.remove(&plugin_rule.name) {
+                Some((json, original)) if json == serde_json::to_string(&plugin_rule)? => original,
+                Some((_, original)) => {
+                    plugin_rule.original = Some(Box::new(original));
+                    Rule::Plugin(plugin_rule)
+                }
+                None => Rule::Plugin(plugin_rule),
+            })
+        })
+        .collect()
+}
+
+fn run_plugin(command: &[String], paths: &Paths, rules: &[PluginRule]) -> Result<Vec<PluginRule>> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("empty command");
+    };
+
+    log::info!("Running plugin {:?}", command);
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&paths.third_party_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread, so that a plugin which starts writing its
+    // output before reading all its input doesn't deadlock.
+    let input = serde_json::to_vec(&PluginInput { rules })?;
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let written = writer.join().unwrap();
+
+    if !output.status.success() {
+        bail!("failed ({})", output.status);
+    }
+    written.context("writing rules")?;
+
+    let output: PluginOutput =
+        serde_json::from_slice(&output.stdout).context("parsing rules from output")?;
+    output
+        .rules
+        .into_iter()
+        .map(|rule| {
+            let Some(AttrValue::String(name)) = rule.attrs.get("name") else {
+                bail!("`{}` rule without a string `name`", rule.rule);
+            };
+            for (attr, value) in &rule.attrs {
+                if let Some(value) = fraction(value) {
+                    bail!(
+                        "`{}` attribute `{}`: {} isn't a whole number, and the BUCK file can't have floats",
+                        name,
+                        attr,
+                        value,
+                    );
+                }
+            }
+            Ok(PluginRule {
+                name: Name(name.clone()),
+                rule: rule.rule,
+                attrs: rule.attrs,
+                original: None,
+            })
+        })
+        .collect()
+}
+
+/// A float anywhere in the value which isn't a whole number.
+fn fraction(value: &AttrValue) -> Option<f64> {
+    match value {
+        AttrValue::Float(value) if value.fract() != 0.0 => Some(*value),
+        AttrValue::List(values) => values.iter().find_map(fraction),
+        AttrValue::Dict(values) => values.values().find_map(fraction),
+        _ => None,
+    }
+}
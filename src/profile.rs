@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Where the time goes, for `--profile`
+//!
+//! Phases which run per package (fixups, srcfiles, rule generation) are
+//! timed separately for each one, and run in parallel across packages, so
+//! their totals add up CPU time rather than wall time.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cargo::Manifest;
+
+/// How many packages to list in the JSON report
+const SLOWEST_PACKAGES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    Json,
+    /// One line per stack with its time in microseconds, as consumed by
+    /// inferno or flamegraph.pl
+    Folded,
+}
+
+impl std::str::FromStr for ProfileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ProfileFormat::Json),
+            "folded" => Ok(ProfileFormat::Folded),
+            _ => anyhow::bail!("unknown profile format `{}`, expected json or folded", s),
+        }
+    }
+}
+
+struct Sample {
+    phase: &'static str,
+    /// Phase this one runs inside of, for the same package
+    parent: Option<&'static str>,
+    package: Option<String>,
+    duration: Duration,
+}
+
+static SAMPLES: Mutex<Vec<Sample>> = Mutex::new(Vec::new());
+
+/// Records the time from its creation until it's dropped.
+#[must_use]
+pub struct Timer {
+    phase: &'static str,
+    parent: Option<&'static str>,
+    package: Option<String>,
+    start: Instant,
+}
+
+impl Timer {
+    /// Attribute the time to a package, within `parent` if that's also being
+    /// timed for the package.
+    pub fn package(mut self, package: &Manifest, parent: Option<&'static str>) -> Self {
+        self.package = Some(package.to_string());
+        self.parent = parent;
+        self
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        SAMPLES.lock().unwrap().push(Sample {
+            phase: self.phase,
+            parent: self.parent,
+            package: self.package.take(),
+            duration: self.start.elapsed(),
+        });
+    }
+}
+
+pub fn time(phase: &'static str) -> Timer {
+    Timer {
+        phase,
+        parent: None,
+        package: None,
+        start: Instant::now(),
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    /// Milliseconds per phase
+    phases: BTreeMap<&'a str, f64>,
+    slowest_packages: Vec<PackageReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct PackageReport<'a> {
+    package: &'a str,
+    /// Milliseconds per phase
+    phases: BTreeMap<&'a str, f64>,
+}
+
+pub fn write(path: &Path, format: ProfileFormat) -> Result<()> {
+    let samples = SAMPLES.lock().unwrap();
+
+    // Total time per (package, phase).
+    let mut totals: BTreeMap<(Option<&str>, &str), Duration> = BTreeMap::new();
+    for sample in samples.iter() {
+        *totals
+            .entry((sample.package.as_deref(), sample.phase))
+            .or_default() += sample.duration;
+    }
+
+    let out = match format {
+        ProfileFormat::Json => {
+            let mut phases = BTreeMap::new();
+            let mut packages: BTreeMap<&str, BTreeMap<&str, f64>> = BTreeMap::new();
+            for ((package, phase), duration) in &totals {
+                let ms = duration.as_secs_f64() * 1000.0;
+                *phases.entry(*phase).or_default() += ms;
+                if let Some(package) = package {
+                    packages.entry(package).or_default().insert(phase, ms);
+                }
+            }
+
+            // A package's outermost phase includes all the others.
+            let package_total = |phases: &BTreeMap<&str, f64>| -> f64 {
+                phases.values().copied().fold(0.0, f64::max)
+            };
+            let mut slowest_packages: Vec<PackageReport> = packages
+                .into_iter()
+                .map(|(package, phases)| PackageReport { package, phases })
+                .collect();
+            slowest_packages
+                .sort_by(|a, b| package_total(&b.phases).total_cmp(&package_total(&a.phases)));
+            slowest_packages.truncate(SLOWEST_PACKAGES);
+
+            serde_json::to_string_pretty(&Report {
+                phases,
+                slowest_packages,
+            })?
+        }
+
+        ProfileFormat::Folded => {
+            // Each stack's own time, excluding nested phases, since the
+            // flamegraph adds those back in.
+            let mut own: BTreeMap<String, Duration> = BTreeMap::new();
+            let mut nested: BTreeMap<String, Duration> = BTreeMap::new();
+            for sample in samples.iter() {
+                let stack = match (&sample.package, sample.parent) {
+                    (Some(package), Some(parent)) => {
+                        *nested.entry(format!("{};{}", parent, package)).or_default() +=
+                            sample.duration;
+                        format!("{};{};{}", parent, package, sample.phase)
+                    }
+                    (Some(package), None) => format!("{};{}", sample.phase, package),
+                    (None, _) => sample.phase.to_owned(),
+                };
+                *own.entry(stack).or_default() += sample.duration;
+            }
+            for (stack, duration) in nested {
+                let parent_own = own.entry(stack).or_default();
+                *parent_own = parent_own.saturating_sub(duration);
+            }
+
+            let mut out = String::new();
+            for (stack, duration) in own {
+                writeln!(out, "{} {}", stack, duration.as_micros())?;
+            }
+            out
+        }
+    };
+
+    fs::write(path, out).with_context(|| format!("writing {}", path.display()))
+}
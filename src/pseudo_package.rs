@@ -0,0 +1,68 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Generate the third-party Cargo.toml from the `[dependencies]` in
+//! reindeer.toml, for those who'd rather not maintain a pseudo-package by hand.
+
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::Paths;
+
+const HEADER: &str = "# @generated by reindeer from the [dependencies] in reindeer.toml";
+
+/// Write Cargo.toml for a package depending on everything in `dependencies`,
+/// unless it's already up to date. A hand-written Cargo.toml is never
+/// overwritten.
+pub(crate) fn write_cargo_toml(dependencies: &toml::Table, paths: &Paths) -> Result<()> {
+    let manifest_path = &paths.manifest_path;
+    let existing = match fs::read_to_string(manifest_path) {
+        Ok(existing) => Some(existing),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err).context(format!("reading {}", manifest_path.display())),
+    };
+    if let Some(existing) = &existing {
+        if !existing.starts_with(HEADER) {
+            bail!(
+                "reindeer.toml has [dependencies], but {} was not generated from them; remove one or the other",
+                manifest_path.display(),
+            );
+        }
+    }
+
+    // Cargo needs a target, but never looks at its source.
+    let mut content = format!(
+        r#"{}. Don't edit.
+
+[package]
+name = "rust-third-party"
+version = "0.0.0"
+edition = "2021"
+publish = false
+
+[lib]
+path = "top.rs"
+
+[workspace]
+
+"#,
+        HEADER,
+    );
+    let mut table = toml::Table::new();
+    table.insert("dependencies".to_owned(), dependencies.clone().into());
+    content += &toml::to_string(&table).context("serializing Cargo.toml")?;
+
+    if existing.as_deref() != Some(content.as_str()) {
+        fs::write(manifest_path, content)
+            .with_context(|| format!("writing {}", manifest_path.display()))?;
+    }
+    Ok(())
+}
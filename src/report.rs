@@ -0,0 +1,415 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A summary of every crate in the dependency graph, for reviewing them:
+//! how old its version is, what depends on it, whether it has a build script
+//! or unsafe code, its license, and which other versions of it are around.
+//!
+//! Publication dates and newer versions come from Cargo's cached copy of the
+//! registry index, like yanked versions do, and are left empty for packages
+//! which aren't in it.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::date;
+use crate::index::Index;
+use crate::srcs_cache::cached_crate_srcfiles;
+use crate::yanked;
+use crate::Args;
+use crate::Paths;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => anyhow::bail!("unknown report format `{}`, expected text, json or csv", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum UnsafeCode {
+    /// The library has `#![forbid(unsafe_code)]`.
+    Forbidden,
+    No,
+    Yes,
+    /// The sources aren't there to look at.
+    Unknown,
+}
+
+#[derive(Serialize)]
+struct CrateReport<'meta> {
+    name: &'meta str,
+    version: String,
+    public: bool,
+    published: Option<String>,
+    age_days: Option<u64>,
+    newest_version: Option<String>,
+    dependents: usize,
+    build_script: bool,
+    unsafe_code: UnsafeCode,
+    license: Option<&'meta str>,
+    other_versions: Vec<String>,
+}
+
+pub(crate) fn report(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    format: ReportFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+    let index = Index::new(
+        config.include_top_level,
+        config.naming,
+        config.collision_suffix,
+        &metadata,
+    )?;
+
+    let mut packages: Vec<&Manifest> = index
+        .dependency_chains(|_| false)
+        .packages()
+        .filter(|pkg| !index.workspace_members.contains(pkg))
+        .collect();
+    packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    let mut dependents: BTreeMap<&Manifest, BTreeSet<&Manifest>> = BTreeMap::new();
+    let mut versions: BTreeMap<&str, Vec<&Manifest>> = BTreeMap::new();
+    for &pkg in index.workspace_members.iter().chain(&packages) {
+        for tgt in &pkg.targets {
+            for dep in index.resolved_deps_for_target(pkg, tgt) {
+                dependents.entry(dep.package).or_default().insert(pkg);
+            }
+        }
+    }
+    for &pkg in &packages {
+        versions.entry(&pkg.name).or_default().push(pkg);
+    }
+
+    let index_dirs = yanked::index_dirs(paths);
+    let today = date::today()?;
+    let crates: Vec<CrateReport> = packages
+        .iter()
+        .map(|&pkg| {
+            let cached = yanked::cached_versions(&index_dirs, pkg);
+            let published = cached
+                .as_ref()
+                .and_then(|cached| cached.get(&pkg.version.to_string())?.pubtime.clone());
+            let newest_version = cached.as_ref().and_then(|cached| {
+                cached
+                    .values()
+                    .filter(|entry| !entry.yanked)
+                    .filter_map(|entry| semver::Version::parse(&entry.vers).ok())
+                    .filter(|version| version.pre.is_empty() || !pkg.version.pre.is_empty())
+                    .max()
+                    .map(|version| version.to_string())
+            });
+            CrateReport {
+                name: &pkg.name,
+                version: pkg.version.to_string(),
+                public: index.is_public_package(pkg),
+                age_days: published
+                    .as_deref()
+                    .and_then(date::days_since_epoch)
+                    .map(|days| today.saturating_sub(days)),
+                published,
+                newest_version,
+                dependents: dependents.get(pkg).map_or(0, BTreeSet::len),
+                build_script: pkg.targets.iter().any(|tgt| tgt.kind_custom_build()),
+                unsafe_code: unsafe_code(paths, pkg),
+                license: pkg.license.as_deref(),
+                other_versions: versions[pkg.name.as_str()]
+                    .iter()
+                    .filter(|other| other.version != pkg.version)
+                    .map(|other| other.version.to_string())
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let out = match format {
+        ReportFormat::Text => text(&crates),
+        ReportFormat::Json => serde_json::to_string_pretty(&crates)? + "\n",
+        ReportFormat::Csv => csv(&crates),
+    };
+    match output {
+        Some(path) => {
+            fs::write(path, out).with_context(|| format!("writing {}", path.display()))?;
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+fn text(crates: &[CrateReport]) -> String {
+    let mut out = String::new();
+    for krate in crates {
+        let mut notes = Vec::new();
+        if let Some(age_days) = krate.age_days {
+            notes.push(format!("{} days old", age_days));
+        }
+        match &krate.newest_version {
+            Some(newest) if *newest != krate.version => notes.push(format!("newest {}", newest)),
+            _ => {}
+        }
+        notes.push(format!(
+            "{} dependent{}",
+            krate.dependents,
+            if krate.dependents == 1 { "" } else { "s" },
+        ));
+        if krate.build_script {
+            notes.push("build script".to_owned());
+        }
+        match krate.unsafe_code {
+            UnsafeCode::Yes => notes.push("unsafe".to_owned()),
+            UnsafeCode::Forbidden => notes.push("forbids unsafe".to_owned()),
+            UnsafeCode::No | UnsafeCode::Unknown => {}
+        }
+        notes.push(krate.license.unwrap_or("no license").to_owned());
+        if !krate.other_versions.is_empty() {
+            notes.push(format!("also {}", krate.other_versions.join(", ")));
+        }
+        let _ = writeln!(
+            out,
+            "{} {}{}: {}",
+            krate.name,
+            krate.version,
+            if krate.public { " (public)" } else { "" },
+            notes.join(", "),
+        );
+    }
+    out
+}
+
+fn csv(crates: &[CrateReport]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_owned()
+        }
+    }
+
+    let mut out = String::from(
+        "name,version,public,published,age_days,newest_version,dependents,\
+        build_script,unsafe_code,license,other_versions\n",
+    );
+    for krate in crates {
+        let unsafe_code = serde_json::to_value(krate.unsafe_code).unwrap();
+        let row = [
+            krate.name.to_owned(),
+            krate.version.clone(),
+            krate.public.to_string(),
+            krate.published.clone().unwrap_or_default(),
+            krate
+                .age_days
+                .map(|days| days.to_string())
+                .unwrap_or_default(),
+            krate.newest_version.clone().unwrap_or_default(),
+            krate.dependents.to_string(),
+            krate.build_script.to_string(),
+            unsafe_code.as_str().unwrap().to_owned(),
+            krate.license.unwrap_or_default().to_owned(),
+            krate.other_versions.join(" "),
+        ];
+        let row: Vec<String> = row.iter().map(|value| field(value)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Whether the package's library forbids unsafe code, and otherwise whether
+/// any of its sources use it. Those are the files its rule would have as
+/// `srcs`: the modules found by parsing it, or failing that the `.rs` files
+/// next to its root.
+fn unsafe_code(paths: &Paths, pkg: &Manifest) -> UnsafeCode {
+    let Some(lib) = pkg.dependency_target() else {
+        return UnsafeCode::Unknown;
+    };
+    match fs::read_to_string(&lib.src_path) {
+        Ok(source) if source.contains("forbid(unsafe_code)") => return UnsafeCode::Forbidden,
+        Ok(_) => {}
+        Err(_) => return UnsafeCode::Unknown,
+    }
+
+    let (Some(manifest_dir), Some(src_dir)) = (pkg.manifest_path.parent(), lib.src_path.parent())
+    else {
+        return UnsafeCode::Unknown;
+    };
+    let sources = cached_crate_srcfiles(paths, manifest_dir, &lib.src_path);
+    let srcs: Vec<PathBuf> = if sources.errors.is_empty() {
+        sources.files.into_iter().collect()
+    } else {
+        WalkDir::new(src_dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "rs"))
+            .map(|entry| entry.into_path())
+            .collect()
+    };
+    let uses_unsafe = srcs
+        .iter()
+        .filter_map(|src| fs::read_to_string(src).ok())
+        .any(|source| has_unsafe_keyword(&source));
+    if uses_unsafe {
+        UnsafeCode::Yes
+    } else {
+        UnsafeCode::No
+    }
+}
+
+/// Whether Rust source has the `unsafe` keyword in it, as a token rather than
+/// in a comment, a string or another identifier like `unsafe_code`.
+fn has_unsafe_keyword(source: &str) -> bool {
+    let bytes = source.as_bytes();
+    let ident = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(b"//") {
+            i += rest
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .unwrap_or(rest.len());
+        } else if rest.starts_with(b"/*") {
+            // Block comments nest.
+            let mut depth = 0;
+            let mut j = 0;
+            while j < rest.len() {
+                if rest[j..].starts_with(b"/*") {
+                    depth += 1;
+                    j += 2;
+                } else if rest[j..].starts_with(b"*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    j += 1;
+                }
+            }
+            i += j;
+        } else if let Some(len) = raw_string_len(rest) {
+            i += len;
+        } else if rest[0] == b'"' {
+            let mut j = 1;
+            while j < rest.len() && rest[j] != b'"' {
+                j += if rest[j] == b'\\' { 2 } else { 1 };
+            }
+            i += j + 1;
+        } else if rest[0] == b'\'' {
+            // A char literal, or else a lifetime.
+            let len = if rest.get(1) == Some(&b'\\') {
+                rest[2..]
+                    .iter()
+                    .skip(1)
+                    .position(|&byte| byte == b'\'')
+                    .map_or(1, |end| end + 4)
+            } else {
+                let char_len = str::from_utf8(&rest[1..rest.len().min(5)])
+                    .or_else(|err| str::from_utf8(&rest[1..1 + err.valid_up_to()]))
+                    .ok()
+                    .and_then(|rest| rest.chars().next())
+                    .map_or(1, char::len_utf8);
+                if rest.get(1 + char_len) == Some(&b'\'') {
+                    char_len + 2
+                } else {
+                    1
+                }
+            };
+            i += len;
+        } else if ident(rest[0]) {
+            let len = rest
+                .iter()
+                .position(|&byte| !ident(byte))
+                .unwrap_or(rest.len());
+            if &rest[..len] == b"unsafe" {
+                return true;
+            }
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Length of the raw string literal at the start of `rest`, like `r#"..."#`
+/// or `br"..."`, if there is one.
+fn raw_string_len(rest: &[u8]) -> Option<usize> {
+    let start = rest.strip_prefix(b"b").unwrap_or(rest);
+    let after_r = start.strip_prefix(b"r")?;
+    let hashes = after_r.iter().take_while(|&&byte| byte == b'#').count();
+    let body = after_r[hashes..].strip_prefix(b"\"")?;
+    let mut closing = vec![b'"'];
+    closing.extend(iter::repeat_n(b'#', hashes));
+    let end = body
+        .windows(closing.len())
+        .position(|window| window == closing)
+        .map_or(body.len(), |end| end + closing.len());
+    Some(rest.len() - body.len() + end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::has_unsafe_keyword;
+
+    #[test]
+    fn finds_unsafe_keyword() {
+        for source in [
+            "unsafe{ x }",
+            "pub unsafe  fn f() {}",
+            "unsafe\nimpl Send for X {}",
+            "fn f<'a>(x: &'a u8) { unsafe { g(x) } }",
+            "let c = '\"'; unsafe {}",
+            "let c = '\\''; unsafe {}",
+            "let s = r#\"a \" b\"#; unsafe {}",
+        ] {
+            assert!(has_unsafe_keyword(source), "{}", source);
+        }
+        for source in [
+            "#![forbid(unsafe_code)]",
+            "// unsafe {",
+            "/* /* nested */ unsafe { */",
+            "let s = \"unsafe {\";",
+            "let s = r#\"unsafe \" {\"#;",
+            "let not_unsafe = unsafe_op;",
+        ] {
+            assert!(!has_unsafe_keyword(source), "{}", source);
+        }
+    }
+}
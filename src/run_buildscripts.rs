@@ -0,0 +1,405 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Run build scripts ahead of time and commit their outputs
+//!
+//! Each selected package's build script is run by Cargo in a scratch target
+//! directory. The generated OUT_DIR contents are copied into the package's
+//! fixup directory, and the `cargo:rustc-cfg` and `cargo:rustc-env` outputs
+//! are recorded as a `buildscript.prerun` fixup so Buck never has to build or
+//! run the script itself.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use toml_edit::Array;
+use toml_edit::ArrayOfTables;
+use toml_edit::Document;
+use toml_edit::Item;
+use toml_edit::Table;
+use walkdir::WalkDir;
+
+use crate::buckify::relative_path;
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::config::Config;
+use crate::fixups::primary_fixup_dir;
+use crate::platform::PlatformExpr;
+use crate::platform::PlatformPredicate;
+use crate::Args;
+use crate::Paths;
+
+/// The subset of Cargo's `build-script-executed` JSON message we care about
+#[derive(Debug, Deserialize)]
+struct BuildScriptExecuted {
+    package_id: String,
+    #[serde(default)]
+    linked_libs: Vec<String>,
+    #[serde(default)]
+    linked_paths: Vec<String>,
+    #[serde(default)]
+    cfgs: BTreeSet<String>,
+    #[serde(default)]
+    env: Vec<(String, String)>,
+    out_dir: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "reason")]
+enum Message {
+    #[serde(rename = "build-script-executed")]
+    BuildScriptExecuted(BuildScriptExecuted),
+    #[serde(other)]
+    Other,
+}
+
+pub(crate) fn run_buildscripts(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    packages: &[String],
+    platform: Option<&PlatformExpr>,
+) -> Result<()> {
+    if let Some(platform) = platform {
+        PlatformPredicate::parse(platform)
+            .with_context(|| format!("Bad platform expression \"{}\"", platform))?;
+    }
+
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+
+    let mut selected = Vec::new();
+    for name in packages {
+        let candidates: Vec<&Manifest> = metadata
+            .packages
+            .iter()
+            .filter(|pkg| &pkg.name == name)
+            .collect();
+        match candidates.as_slice() {
+            [] => bail!("No package named `{}` in the dependency graph", name),
+            [pkg] => {
+                if !pkg.targets.iter().any(|tgt| tgt.kind_custom_build()) {
+                    bail!("Package {} does not have a build script", pkg);
+                }
+                selected.push(*pkg);
+            }
+            _ => bail!(
+                "Package name `{}` is ambiguous, multiple versions are present: {}",
+                name,
+                candidates
+                    .iter()
+                    .map(|pkg| pkg.version.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        }
+    }
+
+    let target_dir = tempfile::tempdir().context("creating scratch target dir")?;
+
+    for pkg in selected {
+        let output = run_buildscript(config, args, paths, pkg, target_dir.path())
+            .with_context(|| format!("running build script for {}", pkg))?;
+
+        if !output.linked_libs.is_empty() || !output.linked_paths.is_empty() {
+            log::warn!(
+                "{} build script asks to link {:?} (search paths {:?}); these are not \
+                 captured, use a cxx_library fixup instead",
+                pkg,
+                output.linked_libs,
+                output.linked_paths,
+            );
+        }
+
+        let fixup_dir = primary_fixup_dir(config, paths, pkg);
+        let out_dir = copy_out_dir(&output.out_dir, &fixup_dir, platform)?;
+
+        // Env vars pointing into the scratch OUT_DIR (eg. to be `include!`d)
+        // are redirected to the committed copy.
+        let mut output = output;
+        for (var, value) in &mut output.env {
+            if let (Some(out_dir), Ok(rest)) =
+                (&out_dir, Path::new(value).strip_prefix(&output.out_dir))
+            {
+                let committed = relative_path(&paths.third_party_dir, &fixup_dir.join(out_dir));
+                *value = committed.join(rest).to_string_lossy().into_owned();
+            } else if Path::new(value).starts_with(target_dir.path()) {
+                log::warn!(
+                    "{} build script sets {} to a path in its build directory: {}",
+                    pkg,
+                    var,
+                    value,
+                );
+            }
+        }
+        write_prerun_fixup(&fixup_dir, platform, out_dir.as_deref(), &output)?;
+
+        log::info!(
+            "Captured build script outputs for {} in {}",
+            pkg,
+            relative_path(&paths.third_party_dir, &fixup_dir).display(),
+        );
+    }
+
+    Ok(())
+}
+
+fn run_buildscript(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    pkg: &Manifest,
+    target_dir: &Path,
+) -> Result<BuildScriptExecuted> {
+    let pkgspec = format!("{}@{}", pkg.name, pkg.version);
+    let mut cargo_flags = vec![
+        "check",
+        "--message-format=json",
+        "--manifest-path",
+        paths.manifest_path.to_str().unwrap(),
+        "--target-dir",
+        target_dir.to_str().unwrap(),
+        "--package",
+        &pkgspec,
+    ];
+
+    let cargo_home = if config.vendor.is_some() {
+        // Same as buckify: only ever build what was vendored.
+        cargo_flags.extend(["--frozen", "--locked", "--offline"]);
+        Some(paths.cargo_home.as_path())
+    } else {
+        None
+    };
+
+    let stdout = cargo::run_cargo(
+        config,
+        cargo_home,
+        &paths.third_party_dir,
+        args,
+        &cargo_flags,
+    )?;
+
+    for line in String::from_utf8(stdout)?.lines() {
+        let message: Message = serde_json::from_str(line)
+            .with_context(|| format!("deserializing cargo message: {}", line))?;
+        if let Message::BuildScriptExecuted(output) = message {
+            if output.package_id == pkg.id.0 {
+                return Ok(output);
+            }
+        }
+    }
+
+    bail!("Cargo did not report running the build script")
+}
+
+/// Name of the directory, relative to the fixup dir, which holds the committed
+/// OUT_DIR for the given platform.
+fn out_dir_name(platform: Option<&PlatformExpr>) -> String {
+    match platform {
+        None => "out_dir".to_owned(),
+        Some(platform) => {
+            let platform = platform.to_string();
+            let words: Vec<&str> = platform
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect();
+            format!("out_dir-{}", words.join("_"))
+        }
+    }
+}
+
+/// Replace the committed copy of OUT_DIR with what the build script just
+/// generated. Returns `None` if the build script didn't generate anything.
+fn copy_out_dir(
+    out_dir: &Path,
+    fixup_dir: &Path,
+    platform: Option<&PlatformExpr>,
+) -> Result<Option<PathBuf>> {
+    let name = PathBuf::from(out_dir_name(platform));
+    let dest = fixup_dir.join(&name);
+
+    match fs::remove_dir_all(&dest) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| format!("removing {}", dest.display()));
+        }
+    }
+
+    let mut any = false;
+    for entry in WalkDir::new(out_dir) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let dest_path = dest.join(relative_path(out_dir, entry.path()));
+        fs::create_dir_all(dest_path.parent().unwrap())?;
+        fs::copy(entry.path(), &dest_path)
+            .with_context(|| format!("copying to {}", dest_path.display()))?;
+        any = true;
+    }
+
+    Ok(any.then_some(name))
+}
+
+/// Edit fixups.toml in place, preserving any hand-written content, so that the
+/// relevant `buildscript` list contains a `prerun` entry instead of whatever
+/// entries it had before for what the prerun one covers: a previous `prerun`,
+/// `unresolved`, or the `rustc_flags` and `gen_srcs` of running the build
+/// script at build time. Library fixups stay.
+fn write_prerun_fixup(
+    fixup_dir: &Path,
+    platform: Option<&PlatformExpr>,
+    out_dir: Option<&Path>,
+    output: &BuildScriptExecuted,
+) -> Result<()> {
+    let fixup_path = fixup_dir.join("fixups.toml");
+    let mut doc = match fs::read_to_string(&fixup_path) {
+        Ok(content) => content
+            .parse::<Document>()
+            .with_context(|| format!("Failed to parse {}", fixup_path.display()))?,
+        Err(err) if err.kind() == ErrorKind::NotFound => Document::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", fixup_path.display()));
+        }
+    };
+
+    let mut table = doc.as_table_mut();
+    if let Some(platform) = platform {
+        table = implicit_table(table, "platform_fixup")?;
+        table = implicit_table(table, &platform.to_string())?;
+    }
+
+    let existing: Vec<Table> = match table.remove("buildscript") {
+        None => Vec::new(),
+        Some(Item::ArrayOfTables(existing)) => existing.into_iter().collect(),
+        Some(Item::Value(toml_edit::Value::Array(existing))) => existing
+            .into_iter()
+            .map(|value| match value {
+                toml_edit::Value::InlineTable(entry) => Ok(entry.into_table()),
+                _ => bail!("{}: malformed buildscript entry", fixup_path.display()),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("{}: malformed buildscript", fixup_path.display()),
+    };
+    let mut buildscript: ArrayOfTables = existing
+        .into_iter()
+        .filter(|entry| {
+            !["prerun", "unresolved", "rustc_flags", "gen_srcs"]
+                .iter()
+                .any(|kind| entry.contains_key(kind))
+        })
+        .collect();
+
+    let mut prerun = Table::new();
+    if let Some(out_dir) = out_dir {
+        prerun["out_dir"] = toml_edit::value(out_dir.to_string_lossy().as_ref());
+    }
+    if !output.cfgs.is_empty() {
+        prerun["cfgs"] = toml_edit::value(output.cfgs.iter().collect::<Array>());
+    }
+    let env: BTreeMap<&str, &str> = output
+        .env
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    if !env.is_empty() {
+        let mut env_table = Table::new();
+        for (k, v) in env {
+            env_table[k] = toml_edit::value(v);
+        }
+        prerun["env"] = Item::Table(env_table);
+    }
+
+    let mut entry = Table::new();
+    entry.set_implicit(true);
+    entry["prerun"] = Item::Table(prerun);
+    buildscript.push(entry);
+    table["buildscript"] = Item::ArrayOfTables(buildscript);
+
+    fs::create_dir_all(fixup_dir)?;
+    fs::write(&fixup_path, doc.to_string())
+        .with_context(|| format!("Failed to write {}", fixup_path.display()))?;
+
+    Ok(())
+}
+
+fn implicit_table<'a>(table: &'a mut Table, key: &str) -> Result<&'a mut Table> {
+    let item = table.entry(key).or_insert_with(|| {
+        let mut new = Table::new();
+        new.set_implicit(true);
+        Item::Table(new)
+    });
+    match item.as_table_mut() {
+        Some(table) => Ok(table),
+        None => bail!("`{}` in fixups.toml is not a table", key),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::write_prerun_fixup;
+    use super::BuildScriptExecuted;
+
+    #[test]
+    fn replaces_build_time_fixups() {
+        let fixup_dir = tempfile::tempdir().unwrap();
+        let fixup_path = fixup_dir.path().join("fixups.toml");
+        fs::write(
+            &fixup_path,
+            "# hand-written\n\
+             cfgs = [\"foo\"]\n\
+             \n\
+             [[buildscript]]\n\
+             [buildscript.rustc_flags]\n\
+             \n\
+             [[buildscript]]\n\
+             [buildscript.gen_srcs]\n\
+             \n\
+             [[buildscript]]\n\
+             [buildscript.cxx_library]\n\
+             name = \"foo-c\"\n\
+             srcs = [\"src/foo.c\"]\n",
+        )
+        .unwrap();
+
+        let output = BuildScriptExecuted {
+            package_id: "foo 1.0.0".to_owned(),
+            linked_libs: Vec::new(),
+            linked_paths: Vec::new(),
+            cfgs: ["has_foo".to_owned()].into(),
+            env: Vec::new(),
+            out_dir: fixup_dir.path().join("scratch"),
+        };
+        write_prerun_fixup(fixup_dir.path(), None, None, &output).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&fixup_path).unwrap(),
+            "# hand-written\n\
+             cfgs = [\"foo\"]\n\
+             \n\
+             [[buildscript]]\n\
+             [buildscript.cxx_library]\n\
+             name = \"foo-c\"\n\
+             srcs = [\"src/foo.c\"]\n\
+             \n\
+             [[buildscript]]\n\
+             \n\
+             [buildscript.prerun]\n\
+             cfgs = [\"has_foo\"]\n",
+        );
+    }
+}
@@ -0,0 +1,361 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Rustc flags for a rule come from the Cargo profile, fixups, fixups for
+//! particular platforms and build scripts. Before they go into the rule,
+//! repeats of flags that are only needed once are dropped, and flags which
+//! contradict each other are reported along with where each came from. Other
+//! flags, such as `-l` and `-C link-arg`, stay as they are, in order.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buck::PlatformRustCommon;
+use crate::cargo::Manifest;
+use crate::cargo::ManifestTarget;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::platform::platform_names_for_expr;
+use crate::platform::PlatformExpr;
+use crate::platform::PlatformName;
+
+/// Options whose value can be a separate argument, as in `-C opt-level=3`.
+const TAKES_VALUE: &[&str] = &[
+    "-A",
+    "-C",
+    "-D",
+    "-F",
+    "-L",
+    "-W",
+    "-l",
+    "--cap-lints",
+    "--cfg",
+    "--check-cfg",
+    "--codegen",
+    "--edition",
+    "--extern",
+];
+
+/// Codegen options which add to what earlier ones said, rather than replacing
+/// it, like `-C link-arg`. Each `-C target-feature` turns on or off the
+/// features it lists, leaving the others as they were.
+const ACCUMULATING_CODEGEN: &[&str] = &[
+    "link-arg",
+    "link-args",
+    "llvm-args",
+    "passes",
+    "target-feature",
+];
+
+/// A flag with its value, if that's a separate argument.
+#[derive(Debug, Clone)]
+struct Flag {
+    args: Vec<String>,
+    source: String,
+}
+
+impl Flag {
+    /// For flags where only the last one counts, the setting and its value:
+    /// `("-Copt-level", "3")` for `-C opt-level=3`.
+    fn setting(&self) -> Option<(String, String)> {
+        let joined = self.args.join(" ");
+        let (key, value) = if let Some(codegen) = joined
+            .strip_prefix("-C")
+            .or_else(|| joined.strip_prefix("--codegen"))
+        {
+            let codegen = codegen.trim_start_matches([' ', '=']);
+            let (key, value) = codegen.split_once('=').unwrap_or((codegen, ""));
+            if ACCUMULATING_CODEGEN.contains(&key) {
+                return None;
+            }
+            (format!("-C{}", key), value)
+        } else if let Some(value) = joined.strip_prefix("--cap-lints") {
+            (
+                "--cap-lints".to_owned(),
+                value.trim_start_matches([' ', '=']),
+            )
+        } else if let Some(value) = joined.strip_prefix("--edition") {
+            ("--edition".to_owned(), value.trim_start_matches([' ', '=']))
+        } else {
+            return None;
+        };
+        Some((key, value.to_owned()))
+    }
+
+    fn is_cfg(&self) -> bool {
+        self.args[0] == "--cfg" || self.args[0].starts_with("--cfg=")
+    }
+}
+
+/// Rustc flags gathered from all their sources.
+#[derive(Debug, Default)]
+pub(crate) struct RustcFlags {
+    common: Vec<Flag>,
+    perplat: BTreeMap<PlatformName, Vec<Flag>>,
+}
+
+impl RustcFlags {
+    /// Add flags from `source`, for every platform or those matching
+    /// `platform`.
+    pub fn add(
+        &mut self,
+        config: &Config,
+        platform: Option<&PlatformExpr>,
+        args: Vec<String>,
+        source: &str,
+    ) -> Result<()> {
+        let flags = split_flags(args, source);
+        let Some(expr) = platform else {
+            self.common.extend(flags);
+            return Ok(());
+        };
+
+        let plats = platform_names_for_expr(config, expr)
+            .with_context(|| format!("Bad platform expression \"{}\"", expr))?;
+        for plat in plats {
+            let flags = flags.iter().cloned();
+            // Like everything else, flags for the DEFAULT platform apply to
+            // all of them.
+            if plat.is_default() {
+                self.common.extend(flags);
+            } else {
+                self.perplat.entry(plat.clone()).or_default().extend(flags);
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the flags into the rule, without repeats, reporting any which
+    /// conflict.
+    pub fn apply(
+        self,
+        pkg: &Manifest,
+        tgt: &ManifestTarget,
+        base: &mut PlatformRustCommon,
+        perplat: &mut BTreeMap<PlatformName, PlatformRustCommon>,
+    ) {
+        let common = dedup(self.common, &[]);
+        for conflict in conflicts(&common, &[]) {
+            report(pkg, tgt, &conflict, None);
+        }
+
+        for (plat, flags) in self.perplat {
+            let flags = dedup(flags, &common);
+            if flags.is_empty() {
+                continue;
+            }
+            for conflict in conflicts(&common, &flags) {
+                report(pkg, tgt, &conflict, Some(&plat));
+            }
+            let rule = perplat.entry(plat).or_default();
+            rule.rustc_flags
+                .extend(flags.into_iter().flat_map(|flag| flag.args));
+        }
+
+        base.rustc_flags
+            .extend(common.into_iter().flat_map(|flag| flag.args));
+    }
+}
+
+/// Group arguments into flags, so that `-C opt-level=3` is one flag.
+fn split_flags(args: Vec<String>, source: &str) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = Vec::new();
+    for arg in args {
+        match flags.last_mut() {
+            Some(flag) if flag.args.len() == 1 && TAKES_VALUE.contains(&flag.args[0].as_str()) => {
+                flag.args.push(arg);
+            }
+            _ => flags.push(Flag {
+                args: vec![arg],
+                source: source.to_owned(),
+            }),
+        }
+    }
+    flags
+}
+
+/// Drop flags which are repeats, or are already in `existing`: a `--cfg`
+/// given before, or a setting whose last value so far is the same. Anything
+/// else could mean something different the second time, so stays.
+fn dedup(flags: Vec<Flag>, existing: &[Flag]) -> Vec<Flag> {
+    let mut kept: Vec<Flag> = Vec::new();
+    for flag in flags {
+        let mut before = existing.iter().chain(&kept);
+        let repeat = if flag.is_cfg() {
+            before.any(|other| other.args == flag.args)
+        } else if let Some((key, value)) = flag.setting() {
+            before
+                .rev()
+                .filter_map(Flag::setting)
+                .find(|(other, _)| *other == key)
+                .is_some_and(|(_, previous)| previous == value)
+        } else {
+            false
+        };
+        if repeat {
+            log::debug!("dropping repeated rustc flag {:?}", flag.args);
+        } else {
+            kept.push(flag);
+        }
+    }
+    kept
+}
+
+/// Pairs of flags which set the same thing differently, at least one of them
+/// from `flags`. With no `flags`, pairs from `existing`.
+fn conflicts<'a>(existing: &'a [Flag], flags: &'a [Flag]) -> Vec<(&'a Flag, &'a Flag)> {
+    let (earlier, later) = if flags.is_empty() {
+        (&[][..], existing)
+    } else {
+        (existing, flags)
+    };
+
+    let mut conflicts = Vec::new();
+    let mut settings: BTreeMap<String, (String, &Flag)> = BTreeMap::new();
+    for flag in earlier {
+        if let Some((key, value)) = flag.setting() {
+            settings.insert(key, (value, flag));
+        }
+    }
+    for flag in later {
+        let Some((key, value)) = flag.setting() else {
+            continue;
+        };
+        if let Some((previous, previous_flag)) = settings.get(&key) {
+            if *previous != value {
+                conflicts.push((*previous_flag, flag));
+            }
+        }
+        settings.insert(key, (value, flag));
+    }
+    conflicts
+}
+
+fn report(
+    pkg: &Manifest,
+    tgt: &ManifestTarget,
+    conflict: &(&Flag, &Flag),
+    plat: Option<&PlatformName>,
+) {
+    let (first, second) = conflict;
+    let on_platform = match plat {
+        Some(plat) => format!(" on platform {}", plat),
+        None => String::new(),
+    };
+    Diagnostic::new(
+        Severity::Warning,
+        format!(
+            "rustc flags `{}` from {} and `{}` from {} conflict{}, and the last one wins",
+            first.args.join(" "),
+            first.source,
+            second.args.join(" "),
+            second.source,
+            on_platform,
+        ),
+    )
+    .package(pkg)
+    .target(&tgt.name)
+    .suggestion("remove one of them")
+    .report();
+}
+
+#[cfg(test)]
+mod test {
+    use super::conflicts;
+    use super::dedup;
+    use super::split_flags;
+
+    fn args(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| (*flag).to_owned()).collect()
+    }
+
+    #[test]
+    fn drops_repeats() {
+        let common = split_flags(
+            args(&["--cfg", "foo", "--cfg=bar", "--cfg", "foo"]),
+            "fixups",
+        );
+        let common = dedup(common, &[]);
+        let flags: Vec<_> = common.iter().map(|flag| flag.args.join(" ")).collect();
+        assert_eq!(flags, ["--cfg foo", "--cfg=bar"]);
+
+        let perplat = split_flags(args(&["--cfg=bar", "--cfg=baz"]), "fixups");
+        let perplat = dedup(perplat, &common);
+        let flags: Vec<_> = perplat.iter().map(|flag| flag.args.join(" ")).collect();
+        assert_eq!(flags, ["--cfg=baz"]);
+
+        let settings = split_flags(
+            args(&[
+                "-Copt-level=0",
+                "-Copt-level=3",
+                "-Copt-level=0",
+                "-C",
+                "opt-level=0",
+            ]),
+            "fixups",
+        );
+        let flags: Vec<_> = dedup(settings, &[])
+            .iter()
+            .map(|flag| flag.args.join(" "))
+            .collect();
+        assert_eq!(flags, ["-Copt-level=0", "-Copt-level=3", "-Copt-level=0"]);
+    }
+
+    #[test]
+    fn keeps_order_dependent_repeats() {
+        let link = args(&[
+            "-Clink-arg=-framework",
+            "-Clink-arg=Foo",
+            "-Clink-arg=-framework",
+            "-Clink-arg=Bar",
+            "-l",
+            "foo",
+            "-L",
+            "native=lib",
+            "-l",
+            "foo",
+            "-L",
+            "native=lib",
+        ]);
+        let flags = dedup(split_flags(link.clone(), "fixups"), &[]);
+        assert!(conflicts(&flags, &[]).is_empty());
+        let flags: Vec<String> = flags.into_iter().flat_map(|flag| flag.args).collect();
+        assert_eq!(flags, link);
+
+        let common = split_flags(args(&["-Ctarget-feature=+aes"]), "fixups");
+        let perplat = split_flags(
+            args(&["-C", "target-feature=+sse2", "-Ctarget-feature=+aes"]),
+            "fixups for linux",
+        );
+        assert!(conflicts(&common, &perplat).is_empty());
+        let flags: Vec<_> = dedup(perplat, &common)
+            .iter()
+            .map(|flag| flag.args.join(" "))
+            .collect();
+        assert_eq!(flags, ["-C target-feature=+sse2", "-Ctarget-feature=+aes"]);
+    }
+
+    #[test]
+    fn finds_conflicts() {
+        let mut common = split_flags(args(&["-Copt-level=0", "--cfg=foo"]), "the Cargo profile");
+        common.extend(split_flags(
+            args(&["-C", "opt-level=3", "--cfg=bar", "-Cpanic=abort"]),
+            "fixups",
+        ));
+        let found: Vec<_> = conflicts(&common, &[])
+            .into_iter()
+            .map(|(a, b)| (a.source.as_str(), b.args.join(" ")))
+            .collect();
+        assert_eq!(found, [("the Cargo profile", "-C opt-level=3".to_owned())]);
+
+        let perplat = split_flags(args(&["--codegen=panic=unwind"]), "fixups for linux");
+        assert_eq!(conflicts(&common, &perplat).len(), 1);
+    }
+}
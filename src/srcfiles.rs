@@ -21,6 +21,8 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use proc_macro2 as _; // To autocargo with our features (namely `span-locations`)
+use proc_macro2::TokenStream;
+use proc_macro2::TokenTree;
 use syn::visit::Visit;
 
 #[allow(dead_code)]
@@ -30,6 +32,7 @@ pub struct Error {
     kind: ErrorKind,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum ErrorKind {
     FileError {
@@ -149,6 +152,72 @@ impl SourceFinder<'_> {
         });
     }
 
+    /// Add a file named by `include!`, `include_str!` or `include_bytes!`.
+    /// Files from `include!` are Rust code which can declare more modules or
+    /// include more files, so they get searched too.
+    fn visit_include(&mut self, path: &str, is_rust: bool) {
+        let source_path = {
+            let mut p = parent_dir(self.current).to_owned();
+            p.extend(Path::new(path));
+            p
+        };
+        match fs::read_to_string(&source_path) {
+            Ok(content) => {
+                if !self.sources.files.insert(source_path.clone()) || !is_rust {
+                    return;
+                }
+                // `include!` can also be used for a single expression, which
+                // doesn't parse as a file and can't name any other files.
+                if let Ok(ast) = syn::parse_file(&content) {
+                    SourceFinder {
+                        current: &source_path,
+                        sources: self.sources,
+                        mod_ancestors: vec![],
+                        mod_rs: ModRs::Yes,
+                    }
+                    .visit_file(&ast);
+                }
+            }
+            // Not UTF-8, which is fine for `include_bytes!`.
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                self.sources.files.insert(source_path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.push_error(ErrorKind::IncludeNotFound { source_path });
+            }
+            Err(err) => {
+                self.push_error(ErrorKind::FileError {
+                    source_path,
+                    source: err,
+                });
+            }
+        }
+    }
+
+    /// Look for includes with a literal path in tokens which aren't parsed
+    /// otherwise.
+    fn visit_tokens(&mut self, tokens: TokenStream) {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                TokenTree::Ident(ident)
+                    if ident == "include" || ident == "include_str" || ident == "include_bytes" =>
+                {
+                    if let [TokenTree::Punct(bang), TokenTree::Group(group), ..] = &tokens[i + 1..]
+                    {
+                        if bang.as_char() == '!' {
+                            if let Ok(path) = syn::parse2::<syn::LitStr>(group.stream()) {
+                                self.visit_include(&path.value(), ident == "include");
+                            }
+                        }
+                    }
+                }
+                TokenTree::Group(group) => self.visit_tokens(group.stream()),
+                _ => {}
+            }
+        }
+    }
+
     /// Returns `true` if something was added to `sources`. In other words,
     /// returns `false` if the source file was not found.
     fn parse_and_visit_source_file(&mut self, source_path: &Path, mod_rs: ModRs) -> bool {
@@ -305,31 +374,10 @@ impl<'ast> Visit<'ast> for SourceFinder<'_> {
     fn visit_macro(&mut self, node: &'ast syn::Macro) {
         let macro_ident = node.path.segments.last().unwrap().ident.to_string();
 
-        // TODO: Consider parsing the `include` for more modules.
         match macro_ident.as_str() {
             "include_str" | "include_bytes" | "include" => {
                 match node.parse_body::<syn::LitStr>() {
-                    Ok(path) => {
-                        let source_path = {
-                            let mut p = parent_dir(self.current).to_owned();
-                            p.extend(Path::new(&path.value()));
-                            p
-                        };
-                        match fs::File::open(&source_path) {
-                            Ok(_) => {
-                                self.sources.files.insert(source_path);
-                            }
-                            Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                                self.push_error(ErrorKind::IncludeNotFound { source_path });
-                            }
-                            Err(err) => {
-                                self.push_error(ErrorKind::FileError {
-                                    source_path,
-                                    source: err,
-                                });
-                            }
-                        }
-                    }
+                    Ok(path) => self.visit_include(&path.value(), macro_ident == "include"),
                     Err(err) => {
                         self.push_error(ErrorKind::ParserError {
                             line: err.span().start().line,
@@ -351,7 +399,9 @@ impl<'ast> Visit<'ast> for SourceFinder<'_> {
                     });
                 }
             },
-            _ => {}
+            // Includes can also be buried in the input of other macros, as in
+            // `lazy_static! { static ref X: &str = include_str!("x"); }`.
+            _ => self.visit_tokens(node.tokens.clone()),
         };
     }
 }
@@ -651,4 +701,56 @@ mod tests {
             HashSet::<String>::new(),
         );
     }
+
+    #[test]
+    fn test_nested_include() {
+        let dir = scaffold! {
+            "src/lib.rs" => {
+                lazy_static! {
+                    static ref QUERY: &'static str = include_str!("query.sql");
+                }
+                include!("generated.rs");
+                macro_rules! version {
+                    () => {
+                        include!(concat!(env!("OUT_DIR"), "/version.rs"))
+                    };
+                }
+            },
+            "src/query.sql" => {},
+            "src/generated.rs" => {
+                mod inner;
+                const TABLE: [u8; 4] = *include_bytes!("table.bin");
+            },
+            "src/inner.rs" => {},
+            "src/table.bin" => {},
+        }
+        .unwrap();
+
+        let res = crate_srcfiles(dir.path().join("src/lib.rs"));
+
+        assert_eq!(
+            res.files
+                .iter()
+                .map(|x| x.strip_prefix(&dir).unwrap())
+                .collect::<HashSet<_>>(),
+            [
+                "src/lib.rs",
+                "src/query.sql",
+                "src/generated.rs",
+                "src/inner.rs",
+                "src/table.bin",
+            ]
+            .into_iter()
+            .map(Path::new)
+            .collect::<HashSet<_>>(),
+        );
+
+        assert_eq!(
+            res.errors
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<HashSet<_>>(),
+            HashSet::<String>::new(),
+        );
+    }
 }
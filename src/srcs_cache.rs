@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Remember what `crate_srcfiles` found for each target, so crates which
+//! haven't changed since the last buckify don't need to be parsed again.
+//!
+//! An entry is valid as long as every `.rs` file under the crate's directory,
+//! and every file the target was found to use, has the same contents. Other
+//! files, such as a BUCK file in the crate's directory, can't affect which
+//! modules are found.
+
+use std::fs;
+use std::hash::Hasher as _;
+use std::io::ErrorKind;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use fnv::FnvHasher;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::buckify::relative_path;
+use crate::srcfiles::crate_srcfiles;
+use crate::srcfiles::Sources;
+use crate::Paths;
+
+/// Relative to the third-party dir
+pub const CACHE_DIR: &str = ".reindeer-cache";
+
+/// Bump whenever `crate_srcfiles` might find different files for the same
+/// sources, so that entries from before don't match any more. Entries from a
+/// different version of Reindeer don't match either.
+const CACHE_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    fingerprint: String,
+    /// Relative to the crate's directory
+    files: Vec<PathBuf>,
+}
+
+/// Like `crate_srcfiles`, but using and updating the cache. Results with
+/// errors are not cached, so that they get reported every time.
+pub(crate) fn cached_crate_srcfiles(
+    paths: &Paths,
+    manifest_dir: &Path,
+    src_path: &Path,
+) -> Sources {
+    let cache_path = paths
+        .third_party_dir
+        .join(CACHE_DIR)
+        .join("srcfiles")
+        .join(format!("{:016x}.json", hash_path(src_path)));
+
+    match lookup(&cache_path, manifest_dir) {
+        Ok(Some(files)) => {
+            return Sources {
+                files: files.iter().map(|file| manifest_dir.join(file)).collect(),
+                errors: vec![],
+            };
+        }
+        Ok(None) => {}
+        Err(err) => log::debug!("ignoring srcfiles cache entry: {:?}", err),
+    }
+
+    let sources = crate_srcfiles(src_path);
+    if sources.errors.is_empty() {
+        let mut files: Vec<PathBuf> = sources
+            .files
+            .iter()
+            .map(|file| relative_path(manifest_dir, file))
+            .collect();
+        files.sort();
+        if let Err(err) = store(&cache_path, manifest_dir, files) {
+            log::warn!("failed to update srcfiles cache: {:?}", err);
+        }
+    }
+    sources
+}
+
+fn lookup(cache_path: &Path, manifest_dir: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let json = match fs::read(cache_path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context(format!("reading {}", cache_path.display())),
+    };
+    let entry: CacheEntry = serde_json::from_slice(&json)
+        .with_context(|| format!("parsing {}", cache_path.display()))?;
+
+    if fingerprint(manifest_dir, &entry.files)? == entry.fingerprint {
+        Ok(Some(entry.files))
+    } else {
+        Ok(None)
+    }
+}
+
+fn store(cache_path: &Path, manifest_dir: &Path, files: Vec<PathBuf>) -> Result<()> {
+    let entry = CacheEntry {
+        fingerprint: fingerprint(manifest_dir, &files)?,
+        files,
+    };
+    fs::create_dir_all(cache_path.parent().unwrap())?;
+    let json = serde_json::to_vec(&entry)?;
+    fs::write(cache_path, json).with_context(|| format!("writing {}", cache_path.display()))
+}
+
+/// Hash of the contents of the Rust files in the crate's directory, plus the
+/// files that the target uses, and of the cache format and Reindeer version.
+fn fingerprint(manifest_dir: &Path, files: &[PathBuf]) -> Result<String> {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u32(CACHE_FORMAT);
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    let mut hash_file = |relpath: &Path| -> Result<()> {
+        let path = manifest_dir.join(relpath);
+        let content = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+        hasher.write(relpath.to_string_lossy().as_bytes());
+        hasher.write_u64(content.len() as u64);
+        hasher.write(&content);
+        Ok(())
+    };
+
+    let walk = walkdir::WalkDir::new(manifest_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            // Build output and VCS metadata don't affect the sources.
+            entry.depth() == 0
+                || !(entry.file_type().is_dir()
+                    && (entry.file_name() == "target"
+                        || entry.file_name().to_string_lossy().starts_with('.')))
+        });
+    for entry in walk {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension() == Some("rs".as_ref()) {
+            hash_file(entry.path().strip_prefix(manifest_dir).unwrap())?;
+        }
+    }
+
+    // Includes, and modules outside of the crate's directory.
+    for file in files {
+        if file.extension() != Some("rs".as_ref())
+            || file.components().next() == Some(Component::ParentDir)
+        {
+            hash_file(file)?;
+        }
+    }
+
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn hash_path(path: &Path) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(path.to_string_lossy().as_bytes());
+    hasher.finish()
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! List the public crates which nothing first-party uses, as candidates for
+//! removal from Cargo.toml.
+//!
+//! First-party code shows up in two places: as members of the `workspaces`
+//! merged into the dependency graph, and as Buck targets depending on the
+//! public aliases. `unused_query` asks Buck for the latter.
+
+use std::collections::BTreeSet;
+use std::process::Command;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::buckify::buck_package;
+use crate::cargo;
+use crate::cargo::Manifest;
+use crate::cargo::TargetReq;
+use crate::config::Config;
+use crate::index::Index;
+use crate::Args;
+use crate::Paths;
+
+pub(crate) fn unused(config: &Config, args: &Args, paths: &Paths, cargo_only: bool) -> Result<()> {
+    let (_lockfile, metadata) = cargo::cargo_get_lockfile_and_metadata(config, args, paths)?;
+    let index = Index::new(
+        config.include_top_level,
+        config.naming,
+        config.collision_suffix,
+        &metadata,
+    )?;
+
+    // The pseudo-package only lists the third-party crates, so it doesn't
+    // count as using them.
+    let first_party: Vec<&Manifest> = index
+        .workspace_members
+        .iter()
+        .copied()
+        .filter(|pkg| config.include_top_level || !index.is_root_package(pkg))
+        .collect();
+
+    let query = match &config.buck.unused_query {
+        Some(command) if !cargo_only => Some(command),
+        _ => None,
+    };
+    if first_party.is_empty() && query.is_none() {
+        bail!(
+            "no first-party packages in the dependency graph to check against; \
+            set `unused_query` to ask Buck which crates are used"
+        );
+    }
+
+    let mut used: BTreeSet<&Manifest> = first_party
+        .iter()
+        .flat_map(|&member| {
+            member
+                .targets
+                .iter()
+                .flat_map(|tgt| index.resolved_deps_for_target(member, tgt))
+        })
+        .map(|dep| dep.package)
+        .collect();
+
+    if let Some(command) = query {
+        let labels = query_labels(command, paths)?;
+        let package = buck_package(&paths.third_party_dir, &paths.third_party_dir)?;
+        let names: BTreeSet<&str> = labels
+            .iter()
+            .filter_map(|label| {
+                let (pkg, name) = label.rsplit_once(':')?;
+                let (_cell, pkg) = pkg.split_once("//")?;
+                (pkg == package).then_some(name)
+            })
+            .collect();
+        for pkg in index.public_packages() {
+            let public_name = index.public_rule_name(pkg);
+            if names.contains(public_name.0.as_str())
+                || index
+                    .semver_aliases(pkg)
+                    .iter()
+                    .any(|alias| names.contains(alias.0.as_str()))
+            {
+                used.insert(pkg);
+            }
+        }
+    }
+
+    let reachable: BTreeSet<&Manifest> = index.dependency_chains(|_| false).packages().collect();
+    let mut candidates = Vec::new();
+    for pkg in index.public_packages() {
+        if index.workspace_members.contains(&pkg)
+            || !index.is_public_target(pkg, TargetReq::Lib)
+            || used.contains(pkg)
+        {
+            continue;
+        }
+        let without: BTreeSet<&Manifest> = index
+            .dependency_chains(|other| other == pkg)
+            .packages()
+            .collect();
+        let dependents: Vec<&Manifest> = reachable
+            .iter()
+            .copied()
+            .filter(|other| !index.workspace_members.contains(other))
+            .filter(|other| {
+                other.targets.iter().any(|tgt| {
+                    index
+                        .resolved_deps_for_target(other, tgt)
+                        .any(|dep| dep.package == pkg)
+                })
+            })
+            .collect();
+        let dropped = reachable.difference(&without).count() + usize::from(dependents.is_empty());
+
+        let name = index.public_rule_name(pkg);
+        match dependents.first() {
+            Some(dependent) => println!(
+                "{} ({}): unused, but still a dependency of {}",
+                name, pkg, dependent,
+            ),
+            None => println!(
+                "{} ({}): unused, removing it drops {} package{}",
+                name,
+                pkg,
+                dropped,
+                if dropped == 1 { "" } else { "s" },
+            ),
+        }
+        candidates.push(name.0);
+    }
+
+    if !candidates.is_empty() {
+        println!(
+            "Run `reindeer remove {}` to remove them",
+            candidates.join(" "),
+        );
+    }
+    Ok(())
+}
+
+/// Run `unused_query` and return the labels it prints, one per line.
+fn query_labels(command: &[String], paths: &Paths) -> Result<Vec<String>> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("`unused_query` is empty");
+    };
+
+    log::info!("Finding used crates with {:?}", command);
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(&paths.third_party_dir)
+        .output()
+        .with_context(|| format!("running {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "`{}` failed ({}):\n{}",
+            command.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Commands which change the third-party Cargo.toml or Cargo.lock, then
+//! vendor and buckify, and show what changed:
+//!
+//! - `reindeer update <package>...` updates just those packages
+//! - `reindeer add <package>` adds a dependency
+//! - `reindeer remove <package>...` removes dependencies
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::iter;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use toml_edit::Array;
+use toml_edit::Document;
+use toml_edit::InlineTable;
+use toml_edit::Item;
+use toml_edit::Table;
+
+use crate::buck;
+use crate::buckify;
+use crate::cargo;
+use crate::config;
+use crate::config::Config;
+use crate::lockfile::Lockfile;
+use crate::pseudo_package;
+use crate::vendor;
+use crate::Args;
+use crate::Paths;
+
+/// How to add a dependency with `reindeer add`
+pub(crate) struct NewDependency<'a> {
+    pub package: &'a str,
+    /// Version requirement. If unset, the version Cargo picks is used.
+    pub version: Option<&'a str>,
+    pub features: &'a [String],
+    pub no_default_features: bool,
+}
+
+pub(crate) fn update_packages(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    packages: &[String],
+) -> Result<()> {
+    let before = Snapshot::take(config, paths)?;
+
+    let mut updated = BTreeSet::new();
+    for (manifest_path, lockfile_path) in before.manifest_paths.iter().zip(&before.lockfile_paths) {
+        // Cargo complains about specs which don't match anything in the
+        // lockfile, and each workspace may only have some of the packages.
+        let lockfile = Lockfile::load(lockfile_path)?;
+        let specs: Vec<&str> = packages
+            .iter()
+            .filter(|spec| {
+                let name = spec.split_once('@').map_or(spec.as_str(), |(name, _)| name);
+                lockfile.packages.iter().any(|pkg| pkg.name == name)
+            })
+            .map(String::as_str)
+            .collect();
+        if specs.is_empty() {
+            continue;
+        }
+
+        let mut cmdline = vec!["update", "--manifest-path", manifest_path.to_str().unwrap()];
+        for spec in &specs {
+            cmdline.extend(["--package", spec]);
+        }
+        run_cargo_update(config, args, paths, &cmdline)?;
+        updated.extend(specs);
+    }
+
+    let missing: Vec<&String> = packages
+        .iter()
+        .filter(|spec| !updated.contains(spec.as_str()))
+        .collect();
+    if !missing.is_empty() {
+        bail!("not in any Cargo.lock: {:?}", missing);
+    }
+
+    vendor_and_buckify(config, args, paths, before)
+}
+
+pub(crate) fn add_package(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    dep: &NewDependency,
+) -> Result<()> {
+    let before = Snapshot::take(config, paths)?;
+
+    let original = read_manifest(config, paths)?;
+    let mut doc = original.clone();
+    let dependencies = doc
+        .entry("dependencies")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_like_mut()
+        .context("`dependencies` in Cargo.toml is not a table")?;
+    if dependencies.contains_key(dep.package) {
+        bail!(
+            "{} is already a dependency; edit Cargo.toml to change it, or use `reindeer update {}`",
+            dep.package,
+            dep.package,
+        );
+    }
+    dependencies.insert(
+        dep.package,
+        dependency_item(dep, dep.version.unwrap_or("*")),
+    );
+    write_manifest(config, paths, &doc)?;
+
+    update_workspace(config, args, paths, &original)?;
+
+    // Like `cargo add`, pin the requirement to the version that was picked,
+    // which will be one already in Cargo.lock if there is one.
+    if dep.version.is_none() {
+        let lockfile = Lockfile::load(&paths.lockfile_path)?;
+        let version = lockfile
+            .packages
+            .iter()
+            .filter(|pkg| pkg.name == dep.package)
+            .map(|pkg| &pkg.version)
+            .max()
+            .with_context(|| format!("{} is missing from Cargo.lock", dep.package))?;
+        doc["dependencies"][dep.package] = dependency_item(dep, &version.to_string());
+        write_manifest(config, paths, &doc)?;
+    }
+
+    vendor_and_buckify(config, args, paths, before)
+}
+
+pub(crate) fn remove_packages(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    packages: &[String],
+) -> Result<()> {
+    let before = Snapshot::take(config, paths)?;
+
+    let original = read_manifest(config, paths)?;
+    let mut doc = original.clone();
+    let mut removed = BTreeSet::new();
+    let mut remove_from = |table: &mut dyn toml_edit::TableLike| {
+        let keys: Vec<String> = table
+            .iter()
+            .filter(|(key, item)| {
+                // Renamed dependencies have their package name in `package`.
+                let package = item.get("package").and_then(Item::as_str).unwrap_or(key);
+                packages.iter().any(|name| name == package)
+            })
+            .map(|(key, _)| key.to_owned())
+            .collect();
+        for key in keys {
+            let item = table.remove(&key).unwrap();
+            removed.insert(
+                item.get("package")
+                    .and_then(Item::as_str)
+                    .unwrap_or(&key)
+                    .to_owned(),
+            );
+        }
+    };
+    if let Some(dependencies) = doc
+        .get_mut("dependencies")
+        .and_then(Item::as_table_like_mut)
+    {
+        remove_from(dependencies);
+    }
+    if let Some(targets) = doc.get_mut("target").and_then(Item::as_table_like_mut) {
+        for (_, target) in targets.iter_mut() {
+            if let Some(dependencies) = target
+                .get_mut("dependencies")
+                .and_then(Item::as_table_like_mut)
+            {
+                remove_from(dependencies);
+            }
+        }
+    }
+
+    let missing: Vec<&String> = packages
+        .iter()
+        .filter(|name| !removed.contains(name.as_str()))
+        .collect();
+    if !missing.is_empty() {
+        bail!("not dependencies in Cargo.toml: {:?}", missing);
+    }
+    write_manifest(config, paths, &doc)?;
+
+    update_workspace(config, args, paths, &original)?;
+    vendor_and_buckify(config, args, paths, before)
+}
+
+fn dependency_item(dep: &NewDependency, version: &str) -> Item {
+    if dep.features.is_empty() && !dep.no_default_features {
+        return toml_edit::value(version);
+    }
+    let mut table = InlineTable::new();
+    table.insert("version", version.into());
+    if dep.no_default_features {
+        table.insert("default-features", false.into());
+    }
+    if !dep.features.is_empty() {
+        table.insert("features", dep.features.iter().collect::<Array>().into());
+    }
+    toml_edit::value(table)
+}
+
+/// The file with the `[dependencies]` to edit, which is reindeer.toml if
+/// Cargo.toml is generated from it.
+fn dependencies_path(config: &Config, paths: &Paths) -> PathBuf {
+    if config.dependencies.is_some() {
+        config.config_path.join("reindeer.toml")
+    } else {
+        paths.manifest_path.clone()
+    }
+}
+
+fn read_manifest(config: &Config, paths: &Paths) -> Result<Document> {
+    let path = dependencies_path(config, paths);
+    fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse::<Document>()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn write_manifest(config: &Config, paths: &Paths, doc: &Document) -> Result<()> {
+    let path = dependencies_path(config, paths);
+    fs::write(&path, doc.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    if config.dependencies.is_some() {
+        let config = config::read_config(&paths.third_party_dir)?;
+        let dependencies = config.dependencies.unwrap_or_default();
+        pseudo_package::write_cargo_toml(&dependencies, paths)?;
+    }
+    Ok(())
+}
+
+/// Bring Cargo.lock up to date with Cargo.toml, without updating any
+/// packages which don't need to change. If Cargo can't, put back the original
+/// Cargo.toml.
+fn update_workspace(
+    config: &Config,
+    args: &Args,
+    paths: &Paths,
+    original: &Document,
+) -> Result<()> {
+    let result = run_cargo_update(
+        config,
+        args,
+        paths,
+        &[
+            "update",
+            "--workspace",
+            "--manifest-path",
+            paths.manifest_path.to_str().unwrap(),
+        ],
+    );
+    if result.is_err() {
+        write_manifest(config, paths, original)?;
+    }
+    result
+}
+
+fn run_cargo_update(config: &Config, args: &Args, paths: &Paths, cmdline: &[&str]) -> Result<()> {
+    log::info!("Running cargo {:?}", cmdline);
+    // Not in Reindeer's Cargo home, where crates.io is replaced by the
+    // vendor directory, which doesn't have the new versions yet.
+    cargo::run_cargo(config, None, &paths.third_party_dir, args, cmdline)?;
+    Ok(())
+}
+
+/// Versions and rules from before a change, to show what it did.
+struct Snapshot {
+    manifest_paths: Vec<PathBuf>,
+    lockfile_paths: Vec<PathBuf>,
+    versions: BTreeMap<String, BTreeSet<semver::Version>>,
+    rules: BTreeSet<String>,
+}
+
+impl Snapshot {
+    fn take(config: &Config, paths: &Paths) -> Result<Self> {
+        let manifest_paths: Vec<PathBuf> = iter::once(paths.manifest_path.clone())
+            .chain(
+                config
+                    .workspaces
+                    .iter()
+                    .map(|manifest_path| paths.third_party_dir.join(manifest_path)),
+            )
+            .collect();
+        let lockfile_paths: Vec<PathBuf> = manifest_paths
+            .iter()
+            .map(|manifest_path| manifest_path.with_file_name("Cargo.lock"))
+            .collect();
+        let versions = locked_versions(&lockfile_paths)?;
+        let rules = rule_names(config, paths)?;
+        Ok(Snapshot {
+            manifest_paths,
+            lockfile_paths,
+            versions,
+            rules,
+        })
+    }
+}
+
+fn vendor_and_buckify(config: &Config, args: &Args, paths: &Paths, before: Snapshot) -> Result<()> {
+    // Cargo only re-copies packages into the vendor directory if their
+    // directory doesn't already exist, which with versioned directories
+    // means just the new versions.
+    if config.vendor.is_some() {
+        vendor::cargo_vendor(config, false, false, false, args, paths)?;
+    }
+    buckify::buckify(config, args, paths, false, &[])?;
+
+    let versions = locked_versions(&before.lockfile_paths)?;
+    let rules = rule_names(config, paths)?;
+
+    println!("Versions:");
+    print_version_changes(&before.versions, &versions);
+    println!("Rules:");
+    for name in before.rules.difference(&rules) {
+        println!("  - {}", name);
+    }
+    for name in rules.difference(&before.rules) {
+        println!("  + {}", name);
+    }
+
+    Ok(())
+}
+
+/// Every version of each package in any of the lockfiles
+fn locked_versions(
+    lockfile_paths: &[PathBuf],
+) -> Result<BTreeMap<String, BTreeSet<semver::Version>>> {
+    let mut versions: BTreeMap<String, BTreeSet<semver::Version>> = BTreeMap::new();
+    for lockfile_path in lockfile_paths {
+        match Lockfile::load(lockfile_path) {
+            Ok(lockfile) => {
+                for pkg in lockfile.packages {
+                    versions.entry(pkg.name).or_default().insert(pkg.version);
+                }
+            }
+            // No Cargo.lock yet, before the first `reindeer add`.
+            Err(_) if !lockfile_path.exists() => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(versions)
+}
+
+fn print_version_changes(
+    before: &BTreeMap<String, BTreeSet<semver::Version>>,
+    after: &BTreeMap<String, BTreeSet<semver::Version>>,
+) {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let none = BTreeSet::new();
+    for name in names {
+        let before = before.get(name).unwrap_or(&none);
+        let after = after.get(name).unwrap_or(&none);
+        let removed: Vec<_> = before.difference(after).collect();
+        let added: Vec<_> = after.difference(before).collect();
+        match (removed.as_slice(), added.as_slice()) {
+            ([], []) => {}
+            ([old], [new]) => println!("  {} {} -> {}", name, old, new),
+            _ => {
+                for old in removed {
+                    println!("  - {} {}", name, old);
+                }
+                for new in added {
+                    println!("  + {} {}", name, new);
+                }
+            }
+        }
+    }
+}
+
+/// Names of the rules in the BUCK file, and in the shards it loads them from
+/// if they're sharded.
+fn rule_names(config: &Config, paths: &Paths) -> Result<BTreeSet<String>> {
+    let mut buckpaths = vec![paths.third_party_dir.join(&config.buck.file_name)];
+    match buck::shard_files(&paths.third_party_dir) {
+        Ok(shards) => buckpaths.extend(shards),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).context(format!("reading {}", paths.third_party_dir.display()))
+        }
+    }
+
+    let mut rules = BTreeSet::new();
+    for buckpath in buckpaths {
+        match fs::read_to_string(&buckpath) {
+            Ok(buckfile) => rules.extend(buck::rule_names(&buckfile)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(format!("reading {}", buckpath.display())),
+        }
+    }
+    Ok(rules)
+}
@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Check the generated rules by running a Buck command over them, such as
+//! `buck2 uquery`, and blame its errors on the packages whose rules they name.
+
+use std::collections::BTreeSet;
+use std::process::Command;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::cargo::Manifest;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::index::Index;
+use crate::profile;
+use crate::Paths;
+
+pub(crate) fn validate(command: &[String], paths: &Paths, index: &Index) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(());
+    };
+
+    log::info!("Validating generated rules with {:?}", command);
+    let output = {
+        let _timer = profile::time("validate");
+        Command::new(program)
+            .args(args)
+            .current_dir(&paths.third_party_dir)
+            .output()
+            .with_context(|| format!("running {}", program))?
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // Longest first, so that `foo-sys` is blamed rather than `foo`.
+    let mut rule_names: Vec<(&str, &Manifest)> = index
+        .dependency_chains(|_| false)
+        .packages()
+        .map(|pkg| (index.package_rule_name(pkg), pkg))
+        .collect();
+    rule_names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut reported = BTreeSet::new();
+    for line in stderr.lines() {
+        let line = line.trim();
+        let Some((_, pkg)) = rule_names.iter().find(|(name, _)| mentions(line, name)) else {
+            continue;
+        };
+        if reported.insert((&pkg.id, line)) {
+            Diagnostic::new(Severity::Error, line).package(pkg).report();
+        }
+    }
+
+    let mut message = format!(
+        "`{}` failed on the generated rules ({})",
+        command.join(" "),
+        output.status,
+    );
+    if reported.is_empty() {
+        message.push_str(":\n");
+        message.push_str(stderr.trim_end());
+    }
+    bail!(message);
+}
+
+/// Whether `line` names one of the rules starting with `rule_name`, such as
+/// `//third-party:foo-1.0.0` or `foo-1.0.0-build-script-run`.
+fn mentions(line: &str, rule_name: &str) -> bool {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
+    line.match_indices(rule_name).any(|(start, _)| {
+        let before = line[..start].chars().next_back();
+        let after = line[start + rule_name.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(|c| c != '-' && is_name_char(c))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::mentions;
+
+    #[test]
+    fn test_mentions() {
+        assert!(mentions("Unknown target `foo-1.0.0`", "foo-1.0.0"));
+        assert!(mentions(
+            "root//third-party:foo-1.0.0-build-script-run",
+            "foo-1.0.0"
+        ));
+        assert!(mentions("error in foo: missing dep", "foo"));
+        assert!(!mentions("Unknown target `foo-1.0.0`", "oo-1.0.0"));
+        assert!(!mentions("Unknown target `foo-1.0.0`", "foo-1.0"));
+        assert!(!mentions("Unknown target `libfoo`", "foo"));
+    }
+}
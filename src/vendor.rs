@@ -5,24 +5,45 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs;
+use std::io;
 use std::io::ErrorKind;
+use std::io::IsTerminal as _;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use globset::GlobBuilder;
+use globset::GlobSet;
 use globset::GlobSetBuilder;
+use ignore::gitignore::Gitignore;
 use ignore::gitignore::GitignoreBuilder;
 use indexmap::IndexMap;
+use rayon::iter::IntoParallelRefIterator as _;
+use rayon::iter::ParallelIterator as _;
 use serde::Deserialize;
 use serde::Serialize;
+use walkdir::WalkDir;
 
 use crate::buckify::relative_path;
 use crate::cargo;
 use crate::config::Config;
 use crate::config::VendorConfig;
+use crate::config::VendorLink;
+use crate::diagnostics::Failure;
+use crate::diagnostics::FailureKind;
+use crate::lockfile::Lockfile;
+use crate::profile;
 use crate::remap::RemapConfig;
+use crate::srcs_cache::CACHE_DIR;
 use crate::Args;
 use crate::Paths;
 
@@ -42,27 +63,65 @@ pub(crate) fn cargo_vendor(
 ) -> Result<()> {
     let vendordir = Path::new("vendor"); // relative to third_party_dir
 
+    let workspaces: Vec<PathBuf> = config
+        .workspaces
+        .iter()
+        .map(|manifest_path| paths.third_party_dir.join(manifest_path))
+        .collect();
+
     let mut cmdline = vec![
         "vendor",
         "--manifest-path",
         paths.manifest_path.to_str().unwrap(),
         vendordir.to_str().unwrap(),
-        "--versioned-dirs",
     ];
+    // Without this Cargo only adds the version to the directory names of
+    // packages which have more than one version.
+    if config.naming.versioned_dirs() {
+        cmdline.push("--versioned-dirs");
+    }
+    for manifest_path in &workspaces {
+        cmdline.extend(["--sync", manifest_path.to_str().unwrap()]);
+    }
     if no_delete {
         cmdline.push("--no-delete");
     }
 
     fs::create_dir_all(&paths.cargo_home)?;
 
+    // Cargo needs to see real directories, so take the symlinks into the
+    // store out of its way, other than those of packages it will leave alone
+    // anyway.
+    let store = config
+        .vendor
+        .as_ref()
+        .and_then(|vendor| vendor.store.as_ref());
+    let store_links = match store {
+        Some(_) => remove_store_links(
+            &paths.third_party_dir.join(vendordir),
+            &unchanged_checksums(config, paths)?,
+        )?,
+        None => Vec::new(),
+    };
+    // Cargo leaves alone packages it vendored before into directories named
+    // with their version, so there's no need to link their files again.
+    let previously_vendored = checksum_times(&paths.third_party_dir.join(vendordir));
+
     log::info!("Running cargo {:?}", cmdline);
-    let cargoconfig = cargo::run_cargo(
-        config,
-        Some(&paths.cargo_home),
-        &paths.third_party_dir,
-        args,
-        &cmdline,
-    )?;
+    let cargoconfig = {
+        let _timer = profile::time("vendor");
+        cargo::run_cargo(
+            config,
+            Some(&paths.cargo_home),
+            &paths.third_party_dir,
+            args,
+            &cmdline,
+        )
+        .context(Failure::new(
+            FailureKind::Vendor,
+            "Failed to vendor sources",
+        ))?
+    };
 
     fs::write(paths.cargo_home.join("config.toml"), &cargoconfig)?;
     if !cargoconfig.is_empty() {
@@ -70,7 +129,15 @@ pub(crate) fn cargo_vendor(
     }
 
     if let Some(vendor_config) = &config.vendor {
-        filter_checksum_files(&paths.third_party_dir, vendordir, vendor_config)?;
+        let _timer = profile::time("processing vendored packages");
+        process_vendored(
+            paths,
+            vendordir,
+            vendor_config,
+            no_delete,
+            store_links,
+            &previously_vendored,
+        )?;
     }
 
     if audit_sec {
@@ -113,53 +180,463 @@ pub(crate) fn is_vendored(paths: &Paths) -> Result<bool> {
     }
 }
 
-fn filter_checksum_files(
-    third_party_dir: &Path,
+/// Everything done to the vendored packages after `cargo vendor`, one
+/// package at a time on the thread pool.
+fn process_vendored(
+    paths: &Paths,
     vendordir: &Path,
-    config: &VendorConfig,
+    vendor_config: &VendorConfig,
+    no_delete: bool,
+    store_links: Vec<(OsString, PathBuf)>,
+    previously_vendored: &HashMap<PathBuf, SystemTime>,
 ) -> Result<()> {
-    if config.checksum_exclude.is_empty() && config.gitignore_checksum_exclude.is_empty() {
-        return Ok(());
+    let vendor_dir = paths.third_party_dir.join(vendordir);
+    let checksum_filter = ChecksumFilter::new(&paths.third_party_dir, vendor_config)?;
+    let registry_dirs = match vendor_config.link {
+        VendorLink::Copy => HashMap::new(),
+        VendorLink::Reflink | VendorLink::Hardlink => registry_dirs(paths)?,
+    };
+    // Hardlinks only ever go to copies of our own, so that editing a
+    // vendored file can't change what Cargo extracted.
+    let link_store = match vendor_config.link {
+        VendorLink::Hardlink => Some(paths.third_party_dir.join(CACHE_DIR).join("vendor")),
+        VendorLink::Copy | VendorLink::Reflink => None,
+    };
+    let store_dir = match &vendor_config.store {
+        Some(store) => {
+            let store_dir = paths.third_party_dir.join(store);
+            fs::create_dir_all(&store_dir)?;
+            Some(store_dir)
+        }
+        None => None,
+    };
+
+    let pkgdirs = package_dirs(&vendor_dir)?;
+    let progress = Progress::new("Processing vendored packages", pkgdirs.len());
+    let linked = AtomicUsize::new(0);
+    let copied = AtomicUsize::new(0);
+    pkgdirs.par_iter().try_for_each(|pkgdir| -> Result<()> {
+        if let Some(checksum_filter) = &checksum_filter {
+            checksum_filter
+                .filter(&paths.third_party_dir, pkgdir, false)
+                .context(Failure::new(
+                    FailureKind::Vendor,
+                    "Failed to filter the vendored checksums",
+                ))?;
+        }
+        let fresh = previously_vendored.get(pkgdir) != checksum_time(pkgdir).as_ref();
+        if vendor_config.link != VendorLink::Copy && fresh {
+            let (pkg_linked, pkg_copied) =
+                link_package(pkgdir, &registry_dirs, link_store.as_deref()).context(
+                    Failure::new(FailureKind::Vendor, "Failed to link the vendored sources"),
+                )?;
+            linked.fetch_add(pkg_linked, Ordering::Relaxed);
+            copied.fetch_add(pkg_copied, Ordering::Relaxed);
+        }
+        if let Some(store_dir) = &store_dir {
+            move_into_store(&vendor_dir, store_dir, pkgdir).context(Failure::new(
+                FailureKind::Vendor,
+                "Failed to move the vendored sources into the store",
+            ))?;
+        }
+        progress.advance();
+        Ok(())
+    })?;
+    progress.finish();
+
+    match vendor_config.link {
+        VendorLink::Copy => {}
+        VendorLink::Reflink => log::info!(
+            "Cloned {} vendored files, {} left as copies",
+            linked.into_inner(),
+            copied.into_inner(),
+        ),
+        VendorLink::Hardlink => log::info!(
+            "Hardlinked {} vendored files, {} left as copies",
+            linked.into_inner(),
+            copied.into_inner(),
+        ),
     }
 
-    log::debug!(
-        "vendor.gitignore_checksum_exclude = {:?} vendor.checksum_exclude = {:?}",
-        config.gitignore_checksum_exclude,
-        config.checksum_exclude
-    );
+    // Cargo left alone whatever it would otherwise have deleted.
+    if store_dir.is_some() && no_delete {
+        for (name, target) in store_links {
+            let link = vendor_dir.join(name);
+            if !link.exists() {
+                symlink_dir(&target, &link)?;
+            }
+        }
+    }
 
-    // re-write checksum files to exclude things we don't want (like Cargo.lock)
-    let mut remove_globs = GlobSetBuilder::new();
-    for glob in &config.checksum_exclude {
-        let glob = GlobBuilder::new(glob)
-            .literal_separator(true)
-            .build()
-            .with_context(|| format!("Invalid checksum exclude glob `{}`", glob))?;
-        remove_globs.add(glob);
+    Ok(())
+}
+
+/// The vendored packages' directories, including through the symlinks of a
+/// `store`.
+fn package_dirs(vendor_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut pkgdirs = Vec::new();
+    for entry in fs::read_dir(vendor_dir)? {
+        let path = entry?.path();
+        if fs::metadata(&path)?.is_dir() {
+            pkgdirs.push(path);
+        }
     }
-    let remove_globs = remove_globs.build()?;
+    pkgdirs.sort();
+    Ok(pkgdirs)
+}
 
-    let mut gitignore = GitignoreBuilder::new(third_party_dir);
-    for ignore in &config.gitignore_checksum_exclude {
-        if let Some(err) = gitignore.add(third_party_dir.join(ignore)) {
-            log::warn!(
-                "Failed to read ignore file {}: {}; skipping",
-                ignore.display(),
-                err
-            );
+/// When each vendored package's .cargo-checksum.json was last written, by
+/// package directory.
+fn checksum_times(vendor_dir: &Path) -> HashMap<PathBuf, SystemTime> {
+    let Ok(pkgdirs) = package_dirs(vendor_dir) else {
+        return HashMap::new();
+    };
+    pkgdirs
+        .into_iter()
+        .filter_map(|pkgdir| {
+            let time = checksum_time(&pkgdir)?;
+            Some((pkgdir, time))
+        })
+        .collect()
+}
+
+fn checksum_time(pkgdir: &Path) -> Option<SystemTime> {
+    fs::metadata(pkgdir.join(".cargo-checksum.json"))
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Count of finished work, shown on stderr if that's a terminal.
+struct Progress {
+    what: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    show: bool,
+}
+
+impl Progress {
+    fn new(what: &'static str, total: usize) -> Self {
+        Progress {
+            what,
+            total,
+            done: AtomicUsize::new(0),
+            show: io::stderr().is_terminal(),
         }
     }
-    let gitignore = gitignore.build()?;
 
-    log::debug!(
-        "remove_globs {:#?}, gitignore {:#?}",
-        remove_globs,
-        gitignore
-    );
+    fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.show {
+            eprint!("\r{}: {}/{}", self.what, done, self.total);
+        }
+    }
+
+    fn finish(&self) {
+        if self.show && self.total != 0 {
+            eprintln!();
+        }
+    }
+}
+
+/// Where Cargo extracted each package it downloaded, by `<name>-<version>`.
+fn registry_dirs(paths: &Paths) -> Result<HashMap<OsString, PathBuf>> {
+    // Cargo extracts each package to registry/src/<index>/<name>-<version>.
+    let mut registry_dirs = HashMap::new();
+    if let Ok(indexes) = fs::read_dir(paths.cargo_home.join("registry").join("src")) {
+        for index in indexes {
+            for entry in fs::read_dir(index?.path())? {
+                let entry = entry?;
+                registry_dirs.insert(entry.file_name(), entry.path());
+            }
+        }
+    }
+    Ok(registry_dirs)
+}
 
-    for entry in fs::read_dir(third_party_dir.join(vendordir))? {
+/// Replace the files of a freshly vendored package with copy-on-write clones
+/// of the ones in the registry source directory which `cargo vendor` copied
+/// them from, or with `link_store`, with hardlinks to copies of those kept
+/// there. Files which the filesystem can't clone or link stay as `cargo
+/// vendor` copied them. Returns how many files were linked, and how many not.
+fn link_package(
+    pkgdir: &Path,
+    registry_dirs: &HashMap<OsString, PathBuf>,
+    link_store: Option<&Path>,
+) -> Result<(usize, usize)> {
+    let mut linked = 0;
+    let mut copied = 0;
+    let Some(name) = package_dir_name(pkgdir) else {
+        return Ok((linked, copied));
+    };
+    let Some(registry_dir) = registry_dirs.get(&name) else {
+        // Not from a registry, such as a git dependency
+        return Ok((linked, copied));
+    };
+    for entry in WalkDir::new(pkgdir) {
         let entry = entry?;
-        let path = entry.path(); // full/path/to/vendor/foo-1.2.3
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let vendored = entry.path();
+        let relative = vendored.strip_prefix(pkgdir)?;
+        let original = registry_dir.join(relative);
+        // `cargo vendor` copies files as they are, other than writing its
+        // own .cargo-checksum.json.
+        if !same_size(&original, vendored) {
+            copied += 1;
+            continue;
+        }
+        let result = match link_store {
+            Some(link_store) => {
+                let stored = link_store.join(&name).join(relative);
+                store_copy(&original, &stored).and_then(|()| hardlink(&stored, vendored))
+            }
+            None => reflink(&original, vendored),
+        };
+        match result {
+            Ok(()) => linked += 1,
+            Err(err) => {
+                log::debug!("leaving {} as a copy: {}", vendored.display(), err);
+                copied += 1;
+            }
+        }
+    }
+    Ok((linked, copied))
+}
+
+/// Move a package vendored from a registry to the store, under its checksum,
+/// and replace it with a symlink. Packages already in the store are not
+/// touched, other than to pick up a newly filtered checksum file, so the
+/// store only ever gains directories.
+fn move_into_store(vendor_dir: &Path, store_dir: &Path, pkgdir: &Path) -> Result<()> {
+    if fs::symlink_metadata(pkgdir)?.file_type().is_symlink() {
+        // Kept from before, and already in the store
+        return Ok(());
+    }
+    let checksum_path = pkgdir.join(".cargo-checksum.json");
+    let checksums: CargoChecksums = serde_json::from_slice(&fs::read(&checksum_path)?)
+        .with_context(|| format!("Failed to deserialize {}", checksum_path.display()))?;
+    let Some(checksum) = checksums.package else {
+        // Only packages from a registry have a checksum; git packages stay
+        // where they are.
+        return Ok(());
+    };
+
+    let stored = store_dir.join(&checksum);
+    if stored.exists() {
+        fs::copy(&checksum_path, stored.join(".cargo-checksum.json"))?;
+        fs::remove_dir_all(pkgdir)?;
+    } else {
+        fs::rename(pkgdir, &stored)?;
+    }
+    symlink_dir(&relative_path(vendor_dir, &stored), pkgdir)?;
+    Ok(())
+}
+
+/// The checksum of each package in Cargo.lock, by the directory `cargo
+/// vendor` gives it. Cargo skips the directories it finds already vendored
+/// only if their names have the version, so there are none without
+/// `--versioned-dirs`.
+fn unchanged_checksums(config: &Config, paths: &Paths) -> Result<HashMap<OsString, OsString>> {
+    if !config.naming.versioned_dirs() || !paths.lockfile_path.exists() {
+        return Ok(HashMap::new());
+    }
+    let lockfile = Lockfile::load(&paths.lockfile_path)?;
+    Ok(lockfile
+        .packages
+        .into_iter()
+        .filter_map(|pkg| {
+            let checksum = pkg.checksum?;
+            let dir = format!("{}-{}", pkg.name, pkg.version);
+            Some((OsString::from(dir), OsString::from(checksum)))
+        })
+        .collect())
+}
+
+/// Remove the symlinks from the vendor directory, returning each one's name
+/// and target. Links to the store entry of the checksum a package has in
+/// `unchanged` stay.
+fn remove_store_links(
+    vendor_dir: &Path,
+    unchanged: &HashMap<OsString, OsString>,
+) -> Result<Vec<(OsString, PathBuf)>> {
+    let mut links = Vec::new();
+    let entries = match fs::read_dir(vendor_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(links),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_symlink() {
+            let path = entry.path();
+            let target = fs::read_link(&path)?;
+            if unchanged.get(&entry.file_name()).map(OsString::as_os_str) == target.file_name()
+                && path.join(".cargo-checksum.json").exists()
+            {
+                continue;
+            }
+            links.push((entry.file_name(), target));
+            // A directory symlink on Windows is removed as a directory.
+            fs::remove_file(&path).or_else(|_| fs::remove_dir(&path))?;
+        }
+    }
+    Ok(links)
+}
+
+#[cfg(unix)]
+fn symlink_dir(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+/// `<name>-<version>`, from the vendored package's Cargo.toml. The vendored
+/// directory itself may not have the version in its name.
+fn package_dir_name(pkgdir: &Path) -> Option<OsString> {
+    let manifest: toml::Value =
+        toml::from_str(&fs::read_to_string(pkgdir.join("Cargo.toml")).ok()?).ok()?;
+    let package = manifest.get("package")?;
+    let name = package.get("name")?.as_str()?;
+    let version = package.get("version")?.as_str()?;
+    Some(OsString::from(format!("{}-{}", name, version)))
+}
+
+fn same_size(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) => a_meta.len() == b_meta.len(),
+        _ => false,
+    }
+}
+
+/// Put a copy of `original` at `stored` for hardlinking to, cloning it where
+/// the filesystem can, unless it's there from an earlier run.
+fn store_copy(original: &Path, stored: &Path) -> io::Result<()> {
+    if stored.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(stored.parent().unwrap())?;
+    replace_with(stored, |tmp| {
+        reflink_copy::reflink_or_copy(original, tmp).map(drop)
+    })
+}
+
+/// Replace `clone` with a copy-on-write clone of `original`, leaving it alone
+/// on failure.
+fn reflink(original: &Path, clone: &Path) -> io::Result<()> {
+    replace_with(clone, |tmp| reflink_copy::reflink(original, tmp))
+}
+
+/// Replace `link` with a hardlink to `original`, leaving it alone on failure.
+fn hardlink(original: &Path, link: &Path) -> io::Result<()> {
+    replace_with(link, |tmp| fs::hard_link(original, tmp))
+}
+
+/// Create a file next to `path` and rename it over `path`.
+fn replace_with(path: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".reindeer-link");
+    let tmp = PathBuf::from(tmp);
+    let _ = fs::remove_file(&tmp);
+    create(&tmp)?;
+    let result = fs::rename(&tmp, path);
+    // Renaming does nothing if `path` is already a hardlink to the same
+    // file, from an earlier run.
+    let _ = fs::remove_file(&tmp);
+    result
+}
+
+pub(crate) fn filter_checksums(config: &Config, paths: &Paths, dry_run: bool) -> Result<()> {
+    let Some(vendor_config) = &config.vendor else {
+        bail!("Nothing is vendored with `vendor = false`");
+    };
+    let Some(checksum_filter) = ChecksumFilter::new(&paths.third_party_dir, vendor_config)? else {
+        return Ok(());
+    };
+    let vendor_dir = paths.third_party_dir.join("vendor");
+
+    let excluded = package_dirs(&vendor_dir)?
+        .par_iter()
+        .map(|pkgdir| checksum_filter.filter(&paths.third_party_dir, pkgdir, dry_run))
+        .collect::<Result<Vec<_>>>()?;
+    for (pkgdir, files) in excluded.into_iter().flatten() {
+        println!("{}:", pkgdir.display());
+        for file in files {
+            println!("    {}", file);
+        }
+    }
+    Ok(())
+}
+
+/// Which entries to take out of the vendored packages' .cargo-checksum.json,
+/// so that Cargo doesn't insist on those files being present and unchanged:
+/// the ones matching `checksum_exclude`, or ignored by the
+/// `gitignore_checksum_exclude` files.
+struct ChecksumFilter {
+    remove_globs: GlobSet,
+    gitignore: Gitignore,
+}
+
+impl ChecksumFilter {
+    fn new(third_party_dir: &Path, config: &VendorConfig) -> Result<Option<Self>> {
+        if config.checksum_exclude.is_empty() && config.gitignore_checksum_exclude.is_empty() {
+            return Ok(None);
+        }
+
+        log::debug!(
+            "vendor.gitignore_checksum_exclude = {:?} vendor.checksum_exclude = {:?}",
+            config.gitignore_checksum_exclude,
+            config.checksum_exclude
+        );
+
+        let mut remove_globs = GlobSetBuilder::new();
+        for glob in &config.checksum_exclude {
+            let glob = GlobBuilder::new(glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid checksum exclude glob `{}`", glob))?;
+            remove_globs.add(glob);
+        }
+        let remove_globs = remove_globs.build()?;
+
+        let mut gitignore = GitignoreBuilder::new(third_party_dir);
+        for ignore in &config.gitignore_checksum_exclude {
+            if let Some(err) = gitignore.add(third_party_dir.join(ignore)) {
+                log::warn!(
+                    "Failed to read ignore file {}: {}; skipping",
+                    ignore.display(),
+                    err
+                );
+            }
+        }
+        let gitignore = gitignore.build()?;
+
+        log::debug!(
+            "remove_globs {:#?}, gitignore {:#?}",
+            remove_globs,
+            gitignore
+        );
+
+        Ok(Some(ChecksumFilter {
+            remove_globs,
+            gitignore,
+        }))
+    }
+
+    /// Re-write a package's checksum file to exclude things we don't want
+    /// (like Cargo.lock). Returns the package's directory relative to the
+    /// third-party directory, with the excluded entries, including files whose
+    /// entries earlier runs already removed. With `dry_run` the checksum file
+    /// is left as it is.
+    fn filter(
+        &self,
+        third_party_dir: &Path,
+        path: &Path, // full/path/to/vendor/foo-1.2.3
+        dry_run: bool,
+    ) -> Result<Option<(PathBuf, BTreeSet<String>)>> {
         let checksum = path.join(".cargo-checksum.json"); // full/path/to/vendor/foo-1.2.3/.cargo-checksum.json
 
         log::trace!("Reading checksum {}", checksum.display());
@@ -167,7 +644,7 @@ fn filter_checksum_files(
         let file = match fs::read(&checksum) {
             Err(err) => {
                 log::warn!("Failed to read {}: {}", checksum.display(), err);
-                continue;
+                return Ok(None);
             }
             Ok(file) => file,
         };
@@ -175,33 +652,104 @@ fn filter_checksum_files(
         let mut checksums: CargoChecksums = match serde_json::from_slice(&file) {
             Err(err) => {
                 log::warn!("Failed to deserialize {}: {}", checksum.display(), err);
-                continue;
+                return Ok(None);
             }
             Ok(cs) => cs,
         };
 
-        let mut changed = false;
-
-        let pkgdir = relative_path(third_party_dir, &path); // vendor/foo-1.2.3
+        let pkgdir = relative_path(third_party_dir, path); // vendor/foo-1.2.3
+        let is_excluded = |k: &str| {
+            self.remove_globs.is_match(k)
+                || self
+                    .gitignore
+                    .matched_path_or_any_parents(pkgdir.join(k), false)
+                    .is_ignore()
+        };
 
+        let mut removed = BTreeSet::new();
         checksums.files.retain(|k, _| {
             log::trace!("{}: checking {}", checksum.display(), k);
-            let del = remove_globs.is_match(k)
-                || gitignore
-                    .matched_path_or_any_parents(pkgdir.join(k), false)
-                    .is_ignore();
+            let del = is_excluded(k);
             if del {
                 log::debug!("{}: removing {}", checksum.display(), k);
-                changed = true;
+                removed.insert(k.clone());
             };
             !del
         });
 
-        if changed {
+        let changed = !removed.is_empty();
+        for file in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+            if !file.file_type().is_file() {
+                continue;
+            }
+            let relative = relative_path(path, file.path());
+            let k = relative.to_string_lossy().replace('\\', "/");
+            if k != ".cargo-checksum.json" && is_excluded(&k) {
+                removed.insert(k);
+            }
+        }
+
+        if changed && !dry_run {
             log::info!("Rewriting checksum {}", checksum.display());
-            fs::write(checksum, serde_json::to_vec(&checksums)?)?;
+            // Replaced rather than written through, in case it's a link.
+            replace_with(&checksum, |tmp| {
+                fs::write(tmp, serde_json::to_vec(&checksums)?)
+            })?;
+        }
+
+        if removed.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((pkgdir, removed)))
         }
     }
+}
 
-    Ok(())
+#[cfg(all(test, unix))]
+mod test {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::fs;
+
+    use super::remove_store_links;
+
+    #[test]
+    fn keeps_links_with_unchanged_checksums() {
+        let tmp = tempfile::tempdir().unwrap();
+        let vendor_dir = tmp.path().join("vendor");
+        let store_dir = tmp.path().join("vendor-store");
+        fs::create_dir(&vendor_dir).unwrap();
+        for checksum in ["aaa", "bbb"] {
+            fs::create_dir_all(store_dir.join(checksum)).unwrap();
+            fs::write(store_dir.join(checksum).join(".cargo-checksum.json"), "{}").unwrap();
+        }
+        for (name, checksum) in [
+            ("foo-1.0.0", "aaa"),
+            ("bar-1.0.0", "bbb"),
+            ("baz-1.0.0", "ccc"),
+        ] {
+            std::os::unix::fs::symlink(
+                format!("../vendor-store/{}", checksum),
+                vendor_dir.join(name),
+            )
+            .unwrap();
+        }
+
+        let unchanged = HashMap::from([
+            (OsString::from("foo-1.0.0"), OsString::from("aaa")),
+            (OsString::from("bar-1.0.0"), OsString::from("ddd")),
+            (OsString::from("baz-1.0.0"), OsString::from("ccc")),
+        ]);
+        let mut removed: Vec<OsString> = remove_store_links(&vendor_dir, &unchanged)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _target)| name)
+            .collect();
+        removed.sort();
+
+        // bar's checksum changed, and baz's store entry is gone.
+        assert_eq!(removed, ["bar-1.0.0", "baz-1.0.0"]);
+        assert!(vendor_dir.join("foo-1.0.0/.cargo-checksum.json").exists());
+        assert!(fs::symlink_metadata(vendor_dir.join("bar-1.0.0")).is_err());
+    }
 }
@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Find packages locked to versions which have been yanked from their
+//! registry.
+//!
+//! Rather than querying the registry, this reads the copy of the index which
+//! Cargo keeps under `$CARGO_HOME/registry/index/*/.cache`, which is brought up
+//! to date whenever Cargo resolves or vendors dependencies while online.
+//! Packages which aren't in the cache are skipped.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::cargo::Manifest;
+use crate::cargo::Source;
+use crate::config::CheckLevel;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::diagnostics::Severity;
+use crate::index::display_chain;
+use crate::index::Index;
+use crate::Paths;
+
+pub(crate) fn check_yanked(config: &Config, paths: &Paths, index: &Index) -> Result<()> {
+    let severity = match config.yanked {
+        CheckLevel::Ignore => return Ok(()),
+        CheckLevel::Warn => Severity::Warning,
+        CheckLevel::Error => Severity::Error,
+    };
+
+    let index_dirs = index_dirs(paths);
+    let chains = index.dependency_chains(|_| false);
+    let mut yanked = 0;
+    for pkg in chains.packages() {
+        let Some(versions) = cached_versions(&index_dirs, pkg) else {
+            continue;
+        };
+        if !versions
+            .get(&pkg.version.to_string())
+            .is_some_and(|entry| entry.yanked)
+        {
+            continue;
+        }
+
+        yanked += 1;
+        Diagnostic::new(
+            severity,
+            format!(
+                "version is yanked from its registry (reached through {})",
+                display_chain(&chains.chain(pkg)),
+            ),
+        )
+        .package(pkg)
+        .suggestion(format!(
+            "move off it with `cargo update -p {}@{}`",
+            pkg.name, pkg.version,
+        ))
+        .report();
+    }
+
+    if yanked > 0 && config.yanked == CheckLevel::Error {
+        bail!(
+            "{} package{} locked to a yanked version",
+            yanked,
+            if yanked == 1 { "" } else { "s" },
+        );
+    }
+    Ok(())
+}
+
+/// The registry index directories in Cargo's caches. Reindeer's own Cargo
+/// home is used for vendoring, and the user's for everything else.
+pub(crate) fn index_dirs(paths: &Paths) -> Vec<PathBuf> {
+    let mut index_dirs = Vec::new();
+    let user_cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    for cargo_home in [Some(paths.cargo_home.clone()), user_cargo_home]
+        .into_iter()
+        .flatten()
+    {
+        if let Ok(entries) = fs::read_dir(cargo_home.join("registry").join("index")) {
+            index_dirs.extend(entries.filter_map(Result::ok).map(|entry| entry.path()));
+        }
+    }
+    index_dirs
+}
+
+/// Start of the names of the index directories Cargo may use for a package's
+/// registry, which are the registry's host followed by a hash.
+pub(crate) fn registry_hosts(source: &Source) -> Option<Vec<String>> {
+    match source {
+        Source::CratesIo => Some(vec!["index.crates.io".to_owned(), "github.com".to_owned()]),
+        Source::Unrecognized(source) => {
+            let url = source
+                .strip_prefix("registry+")
+                .or_else(|| source.strip_prefix("sparse+"))?;
+            let url = url::Url::parse(url).ok()?;
+            Some(vec![url.host_str()?.to_owned()])
+        }
+        Source::Local | Source::Git { .. } => None,
+    }
+}
+
+/// The registry index's entry for each version of a package, from the most
+/// recently updated cache of it, if any.
+pub(crate) fn cached_versions(
+    index_dirs: &[PathBuf],
+    pkg: &Manifest,
+) -> Option<HashMap<String, IndexEntry>> {
+    let hosts = registry_hosts(&pkg.source)?;
+    let relative = index_path(&pkg.name.to_lowercase());
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for dir in index_dirs {
+        let Some(dir_name) = dir.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !hosts
+            .iter()
+            .any(|host| dir_name.starts_with(&format!("{}-", host)))
+        {
+            continue;
+        }
+        let path = dir.join(".cache").join(&relative);
+        if let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                newest = Some((modified, path));
+            }
+        }
+    }
+
+    let Some((_, path)) = newest else {
+        log::debug!("{} is not in any cached registry index", pkg);
+        return None;
+    };
+    match fs::read(&path) {
+        Ok(content) => Some(parse_cache_entry(&content)),
+        Err(err) => {
+            log::debug!("reading {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Where a package is in the registry index, such as `se/rd/serde`.
+fn index_path(name: &str) -> PathBuf {
+    match name.len() {
+        1 => Path::new("1").join(name),
+        2 => Path::new("2").join(name),
+        3 => Path::new("3").join(&name[..1]).join(name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(name),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IndexEntry {
+    pub vers: String,
+    #[serde(default)]
+    pub yanked: bool,
+    /// When the version was published, such as `2023-01-15T12:34:56Z`. Only
+    /// recent index entries have it.
+    #[serde(default)]
+    pub pubtime: Option<String>,
+}
+
+/// Cargo's cache files start with a small header, followed by
+/// NUL-terminated pairs of a version and the index's JSON line for it.
+fn parse_cache_entry(content: &[u8]) -> HashMap<String, IndexEntry> {
+    content
+        .split(|&byte| byte == 0)
+        .filter(|segment| segment.first() == Some(&b'{'))
+        .filter_map(|json| serde_json::from_slice::<IndexEntry>(json).ok())
+        .map(|entry| (entry.vers.clone(), entry))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::index_path;
+    use super::parse_cache_entry;
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), Path::new("1/a"));
+        assert_eq!(index_path("cc"), Path::new("2/cc"));
+        assert_eq!(index_path("syn"), Path::new("3/s/syn"));
+        assert_eq!(index_path("serde"), Path::new("se/rd/serde"));
+    }
+
+    #[test]
+    fn test_parse_cache_entry() {
+        let mut content = b"\x03\x02\x00\x00\x00etag\x00".to_vec();
+        content.extend(b"0.1.0\x00{\"name\":\"foo\",\"vers\":\"0.1.0\",\"yanked\":false}\x00");
+        content.extend(b"0.1.1\x00{\"name\":\"foo\",\"vers\":\"0.1.1\",\"yanked\":true,\"pubtime\":\"2023-01-15T12:34:56Z\"}\x00");
+        let versions = parse_cache_entry(&content);
+        assert_eq!(versions.get("0.1.0").map(|entry| entry.yanked), Some(false));
+        assert_eq!(versions.get("0.1.1").map(|entry| entry.yanked), Some(true));
+        assert_eq!(
+            versions["0.1.1"].pubtime.as_deref(),
+            Some("2023-01-15T12:34:56Z"),
+        );
+        assert!(versions["0.1.0"].pubtime.is_none());
+        assert!(!versions.contains_key("0.1.2"));
+    }
+}